@@ -3,126 +3,848 @@
 
 use crate::ir::*;
 use crate::types::Type;
-use crate::errors::Span;
+use crate::errors::{self, Code, Diagnostic, Logger, Span};
+use crate::cfg;
+use crate::opt;
+use crate::verify::verify;
 
-use std::collections::HashMap;
+use crate::astgen::Node;
 
-type Constraints = Vec<(Type, Type)>;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+
+type Constraints = Vec<Constraint>;
+type Substitution = HashMap<Type, Type>;
+/// The literal type that each variable standing in for a literal was given by the IR builder
+type Literals = HashMap<Type, Type>;
 
 impl<'i> IRBuilder<'i> {
-    pub fn analyze(&mut self) -> Option<()> {
+    /// Returns how many errors were found, a proc that fails to analyze
+    /// doesn't stop the others from being checked
+    pub fn analyze(&mut self) -> usize {
+        let before = self.logger.error_count();
         self.scopes.clear();
+        self.check_main();
+        // analysis trusts the shape of the IR, so a proc whose IR is broken isn't analyzed.
+        // That's only the compiler's fault if nothing was wrong with the source,
+        // the IR builder keeps going past errors by leaving in whatever it can.
+        let verified: Vec<_> = self.procs.iter()
+            .map(|proc| match verify(proc, &self.procs, &self.globals) {
+                Ok(()) => true,
+                Err(diagnostic) => {
+                    if before == 0 {
+                        self.logger.report(vec![diagnostic]);
+                    }
+                    false
+                }
+            })
+            .collect();
+        // procs from the cache were analyzed before they were cached
+        let verified: Vec<_> = self.procs.iter()
+            .zip(verified)
+            .map(|(proc, verified)| verified && !self.is_cached(&proc.name))
+            .collect();
+        let typed = self.infer_arg_types(&verified);
+        let ready: Vec<_> = verified.iter().zip(&typed).map(|(verified, typed)| verified & typed).collect();
         let mut new_procs = Vec::new();
-        let mut index = 0;
-        while index < self.procs.len() {
-            self.scopes.push(HashMap::new());
-            let scope = self.scopes.last_mut().unwrap();
-            for (i, arg_type) in self.procs[index].arg_types.iter().enumerate() {
-                scope.insert(self.procs[index].args[i].clone(), arg_type.clone());
+        // everything a proc's analysis produced is reported in proc order,
+        // no matter which order they actually ran in
+        for (analyzed, proc) in analyze_all(&self.procs, &ready, self.trace).into_iter().zip(&self.procs) {
+            for line in analyzed.trace {
+                eprintln!("{}", line);
+            }
+            self.logger.report(analyzed.errors);
+            new_procs.push(analyzed.proc.unwrap_or_else(|| proc.clone()));
+        }
+        // only before analysis can a variable declared with a type be told apart from one without
+        let unsolved = std::mem::replace(&mut self.procs, new_procs);
+        let errors = self.logger.error_count() - before;
+        // a proc that failed to build has no body, which would make
+        // everything it uses look unused
+        if self.warnings && self.logger.error_count() == 0 {
+            self.warn_unused();
+            for proc in &unsolved {
+                warn_unassigned(proc, &self.logger);
+            }
+        }
+        errors
+    }
+
+    /// main is what the program starts with, so there has to be one, which the C runtime can call.
+    /// What it returns is the exit code, which is an int in C.
+    fn check_main(&self) {
+        let Some(main) = self.procs.iter().find(|proc| proc.name == "main") else {
+            // it may well be there, but not have parsed
+            if self.logger.error_count() == 0 {
+                self.logger.name_error(Code::NoMain, "There is no proc named main, so there's nowhere for the program to start", 0, 0);
+            }
+            return;
+        };
+        let (pos, len) = self.proc_span("main");
+        if !main.args.is_empty() {
+            self.logger.type_error(
+                Code::InvalidMain,
+                format!("main can't take any arguments, but this one takes {}", main.args.len()).as_str(),
+                pos,
+                len,
+            );
+        }
+        let returns_int = main.ret_type.is_integer() && opt::bits(&main.ret_type) <= 32;
+        if !returns_int && main.ret_type != Type::Undefined {
+            self.logger.type_error(
+                Code::InvalidMain,
+                format!(
+                    "main has to return an integer of at most 32 bits, which is the exit code, or nothing, not a {}",
+                    main.ret_type,
+                ).as_str(),
+                pos,
+                len,
+            );
+        }
+    }
+
+    /// Works out the types of the arguments that procs were declared without, from their calls.
+    /// Analysis is otherwise done one proc at a time, so this is the only place
+    /// where what one proc does decides the types of another.
+    ///
+    /// A call only says what it passes once the proc making it has argument types of its own,
+    /// so callers go first. Every round analyzes the procs that have just gotten all of their
+    /// argument types, then settles each unannotated proc that has heard from all of its callers,
+    /// until a round gets nowhere. That works through the procs in dependency order
+    /// without building the call graph, and leaves out procs that are only called from each other.
+    /// A proc's calls to itself don't count, they're checked like any other call once it's settled.
+    ///
+    /// Returns which procs ended up with every argument type known.
+    fn infer_arg_types(&mut self, verified: &[bool]) -> Vec<bool> {
+        let mut typed: Vec<_> = self.procs.iter()
+            .map(|proc| proc.arg_types.iter().all(|t| find_variable(t).is_none()))
+            .collect();
+        let mut analyzed = vec![false; self.procs.len()];
+        // every call to each unannotated proc that's been seen so far
+        let mut calls: HashMap<String, Vec<InferredCall>> = HashMap::new();
+        // procs with a caller that has errors of its own, which get reported by its analysis
+        let mut quiet = HashSet::new();
+        loop {
+            let mut progress = false;
+            for i in 0..self.procs.len() {
+                if !typed[i] || analyzed[i] || !verified[i] {
+                    continue;
+                }
+                analyzed[i] = true;
+                progress = true;
+                let mut analyzer = ProcAnalyzer::new(&self.procs, false);
+                let proc = analyzer.number_literals(&self.procs[i]);
+                // errors are left in the analyzer's own logger, for the real analysis to report
+                let substitution = analyzer.gen_constraints(&proc)
+                    .and_then(|constraints| analyzer.solve_constraints(&constraints));
+                for mut call in analyzer.calls {
+                    match &substitution {
+                        Some(substitution) => {
+                            call.args = call.args.iter().map(|t| resolve(substitution, t)).collect();
+                            calls.entry(call.proc.clone()).or_default().push(call);
+                        }
+                        None => {
+                            quiet.insert(call.proc);
+                        }
+                    }
+                }
+            }
+
+            for j in 0..self.procs.len() {
+                // procs that were given up on count as analyzed
+                if typed[j] || analyzed[j] {
+                    continue;
+                }
+                let name = &self.procs[j].name;
+                // a proc's calls are all in by now if every proc making them has been analyzed
+                let waiting = self.procs.iter().enumerate().any(|(i, caller)| {
+                    i != j && !analyzed[i] && verified[i] && caller.body.iter()
+                        .any(|ins| matches!(&ins.contents.ins, InstructionType::Call(callee) if callee == name))
+                });
+                if waiting || quiet.contains(name) {
+                    continue;
+                }
+                typed[j] = true;
+                progress = true;
+                let proc_calls = calls.remove(name).unwrap_or_default();
+                if let Some(substitution) = self.settle_arg_types(&self.procs[j], &proc_calls) {
+                    let proc = &mut self.procs[j];
+                    proc.arg_types = proc.arg_types.iter().map(|t| resolve(&substitution, t)).collect();
+                    substitute_proc_body(&mut proc.body, &substitution);
+                } else {
+                    // analyzing it would only complain about the same arguments again
+                    analyzed[j] = true;
+                    typed[j] = false;
+                }
+            }
+            if !progress {
+                break;
+            }
+        }
+
+        for (j, proc) in self.procs.iter().enumerate() {
+            if !typed[j] && !analyzed[j] && verified[j] && !quiet.contains(&proc.name) {
+                let (pos, len) = self.proc_span(&proc.name);
+                self.logger.type_error(
+                    Code::CannotInfer,
+                    format!("The types of the arguments of proc {} can't be inferred, \
+                        since it's only called by procs whose argument types are inferred from it", proc.name).as_str(),
+                    pos,
+                    len,
+                );
             }
-            let proc = self.procs[index].clone();
-            let mut constraints = self.gen_constraints(&proc)?;
-            add_literal_constaints(&mut constraints, &mut self.procs);
-            new_procs.push(self.solve_constraints(&proc, &constraints)?);
-            index += 1;
         }
-        self.procs = dbg!(new_procs);
-        Some(())
+        typed
+    }
+
+    /// What the argument types of `proc` are, going by every call to it, or None if the calls
+    /// don't agree or don't say. The errors for either are reported here.
+    fn settle_arg_types(&self, proc: &IRProc, calls: &[InferredCall]) -> Option<Substitution> {
+        if calls.is_empty() {
+            // main isn't meant to have any, which check_main already said
+            if proc.name != "main" {
+                let (pos, len) = self.proc_span(&proc.name);
+                self.logger.type_error(
+                    Code::CannotInfer,
+                    format!("Proc {} is never called, so the types of its arguments can't be inferred", proc.name).as_str(),
+                    pos,
+                    len,
+                );
+            }
+            return None;
+        }
+        let mut substitution = Substitution::new();
+        for (k, (arg, typ)) in proc.args.iter().zip(&proc.arg_types).enumerate() {
+            if find_variable(typ).is_none() {
+                continue;
+            }
+            // a value whose type its caller couldn't work out says nothing
+            let mut known = calls.iter().filter(|call| find_variable(&call.args[k]).is_none());
+            let first = match known.next() {
+                Some(first) => first,
+                None => {
+                    let (pos, len) = self.proc_span(&proc.name);
+                    self.logger.type_error(
+                        Code::CannotInfer,
+                        format!("Couldn't infer the type of argument {} of proc {} from its calls", arg, proc.name).as_str(),
+                        pos,
+                        len,
+                    );
+                    return None;
+                }
+            };
+            if let Some(other) = known.find(|call| call.args[k] != first.args[k]) {
+                self.logger.type_error(
+                    Code::ConflictingArgumentTypes,
+                    format!(
+                        "Proc {} is given a {} as its argument {} here, but a {} elsewhere",
+                        proc.name, other.args[k], arg, first.args[k],
+                    ).as_str(),
+                    other.pos,
+                    other.len,
+                );
+                self.logger.note(format!("It's given a {} here", first.args[k]).as_str(), first.pos, first.len);
+                return None;
+            }
+            substitution.insert(typ.clone(), first.args[k].clone());
+        }
+        Some(substitution)
+    }
+
+    fn proc_span(&self, name: &str) -> (usize, usize) {
+        self.ast.iter()
+            .find(|node| matches!(&node.contents, Node::ProcStatement(decl) if decl.name == name))
+            .map_or((0, 0), |node| (node.pos, node.len))
+    }
+
+    /// Warns about variables that are never loaded and procs other than main
+    /// that are never called, pointing at their declarations
+    fn warn_unused(&self) {
+        let mut called = HashSet::new();
+        for proc in &self.procs {
+            let mut declared = vec![];
+            let mut loaded = HashSet::new();
+            for ins in &proc.body {
+                match &ins.contents.ins {
+                    // names with a . in them are made up by the IR builder
+                    InstructionType::Allocate(name) if !name.contains('.')
+                        && !declared.iter().any(|(n, _, _)| *n == name) => {
+                            declared.push((name, ins.pos, ins.len));
+                        }
+                    InstructionType::Load(name) => {
+                        loaded.insert(name);
+                    }
+                    // a proc that's referred to is as good as called, it's likely to be somewhere
+                    InstructionType::Call(name) | InstructionType::ProcRef(name) => {
+                        called.insert(name);
+                    }
+                    _ => (),
+                }
+            }
+            for (name, pos, len) in declared {
+                if !loaded.contains(name) {
+                    self.logger.warning(Code::UnusedVariable, format!("The variable {} is never used", name).as_str(), pos, len);
+                }
+            }
+        }
+        for node in self.ast {
+            if let Node::ProcStatement(decl) = &node.contents {
+                if decl.name != "main" && !called.contains(&decl.name) {
+                    self.logger.warning(
+                        Code::UnusedProc,
+                        format!("The proc {} is never called", decl.name).as_str(),
+                        node.pos,
+                        node.len,
+                    );
+                }
+            }
+        }
+    }
+}
+
+/// Warns about loading a variable that was declared without a type or a value before it's been stored to
+/// on every way of getting there. One declared with a type starts out as zero, so it's fine to read.
+/// The body is split into basic blocks, and what's surely been stored to at the start of a block is
+/// what's been stored to at the end of everything that leads to it, worked out until nothing changes.
+/// Storing to an element or a field counts as storing to the whole thing, so only variables never
+/// stored to at all are caught in arrays and structs.
+fn warn_unassigned(proc: &IRProc, logger: &Logger) {
+    use InstructionType::*;
+    let body = &proc.body;
+    let blocks = cfg::basic_blocks(body);
+    if blocks.is_empty() {
+        return;
+    }
+    // None for a block nothing has been found to lead to yet
+    let mut entry: Vec<Option<HashSet<&String>>> = vec![None; blocks.len()];
+    entry[0] = Some(HashSet::new());
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for (i, block) in blocks.iter().enumerate() {
+            let Some(mut stored) = entry[i].clone() else { continue };
+            for j in block.instructions.clone() {
+                transfer(body, j, &mut stored);
+            }
+            for &next in &block.successors {
+                let merged = match &entry[next] {
+                    Some(before) => before.intersection(&stored).cloned().collect(),
+                    None => stored.clone(),
+                };
+                if entry[next].as_ref() != Some(&merged) {
+                    entry[next] = Some(merged);
+                    changed = true;
+                }
+            }
+        }
+    }
+
+    let declared: HashSet<_> = (0..body.len())
+        .filter_map(|i| match &body[i].contents.ins {
+            Allocate(name) if declared_empty(body, i) => Some(name),
+            _ => None,
+        })
+        .collect();
+    let mut reported = HashSet::new();
+    for (block, stored) in blocks.iter().zip(entry) {
+        let Some(mut stored) = stored else { continue };
+        for i in block.instructions.clone() {
+            if let Load(name) = &body[i].contents.ins {
+                if declared.contains(name) && !stored.contains(name) && reported.insert(name) {
+                    logger.warning(
+                        Code::UsedBeforeAssigned,
+                        format!(
+                            "The variable {} may be used before it's given a value",
+                            name.split('.').next().unwrap(),
+                        ).as_str(),
+                        body[i].pos,
+                        body[i].len,
+                    );
+                }
+            }
+            transfer(body, i, &mut stored);
+        }
+    }
+}
+
+/// Whether the Allocate at `i` is of a variable declared without a type or a value,
+/// which has to be before analysis has given it a type
+fn declared_empty(body: &[Span<Instruction>], i: usize) -> bool {
+    i > 0 && matches!(body[i].contents.typ, Type::Variable(_)) && matches!(
+        &body[i - 1].contents,
+        Instruction { ins: InstructionType::Push(value), typ: Type::Undefined } if value == "undefined"
+    )
+}
+
+/// Updates which variables have surely been stored to after the instruction at `i`
+fn transfer<'b>(body: &'b [Span<Instruction>], i: usize, stored: &mut HashSet<&'b String>) {
+    use InstructionType::*;
+    match &body[i].contents.ins {
+        Allocate(name) if declared_empty(body, i) => {
+            stored.remove(name);
+        }
+        Allocate(name) | Store(name) | StoreIndexed(name, _) | StoreField(name, _) => {
+            stored.insert(name);
+        }
+        _ => (),
+    }
+}
+
+struct AnalyzedProc {
+    proc: Option<IRProc>,
+    // always empty unless tracing was asked for
+    trace: Vec<String>,
+    errors: Vec<Diagnostic>,
+}
+
+#[cfg(not(feature = "parallel"))]
+fn analyze_all(procs: &[IRProc], verified: &[bool], trace: bool) -> Vec<AnalyzedProc> {
+    (0..procs.len()).map(|i| analyze_proc(procs, verified, i, trace)).collect()
+}
+
+/// Procs only depend on each other's signatures, which are all known by now,
+/// so they are split into one contiguous chunk per core.
+#[cfg(feature = "parallel")]
+fn analyze_all(procs: &[IRProc], verified: &[bool], trace: bool) -> Vec<AnalyzedProc> {
+    let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+    let chunk_size = procs.len().div_ceil(threads).max(1);
+    std::thread::scope(|s| {
+        let handles: Vec<_> = (0..procs.len())
+            .step_by(chunk_size)
+            .map(|start| {
+                let end = (start + chunk_size).min(procs.len());
+                s.spawn(move || (start..end).map(|i| analyze_proc(procs, verified, i, trace)).collect::<Vec<_>>())
+            })
+            .collect();
+        // a panic carries on in this thread as it was, rather than as a failure to join
+        handles
+            .into_iter()
+            .flat_map(|h| h.join().unwrap_or_else(|payload| std::panic::resume_unwind(payload)))
+            .collect()
+    })
+}
+
+fn analyze_proc(procs: &[IRProc], verified: &[bool], index: usize, trace: bool) -> AnalyzedProc {
+    if !verified[index] {
+        return AnalyzedProc {
+            proc: None,
+            trace: vec![],
+            errors: vec![],
+        };
+    }
+    let mut analyzer = ProcAnalyzer::new(procs, trace);
+    analyzer.trace(|| format!("Analyzing proc {}:", procs[index].name));
+    let proc = analyzer.number_literals(&procs[index]);
+    let proc = analyzer.gen_constraints(&proc)
+        .and_then(|constraints| analyzer.solve_constraints(&constraints))
+        .and_then(|substitution| typed_proc(proc, &substitution, &analyzer.logger));
+    AnalyzedProc {
+        proc,
+        errors: analyzer.logger.drain(),
+        trace: analyzer.trace,
+    }
+}
+
+/// The start of the reason for the operands of an instruction having to match,
+/// which a mismatch between two numbers is reported differently for
+const OPERANDS: &str = "the operands of ";
+
+/// The reason an indirect call's arguments and result have to fit the proc it calls
+const CALLED: &str = "the proc being called";
+
+/// Two types that have to be the same, which `expected` and `found` only say which way round
+/// to describe them if they turn out not to be
+#[derive(Clone)]
+struct Constraint {
+    expected: Type,
+    found: Type,
+    // where in the source it came from, and what about it made the types have to match
+    at: Span<()>,
+    reason: String,
+}
+
+/// A call to a proc whose argument types are being inferred, with the types of what it was given
+struct InferredCall {
+    proc: String,
+    args: Vec<Type>,
+    pos: usize,
+    len: usize,
+}
+
+/// A field or element can only be looked up once the type of what it's in is known,
+/// so these wait until the solver has gotten that far.
+struct MemberConstraint {
+    object: Type,
+    member: Member,
+    typ: Type,
+    pos: usize,
+    len: usize,
+}
+
+enum Member {
+    Field(String),
+    // whatever indexing gives back, at the index if it's a literal
+    Element(Option<i128>),
+}
+
+impl Member {
+    /// The type of this member of `object`, or an error message saying why there isn't one
+    fn of(&self, object: &Type) -> Result<Type, String> {
+        match (self, object) {
+            (Member::Field(field), _) => object.field(field)
+                .map(|(_, typ)| typ)
+                .ok_or_else(|| format!("Type {} has no field named {}", object, field)),
+            (Member::Element(Some(index)), Type::Array(size, _)) if *index < 0 || *index >= *size as i128 => {
+                Err(format!("Index {} is out of bounds for a {}", index, object))
+            }
+            (Member::Element(_), Type::Array(_, typ)) => Ok(*typ.clone()),
+            (Member::Element(_), Type::Str) => Ok(Type::N8),
+            (Member::Element(_), _) => Err(format!("Type {} can't be indexed", object)),
+        }
+    }
+
+    /// What to say if the type of `object` never gets worked out
+    fn unresolved(&self) -> String {
+        match self {
+            Member::Field(field) => format!("Couldn't infer the type of the struct whose field {} is accessed", field),
+            Member::Element(_) => "Couldn't infer the type of the value being indexed".to_owned(),
+        }
+    }
+}
+
+/// The index of an Index or StoreIndexed, if it's a literal that comes right before it
+fn literal_index(before: &[Span<Instruction>]) -> Option<i128> {
+    match &before.last()?.contents.ins {
+        InstructionType::Push(value) => value.parse().ok(),
+        _ => None,
+    }
+}
+
+/// All of the state needed to analyze a single proc
+struct ProcAnalyzer<'a> {
+    procs: &'a [IRProc],
+    members: Vec<MemberConstraint>,
+    calls: Vec<InferredCall>,
+    literals: Literals,
+    tracing: bool,
+    trace: Vec<String>,
+    // the first type variable that nothing in the proc uses yet
+    next_variable: usize,
+    // kept apart from everything else, so that each proc's diagnostics can be reported in order
+    logger: Logger,
+}
+
+impl<'a> ProcAnalyzer<'a> {
+    fn new(procs: &'a [IRProc], tracing: bool) -> Self {
+        ProcAnalyzer {
+            procs,
+            members: vec![],
+            calls: vec![],
+            literals: Literals::new(),
+            tracing,
+            trace: vec![],
+            next_variable: 0,
+            logger: Logger::new(),
+        }
+    }
+
+    /// Only builds the line if it's going to be shown
+    fn trace(&mut self, line: impl FnOnce() -> String) {
+        if self.tracing {
+            self.trace.push(line());
+        }
+    }
+
+    fn locate_proc(&self, name: &str, pos: usize, len: usize) -> Option<&'a IRProc> {
+        locate_proc(self.procs, name, pos, len, &self.logger)
+    }
+
+    /// A copy of `proc` where every literal has a type variable of its own,
+    /// so that each one is worked out from where it's used instead of from every literal in the proc.
+    /// What kind of literal each variable stands for is kept, for the solver to check and default them by.
+    fn number_literals(&mut self, proc: &IRProc) -> IRProc {
+        let mut proc = proc.clone();
+        let mut next = proc.body.iter()
+            .map(|ins| &ins.contents.typ)
+            .chain(proc.body.iter().flat_map(|ins| operand_types(&ins.contents.ins)))
+            .chain(&proc.arg_types)
+            .filter_map(last_variable)
+            .max()
+            .map_or(0, |n| n + 1);
+        for ins in &mut proc.body {
+            if let literal @ (Type::IntLiteral | Type::FloatLiteral | Type::StrLiteral) = &ins.contents.typ {
+                self.literals.insert(Type::Variable(next), literal.clone());
+                ins.contents.typ = Type::Variable(next);
+                next += 1;
+            }
+        }
+        self.next_variable = next;
+        proc
+    }
+
+    /// A type variable for something that isn't the type of any instruction,
+    /// only valid once `number_literals` has seen the proc
+    fn fresh_variable(&mut self) -> Type {
+        self.next_variable += 1;
+        Type::Variable(self.next_variable - 1)
     }
 
     fn gen_constraints(&mut self, proc: &IRProc) -> Option<Constraints> {
         use InstructionType::*;
         let mut constraints = Vec::new();
         let mut stack = vec![];
-        for ins in &proc.body {
+        // the IR builder already resolved every variable,
+        // and gave each instruction that uses one that variable's type
+        for (i, ins) in proc.body.iter().enumerate() {
+            errors::working_on(ins.pos, ins.len);
             match ins.contents.ins.clone() {
                 Push(_) => {
                     stack.push(ins.contents.typ.clone());
                 }
-                Load(var) => {
-                    stack.push(self.locate_var(&var)?);
+                Load(_) | ProcRef(_) => {
+                    stack.push(ins.contents.typ.clone());
+                }
+                Store(name) => {
+                    let typ = pop(&mut stack);
+                    let reason = format!("the assignment to {}", name);
+                    self.add_constraint(&mut constraints, ins.contents.typ.clone(), typ, ins, reason);
                 }
-                Store(var) => {
-                    let typ = stack.pop().unwrap();
-                    self.add_constraint(&mut constraints, ins.contents.typ.clone(), typ);
-                    self.add_constraint(&mut constraints, ins.contents.typ.clone(), self.locate_var(&var)?);
+                // anything at all can be thrown away
+                Pop => {
+                    pop(&mut stack);
                 }
-                StoreIndexed(var) => {
-                    let _index_type = stack.pop().unwrap();
-                    let value_type = stack.pop().unwrap();
-                    if let Type::Array(_, t) = self.locate_var(&var)? {
-                        self.add_constraint(&mut constraints, *t, value_type);
+                StoreIndexed(_, ref indices) => {
+                    let depth = indices.len();
+                    for index in indices.iter().rev() {
+                        let found = pop(&mut stack);
+                        self.add_constraint(&mut constraints, index.clone(), found, ins, "an index".to_owned());
                     }
-                    // TODO what happens here?
+                    let value_type = pop(&mut stack);
+                    // every index but the last gives back another array, which isn't known yet
+                    let mut object = ins.contents.typ.clone();
+                    for _ in 1..depth {
+                        let element = self.fresh_variable();
+                        self.members.push(MemberConstraint {
+                            object,
+                            member: Member::Element(None),
+                            typ: element.clone(),
+                            pos: ins.pos,
+                            len: ins.len,
+                        });
+                        object = element;
+                    }
+                    self.members.push(MemberConstraint {
+                        object,
+                        member: Member::Element(literal_index(&proc.body[..i])),
+                        typ: value_type,
+                        pos: ins.pos,
+                        len: ins.len,
+                    });
                 }
-                Allocate(var) => {
-                    let content_type = stack.pop().unwrap();
+                Allocate(name) => {
+                    let content_type = pop(&mut stack);
                     let var_type = ins.contents.typ.clone();
-                    let scope_index = self.scopes.len() - 1;
-                    self.scopes[scope_index].insert(var, var_type.clone());
-                    self.add_constraint(&mut constraints, var_type, content_type);
-                }
-                Index => {
-                    let _index_type = stack.pop().unwrap();
-                    let object_type = stack.pop().unwrap();
-                    if let Type::Array(_, t) = object_type {
-                        stack.push(*t);
-                    } else {
-                        panic!();
+                    // the slot a match keeps its scrutinee in isn't something the source declares
+                    let reason = match name.split_once('.') {
+                        Some(("match", _)) => "the value being matched".to_owned(),
+                        Some(("tuple", _)) => "the tuple being unpacked".to_owned(),
+                        _ => format!("the declaration of {}", name),
+                    };
+                    self.add_constraint(&mut constraints, var_type, content_type, ins, reason);
+                }
+                // the index doesn't have to be any one type of integer
+                Index(ref index) => {
+                    let found = pop(&mut stack);
+                    self.add_constraint(&mut constraints, index.clone(), found, ins, "the index".to_owned());
+                    let object = pop(&mut stack);
+                    self.members.push(MemberConstraint {
+                        object,
+                        member: Member::Element(literal_index(&proc.body[..i])),
+                        typ: ins.contents.typ.clone(),
+                        pos: ins.pos,
+                        len: ins.len,
+                    });
+                    stack.push(ins.contents.typ.clone());
+                }
+                Field(field) => {
+                    let object = pop(&mut stack);
+                    self.members.push(MemberConstraint {
+                        object,
+                        member: Member::Field(field),
+                        typ: ins.contents.typ.clone(),
+                        pos: ins.pos,
+                        len: ins.len,
+                    });
+                    stack.push(ins.contents.typ.clone());
+                }
+                StoreField(_, field) => {
+                    let value_type = pop(&mut stack);
+                    self.members.push(MemberConstraint {
+                        object: ins.contents.typ.clone(),
+                        member: Member::Field(field),
+                        typ: value_type,
+                        pos: ins.pos,
+                        len: ins.len,
+                    });
+                }
+                Construct(count) => {
+                    let typ = ins.contents.typ.clone();
+                    if let Type::Struct(s) = &typ {
+                        if count != s.fields.len() {
+                            self.logger.type_error(
+                                Code::WrongArgumentCount,
+                                format!("Struct {} has {} fields, but {} values were given", s.name, s.fields.len(), count).as_str(),
+                                ins.pos,
+                                ins.len,
+                            );
+                            return None;
+                        }
+                        let values = stack.split_off(stack.len() - count);
+                        for (value, (field, field_type)) in values.into_iter().zip(&s.fields) {
+                            let reason = format!("field {} of a new {}", field, s.name);
+                            self.add_constraint(&mut constraints, field_type.clone(), value, ins, reason);
+                        }
                     }
+                    if let Type::Tuple(elements) = &typ {
+                        let values = stack.split_off(stack.len() - count);
+                        for (i, (value, element)) in values.into_iter().zip(elements).enumerate() {
+                            let reason = format!("element {} of a new tuple", i);
+                            self.add_constraint(&mut constraints, element.clone(), value, ins, reason);
+                        }
+                    }
+                    stack.push(typ);
+                }
+                Cast(target) => {
+                    let value = pop(&mut stack);
+                    let reason = format!("the value being cast to {}", target);
+                    self.add_constraint(&mut constraints, ins.contents.typ.clone(), value, ins, reason);
+                    stack.push(target);
+                }
+                Length => {
+                    let object = pop(&mut stack);
+                    self.add_constraint(&mut constraints, Type::Str, object, ins, "the str whose length is taken".to_owned());
+                    stack.push(ins.contents.typ.clone());
                 }
 
                 Branch(_, _) => {
-                    self.add_constraint(
-                        &mut constraints,
-                        stack.pop().unwrap(),
-                        Type::Bool,
-                    );
+                    let condition = pop(&mut stack);
+                    self.add_constraint(&mut constraints, Type::Bool, condition, ins, "the condition".to_owned());
                 }
                 Jump(_) => (),
                 Label(_) => (),
+                Fail(_) => (),
+                Print(_) => {
+                    let value = pop(&mut stack);
+                    self.add_constraint(&mut constraints, ins.contents.typ.clone(), value, ins, "the value printed".to_owned());
+                }
 
                 Call(proc_name) => {
-                    let proc = self.locate_proc(&proc_name)?.clone();
+                    let proc = self.locate_proc(&proc_name, ins.pos, ins.len)?.clone();
                     //let arg_count = proc.arg_types.len();
                     {
                         let args = &stack[stack.len() - proc.args.len()..];
-                        for (i, arg) in args.iter().enumerate() {
-                            self.add_constraint(&mut constraints, arg.clone(), proc.arg_types[i].clone());
+                        if proc.arg_types.iter().any(|t| find_variable(t).is_some()) {
+                            // these decide the argument types, instead of having to match them
+                            self.calls.push(InferredCall {
+                                proc: proc_name,
+                                args: args.to_vec(),
+                                pos: ins.pos,
+                                len: ins.len,
+                            });
+                        } else {
+                            for (i, arg) in args.iter().enumerate() {
+                                let reason = format!("argument {} of the call to {}", i + 1, proc.name);
+                                self.add_constraint(&mut constraints, proc.arg_types[i].clone(), arg.clone(), ins, reason);
+                            }
                         }
                     }
                     stack.truncate(stack.len() - proc.args.len());
                     stack.push(proc.ret_type.clone());
                 }
+                // the proc has to be one that takes what it's given and returns what the call does
+                CallIndirect(count) => {
+                    let args = stack.split_off(stack.len() - count);
+                    let callee = pop(&mut stack);
+                    let called = Type::new_proc(args, ins.contents.typ.clone());
+                    self.add_constraint(&mut constraints, callee, called, ins, CALLED.to_owned());
+                    stack.push(ins.contents.typ.clone());
+                }
                 Return => {
-                    let type_to_return = stack.pop().unwrap();
+                    let type_to_return = pop(&mut stack);
+                    let message = match (&proc.ret_type, &type_to_return) {
+                        (Type::Undefined, Type::Undefined) => None,
+                        // return types are never inferred, the type of a recursive call has to come from somewhere
+                        (Type::Undefined, _) if calls_itself(proc) => Some(format!(
+                            "Proc {} calls itself, so it needs an explicit return type to return a value",
+                            proc.name,
+                        )),
+                        (Type::Undefined, _) => {
+                            Some(format!("Proc {} doesn't return a value, so this return can't have one", proc.name))
+                        }
+                        (ret_type, Type::Undefined) => {
+                            Some(format!("Proc {} has to return a {}, but this return has no value", proc.name, ret_type))
+                        }
+                        _ => None,
+                    };
+                    if let Some(message) = message {
+                        self.logger.type_error(Code::ReturnMismatch, message.as_str(), ins.pos, ins.len);
+                        return None;
+                    }
                     //let ret_type = ins.typ.clone();
-                    self.add_constraint(&mut constraints, type_to_return, proc.ret_type.clone());
+                    let reason = format!("the value returned from {}", proc.name);
+                    self.add_constraint(&mut constraints, proc.ret_type.clone(), type_to_return, ins, reason);
                 }
 
-                Negate(_) => {
-                    let t1 = stack.pop().unwrap();
-                    self.add_constraint(&mut constraints, t1.clone(), ins.contents.typ.clone());
+                Negate(_) | Plus => {
+                    let t1 = pop(&mut stack);
+                    let reason = format!("the operand of {}", if ins.contents.ins == Plus { "+" } else { "-" });
+                    self.add_constraint(&mut constraints, ins.contents.typ.clone(), t1.clone(), ins, reason);
+                    stack.push(ins.contents.typ.clone());
+                }
+                Not => {
+                    let operand = pop(&mut stack);
+                    if !matches!(operand, Type::Bool | Type::Variable(_)) || self.literals.contains_key(&operand) {
+                        self.logger.type_error(
+                            Code::InvalidOperation,
+                            format!("Expected a bool operand for !, found {}", type_name(&operand, &self.literals)).as_str(),
+                            ins.pos,
+                            ins.len,
+                        );
+                        return None;
+                    }
+                    self.add_constraint(&mut constraints, Type::Bool, operand, ins, "the operand of !".to_owned());
+                    stack.push(Type::Bool);
                 }
                 // TODO more specific constraints???
-                Add(_) | Subtract(_) | Multiply(_) | IntDivide | Divide => {
-                    let t1 = stack.pop().unwrap();
-                    let t2 = stack.pop().unwrap();
-                    self.add_constraint(&mut constraints, t1.clone(), t2.clone());
-                    self.add_constraint(&mut constraints, t1.clone(), ins.contents.typ.clone());
-                    self.add_constraint(&mut constraints, t2.clone(), ins.contents.typ.clone());
+                Add(_) | Subtract(_) | Multiply(_) | IntDivide | Divide | Power | BitAnd | BitOr | BitXor => {
+                    let t1 = pop(&mut stack);
+                    let t2 = pop(&mut stack);
+                    // t2 is the left operand, so a mismatch is only reported once, against it
+                    let reason = format!("{}{}", OPERANDS, operator(&ins.contents.ins));
+                    self.add_constraint(&mut constraints, t2.clone(), t1.clone(), ins, reason);
+                    let reason = format!("the result of {}", operator(&ins.contents.ins));
+                    self.add_constraint(&mut constraints, ins.contents.typ.clone(), t2.clone(), ins, reason);
+                    stack.push(ins.contents.typ.clone());
+                }
+
+                // the amount doesn't have to be the same type as what's shifted
+                Shl(ref amount) | Shr(ref amount) => {
+                    let t1 = pop(&mut stack);
+                    let t2 = pop(&mut stack);
+                    let reason = format!("the amount of {}", operator(&ins.contents.ins));
+                    self.add_constraint(&mut constraints, amount.clone(), t1, ins, reason);
+                    let reason = format!("the result of {}", operator(&ins.contents.ins));
+                    self.add_constraint(&mut constraints, ins.contents.typ.clone(), t2, ins, reason);
                     stack.push(ins.contents.typ.clone());
                 }
 
                 Compare(_) => {
-                    let t1 = stack.pop().unwrap();
-                    let t2 = stack.pop().unwrap();
-                    self.add_constraint(&mut constraints, t1.clone(), t2.clone());
-                    self.add_constraint(
-                        &mut constraints,
-                        ins.contents.typ.clone(),
-                        Type::Bool,
-                    );
+                    let t1 = pop(&mut stack);
+                    let t2 = pop(&mut stack);
+                    let reason = format!("{}{}", OPERANDS, operator(&ins.contents.ins));
+                    self.add_constraint(&mut constraints, t2.clone(), t1.clone(), ins, reason.clone());
+                    // the operands' type, which decides between signed and unsigned comparisons
+                    self.add_constraint(&mut constraints, ins.contents.typ.clone(), t1.clone(), ins, reason);
                     stack.push(Type::Bool);
                 }
             };
@@ -130,121 +852,637 @@ impl<'i> IRBuilder<'i> {
         Some(constraints)
     }
 
-    fn solve_constraints(&self, proc: &IRProc, constraints: &Constraints) -> Option<IRProc> {
-        println!("Generated constraints:");
-        for (t1, t2) in constraints {
-            println!("{:?} == {:?}", t1, t2);
+    /// Returns what every type variable and literal in the proc turned out to be
+    fn solve_constraints(&mut self, constraints: &Constraints) -> Option<Substitution> {
+        self.trace(|| "Generated constraints:".to_owned());
+        for constraint in constraints {
+            self.trace(|| format!("{:?} == {:?} ({})", constraint.expected, constraint.found, constraint.reason));
         }
-        println!("------------------------");
-        let mut new_body = proc.body.clone();
-        let mut new_constraints = constraints.clone();
+        self.trace(|| "------------------------".to_owned());
 
-        //while new_constraints.len() > 0 {
-        for _ in 1..4 {
-            for (t1, t2) in constraints {
-                // set t1 == t2
-                new_body = substitute_proc_body(new_body, t1, t2); // replace in the proc
-                new_constraints = substitute_constraints(&new_constraints, t1, t2);
-                // replace in the rules
+        let mut constraints = constraints.clone();
+        let mut pending = std::mem::take(&mut self.members);
+        // every object that gets resolved can reveal the type of the next one
+        // in a chain like `a.b[i].c`, so keep going until nothing changes
+        let substitution = loop {
+            let substitution = solve(&constraints, &self.literals, &self.logger)?;
+            let waiting = pending.len();
+            let mut unresolved = vec![];
+            for constraint in pending {
+                let object = match resolve(&substitution, &constraint.object) {
+                    // a string literal can't become anything but a str, so there's no need to wait
+                    object if self.literals.get(&object) == Some(&Type::StrLiteral) => Type::Str,
+                    object => object,
+                };
+                if let Type::Variable(_) = object {
+                    unresolved.push(constraint);
+                    continue;
+                }
+                match constraint.member.of(&object) {
+                    Ok(member_type) => {
+                        let at = Span { contents: (), pos: constraint.pos, len: constraint.len };
+                        let reason = match &constraint.member {
+                            Member::Field(field) => format!("field {} of a {}", field, object),
+                            Member::Element(_) => format!("an element of a {}", object),
+                        };
+                        self.add_constraint(&mut constraints, member_type, constraint.typ, &at, reason);
+                    }
+                    Err(message) => {
+                        self.logger.type_error(Code::NoSuchMember, message.as_str(), constraint.pos, constraint.len);
+                        return None;
+                    }
+                }
             }
+            pending = unresolved;
+            if pending.len() == waiting {
+                break substitution;
+            }
+        };
+        if let Some(constraint) = pending.first() {
+            self.logger.type_error(
+                Code::CannotInfer,
+                constraint.member.unresolved().as_str(),
+                constraint.pos,
+                constraint.len,
+            );
+            return None;
         }
-
-        Some(IRProc {
-            name: proc.name.clone(),
-            args: proc.args.clone(),
-            arg_types: proc.arg_types.clone(),
-            ret_type: proc.ret_type.clone(),
-            body: new_body,
-        })
+        let substitution = default_literals(substitution, &self.literals);
+        if self.tracing {
+            let mut solved: Vec<_> = substitution.iter()
+                .map(|(from, to)| format!("{:?} := {:?}", from, resolve(&substitution, to)))
+                .collect();
+            solved.sort();
+            self.trace.push("Solved:".to_owned());
+            self.trace.append(&mut solved);
+            self.trace.push("------------------------".to_owned());
+        }
+        Some(substitution)
     }
 
-
-    fn add_constraint(&mut self, constraints: &mut Constraints, t1: Type, t2: Type) {
-        println!("Trying to add constraint: {:?} == {:?}", t1.clone(), t2.clone());
-        // TODO Some of these constraints just shouldn't be permitted at all and should raise a type
-        // error. For example, you shouldn't be able to add a constraint i8 == f64
-        if t1 == t2 {
+    /// `expected` and `found` only matter for the error message if they turn out not to match,
+    /// which the solver reports at the span of `at`
+    fn add_constraint<T: fmt::Debug>(
+        &mut self,
+        constraints: &mut Constraints,
+        expected: Type,
+        found: Type,
+        at: &Span<T>,
+        reason: String,
+    ) {
+        self.trace(|| format!("Adding constraint: {:?} == {:?} ({})", expected, found, reason));
+        if expected == found {
             return;
         }
-        if t1 == Type::StrLiteral || t2 == Type::StrLiteral {
+        if expected == Type::Undefined || found == Type::Undefined {
             return;
         }
-        if t1 == Type::Undefined || t2 == Type::Undefined {
-            return;
+        constraints.push(Constraint {
+            expected,
+            found,
+            at: Span { contents: (), pos: at.pos, len: at.len },
+            reason,
+        });
+    }
+}
+
+/// Whether the solver can bind the variable `var` to `t`.
+/// A variable standing in for a literal can only become a type that literal fits in,
+/// or another literal's variable, which the two then share. Any other variable can become anything,
+/// including a literal's variable, which makes it the one that gets checked and defaulted.
+fn is_bindable(var: &Type, t: &Type, literals: &Literals) -> bool {
+    match literals.get(var) {
+        Some(literal) => literal_fits(literal, literals.get(t).unwrap_or(t)),
+        None => matches!(var, Type::Variable(_)),
+    }
+}
+
+/// Whether a literal of type `literal` can be used as a `t`.
+/// An int literal mixed with a float literal makes them both floats.
+fn literal_fits(literal: &Type, t: &Type) -> bool {
+    match literal {
+        Type::IntLiteral => t.is_integer() || t.is_float() || matches!(t, Type::IntLiteral | Type::FloatLiteral),
+        Type::FloatLiteral => t.is_float() || *t == Type::FloatLiteral,
+        Type::StrLiteral => matches!(t, Type::Str | Type::StrLiteral),
+        _ => false,
+    }
+}
+
+/// The type a literal ends up as if nothing else decides it
+fn literal_default(t: &Type) -> Option<Type> {
+    match t {
+        Type::IntLiteral => Some(Type::I32),
+        Type::FloatLiteral => Some(Type::F64),
+        Type::StrLiteral => Some(Type::Str),
+        _ => None,
+    }
+}
+
+/// Literals that nothing pinned down become their default type, each on its own,
+/// so one that had to be an i64 doesn't stop the others from being i32s
+fn default_literals(mut substitution: Substitution, literals: &Literals) -> Substitution {
+    for var in literals.keys() {
+        let t = resolve(&substitution, var);
+        // literals that were used together share the variable of whichever one was the float
+        if let Some(default) = literals.get(&t).and_then(literal_default) {
+            substitution.insert(t, default);
+        }
+    }
+    substitution
+}
+
+/// How a type is named in error messages, a literal goes by the type it would default to.
+/// A tuple with elements that aren't known yet, like the one a var statement unpacks, only goes by its length.
+fn type_name(t: &Type, literals: &Literals) -> String {
+    let t = literals.get(t).unwrap_or(t);
+    match t {
+        Type::Tuple(elements) if !is_known(t, literals) => format!("a tuple of {} values", elements.len()),
+        Type::Tuple(elements) => {
+            let elements: Vec<_> = elements.iter().map(|t| type_name(t, literals)).collect();
+            format!("({})", elements.join(", "))
+        }
+        Type::Proc(p) => {
+            let args: Vec<_> = p.args.iter().map(|t| type_name(t, literals)).collect();
+            match p.ret {
+                Type::Undefined => format!("proc({})", args.join(", ")),
+                _ => format!("proc({}): {}", args.join(", "), type_name(&p.ret, literals)),
+            }
+        }
+        t => literal_default(t).unwrap_or_else(|| t.clone()).to_string(),
+    }
+}
+
+/// Whether `t` has no variables in it other than ones standing in for literals
+fn is_known(t: &Type, literals: &Literals) -> bool {
+    match t {
+        Type::Variable(_) => literals.contains_key(t),
+        Type::Ptr(t) | Type::Array(_, t) => is_known(t, literals),
+        Type::Tuple(elements) => elements.iter().all(|t| is_known(t, literals)),
+        Type::Proc(p) => p.args.iter().all(|t| is_known(t, literals)) && is_known(&p.ret, literals),
+        _ => true,
+    }
+}
+
+fn resolve(substitution: &Substitution, t: &Type) -> Type {
+    let mut t = t;
+    while let Some(next) = substitution.get(t) {
+        t = next;
+    }
+    match t {
+        Type::Array(size, element) => Type::Array(*size, Box::new(resolve(substitution, element))),
+        Type::Ptr(to) => Type::Ptr(Box::new(resolve(substitution, to))),
+        Type::Tuple(elements) => Type::Tuple(elements.iter().map(|t| resolve(substitution, t)).collect()),
+        Type::Proc(p) => Type::new_proc(
+            p.args.iter().map(|t| resolve(substitution, t)).collect(),
+            resolve(substitution, &p.ret),
+        ),
+        t => t.clone(),
+    }
+}
+
+/// Worklist solver: every constraint is visited once, except that a constraint
+/// between two still-unbound variables is parked on both of them and revisited
+/// only when one side gets bound. Whatever is still parked once the worklist
+/// drains just links the two variables together.
+fn solve(constraints: &Constraints, literals: &Literals, logger: &Logger) -> Option<Substitution> {
+    // matching arrays adds a constraint between their elements
+    let mut constraints = constraints.clone();
+    let mut substitution = Substitution::new();
+    let mut failed = false;
+    let mut mentions: HashMap<Type, Vec<usize>> = HashMap::new();
+    let mut parked = vec![false; constraints.len()];
+    let mut worklist: VecDeque<usize> = (0..constraints.len()).collect();
+    // the constraint that bound each variable, to explain a mismatch with
+    let mut origins: HashMap<Type, usize> = HashMap::new();
+
+    while let Some(i) = worklist.pop_front() {
+        let Constraint { expected: t1, found: t2, at, reason } = constraints[i].clone();
+        let left = resolve(&substitution, &t1);
+        let right = resolve(&substitution, &t2);
+        if left == right {
+            continue;
         }
-        println!("After transformation: {:?} == {:?}", t1.clone(), t2.clone());
-        if let Type::Variable(_) = t2 {
-            constraints.push((t2, t1));
-        } else {
-            if t2 == Type::IntLiteral
-                || t2 == Type::FloatLiteral
-                || t2 == Type::StrLiteral {
-                constraints.push((t2, t1));
-            } else {
-                constraints.push((t1, t2));
+        let (var, typ) = match (left, right) {
+            // a literal's variable is already partly known, so only two plain variables wait
+            (l @ Type::Variable(_), r @ Type::Variable(_)) if !literals.contains_key(&l) && !literals.contains_key(&r) => {
+                // nothing to learn yet, wait for either side to be bound
+                parked[i] = true;
+                mentions.entry(l).or_default().push(i);
+                mentions.entry(r).or_default().push(i);
+                continue;
             }
+            (l, r) if is_bindable(&l, &r, literals) => (l, r),
+            (l, r) if is_bindable(&r, &l, literals) => (r, l),
+            (Type::Array(n, l), Type::Array(m, r)) if n != m => {
+                logger.type_error(
+                    Code::MismatchedTypes,
+                    format!(
+                        "Mismatched types: expected {}, found {}, which is a different length",
+                        Type::Array(n, l),
+                        Type::Array(m, r),
+                    ).as_str(),
+                    at.pos,
+                    at.len,
+                );
+                explain_mismatch(&constraints[i], &constraints, &substitution, &origins, literals, logger);
+                failed = true;
+                continue
+            }
+            // whatever the elements are made of is worked out like anything else
+            (Type::Array(_, l), Type::Array(_, r)) if find_variable(&l).is_some() || find_variable(&r).is_some() => {
+                constraints.push(Constraint {
+                    expected: *l,
+                    found: *r,
+                    at,
+                    reason: format!("the elements of {}", reason),
+                });
+                parked.push(false);
+                worklist.push_back(constraints.len() - 1);
+                continue
+            }
+            (Type::Tuple(l), Type::Tuple(r)) if l.len() != r.len() => {
+                logger.type_error(
+                    Code::MismatchedTypes,
+                    format!(
+                        "Mismatched types: expected {}, found {}",
+                        type_name(&Type::Tuple(l), literals),
+                        type_name(&Type::Tuple(r), literals),
+                    ).as_str(),
+                    at.pos,
+                    at.len,
+                );
+                explain_mismatch(&constraints[i], &constraints, &substitution, &origins, literals, logger);
+                failed = true;
+                continue
+            }
+            // each element is matched up on its own, so a mismatch is reported for just that element
+            (Type::Tuple(l), Type::Tuple(r)) => {
+                for (n, (l, r)) in l.into_iter().zip(r).enumerate() {
+                    constraints.push(Constraint {
+                        expected: l,
+                        found: r,
+                        at: at.clone(),
+                        reason: format!("element {} of {}", n, reason),
+                    });
+                    parked.push(false);
+                    worklist.push_back(constraints.len() - 1);
+                }
+                continue
+            }
+            (Type::Proc(l), Type::Proc(r)) if l.args.len() != r.args.len() => {
+                // what a call passes is all there is to its side, it has no return type of its own to show
+                let message = if reason == CALLED {
+                    format!(
+                        "Mismatched types: the proc called is a {}, which takes {} argument{}, but it's given {}",
+                        type_name(&Type::Proc(l.clone()), literals),
+                        l.args.len(),
+                        if l.args.len() == 1 { "" } else { "s" },
+                        r.args.len(),
+                    )
+                } else {
+                    format!(
+                        "Mismatched types: expected {}, found {}, which takes a different number of arguments",
+                        type_name(&Type::Proc(l), literals),
+                        type_name(&Type::Proc(r), literals),
+                    )
+                };
+                logger.type_error(Code::MismatchedTypes, message.as_str(), at.pos, at.len);
+                explain_mismatch(&constraints[i], &constraints, &substitution, &origins, literals, logger);
+                failed = true;
+                continue
+            }
+            // the same as a tuple, with the return type matched up after the arguments
+            (Type::Proc(l), Type::Proc(r)) => {
+                let (l, r) = (*l, *r);
+                let n = l.args.len();
+                let parts = l.args.into_iter().chain(std::iter::once(l.ret)).zip(r.args.into_iter().chain(std::iter::once(r.ret)));
+                for (k, (l, r)) in parts.enumerate() {
+                    constraints.push(Constraint {
+                        expected: l,
+                        found: r,
+                        at: at.clone(),
+                        reason: if k < n {
+                            format!("argument {} of {}", k + 1, reason)
+                        } else {
+                            format!("what {} returns", reason)
+                        },
+                    });
+                    parked.push(false);
+                    worklist.push_back(constraints.len() - 1);
+                }
+                continue
+            }
+            (expected, found) => {
+                // numbers of different types never mix on their own, not even an i32 with an i64,
+                // so which of them to convert is left to whoever wrote it
+                let message = match reason.strip_prefix(OPERANDS) {
+                    Some(op) if is_number(&expected) && is_number(&found) => format!(
+                        "Mismatched types: the left operand of {} is {} and the right one is {}, \
+                        cast the right one with as {} or the left one with as {}",
+                        op, expected, found, expected, found,
+                    ),
+                    _ => format!(
+                        "Mismatched types: expected {}, found {}{}",
+                        type_name(&expected, literals),
+                        type_name(&found, literals),
+                        if is_number(&expected) && is_number(&found) { ", convert between them with as" } else { "" },
+                    ),
+                };
+                logger.type_error(Code::MismatchedTypes, message.as_str(), at.pos, at.len);
+                explain_mismatch(&constraints[i], &constraints, &substitution, &origins, literals, logger);
+                failed = true;
+                continue
+            }
+        };
+        // binding a variable to something made out of itself would make resolving it go on forever
+        if occurs(&var, &typ) {
+            logger.type_error(
+                Code::CannotInfer,
+                format!("Couldn't infer the type of this, because of {} it would have to contain itself", reason).as_str(),
+                at.pos,
+                at.len,
+            );
+            failed = true;
+            continue
+        }
+        origins.insert(var.clone(), i);
+        substitution.insert(var.clone(), typ);
+        for j in mentions.remove(&var).unwrap_or_default() {
+            if parked[j] {
+                parked[j] = false;
+                worklist.push_back(j);
+            }
+        }
+    }
+
+    for (i, constraint) in constraints.iter().enumerate() {
+        if !parked[i] {
+            continue;
+        }
+        let left = resolve(&substitution, &constraint.expected);
+        let right = resolve(&substitution, &constraint.found);
+        if left != right {
+            substitution.insert(left, right);
         }
     }
+
+    if failed {
+        None
+    } else {
+        Some(substitution)
+    }
+}
+
+/// Notes on where each side of a mismatched constraint got its type, for a side that was only a
+/// variable in the constraint itself, so the type came from something else the proc does
+fn explain_mismatch(
+    constraint: &Constraint,
+    constraints: &Constraints,
+    substitution: &Substitution,
+    origins: &HashMap<Type, usize>,
+    literals: &Literals,
+    logger: &Logger,
+) {
+    for (side, mut typ) in [("Expected", &constraint.expected), ("Found", &constraint.found)] {
+        // the last variable in the chain is the one that was bound to the type itself
+        let mut origin = None;
+        while let Some(next) = substitution.get(typ) {
+            origin = origins.get(typ);
+            typ = next;
+        }
+        let Some(&j) = origin else { continue };
+        let source = &constraints[j];
+        if (source.at.pos, source.at.len) == (constraint.at.pos, constraint.at.len) {
+            continue;
+        }
+        logger.note(
+            format!(
+                "{} to be {} because of {} here",
+                side,
+                type_name(&resolve(substitution, typ), literals),
+                source.reason,
+            ).as_str(),
+            source.at.pos,
+            source.at.len,
+        );
+    }
+}
+
+/// The proc with every type in its body filled in from the solution
+fn typed_proc(mut proc: IRProc, substitution: &Substitution, logger: &Logger) -> Option<IRProc> {
+    substitute_proc_body(&mut proc.body, substitution);
+    // anything still holding a variable would only make codegen fall over,
+    // so each of them is reported once where it first shows up
+    let mut reported = HashSet::new();
+    for ins in &proc.body {
+        if let Some(var) = find_variable(&ins.contents.typ) {
+            if !reported.insert(var) {
+                continue;
+            }
+            // a variable that's declared without a value and never stored to has nothing to go on
+            let message = match &ins.contents.ins {
+                InstructionType::Allocate(name) => format!(
+                    "Couldn't infer the type of {}, give it a type annotation",
+                    name.split('.').next().unwrap(),
+                ),
+                _ => "Couldn't infer the type of this".to_owned(),
+            };
+            logger.type_error(Code::CannotInfer, message.as_str(), ins.pos, ins.len);
+        }
+    }
+    // both checks report everything they find, so neither is skipped
+    if !reported.is_empty() || !check_body(&proc.body, logger) | !check_literals(&proc.body, logger) {
+        return None;
+    }
+    Some(proc)
 }
 
-fn substitute_proc_body(body: Vec<Span<Instruction>>, t1: &Type, t2: &Type) -> Vec<Span<Instruction>> {
-    let mut new_body = vec![];
+/// Reports instructions that only make sense for some types, now that those are known
+fn check_body(body: &[Span<Instruction>], logger: &Logger) -> bool {
+    use InstructionType::*;
+    let mut ok = true;
+    for ins in body {
+        let typ = &ins.contents.typ;
+        let message = match ins.contents.ins {
+            Negate(_) if typ.is_integer() && !typ.is_signed() => {
+                format!("Can't negate a value of type {}, cast it to a signed type first", typ)
+            }
+            Negate(_) if !is_number(typ) => format!("Can't negate a value of type {}", typ),
+            Plus if !is_number(typ) => format!("Can't use + on a value of type {}, only on numbers", typ),
+            Add(_) | Subtract(_) | Multiply(_) | IntDivide | Divide | Power
+                if !is_number(typ) => format!("Can't do arithmetic on a {}", typ),
+            Divide if typ.is_integer() => format!("Can't use / on a {}, use // to divide integers", typ),
+            BitAnd | BitOr | BitXor if !typ.is_integer() => {
+                // bools have logical operators of their own instead
+                let hint = match (typ, &ins.contents.ins) {
+                    (Type::Bool, BitAnd) => ", use && for bools",
+                    (Type::Bool, BitOr) => ", use || for bools",
+                    (Type::Bool, _) => ", use != for bools",
+                    _ => "",
+                };
+                format!("Bitwise operators only work on integers, not on a {}{}", typ, hint)
+            }
+            Shl(_) | Shr(_) if !typ.is_integer() => format!("Can't shift a {}, only an integer", typ),
+            Shl(ref amount) | Shr(ref amount) if !amount.is_integer() => {
+                format!("Can't shift by a {}, the amount has to be an integer", amount)
+            }
+            Index(ref index) if !index.is_integer() => format!("Can't index with a {}, the index has to be an integer", index),
+            StoreIndexed(_, ref indices) if indices.iter().any(|index| !index.is_integer()) => {
+                let index = indices.iter().find(|index| !index.is_integer()).unwrap();
+                format!("Can't index with a {}, the index has to be an integer", index)
+            }
+            StoreIndexed(..) if *typ == Type::Str => "A str can't be changed, only read from".to_owned(),
+            Cast(ref target) if !can_cast(typ, target) => format!("Can't cast a {} to a {}", typ, target),
+            Print(_) if !printable(typ) => format!("Can't print a {}, only a number, bool or str", typ),
+            Compare(_) if !typ.is_integer() && !typ.is_float() && *typ != Type::Bool => {
+                format!("Can't compare values of type {}", typ)
+            }
+            _ => continue,
+        };
+        logger.type_error(Code::InvalidOperation, message.as_str(), ins.pos, ins.len);
+        ok = false;
+    }
+    ok
+}
 
+/// Reports literals that don't fit in the type they ended up with.
+/// A minus sign in front of a number is part of the literal, so it's in the value of the Push.
+fn check_literals(body: &[Span<Instruction>], logger: &Logger) -> bool {
+    let mut ok = true;
     for ins in body {
-        new_body.push(spanned(Instruction {
-            ins: ins.contents.ins,
-            typ: if ins.contents.typ.clone() == t1.clone() {
-                t2.clone()
-            //} else if ins.typ.clone() == t2.clone() {
-            //    t1.clone()
-            } else {
-                ins.contents.typ
-            },
-        }, ins.pos, ins.len));
-    }
-    new_body
-}
-
-fn substitute_constraints(constraints: &Constraints, t1: &Type, t2: &Type) -> Constraints {
-    let mut new_constraints = Vec::new();
-
-    for (left, right) in constraints {
-        let new_left = if *left == *t1 {
-            t2.clone()
-        } else {
-            left.clone()
+        let literal = match &ins.contents.ins {
+            // not a number, even if it's typed as one, like what's left in place of a misplaced keyword
+            InstructionType::Push(value) if value != "undefined" => value,
+            _ => continue,
         };
+        let typ = &ins.contents.typ;
+        let digits = literal.strip_prefix('-').unwrap_or(literal);
 
-        let new_right = if *right == *t1 {
-            t2.clone()
-        } else {
-            right.clone()
+        if typ.is_float() {
+            let fits = match digits.parse::<f64>() {
+                Ok(v) if *typ == Type::F32 => v.is_finite() && v <= f32::MAX as f64,
+                Ok(v) => v.is_finite(),
+                Err(_) => true,
+            };
+            if !fits {
+                logger.warning(Code::FloatLiteralOverflow, format!("The literal {} is too big for a {}, so it becomes infinity", literal, typ).as_str(), ins.pos, ins.len);
+            }
+            continue;
+        } else if !typ.is_integer() {
+            continue;
+        }
+        let message = match opt::int_literal(literal, typ) {
+            Ok(_) => continue,
+            Err(message) => message,
         };
+        logger.type_error(Code::LiteralOutOfRange, message.as_str(), ins.pos, ins.len);
+        ok = false;
+    }
+    ok
+}
+
+/// The IR has been verified by this point, so there's always something to pop
+fn pop(stack: &mut Vec<Type>) -> Type {
+    stack.pop().expect("the IR verifier makes sure the stack is deep enough")
+}
+
+fn calls_itself(proc: &IRProc) -> bool {
+    proc.body.iter().any(|ins| matches!(&ins.contents.ins, InstructionType::Call(name) if *name == proc.name))
+}
+
+fn is_number(t: &Type) -> bool {
+    t.is_integer() || t.is_float()
+}
 
-        new_constraints.push((new_left, new_right));
+/// The operator an arithmetic, bitwise, shift or comparison instruction came from, as it's written
+fn operator(ins: &InstructionType) -> &'static str {
+    use InstructionType::*;
+    match ins {
+        Add(wrap) => if *wrap { "+~" } else { "+" },
+        Subtract(wrap) => if *wrap { "-~" } else { "-" },
+        Multiply(wrap) => if *wrap { "*~" } else { "*" },
+        IntDivide => "//",
+        Divide => "/",
+        Power => "**",
+        BitAnd => "&",
+        BitOr => "|",
+        BitXor => "^",
+        Shl(_) => "<<",
+        Shr(_) => ">>",
+        Compare(CompareType::EQ) => "==",
+        Compare(CompareType::NE) => "!=",
+        Compare(CompareType::GT) => ">",
+        Compare(CompareType::LT) => "<",
+        Compare(CompareType::GE) => ">=",
+        Compare(CompareType::LE) => "<=",
+        _ => unreachable!(),
     }
+}
+
+/// What print can write out, which leaves out the 128 bit numbers since printf can't
+fn printable(t: &Type) -> bool {
+    (is_number(t) && !matches!(t, Type::I128 | Type::N128 | Type::F128)) || matches!(t, Type::Bool | Type::Str)
+}
+
+/// Only numbers can be converted into each other, anything else has to already be the right type
+fn can_cast(from: &Type, to: &Type) -> bool {
+    from == to || is_number(from) && is_number(to)
+}
+
+/// The first type variable that `t` is made of, if any
+fn find_variable(t: &Type) -> Option<usize> {
+    match t {
+        Type::Variable(n) => Some(*n),
+        Type::Ptr(t) | Type::Array(_, t) => find_variable(t),
+        Type::Tuple(elements) => elements.iter().find_map(find_variable),
+        Type::Proc(p) => p.args.iter().find_map(find_variable).or_else(|| find_variable(&p.ret)),
+        _ => None,
+    }
+}
 
-    new_constraints
+/// Whether the variable `var` is part of `t`, which has already been resolved
+fn occurs(var: &Type, t: &Type) -> bool {
+    match t {
+        Type::Ptr(t) | Type::Array(_, t) => occurs(var, t),
+        Type::Tuple(elements) => elements.iter().any(|t| occurs(var, t)),
+        Type::Proc(p) => p.args.iter().any(|t| occurs(var, t)) || occurs(var, &p.ret),
+        t => t == var,
+    }
 }
 
-fn add_literal_constaints(constraints: &mut Constraints, procs: &mut Vec<IRProc>) {
-    let mut has_int_literal = false;
-    let mut has_float_literal = false;
-    for proc in procs {
-        for ins in &proc.body {
-            if ins.contents.typ == Type::IntLiteral {
-                has_int_literal = true;
+/// The highest-numbered type variable that `t` is made of, if any
+fn last_variable(t: &Type) -> Option<usize> {
+    match t {
+        Type::Variable(n) => Some(*n),
+        Type::Ptr(t) | Type::Array(_, t) => last_variable(t),
+        Type::Tuple(elements) => elements.iter().filter_map(last_variable).max(),
+        Type::Proc(p) => p.args.iter().filter_map(last_variable).chain(last_variable(&p.ret)).max(),
+        _ => None,
+    }
+}
 
-            } else if ins.contents.typ == Type::FloatLiteral {
-                has_float_literal = true;
+fn substitute_proc_body(body: &mut [Span<Instruction>], substitution: &Substitution) {
+    for ins in body {
+        ins.contents.typ = resolve(substitution, &ins.contents.typ);
+        match &mut ins.contents.ins {
+            InstructionType::Shl(typ) | InstructionType::Shr(typ) | InstructionType::Index(typ) => {
+                *typ = resolve(substitution, typ);
             }
+            InstructionType::StoreIndexed(_, indices) => {
+                for index in indices {
+                    *index = resolve(substitution, index);
+                }
+            }
+            _ => (),
         }
     }
+}
 
-    if has_int_literal {
-        constraints.push((Type::IntLiteral, Type::I32));
-    }
-    if has_float_literal {
-        constraints.push((Type::FloatLiteral, Type::F64));
+/// The types an instruction has besides its own, like the amount of a shift or the type of an index
+fn operand_types(ins: &InstructionType) -> &[Type] {
+    match ins {
+        InstructionType::Shl(typ) | InstructionType::Shr(typ) | InstructionType::Index(typ) => std::slice::from_ref(typ),
+        InstructionType::StoreIndexed(_, indices) => indices,
+        _ => &[],
     }
 }
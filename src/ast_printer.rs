@@ -0,0 +1,197 @@
+//! A compact, indented rendering of the AST, for reading instead of the Debug dump
+//! Every node gets a line of its kind and contents, followed by its source span,
+//! with the nodes inside it on the lines below, indented one step further
+
+use crate::astgen::Node;
+use crate::errors::Span;
+
+use std::fmt::Write;
+
+pub fn print_ast(nodes: &[Span<Node>]) -> String {
+    let mut printer = Printer { out: String::new(), depth: 0 };
+    for node in nodes {
+        printer.node(node);
+    }
+    printer.out
+}
+
+struct Printer {
+    out: String,
+    depth: usize,
+}
+
+impl Printer {
+    fn line(&mut self, text: &str, span: Option<(usize, usize)>) {
+        for _ in 0..self.depth {
+            self.out.push_str("  ");
+        }
+        self.out.push_str(text);
+        if let Some((pos, len)) = span {
+            let _ = write!(self.out, " @{}+{}", pos, len);
+        }
+        self.out.push('\n');
+    }
+
+    /// A child that isn't a node on its own, like the parts of a for loop, gets a labelled line
+    fn labelled(&mut self, label: &str, node: &Span<Node>) {
+        self.line(&format!("{}:", label), None);
+        self.nested(|p| p.node(node));
+    }
+
+    fn nested(&mut self, f: impl FnOnce(&mut Self)) {
+        self.depth += 1;
+        f(self);
+        self.depth -= 1;
+    }
+
+    fn node(&mut self, node: &Span<Node>) {
+        use Node::*;
+        let span = Some((node.pos, node.len));
+        match &node.contents {
+            Literal { typ, value } => self.line(&format!("Literal {} {:?}", value, typ), span),
+            Call { name, args } => {
+                self.line(&format!("Call {}", name), span);
+                self.nested(|p| args.iter().for_each(|arg| p.node(arg)));
+            }
+            InfixOp { op, left, right, op_span } => {
+                self.line(&format!("InfixOp {} at @{}+{}", op, op_span.pos, op_span.len), span);
+                self.nested(|p| {
+                    p.node(left);
+                    p.node(right);
+                });
+            }
+            PrefixOp { op, right, op_span } => {
+                self.line(&format!("PrefixOp {} at @{}+{}", op, op_span.pos, op_span.len), span);
+                self.nested(|p| p.node(right));
+            }
+            PostfixOp { op, left, op_span } => {
+                self.line(&format!("PostfixOp {} at @{}+{}", op, op_span.pos, op_span.len), span);
+                self.nested(|p| p.node(left));
+            }
+            IndexOp { object, index } => {
+                self.line("IndexOp", span);
+                self.nested(|p| {
+                    p.node(object);
+                    p.node(index);
+                });
+            }
+            VariableRef { name } => self.line(&format!("VariableRef {}", name), span),
+            FieldAccess { object, field } => {
+                self.line(&format!("FieldAccess .{}", field), span);
+                self.nested(|p| p.node(object));
+            }
+            Cast { value, typ } => {
+                self.line(&format!("Cast {:?}", typ), span);
+                self.nested(|p| p.node(value));
+            }
+            Tuple { elements } => {
+                self.line("Tuple", span);
+                self.nested(|p| elements.iter().for_each(|element| p.node(element)));
+            }
+            IfStatement { condition, body, else_body } => {
+                self.line("IfStatement", span);
+                self.nested(|p| {
+                    p.labelled("condition", condition);
+                    p.labelled("then", body);
+                    p.labelled("else", else_body);
+                });
+            }
+            IfExpression { condition, body, else_body } => {
+                self.line("IfExpression", span);
+                self.nested(|p| {
+                    p.labelled("condition", condition);
+                    p.labelled("then", body);
+                    if let Some(else_body) = else_body {
+                        p.labelled("else", else_body);
+                    }
+                });
+            }
+            WhileStatement { condition, body } => {
+                self.line("WhileStatement", span);
+                self.nested(|p| {
+                    p.labelled("condition", condition);
+                    p.labelled("body", body);
+                });
+            }
+            MatchStatement { scrutinee, arms, default } => {
+                self.line("MatchStatement", span);
+                self.nested(|p| {
+                    p.labelled("scrutinee", scrutinee);
+                    for (pattern, body) in arms {
+                        p.labelled("arm", pattern);
+                        p.nested(|p| p.node(body));
+                    }
+                    if let Some(default) = default {
+                        p.labelled("else", default);
+                    }
+                });
+            }
+            ForStatement { var, start, end, body } => {
+                self.line(&format!("ForStatement {}", var), span);
+                self.nested(|p| {
+                    p.labelled("start", start);
+                    p.labelled("end", end);
+                    p.labelled("body", body);
+                });
+            }
+            Block { nodes } => {
+                self.line("Block", span);
+                self.nested(|p| nodes.iter().for_each(|node| p.node(node)));
+            }
+            VarStatement { name, typ, value } => {
+                self.line(&format!("VarStatement {}: {:?}", name, typ), span);
+                self.nested(|p| p.node(value));
+            }
+            VarTupleStatement { names, value } => {
+                self.line(&format!("VarTupleStatement ({})", names.join(", ")), span);
+                self.nested(|p| p.node(value));
+            }
+            ConstStatement { name, typ, value } => {
+                self.line(&format!("ConstStatement {}: {:?}", name, typ), span);
+                self.nested(|p| p.node(value));
+            }
+            AssignStatement { name, value } => {
+                self.line(&format!("AssignStatement {}", name), span);
+                self.nested(|p| p.node(value));
+            }
+            IndexAssignStatement { object, index, value } => {
+                self.line("IndexAssignStatement", span);
+                self.nested(|p| {
+                    p.node(object);
+                    p.node(index);
+                    p.node(value);
+                });
+            }
+            FieldAssignStatement { object, field, value } => {
+                self.line(&format!("FieldAssignStatement .{}", field), span);
+                self.nested(|p| {
+                    p.node(object);
+                    p.node(value);
+                });
+            }
+            ProcStatement(decl) => {
+                let args: Vec<_> = decl.args.iter()
+                    .zip(&decl.arg_types)
+                    .map(|(arg, typ)| format!("{}: {:?}", arg, typ))
+                    .collect();
+                self.line(&format!("ProcStatement {}({}): {:?}", decl.name, args.join(", "), decl.ret_type), span);
+                self.nested(|p| p.node(&decl.body));
+            }
+            StructDecl { name, fields } => {
+                self.line(&format!("StructDecl {}", name), span);
+                self.nested(|p| {
+                    for (field, typ) in fields {
+                        p.line(&format!("{}: {:?}", field, typ), None);
+                    }
+                });
+            }
+            ReturnStatement { val } => {
+                self.line("ReturnStatement", span);
+                self.nested(|p| p.node(val));
+            }
+            UseStatement { path } => self.line(&format!("UseStatement {}", path), span),
+            BreakStatement => self.line("BreakStatement", span),
+            ContinueStatement => self.line("ContinueStatement", span),
+        }
+    }
+}
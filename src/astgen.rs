@@ -1,10 +1,13 @@
 //! The Elgin AST generation
 
-use crate::parser::Parser;
+use crate::parser::{keyword_as_ident, Parser, TokenSource};
 use crate::lexer::Token;
-use crate::errors::{Logger, Span};
+use crate::errors::{self, merge, Code, Logger, Span};
+use crate::symbol::Symbol;
 use crate::types::Type;
 
+use std::convert::TryFrom;
+
 #[derive(Debug, Clone)]
 pub enum Node {
     Literal {
@@ -15,17 +18,21 @@ pub enum Node {
         name: String,
         args: Vec<Span<Node>>,
     },
+    // op_span is the operator on its own, which is what its instruction gets blamed for
     InfixOp {
-        op: String,
+        op: Symbol,
+        op_span: Span<()>,
         left: Box<Span<Node>>,
         right: Box<Span<Node>>,
     },
     PrefixOp {
-        op: String,
+        op: Symbol,
+        op_span: Span<()>,
         right: Box<Span<Node>>,
     },
     PostfixOp {
-        op: String,
+        op: Symbol,
+        op_span: Span<()>,
         left: Box<Span<Node>>,
     },
     IndexOp {
@@ -35,15 +42,44 @@ pub enum Node {
     VariableRef {
         name: String,
     },
+    FieldAccess {
+        object: Box<Span<Node>>,
+        field: String,
+    },
+    Cast {
+        value: Box<Span<Node>>,
+        typ: Type,
+    },
+    // at least two elements, `(a)` only groups
+    Tuple {
+        elements: Vec<Span<Node>>,
+    },
     IfStatement {
         condition: Box<Span<Node>>,
         body: Box<Span<Node>>,
         else_body: Box<Span<Node>>,
     },
+    IfExpression {
+        condition: Box<Span<Node>>,
+        body: Box<Span<Node>>,
+        else_body: Option<Box<Span<Node>>>,
+    },
     WhileStatement {
         condition: Box<Span<Node>>,
         body: Box<Span<Node>>,
     },
+    // each arm is the literal it matches and the block run when it does
+    MatchStatement {
+        scrutinee: Box<Span<Node>>,
+        arms: Vec<(Span<Node>, Span<Node>)>,
+        default: Option<Box<Span<Node>>>,
+    },
+    ForStatement {
+        var: String,
+        start: Box<Span<Node>>,
+        end: Box<Span<Node>>,
+        body: Box<Span<Node>>,
+    },
     Block {
         nodes: Vec<Span<Node>>,
     },
@@ -52,6 +88,11 @@ pub enum Node {
         typ: Type,
         value: Box<Span<Node>>,
     },
+    // `var (q, r) = v`, each name getting one element of the tuple v
+    VarTupleStatement {
+        names: Vec<String>,
+        value: Box<Span<Node>>,
+    },
     ConstStatement {
         name: String,
         typ: Type,
@@ -61,17 +102,20 @@ pub enum Node {
         name: String,
         value: Box<Span<Node>>,
     },
-    IndexedAssignStatement {
-        name: String,
+    IndexAssignStatement {
+        object: Box<Span<Node>>,
         index: Box<Span<Node>>,
         value: Box<Span<Node>>,
     },
-    ProcStatement {
+    FieldAssignStatement {
+        object: Box<Span<Node>>,
+        field: String,
+        value: Box<Span<Node>>,
+    },
+    ProcStatement(Box<ProcDecl>),
+    StructDecl {
         name: String,
-        args: Vec<String>,
-        arg_types: Vec<Type>,
-        ret_type: Type,
-        body: Box<Span<Node>>,
+        fields: Vec<(String, Type)>,
     },
     ReturnStatement {
         val: Box<Span<Node>>,
@@ -83,42 +127,183 @@ pub enum Node {
     ContinueStatement,
 }
 
+/// Procs are by far the largest statement and only appear at the top level,
+/// so their payload is boxed to keep every other `Span<Node>` small.
+#[derive(Debug, Clone)]
+pub struct ProcDecl {
+    pub name: String,
+    pub args: Vec<String>,
+    pub arg_types: Vec<Type>,
+    // where each argument's name is
+    pub arg_spans: Vec<(usize, usize)>,
+    pub ret_type: Type,
+    pub body: Span<Node>,
+    // the doc comments right before it, one line each
+    pub doc: Option<String>,
+}
+
+// keep an eye on this one; every Vec<Span<Node>> pays for the largest variant
+const _: () = assert!(std::mem::size_of::<Node>() <= 64);
+
 fn spanned(node: Node, pos: usize, len: usize) -> Span<Node> {
     Span {
-        contents: node.clone(),
+        contents: node,
         pos,
         len,
     }
 }
 
 
-impl<'p> Parser<'p> {
-    pub fn go(&mut self) -> Option<Vec<Span<Node>>> {
+impl<S: TokenSource> Parser<S> {
+    /// Returns every statement that parsed along with how many errors were found
+    pub fn go(&mut self) -> (Vec<Span<Node>>, usize) {
+        self.go_with(|_, _| ())
+    }
+
+    /// Like go, but hands each top-level use statement to `on_use` as soon as it's parsed,
+    /// so that the module it names can be loaded before anything after it needs that module's structs
+    pub fn go_with(&mut self, mut on_use: impl FnMut(&mut Self, &Span<Node>)) -> (Vec<Span<Node>>, usize) {
+        let before = self.logger.error_count();
         let mut nodes = vec![];
+        // doc comments waiting for the statement they're about, blank lines may come in between
+        let mut docs = vec![];
         loop {
             match self.peek().contents {
+                Token::EOF => {
+                    warn_undocumented(&docs, &self.logger);
+                    break;
+                }
                 Token::DocComment(_) => {
-                    self.next(); // one day there will be doc comment support
+                    docs.push(self.next());
                 },
                 Token::Newline => {
                     self.next();
                 },
                 _ => {
-                    nodes.push(self.statement()?);
-                    self.ensure_next(Token::Newline)?;
+                    let parsed = self.statement().and_then(|node| {
+                        if self.peek().contents != Token::EOF {
+                            self.end_statement(&node)?;
+                        }
+                        Some(node)
+                    });
+                    match parsed {
+                        Some(mut node) => {
+                            match &mut node.contents {
+                                Node::ProcStatement(decl) => decl.doc = doc_text(&docs),
+                                Node::ConstStatement { name, typ, value } => {
+                                    let integer = typ.is_integer() || matches!(typ, Type::Variable(_));
+                                    if let Some(value) = self.const_int(&value.contents).filter(|_| integer) {
+                                        self.consts.insert(name.clone(), value);
+                                    }
+                                    warn_undocumented(&docs, &self.logger);
+                                }
+                                Node::UseStatement { .. } => {
+                                    on_use(self, &node);
+                                    warn_undocumented(&docs, &self.logger);
+                                }
+                                _ => warn_undocumented(&docs, &self.logger),
+                            }
+                            nodes.push(node);
+                        }
+                        None => {
+                            // keep going so that later errors get reported too
+                            self.recover();
+                            let _ = self.try_next(Token::RBrace);
+                        }
+                    }
+                    docs.clear();
                 }
             };
-            if self.peek().contents == Token::EOF {
-                break;
+        }
+        (nodes, self.logger.error_count() - before)
+    }
+
+    /// The value of a const made out of integer literals and earlier consts, so that it can be
+    /// the length of an array. The IR builder works out and checks the value of every const,
+    /// this only has to agree with it for those an array's length can be.
+    fn const_int(&self, node: &Node) -> Option<i128> {
+        match node {
+            Node::Literal { typ, value } if typ.is_integer() || *typ == Type::IntLiteral => value.parse().ok(),
+            Node::VariableRef { name } => self.consts.get(name).copied(),
+            Node::InfixOp { op, left, right, .. } => {
+                let left = self.const_int(&left.contents)?;
+                let right = self.const_int(&right.contents)?;
+                match op.as_str() {
+                    "+" => left.checked_add(right),
+                    "-" => left.checked_sub(right),
+                    "*" => left.checked_mul(right),
+                    "//" => left.checked_div(right),
+                    "**" => left.checked_pow(u32::try_from(right).ok()?),
+                    "&" => Some(left & right),
+                    "|" => Some(left | right),
+                    "^" => Some(left ^ right),
+                    "<<" => left.checked_shl(u32::try_from(right).ok()?),
+                    ">>" => left.checked_shr(u32::try_from(right).ok()?),
+                    _ => None,
+                }
             }
+            _ => None,
+        }
+    }
+
+    /// Statements are separated by newlines, or by `;` which lexes the same way
+    fn end_statement(&mut self, statement: &Span<Node>) -> Option<()> {
+        if self.try_next(Token::Newline).is_some() {
+            return Some(());
+        }
+        let found = self.peek().clone();
+        // `print x`, as scripting languages would have it
+        if let (Node::VariableRef { name }, Some(arg)) = (&statement.contents, argument_text(&found.contents)) {
+            let (pos, len) = merge(statement, &found);
+            self.logger.syntax_error(
+                Code::UnexpectedToken,
+                format!("Calls require parentheses: did you mean {}({})?", name, arg).as_str(),
+                pos,
+                len,
+            );
+            return None;
+        }
+        self.logger.syntax_error(
+            Code::UnexpectedToken,
+            format!(
+                "Expected a newline or ; to end this {}, but found {} instead",
+                describe(&statement.contents),
+                found.contents,
+            ).as_str(),
+            found.pos,
+            found.len,
+        );
+        None
+    }
+
+    /// Skips to the start of the next statement after a syntax error, which is
+    /// either past the next newline, at the `}` closing the current block or at the next proc
+    fn recover(&mut self) {
+        let mut depth = 0;
+        loop {
+            match self.peek().contents {
+                // a proc is always at the top level, whatever was left open before it
+                Token::EOF | Token::Proc => return,
+                Token::Newline if depth == 0 => {
+                    self.next();
+                    return;
+                }
+                Token::RBrace if depth == 0 => return,
+                Token::LBrace => depth += 1,
+                Token::RBrace => depth -= 1,
+                _ => (),
+            }
+            self.next();
         }
-        Some(nodes)
     }
 
     fn statement(&mut self) -> Option<Span<Node>> {
+        errors::working_on(self.peek().pos, self.peek().len);
         Some(match self.peek().contents {
-            Token::If => self.if_statement(true)?,
+            Token::If => self.if_statement()?,
             Token::While => self.while_statement()?,
+            Token::Match => self.match_statement()?,
+            Token::For => self.for_statement()?,
             Token::Loop => self.loop_statement()?,
             Token::Var => self.var_statement()?,
             Token::Const => self.const_statement()?,
@@ -127,231 +312,557 @@ impl<'p> Parser<'p> {
             Token::Use => self.use_statement()?,
             Token::Break => self.break_statement()?,
             Token::Continue => self.continue_statement()?,
+            Token::Struct => self.struct_statement()?,
             _ => {
-                let saved_index = self.index;
-                if let Some(stat) = self.assign_statement() {
-                    stat
+                let assignment = match (self.peek().contents.clone(), self.peek2().contents.clone()) {
+                    (Token::Ident(_), Token::Equals) => true,
+                    (Token::Ident(_), Token::Op(op)) => is_assignment_op(op.as_str()),
+                    _ => false,
+                };
+                if assignment {
+                    self.assign_statement()?
                 } else {
-                    self.index = saved_index;
-                    crate::errors::ERRORS.lock().unwrap().pop().unwrap();
-                    self.expr(0)?
+                    let expr = self.expr(0)?;
+                    let assignment = match &self.peek().contents {
+                        Token::Equals => true,
+                        Token::Op(op) => is_assignment_op(op.as_str()),
+                        _ => false,
+                    };
+                    if assignment {
+                        self.place_assign_statement(expr)?
+                    } else {
+                        expr
+                    }
                 }
             }
         })
     }
 
-    fn if_statement(&mut self, ensure_if: bool) -> Option<Span<Node>> {
-        if ensure_if {
-            self.ensure_next(Token::If)?;
-        }
-        let condition = self.expr(0)?;
+    /// Starts at either the `if` or an `elif`, so that each one in a chain is spanned from its keyword
+    fn if_statement(&mut self) -> Option<Span<Node>> {
+        let start = self.next();
+        let condition = self.condition()?;
         let body = self.block()?;
-        let else_body;
-        if self.peek().contents == Token::Elif {
-            self.ensure_next(Token::Elif)?;
-            else_body = self.if_statement(false)?;
+        let else_body = if self.peek().contents == Token::Elif {
+            self.if_statement()?
         } else if self.peek().contents == Token::Else {
             self.ensure_next(Token::Else)?;
-            else_body = self.block()?;
+            self.block()?
         } else {
-            else_body = spanned(Node::Block {
+            // an empty else block where the else would have been, right after the body
+            let pos = body.pos + body.len;
+            spanned(Node::Block {
                 nodes: vec![
                     spanned(Node::Literal {
                         typ: Type::Undefined,
                         value: "undefined".to_owned(),
-                    }, 0, 0)
+                    }, pos, 0)
                 ],
-            }, 0, 0);
-        }
+            }, pos, 0)
+        };
 
+        let (pos, len) = self.span_since(&start);
         Some(spanned(Node::IfStatement {
             condition: Box::new(condition),
-            body: Box::new(body.clone()),
+            body: Box::new(body),
             else_body: Box::new(else_body),
-        }, 0, 0))
+        }, pos, len))
+    }
+
+    /// Like an if statement, except that its blocks end in the value it evaluates to.
+    /// The If token has already been consumed.
+    fn if_expression(&mut self, start: Span<Token>) -> Option<Span<Node>> {
+        let condition = self.condition()?;
+        let body = self.block()?;
+        let else_body = if self.peek().contents == Token::Elif {
+            let elif_token = self.next();
+            // the block holding the rest of the chain stands in for the elif itself
+            let (pos, len) = (elif_token.pos, elif_token.len);
+            let elif = self.if_expression(elif_token)?;
+            Some(spanned(Node::Block {
+                nodes: vec![elif],
+            }, pos, len))
+        } else if self.try_next(Token::Else).is_some() {
+            Some(self.block()?)
+        } else {
+            None
+        };
+
+        let (pos, len) = self.span_since(&start);
+        Some(spanned(Node::IfExpression {
+            condition: Box::new(condition),
+            body: Box::new(body),
+            else_body: else_body.map(Box::new),
+        }, pos, len))
+    }
+
+    /// The condition of an if or a while, which is only ever followed by its block
+    fn condition(&mut self) -> Option<Span<Node>> {
+        let condition = self.expr(0)?;
+        // `=` isn't an operator, so the expression stops right before it
+        let next = self.peek().clone();
+        if next.contents == Token::Equals {
+            self.logger.syntax_error(Code::AssignmentInCondition, "A condition can't assign with =, did you mean == to compare?", next.pos, next.len);
+            return None;
+        }
+        Some(condition)
     }
 
     fn while_statement(&mut self) -> Option<Span<Node>> {
+        let start = self.peek().clone();
         self.ensure_next(Token::While)?;
-        let condition = self.expr(0)?;
+        let condition = self.condition()?;
         let body = self.block()?;
 
+        let (pos, len) = self.span_since(&start);
         Some(spanned(Node::WhileStatement {
             condition: Box::new(condition),
-            body: Box::new(body.clone()),
-        }, 0, 0))
+            body: Box::new(body),
+        }, pos, len))
+    }
+
+    fn for_statement(&mut self) -> Option<Span<Node>> {
+        let start_token = self.peek().clone();
+        self.ensure_next(Token::For)?;
+        let var = self.ensure_ident()?;
+        self.ensure_next(Token::Equals)?;
+        let start = self.expr(0)?;
+        self.ensure_next(Token::Op(Symbol::intern("..")))?;
+        let end = self.expr(0)?;
+        let body = self.block()?;
+
+        let (pos, len) = self.span_since(&start_token);
+        Some(spanned(Node::ForStatement {
+            var,
+            start: Box::new(start),
+            end: Box::new(end),
+            body: Box::new(body),
+        }, pos, len))
     }
 
     fn loop_statement(&mut self) -> Option<Span<Node>> {
+        let start = self.peek().clone();
         self.ensure_next(Token::Loop)?;
         let condition = spanned(Node::Literal {
             typ: Type::Bool,
             value: "true".to_owned(),
-        }, 0, 0);
+        }, start.pos, start.len);
         let body = self.block()?;
 
+        let (pos, len) = self.span_since(&start);
         Some(spanned(Node::WhileStatement {
             condition: Box::new(condition),
-            body: Box::new(body.clone()),
-        }, 0, 0))
+            body: Box::new(body),
+        }, pos, len))
+    }
+
+    /// `match x { 0 { ... } 1 { ... } else { ... } }`, where the arms can be
+    /// on lines of their own and the else block, if any, comes last
+    fn match_statement(&mut self) -> Option<Span<Node>> {
+        let start = self.next();
+        let scrutinee = self.expr(0)?;
+        self.ensure_next(Token::LBrace)?;
+        let mut arms = vec![];
+        let mut default = None;
+        // a bad arm still parses as one, so that the rest of the match doesn't cause more errors
+        let mut failed = false;
+        loop {
+            while self.try_next(Token::Newline).is_some() {}
+            let next = self.peek().clone();
+            if next.contents == Token::RBrace {
+                self.next();
+                break;
+            }
+            if default.is_some() {
+                self.logger.syntax_error(Code::InvalidMatchArm, "The else block has to be the last arm of a match", next.pos, next.len);
+                failed = true;
+            }
+            if self.try_next(Token::Else).is_some() {
+                default = Some(Box::new(self.block()?));
+                continue;
+            }
+            let pattern = self.expr(0)?;
+            if !matches!(&pattern.contents, Node::Literal { typ, .. } if typ.is_integer() || matches!(typ, Type::IntLiteral | Type::Bool)) {
+                self.logger.syntax_error(Code::InvalidMatchArm, "A match arm has to be an integer or bool literal", pattern.pos, pattern.len);
+                failed = true;
+            }
+            arms.push((pattern, self.block()?));
+        }
+        if failed {
+            return None;
+        }
+
+        let (pos, len) = self.span_since(&start);
+        Some(spanned(Node::MatchStatement {
+            scrutinee: Box::new(scrutinee),
+            arms,
+            default,
+        }, pos, len))
     }
 
     fn block(&mut self) -> Option<Span<Node>> {
+        self.nested("block", Self::block_inner)
+    }
+
+    fn block_inner(&mut self) -> Option<Span<Node>> {
         let mut nodes = vec![];
+        let mut failed = false;
+        let start = self.peek().clone();
         self.ensure_next(Token::LBrace)?;
         loop {
-            let _ = self.try_next(Token::Newline);
-            nodes.push(self.statement()?);
-            if self.try_next(Token::Newline).is_none() {
-                self.ensure_next(Token::RBrace)?;
-                break;
+            // blank lines can go anywhere, including right before the }
+            let mut docs = vec![];
+            while matches!(self.peek().contents, Token::Newline | Token::DocComment(_)) {
+                let skipped = self.next();
+                if let Token::DocComment(_) = skipped.contents {
+                    docs.push(skipped);
+                }
             }
-            if self.peek().contents == Token::RBrace {
-                self.ensure_next(Token::RBrace)?;
+            // procs are only ever declared at the top level
+            warn_undocumented(&docs, &self.logger);
+            if self.try_next(Token::RBrace).is_some() {
                 break;
             }
+            // which is also how a proc after a block that's missing its } can be told apart,
+            // the block ends there so that the proc still gets parsed
+            if matches!(self.peek().contents, Token::EOF | Token::Proc) {
+                self.logger.syntax_error(Code::UnclosedBlock, "This block is never closed with a }", start.pos, start.len);
+                if self.peek().contents == Token::Proc {
+                    let proc = self.peek().clone();
+                    self.logger.note("Procs can only be declared at the top level, so the block can't go on past this one", proc.pos, proc.len);
+                }
+                return None;
+            }
+            let parsed = self.statement().and_then(|node| {
+                if self.peek().contents != Token::RBrace {
+                    self.end_statement(&node)?;
+                }
+                Some(node)
+            });
+            match parsed {
+                Some(node) => nodes.push(node),
+                None => {
+                    failed = true;
+                    self.recover();
+                }
+            }
+        }
+        if failed {
+            return None;
         }
+        let (pos, len) = self.span_since(&start);
         Some(spanned(Node::Block {
             nodes,
-        }, 0, 0))
+        }, pos, len))
     }
 
     fn var_statement(&mut self) -> Option<Span<Node>> {
+        let start = self.peek().clone();
         self.ensure_next(Token::Var)?;
+        if self.try_next(Token::LParen).is_some() {
+            return self.var_tuple_statement(start);
+        }
         let name = self.ensure_ident()?;
-        let typ;
-        if self.try_next(Token::Colon).is_some() {
-            typ = self.ensure_type()?;
+        let typ = if self.try_next(Token::Colon).is_some() {
+            self.ensure_type()?
         } else {
-            typ = Type::Variable(self.next_type_var());
-        }
-        let value;
-        if self.peek().contents == Token::Equals {
+            Type::Variable(self.next_type_var())
+        };
+        let value = if self.peek().contents == Token::Equals {
             self.ensure_next(Token::Equals)?;
-            value = self.expr(0)?;
+            self.expr(0)?
         } else {
-            value = spanned(Node::Literal {
+            let (pos, len) = self.span_since(&start);
+            spanned(Node::Literal {
                 typ: Type::Undefined,
                 value: "undefined".to_owned(),
-            }, 0, 0);
-        }
+            }, pos, len)
+        };
 
+        let (pos, len) = self.span_since(&start);
         Some(spanned(Node::VarStatement {
             name,
             typ,
             value: Box::new(value),
-        }, 0, 0))
+        }, pos, len))
     }
 
-    fn assign_statement(&mut self) -> Option<Span<Node>> {
-        let name = self.ensure_ident()?;
-        if self.try_next(Token::Equals).is_none() {
-            // indexed
-            self.ensure_next(Token::LBracket)?;
-            let index = self.expr(0)?;
-            self.ensure_next(Token::RBracket)?;
-            self.ensure_next(Token::Equals)?;
-            let value = self.expr(0)?;
+    /// `var (q, r) = v`, which has to have a value to take apart. The `var (` has already been consumed.
+    fn var_tuple_statement(&mut self, start: Span<Token>) -> Option<Span<Node>> {
+        let mut names = vec![self.ensure_ident()?];
+        while self.try_next(Token::Comma).is_some() {
+            names.push(self.ensure_ident()?);
+        }
+        self.ensure_next(Token::RParen)?;
+        self.ensure_next(Token::Equals)?;
+        let value = self.expr(0)?;
 
-            return Some(spanned(Node::IndexedAssignStatement {
-                name,
-                index: Box::new(index),
-                value: Box::new(value),
-            }, 0, 0));
+        let (pos, len) = self.span_since(&start);
+        if names.len() < 2 {
+            self.logger.syntax_error(
+                Code::TupleTooShort,
+                "Unpacking a tuple takes at least two names, use var x = v for just one",
+                pos,
+                len,
+            );
+            return None;
         }
+        Some(spanned(Node::VarTupleStatement {
+            names,
+            value: Box::new(value),
+        }, pos, len))
+    }
 
+    /// Handles `x = v` as well as `x += v` and friends, which are desugared
+    /// into `x = x + v` spanning the whole statement.
+    fn assign_statement(&mut self) -> Option<Span<Node>> {
+        let start = self.peek().clone();
+        let name = self.ensure_ident()?;
+        let (op, assign) = self.assignment_op()?;
         let value = self.expr(0)?;
+        let pos = start.pos;
+        let len = (value.pos + value.len).max(assign.pos + assign.len) - pos;
+
+        let value = match op {
+            Some(op) => spanned(Node::InfixOp {
+                op: Symbol::intern(op),
+                op_span: Span { contents: (), pos: assign.pos, len: assign.len },
+                left: Box::new(spanned(Node::VariableRef {
+                    name: name.clone(),
+                }, start.pos, start.len)),
+                right: Box::new(value),
+            }, pos, len),
+            None => value,
+        };
 
         Some(spanned(Node::AssignStatement {
             name,
             value: Box::new(value),
-        }, 0, 0))
+        }, pos, len))
+    }
+
+    /// Consumes `=` or a compound assignment operator, returning the operator
+    /// the latter desugars to along with the consumed token
+    fn assignment_op(&mut self) -> Option<(Option<&'static str>, Span<Token>)> {
+        let assign = self.next();
+        let op = match &assign.contents {
+            Token::Equals => None,
+            Token::Op(o) => match o.as_str() {
+                "+=" => Some("+"),
+                "-=" => Some("-"),
+                "*=" => Some("*"),
+                "/=" => Some("/"),
+                "//=" => Some("//"),
+                _ => {
+                    self.logger.syntax_error(
+                        Code::UnknownOperator,
+                        format!("Unknown assignment operator {}", o).as_str(),
+                        assign.pos,
+                        assign.len,
+                    );
+                    return None;
+                }
+            },
+            _ => unreachable!(),
+        };
+        Some((op, assign))
+    }
+
+    /// The target has already been parsed as an expression, since telling
+    /// `a[i] = v` apart from `a[i] + v` would need unbounded lookahead.
+    fn place_assign_statement(&mut self, target: Span<Node>) -> Option<Span<Node>> {
+        if !matches!(target.contents, Node::IndexOp { .. } | Node::FieldAccess { .. }) {
+            self.logger.syntax_error(Code::InvalidAssignmentTarget, "Can't assign to this expression", target.pos, target.len);
+            return None;
+        }
+        let (op, assign) = self.assignment_op()?;
+        let value = self.expr(0)?;
+        let len = (value.pos + value.len).max(target.pos + target.len) - target.pos;
+
+        let value = match op {
+            Some(op) => spanned(Node::InfixOp {
+                op: Symbol::intern(op),
+                op_span: Span { contents: (), pos: assign.pos, len: assign.len },
+                left: Box::new(target.clone()),
+                right: Box::new(value),
+            }, target.pos, len),
+            None => value,
+        };
+
+        Some(spanned(match target.contents {
+            Node::IndexOp { object, index } => Node::IndexAssignStatement {
+                object,
+                index,
+                value: Box::new(value),
+            },
+            Node::FieldAccess { object, field } => Node::FieldAssignStatement {
+                object,
+                field,
+                value: Box::new(value),
+            },
+            _ => unreachable!(),
+        }, target.pos, len))
+    }
+
+    /// `struct Name { field: type, ... }`, with fields separated by commas or newlines
+    fn struct_statement(&mut self) -> Option<Span<Node>> {
+        let start = self.next(); // skip struct
+        let name_token = self.peek().clone();
+        let name = self.ensure_ident()?;
+        if self.structs.contains_key(&name) {
+            self.logger.syntax_error(
+                Code::Redefinition,
+                format!("A struct named {} has already been declared", name).as_str(),
+                name_token.pos,
+                name_token.len,
+            );
+            return None;
+        }
+        self.ensure_next(Token::LBrace)?;
+        let mut fields: Vec<(String, Type)> = vec![];
+        loop {
+            while self.try_next(Token::Newline).is_some() {}
+            if self.try_next(Token::RBrace).is_some() {
+                break;
+            }
+            let field_token = self.peek().clone();
+            let field = self.ensure_ident()?;
+            if fields.iter().any(|(f, _)| *f == field) {
+                self.logger.syntax_error(
+                    Code::Redefinition,
+                    format!("Struct {} already has a field named {}", name, field).as_str(),
+                    field_token.pos,
+                    field_token.len,
+                );
+                return None;
+            }
+            self.ensure_next(Token::Colon)?;
+            fields.push((field, self.ensure_type()?));
+            if self.try_next(Token::Comma).is_none() && self.peek().contents != Token::Newline {
+                self.ensure_next(Token::RBrace)?;
+                break;
+            }
+        }
+
+        self.structs.insert(name.clone(), Type::new_struct(name.clone(), fields.clone()));
+        let (pos, len) = self.span_since(&start);
+        Some(spanned(Node::StructDecl {
+            name,
+            fields,
+        }, pos, len))
     }
 
     fn const_statement(&mut self) -> Option<Span<Node>> {
+        let start = self.peek().clone();
         self.ensure_next(Token::Const)?;
         let name = self.ensure_ident()?;
-        let typ;
-        if self.try_next(Token::Colon).is_some() {
-            typ = self.ensure_type()?;
+        let typ = if self.try_next(Token::Colon).is_some() {
+            self.ensure_type()?
         } else {
-            typ = Type::Variable(self.next_type_var());
-        }
+            Type::Variable(self.next_type_var())
+        };
         self.ensure_next(Token::Equals)?;
         let value = self.expr(0)?;
 
+        let (pos, len) = self.span_since(&start);
         Some(spanned(Node::ConstStatement {
             name,
             typ,
             value: Box::new(value),
-        }, 0, 0))
+        }, pos, len))
     }
 
     fn proc_statement(&mut self) -> Option<Span<Node>> {
+        let start = self.peek().clone();
         self.ensure_next(Token::Proc)?;
         let name = self.ensure_ident()?;
+        let open = self.peek().clone();
         self.ensure_next(Token::LParen)?;
         let mut args = vec![];
         let mut arg_types = vec![];
-        while self.peek().contents != Token::RParen {
+        let mut arg_spans = vec![];
+        // checking for the ) before each argument rather than after each comma
+        // is what lets the last argument have a comma after it
+        while self.peek().contents != Token::RParen && !self.past_end_of_statement() {
+            let arg = self.peek().clone();
             args.push(self.ensure_ident()?);
-            self.ensure_next(Token::Colon)?;
-            arg_types.push(self.ensure_type()?);
+            arg_spans.push((arg.pos, arg.len));
+            // an argument without a type gets it from the calls to the proc, see analysis.rs
+            if self.try_next(Token::Colon).is_some() {
+                arg_types.push(self.ensure_type()?);
+            } else {
+                arg_types.push(Type::Variable(self.next_type_var()));
+            }
             if self.peek().contents != Token::Comma {
                 break;
             } else {
                 self.ensure_next(Token::Comma)?;
             }
         }
-        self.ensure_next(Token::RParen)?;
-        let ret_type;
-        if self.try_next(Token::Colon).is_some() {
-            ret_type = self.ensure_type()?;
+        self.ensure_closed(Token::RParen, &open)?;
+        let ret_type = if self.try_next(Token::Colon).is_some() {
+            self.ensure_type()?
         } else {
-            ret_type = Type::Undefined;
-        }
-        let body;
-        if self.peek().contents == Token::LBrace {
-            body = self.block()?;
+            Type::Undefined
+        };
+        let body = if self.peek().contents == Token::LBrace {
+            self.block()?
         } else {
-            body = spanned(Node::Block {
+            let (pos, len) = self.span_since(&start);
+            if arg_types.iter().any(|t| matches!(t, Type::Variable(_))) {
+                self.logger.type_error(
+                    Code::MissingTypeAnnotation,
+                    format!("Proc {} has no body, so the types of its arguments have to be written out", name).as_str(),
+                    pos,
+                    len,
+                );
+                return None;
+            }
+            spanned(Node::Block {
                 nodes: vec![],
-            }, 0, 0);
-        }
+            }, pos, len)
+        };
 
-        Some(spanned(Node::ProcStatement {
+        let (pos, len) = self.span_since(&start);
+        Some(spanned(Node::ProcStatement(Box::new(ProcDecl {
             name,
             args,
             arg_types,
+            arg_spans,
             ret_type,
-            body: Box::new(body),
-        }, 0, 0))
+            body,
+            doc: None,
+        })), pos, len))
     }
 
     fn return_statement(&mut self) -> Option<Span<Node>> {
+        let start = self.peek().clone();
         self.ensure_next(Token::Return)?;
-        if self.try_next(Token::Newline).is_some() {
+        // the statement's separator is left for whoever parsed the statement
+        if matches!(self.peek().contents, Token::Newline | Token::RBrace | Token::EOF) {
             Some(spanned(Node::ReturnStatement {
                 val: Box::new(spanned(Node::Literal {
                     typ: Type::Undefined,
                     value: "undefined".to_owned(),
-                }, 0, 0)),
-            }, 0, 0))
+                }, start.pos, start.len)),
+            }, start.pos, start.len))
         } else {
             let val = self.expr(0)?;
+            let (pos, len) = self.span_since(&start);
             Some(spanned(Node::ReturnStatement {
                 val: Box::new(val),
-            }, 0, 0))
+            }, pos, len))
         }
     }
 
     fn use_statement(&mut self) -> Option<Span<Node>> {
+        let start = self.peek().clone();
         self.ensure_next(Token::Use)?;
         let mut path = String::new();
         loop {
             path.push_str(&self.ensure_ident()?);
-            if let Token::Op(op) = self.peek().contents {
-                if op == ".".to_owned() {
+            if let Token::Op(op) = self.peek().contents.clone() {
+                if op == "." {
                     self.next();
                     path.push('.');
                 } else {
@@ -361,22 +872,29 @@ impl<'p> Parser<'p> {
                 break;
             }
         }
+        let (pos, len) = self.span_since(&start);
         Some(spanned(Node::UseStatement {
             path,
-        }, 0, 0))
+        }, pos, len))
     }
 
     fn break_statement(&mut self) -> Option<Span<Node>> {
+        let start = self.peek().clone();
         self.ensure_next(Token::Break)?;
-        Some(spanned(Node::BreakStatement, 0, 0))
+        Some(spanned(Node::BreakStatement, start.pos, start.len))
     }
 
     fn continue_statement(&mut self) -> Option<Span<Node>> {
+        let start = self.peek().clone();
         self.ensure_next(Token::Continue)?;
-        Some(spanned(Node::ContinueStatement, 0, 0))
+        Some(spanned(Node::ContinueStatement, start.pos, start.len))
     }
 
     fn expr(&mut self, min_bp: u8) -> Option<Span<Node>> {
+        self.nested("expression", |parser| parser.expr_inner(min_bp))
+    }
+
+    fn expr_inner(&mut self, min_bp: u8) -> Option<Span<Node>> {
         let mut left = match self.next().clone() {
             Span {
                 contents: Token::Ident(id),
@@ -384,9 +902,10 @@ impl<'p> Parser<'p> {
                 len,
             } => {
                 if self.peek().contents == Token::LParen {
-                    self.next(); // pass the LParen;
+                    let open = self.next();
                     let mut args = Vec::new();
-                    while self.peek().contents != Token::RParen {
+                    // a comma can come after the last argument, like it does in the parameters
+                    while self.peek().contents != Token::RParen && !self.past_end_of_statement() {
                         args.push(self.expr(0)?);
                         if self.peek().contents != Token::Comma {
                             break;
@@ -394,14 +913,16 @@ impl<'p> Parser<'p> {
                             self.ensure_next(Token::Comma)?;
                         }
                     }
-                    self.ensure_next(Token::RParen)?;
+                    let close = self.peek().clone();
+                    self.ensure_closed(Token::RParen, &open)?;
+                    let (pos, len) = merge(&Span { contents: (), pos, len }, &close);
                     spanned(Node::Call {
-                        name: id,
+                        name: id.as_str().to_owned(),
                         args,
                     }, pos, len)
                 } else {
                     spanned(Node::VariableRef {
-                        name: id,
+                        name: id.as_str().to_owned(),
                     }, pos, len)
                 }
             }
@@ -409,18 +930,12 @@ impl<'p> Parser<'p> {
                 contents: Token::IntLiteral(int),
                 pos,
                 len,
-            } => spanned(Node::Literal {
-                typ: Type::IntLiteral,
-                value: int,
-            }, pos, len),
+            } => spanned(number_literal(int, Type::IntLiteral, pos, len, &self.logger), pos, len),
             Span {
                 contents: Token::FloatLiteral(float),
                 pos,
                 len,
-            } => spanned(Node::Literal {
-                typ: Type::FloatLiteral,
-                value: float,
-            }, pos, len),
+            } => spanned(number_literal(float, Type::FloatLiteral, pos, len, &self.logger), pos, len),
             Span {
                 contents: Token::StrLiteral(s),
                 pos,
@@ -429,85 +944,205 @@ impl<'p> Parser<'p> {
                 typ: Type::StrLiteral,
                 value: s,
             }, pos, len),
+            // a character is just its ASCII code
             Span {
+                contents: Token::CharLiteral(ch),
+                pos,
+                len,
+            } => spanned(Node::Literal {
+                typ: Type::N8,
+                value: (ch as u32).to_string(),
+            }, pos, len),
+            Span {
+                contents: Token::True,
+                pos,
+                len,
+            } => spanned(Node::Literal {
+                typ: Type::Bool,
+                value: "true".to_owned(),
+            }, pos, len),
+            Span {
+                contents: Token::False,
+                pos,
+                len,
+            } => spanned(Node::Literal {
+                typ: Type::Bool,
+                value: "false".to_owned(),
+            }, pos, len),
+            t @ Span {
+                contents: Token::If,
+                ..
+            } => self.if_expression(t)?,
+            start @ Span {
                 contents: Token::LParen,
                 ..
             } => {
                 let left = self.expr(0)?;
-                self.ensure_next(Token::RParen)?;
-                left
+                if self.peek().contents == Token::Comma {
+                    // it's only a tuple once there's a comma, otherwise the parentheses just group
+                    let mut elements = vec![left];
+                    while self.try_next(Token::Comma).is_some() {
+                        elements.push(self.expr(0)?);
+                    }
+                    self.ensure_closed(Token::RParen, &start)?;
+                    let (pos, len) = self.span_since(&start);
+                    spanned(Node::Tuple { elements }, pos, len)
+                } else {
+                    self.ensure_closed(Token::RParen, &start)?;
+                    left
+                }
             }
             Span {
                 contents: Token::Op(op),
                 pos,
                 len,
             } => {
-                let ((), right_bp) = prefix_binding_power(&op);
+                let right_bp = match prefix_binding_power(op.as_str()) {
+                    Some(((), right_bp)) => right_bp,
+                    None => {
+                        self.logger.syntax_error(
+                            Code::UnknownOperator,
+                            format!("{} can't be used as a prefix operator", op).as_str(),
+                            pos,
+                            len,
+                        );
+                        return None
+                    }
+                };
                 let right = self.expr(right_bp)?;
-                spanned(Node::PrefixOp {
-                    op,
-                    right: Box::new(right),
-                }, pos, len)
+                let op_span = Span { contents: (), pos, len };
+                let (pos, len) = merge(&op_span, &right);
+                // a negative number is one literal, otherwise the smallest value
+                // of a signed type would be out of range before it got negated
+                match (op.as_str(), right.contents) {
+                    ("-", Node::Literal { typ, value }) if is_number_literal(&typ) => {
+                        let value = match value.strip_prefix('-') {
+                            Some(value) => value.to_owned(),
+                            None => format!("-{}", value),
+                        };
+                        spanned(Node::Literal { typ, value }, pos, len)
+                    }
+                    (_, contents) => spanned(Node::PrefixOp {
+                        op,
+                        op_span,
+                        right: Box::new(Span { contents, ..right }),
+                    }, pos, len),
+                }
             }
             Span {
                 contents: Token::EOF,
                 pos,
                 len,
             } => {
-                Logger::syntax_error("Encountered the end of the file while parsing", pos, len);
+                self.logger.syntax_error(Code::UnexpectedEof, "Encountered the end of the file while parsing", pos, len);
+                return None
+            }
+            token if keyword_as_ident(&token, &self.logger).is_some() => {
+                // stands in for the keyword as a value of whatever type is around it,
+                // so that nothing else complains about it like an undefined variable would
+                spanned(Node::Literal {
+                    typ: Type::Variable(self.next_type_var()),
+                    value: "undefined".to_owned(),
+                }, token.pos, token.len)
+            }
+            Span {
+                contents,
+                pos,
+                len,
+            } => {
+                self.logger.syntax_error(
+                    Code::UnexpectedToken,
+                    format!("Expected an expression, but found {} instead", contents).as_str(),
+                    pos,
+                    len,
+                );
                 return None
             }
-            t => panic!("Bad token: {:?}", t),
         };
 
+        // `a < b < c` would compare a bool with c, which is never what was meant
+        let mut last_comparison: Option<Span<Token>> = None;
         loop {
+            // anything else ends the expression, and whoever comes next can
+            // complain about it if it doesn't belong there
             let op = match self.peek().contents.clone() {
-                Token::EOF
-                | Token::Newline
-                | Token::RParen
-                | Token::RBracket
-                | Token::Comma
-                | Token::LBrace
-                | Token::RBrace => break,
                 Token::Op(op) => op,
-                Token::LBracket => "[".to_owned(),
-                t => panic!("Bad token: {:?}", t),
+                Token::LBracket => Symbol::intern("["),
+                Token::As => Symbol::intern("as"),
+                _ => break,
             };
 
-            if let Some((left_bp, ())) = postfix_binding_power(&op) {
+            if let Some((left_bp, ())) = postfix_binding_power(op.as_str()) {
                 if left_bp < min_bp {
                     break;
                 }
-                self.next();
+                let op_token = self.next();
 
                 left = if op == "[" {
                     let right = self.expr(0)?;
-                    self.ensure_next(Token::RBracket)?;
+                    let close = self.peek().clone();
+                    self.ensure_closed(Token::RBracket, &op_token)?;
+                    let (pos, len) = merge(&left, &close);
                     spanned(Node::IndexOp {
                         object: Box::new(left),
                         index: Box::new(right),
-                    }, 0, 0)
+                    }, pos, len)
+                } else if op == "as" {
+                    let typ = self.ensure_type()?;
+                    let (pos, len) = self.span_since(&op_token);
+                    let (pos, len) = merge(&left, &Span { contents: (), pos, len });
+                    spanned(Node::Cast {
+                        value: Box::new(left),
+                        typ,
+                    }, pos, len)
+                } else if op == "." {
+                    // spanned at the field, which is what a bad access gets blamed on
+                    let field = self.peek().clone();
+                    spanned(Node::FieldAccess {
+                        object: Box::new(left),
+                        field: self.ensure_ident()?,
+                    }, field.pos, field.len)
                 } else {
+                    let (pos, len) = merge(&left, &op_token);
                     spanned(Node::PostfixOp {
                         op,
+                        op_span: Span { contents: (), pos: op_token.pos, len: op_token.len },
                         left: Box::new(left),
-                    }, 0, 0)
+                    }, pos, len)
                 };
                 continue;
             }
 
-            if let Some((left_bp, right_bp)) = infix_binding_power(&op) {
+            if let Some((left_bp, right_bp)) = infix_binding_power(op.as_str()) {
                 if left_bp < min_bp {
                     break;
                 }
-                self.next();
+                let op_token = self.next();
+                let op_span = Span { contents: (), pos: op_token.pos, len: op_token.len };
+                if is_comparison_op(op.as_str()) {
+                    if let Some(first) = &last_comparison {
+                        let (pos, len) = merge(first, &op_token);
+                        self.logger.syntax_error(
+                            Code::ChainedComparison,
+                            "Comparison operators can't be chained, use && to combine comparisons",
+                            pos,
+                            len,
+                        );
+                        return None;
+                    }
+                    last_comparison = Some(op_token);
+                } else {
+                    last_comparison = None;
+                }
 
                 let right = self.expr(right_bp)?;
+                let (pos, len) = merge(&left, &right);
                 left = spanned(Node::InfixOp {
                     op,
+                    op_span,
                     left: Box::new(left),
                     right: Box::new(right),
-                }, 0, 0);
+                }, pos, len);
                 continue;
             }
 
@@ -518,26 +1153,169 @@ impl<'p> Parser<'p> {
     }
 }
 
-fn prefix_binding_power(op: &String) -> ((), u8) {
-    match op.as_str() {
-        "!" => ((), 8),
-        "+" | "-" => ((), 9),
-        o => unreachable!(o),
+/// What a statement is called in diagnostics
+/// The text of a run of doc comments, without the space that usually comes after the #:
+fn doc_text(docs: &[Span<Token>]) -> Option<String> {
+    let lines: Vec<_> = docs.iter()
+        .filter_map(|doc| match &doc.contents {
+            Token::DocComment(text) => Some(text.strip_prefix(' ').unwrap_or(text)),
+            _ => None,
+        })
+        .collect();
+    if lines.is_empty() {
+        None
+    } else {
+        Some(lines.join("\n"))
+    }
+}
+
+/// Only procs have docs, so doc comments before anything else are probably a mistake
+fn warn_undocumented(docs: &[Span<Token>], logger: &Logger) {
+    if let (Some(first), Some(last)) = (docs.first(), docs.last()) {
+        let (pos, len) = merge(first, last);
+        logger.warning(Code::DanglingDocComment, "This doc comment isn't followed by a proc, so it doesn't document anything", pos, len);
+    }
+}
+
+/// A number, whose type is `literal` unless it ends in the name of one, like 42i64 or 1.5f32.
+/// A bad suffix is reported and left off, so the number is still there to be checked.
+fn number_literal(number: String, literal: Type, pos: usize, len: usize, logger: &Logger) -> Node {
+    let end = number.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(number.len());
+    let (value, suffix) = number.split_at(end);
+    let typ = match suffix {
+        "" => literal.clone(),
+        "i8" => Type::I8,
+        "i16" => Type::I16,
+        "i32" => Type::I32,
+        "i64" => Type::I64,
+        "i128" => Type::I128,
+
+        "n8" => Type::N8,
+        "n16" => Type::N16,
+        "n32" => Type::N32,
+        "n64" => Type::N64,
+        "n128" => Type::N128,
+
+        "f32" => Type::F32,
+        "f64" => Type::F64,
+        "f128" => Type::F128,
+
+        _ => {
+            logger.name_error(
+                Code::UnknownType,
+                format!("There is no number type named {}", suffix).as_str(),
+                pos + end,
+                len - end,
+            );
+            literal.clone()
+        }
+    };
+    if literal == Type::FloatLiteral && typ.is_integer() {
+        logger.type_error(
+            Code::LiteralOutOfRange,
+            format!("{} has a decimal point, so it can't be a {}", value, typ).as_str(),
+            pos,
+            len,
+        );
+        return Node::Literal { typ: literal, value: value.to_owned() };
+    }
+    Node::Literal { typ, value: value.to_owned() }
+}
+
+/// Whether a literal is a number, with or without a suffix
+fn is_number_literal(typ: &Type) -> bool {
+    typ.is_integer() || typ.is_float() || matches!(typ, Type::IntLiteral | Type::FloatLiteral)
+}
+
+fn describe(node: &Node) -> &'static str {
+    match node {
+        Node::IfStatement { .. } => "if statement",
+        Node::WhileStatement { .. } => "while loop",
+        Node::MatchStatement { .. } => "match statement",
+        Node::ForStatement { .. } => "for loop",
+        Node::VarStatement { .. } | Node::VarTupleStatement { .. } => "var statement",
+        Node::ConstStatement { .. } => "const statement",
+        Node::AssignStatement { .. }
+        | Node::IndexAssignStatement { .. }
+        | Node::FieldAssignStatement { .. } => "assignment",
+        Node::ProcStatement(_) => "proc",
+        Node::StructDecl { .. } => "struct declaration",
+        Node::ReturnStatement { .. } => "return statement",
+        Node::UseStatement { .. } => "use statement",
+        Node::BreakStatement => "break statement",
+        Node::ContinueStatement => "continue statement",
+        Node::Block { .. } => "block",
+        _ => "expression",
+    }
+}
+
+/// How a token that could be the whole argument of a call is written, if it could be
+fn argument_text(token: &Token) -> Option<String> {
+    match token {
+        Token::Ident(name) => Some(name.to_string()),
+        Token::IntLiteral(number) | Token::FloatLiteral(number) => Some(number.clone()),
+        Token::StrLiteral(s) => Some(format!("{:?}", s)),
+        Token::CharLiteral(c) => Some(format!("{:?}", c)),
+        Token::True => Some("true".to_owned()),
+        Token::False => Some("false".to_owned()),
+        _ => None,
     }
 }
 
-fn postfix_binding_power(op: &String) -> Option<(u8, ())> {
-    Some(match op.as_str() {
-        "[" => (11, ()),
+fn is_comparison_op(op: &str) -> bool {
+    matches!(op, "==" | "!=" | "<" | ">" | "<=" | ">=")
+}
+
+/// Operators ending in `=` that aren't comparisons, which are always compound assignments
+fn is_assignment_op(op: &str) -> bool {
+    op.ends_with('=') && !is_comparison_op(op)
+}
+
+fn prefix_binding_power(op: &str) -> Option<((), u8)> {
+    Some(match op {
+        "!" => ((), 17),
+        "+" | "-" | "-~" => ((), 18),
+        _ => return None,
+    })
+}
+
+fn postfix_binding_power(op: &str) -> Option<(u8, ())> {
+    Some(match op {
+        "[" | "." => (22, ()),
+        // tighter than arithmetic, so `a as i64 + b` only casts `a`
+        "as" => (21, ()),
         _ => return None,
     })
 }
 
-fn infix_binding_power(op: &String) -> Option<(u8, u8)> {
-    Some(match op.as_str() {
-        ">" | "<" | ">=" | "<=" | "==" | "!=" => (3, 4),
-        "+" | "-" => (5, 6),
-        "*" | "/" | "//" => (7, 8),
+/// Every operator from loosest to tightest, where the ones on a line group from the left unless it says otherwise:
+///
+/// - `||`
+/// - `&&`
+/// - `|`
+/// - `^`
+/// - `==` `!=` `<` `>` `<=` `>=`, which can't be chained
+/// - `&`
+/// - `+` `-` `+~` `-~`
+/// - `*` `/` `//` `*~` `<<` `>>`
+/// - prefix `!`, then prefix `+` `-` `-~`
+/// - `**`, which groups from the right
+/// - `as`, then `[]` and `.`
+///
+/// `|` and `^` are looser than comparisons and `&` is tighter, so `a & mask == 0` is `(a & mask) == 0`,
+/// and `a | b == c` is `a | (b == c)`
+fn infix_binding_power(op: &str) -> Option<(u8, u8)> {
+    Some(match op {
+        "||" => (1, 2),
+        "&&" => (3, 4),
+        "|" => (5, 6),
+        "^" => (7, 8),
+        ">" | "<" | ">=" | "<=" | "==" | "!=" => (9, 10),
+        "&" => (11, 12),
+        "+" | "-" | "+~" | "-~" => (13, 14),
+        "*" | "/" | "//" | "*~" | "<<" | ">>" => (15, 16),
+        // right associative, and tighter than a prefix -, so `-x ** 2` is `-(x ** 2)`
+        "**" => (20, 19),
         _ => return None,
     })
 }
@@ -0,0 +1,304 @@
+//! Keeping the analyzed IR of used files between builds, so that one that hasn't changed
+//! doesn't have to be lexed, parsed and analyzed all over again.
+//!
+//! Each entry is a text file named after the hash of the source it came from. Along with
+//! what the file declares, it lists the declarations from other files that it depends on,
+//! each as the single line it's known by, and is only used if every one of those is still the same.
+
+use crate::astgen::Node;
+use crate::ir::{type_from_text, unquote, IRGlobal, IRProc};
+use crate::types::Type;
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// The first line of every entry, anything written by another version of the compiler is ignored
+const VERSION: &str = concat!("elgin cache 5 ", env!("CARGO_PKG_VERSION"));
+
+/// FNV-1a, which unlike the standard library's hasher stays the same from one build of the compiler to the next
+pub fn hash(text: &str) -> u64 {
+    text.bytes().fold(0xcbf29ce484222325, |hash, byte| (hash ^ byte as u64).wrapping_mul(0x100000001b3))
+}
+
+pub struct Cache {
+    dir: PathBuf,
+}
+
+impl Cache {
+    pub fn new(dir: &Path) -> Self {
+        Cache { dir: dir.to_owned() }
+    }
+
+    fn path(&self, hash: u64) -> PathBuf {
+        self.dir.join(format!("{:016x}.ir", hash))
+    }
+
+    /// The entry for the source with this hash, if there's one this version of the compiler wrote
+    pub fn load(&self, hash: u64) -> Option<Entry> {
+        let text = fs::read_to_string(self.path(hash)).ok()?;
+        Entry::from_text(&text).ok()
+    }
+
+    pub fn store(&self, hash: u64, entry: &Entry) -> io::Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        fs::write(self.path(hash), entry.to_text())
+    }
+}
+
+/// What used files loaded from the cache contribute to the program, which has already been analyzed
+#[derive(Default)]
+pub struct Cached {
+    pub procs: Vec<IRProc>,
+    pub globals: Vec<IRGlobal>,
+    pub structs: Vec<Type>,
+}
+
+/// Everything a file contributes once it's been analyzed. Spans are from the start of the file,
+/// and structs in types only have their names until they're looked up again.
+#[derive(Default)]
+pub struct Entry {
+    // the name of every used module, along with where its use statement is
+    pub uses: Vec<(String, usize, usize)>,
+    // the declaration lines from other files that this one depends on
+    pub needs: Vec<String>,
+    pub structs: Vec<Type>,
+    pub globals: Vec<IRGlobal>,
+    pub procs: Vec<IRProc>,
+}
+
+impl Entry {
+    /// Procs come last, each after its doc and the spans of its instructions
+    pub fn to_text(&self) -> String {
+        let mut text = format!("{}\n", VERSION);
+        for (name, pos, len) in &self.uses {
+            text.push_str(&format!("use {} {} {}\n", name, pos, len));
+        }
+        for line in &self.needs {
+            text.push_str(&format!("needs {}\n", line));
+        }
+        for typ in &self.structs {
+            text.push_str(&format!("{}\n", struct_line(typ)));
+        }
+        for global in &self.globals {
+            let value = global.value.as_ref().map_or(String::new(), |value| format!(" = {:?}", value));
+            text.push_str(&format!("{} {}: {:?}{}\n", global_kind(global.constant), global.name, global.typ, value));
+        }
+        for proc in &self.procs {
+            if let Some(doc) = &proc.doc {
+                text.push_str(&format!("doc {:?}\n", doc));
+            }
+            let spans: Vec<_> = proc.body.iter().map(|ins| format!("{} {}", ins.pos, ins.len)).collect();
+            text.push_str(&format!("spans {}\n", spans.join(" ")));
+            text.push_str(&proc.to_text());
+        }
+        text
+    }
+
+    pub fn from_text(text: &str) -> Result<Entry, String> {
+        let mut lines = text.lines();
+        if lines.next() != Some(VERSION) {
+            return Err("Written by another version of the compiler".to_owned());
+        }
+        let mut entry = Entry::default();
+        let (mut doc, mut spans) = (None, vec![]);
+        while let Some(line) = lines.next() {
+            let (kind, rest) = line.split_once(' ').ok_or_else(|| format!("Expected a declaration, found {}", line))?;
+            match kind {
+                "use" => {
+                    let parts: Vec<_> = rest.split(' ').collect();
+                    let [name, pos, len] = parts[..] else {
+                        return Err(format!("Expected a module and its span, found {}", rest));
+                    };
+                    entry.uses.push((name.to_owned(), number(pos)?, number(len)?));
+                }
+                "needs" => entry.needs.push(rest.to_owned()),
+                "struct" => entry.structs.push(struct_from_text(rest)?),
+                "const" | "var" => entry.globals.push(global_from_text(rest, kind == "const")?),
+                "doc" => doc = Some(unquote(rest)?),
+                "spans" => {
+                    let numbers = rest.split(' ').filter(|n| !n.is_empty()).map(number).collect::<Result<Vec<_>, _>>()?;
+                    spans = numbers.chunks(2).map(|span| (span[0], span.get(1).copied().unwrap_or(0))).collect();
+                }
+                "proc" => {
+                    // a proc without a body is all on one line
+                    let mut listing = format!("{}\n", line);
+                    if line.ends_with(" {") {
+                        for line in lines.by_ref() {
+                            listing.push_str(line);
+                            listing.push('\n');
+                            if line == "}" {
+                                break;
+                            }
+                        }
+                    }
+                    let mut proc = IRProc::from_text(&listing)?;
+                    if spans.len() != proc.body.len() {
+                        return Err(format!("Proc {} doesn't have a span for every instruction", proc.name));
+                    }
+                    for (ins, &(pos, len)) in proc.body.iter_mut().zip(&spans) {
+                        ins.pos = pos;
+                        ins.len = len;
+                    }
+                    proc.doc = doc.take();
+                    entry.procs.push(proc);
+                }
+                _ => return Err(format!("Expected a declaration, found {}", line)),
+            }
+        }
+        Ok(entry)
+    }
+}
+
+fn number(text: &str) -> Result<usize, String> {
+    text.parse().map_err(|_| format!("Expected a number, found {}", text))
+}
+
+fn global_kind(constant: bool) -> &'static str {
+    if constant {
+        "const"
+    } else {
+        "var"
+    }
+}
+
+/// `struct Name { field: type; ... }`, with other structs in the fields only named
+pub fn struct_line(typ: &Type) -> String {
+    match typ {
+        Type::Struct(s) => {
+            let fields: Vec<_> = s.fields.iter().map(|(field, typ)| format!("{}: {:?}", field, typ)).collect();
+            format!("struct {} {{ {} }}", s.name, fields.join("; "))
+        }
+        typ => format!("struct {:?}", typ),
+    }
+}
+
+fn struct_from_text(text: &str) -> Result<Type, String> {
+    let (name, fields) = text.strip_suffix('}')
+        .and_then(|text| text.split_once(" {"))
+        .ok_or_else(|| format!("Expected a struct, found {}", text))?;
+    let fields = fields.split(';')
+        .map(str::trim)
+        .filter(|field| !field.is_empty())
+        .map(|field| {
+            let (field, typ) = field.split_once(": ").ok_or_else(|| format!("Expected a type for field {}", field))?;
+            Ok((field.to_owned(), type_from_text(typ)?))
+        })
+        .collect::<Result<_, String>>()?;
+    Ok(Type::new_struct(name.to_owned(), fields))
+}
+
+fn global_from_text(text: &str, constant: bool) -> Result<IRGlobal, String> {
+    let (declaration, value) = match text.split_once(" = ") {
+        Some((declaration, value)) => (declaration, Some(unquote(value)?)),
+        None => (text, None),
+    };
+    let (name, typ) = declaration.split_once(": ").ok_or_else(|| format!("Expected a type for {}", declaration))?;
+    Ok(IRGlobal {
+        name: name.to_owned(),
+        typ: type_from_text(typ)?,
+        value,
+        constant,
+    })
+}
+
+/// The line a global is known by. What a var starts out as makes no difference to the code
+/// using it, but a const's value is put right where it's used.
+pub fn global_line(global: &IRGlobal) -> String {
+    match (&global.value, global.constant) {
+        (Some(value), true) => format!("const {}: {:?} = {:?}", global.name, global.typ, value),
+        _ => format!("{} {}: {:?}", global_kind(global.constant), global.name, global.typ),
+    }
+}
+
+/// The name and line of what a top-level node declares, if the line would be the same once
+/// it's been analyzed. Anything that still has types to infer, or a const whose value
+/// isn't an integer the parser could work out, doesn't count.
+pub fn node_declaration(node: &Node, consts: &HashMap<String, i128>) -> Option<(String, String)> {
+    let annotated = |typ: &Type| !has_variable(typ);
+    Some(match node {
+        Node::ProcStatement(decl) if decl.arg_types.iter().all(annotated) && annotated(&decl.ret_type) => {
+            let proc = IRProc {
+                name: decl.name.clone(),
+                args: decl.args.clone(),
+                arg_types: decl.arg_types.clone(),
+                ret_type: decl.ret_type.clone(),
+                body: vec![],
+                doc: None,
+            };
+            (proc.name.clone(), proc.signature())
+        }
+        Node::StructDecl { name, fields } => (name.clone(), struct_line(&Type::new_struct(name.clone(), fields.clone()))),
+        Node::ConstStatement { name, typ, .. } if annotated(typ) => (name.clone(), global_line(&IRGlobal {
+            name: name.clone(),
+            typ: typ.clone(),
+            value: Some(consts.get(name)?.to_string()),
+            constant: true,
+        })),
+        Node::VarStatement { name, typ, .. } if annotated(typ) => (name.clone(), global_line(&IRGlobal {
+            name: name.clone(),
+            typ: typ.clone(),
+            value: None,
+            constant: false,
+        })),
+        _ => return None,
+    })
+}
+
+pub fn has_variable(typ: &Type) -> bool {
+    match typ {
+        Type::Variable(_) => true,
+        Type::Ptr(t) | Type::Array(_, t) => has_variable(t),
+        Type::Tuple(elements) => elements.iter().any(has_variable),
+        Type::Proc(p) => p.args.iter().any(has_variable) || has_variable(&p.ret),
+        _ => false,
+    }
+}
+
+/// The name of every struct that `typ` is or has in it, fields included
+pub fn struct_names(typ: &Type, names: &mut Vec<String>) {
+    match typ {
+        Type::Struct(s) if !names.contains(&s.name) => {
+            names.push(s.name.clone());
+            for (_, field) in &s.fields {
+                struct_names(field, names);
+            }
+        }
+        Type::Ptr(t) | Type::Array(_, t) => struct_names(t, names),
+        Type::Tuple(elements) => {
+            for element in elements {
+                struct_names(element, names);
+            }
+        }
+        Type::Proc(p) => {
+            for arg in &p.args {
+                struct_names(arg, names);
+            }
+            struct_names(&p.ret, names);
+        }
+        _ => (),
+    }
+}
+
+/// `typ` with every struct in it, which the cache only has the name of, looked up in `structs`.
+/// One that isn't there is left as it is.
+pub fn with_structs(typ: &Type, structs: &HashMap<String, Type>) -> Type {
+    match typ {
+        Type::Struct(s) => match structs.get(&s.name) {
+            Some(found) => found.clone(),
+            None => Type::new_struct(
+                s.name.clone(),
+                s.fields.iter().map(|(field, typ)| (field.clone(), with_structs(typ, structs))).collect(),
+            ),
+        },
+        Type::Ptr(t) => Type::Ptr(Box::new(with_structs(t, structs))),
+        Type::Array(size, t) => Type::Array(*size, Box::new(with_structs(t, structs))),
+        Type::Tuple(elements) => Type::Tuple(elements.iter().map(|t| with_structs(t, structs)).collect()),
+        Type::Proc(p) => Type::new_proc(
+            p.args.iter().map(|t| with_structs(t, structs)).collect(),
+            with_structs(&p.ret, structs),
+        ),
+        typ => typ.clone(),
+    }
+}
@@ -0,0 +1,90 @@
+//! A proc's body split into basic blocks, along with where each of them can go next,
+//! for anything that has to follow control flow rather than just the order of the listing
+
+use crate::errors::Span;
+use crate::ir::{IRProc, Instruction, InstructionType};
+
+use std::collections::HashMap;
+use std::ops::Range;
+
+/// A run of instructions that's only ever entered at its start and left at its end
+pub struct BasicBlock {
+    // indices into the body, including the Label the block starts with if it has one
+    pub instructions: Range<usize>,
+    // indices of the blocks it can go on to, the one for true first if it ends in a Branch
+    pub successors: Vec<usize>,
+}
+
+/// A new block starts at every Label and after every Jump, Branch, Return or Fail.
+/// A block after one of those that doesn't start with a Label can't be got to.
+pub fn basic_blocks(body: &[Span<Instruction>]) -> Vec<BasicBlock> {
+    use InstructionType::*;
+    if body.is_empty() {
+        return vec![];
+    }
+    let mut starts = vec![0];
+    for (i, ins) in body.iter().enumerate() {
+        match ins.contents.ins {
+            Label(_) if starts.last() != Some(&i) => starts.push(i),
+            Jump(_) | Branch(_, _) | Return | Fail(_) if i + 1 < body.len() => starts.push(i + 1),
+            _ => (),
+        }
+    }
+    let ranges: Vec<_> = starts.iter()
+        .zip(starts.iter().skip(1).chain([body.len()].iter()))
+        .map(|(&start, &end)| start..end)
+        .collect();
+    let block_of: HashMap<_, _> = ranges.iter().enumerate()
+        .filter_map(|(i, range)| match &body[range.start].contents.ins {
+            Label(label) => Some((label.as_str(), i)),
+            _ => None,
+        })
+        .collect();
+    ranges.iter().enumerate()
+        .map(|(i, range)| {
+            let successors = match &body[range.end - 1].contents.ins {
+                Jump(label) => vec![block_of[label.as_str()]],
+                Branch(body_label, else_label) => vec![block_of[body_label.as_str()], block_of[else_label.as_str()]],
+                Return | Fail(_) => vec![],
+                // falls into the next one
+                _ if i + 1 < ranges.len() => vec![i + 1],
+                _ => vec![],
+            };
+            BasicBlock { instructions: range.clone(), successors }
+        })
+        .collect()
+}
+
+/// The proc's control flow as a Graphviz digraph, with a box listing the instructions of each block
+/// and the edges out of a Branch labeled true and false
+pub fn to_dot(proc: &IRProc) -> String {
+    let blocks = basic_blocks(&proc.body);
+    let mut res = format!("digraph {:?} {{\n", proc.name);
+    res.push_str("    node [shape=box, fontname=monospace];\n");
+    for (i, block) in blocks.iter().enumerate() {
+        // \l ends a line and aligns it to the left
+        let listing: String = proc.body[block.instructions.clone()].iter()
+            .map(|ins| format!("{}\\l", escape(&ins.contents.to_string())))
+            .collect();
+        res.push_str(&format!("    b{} [label=\"{}\"];\n", i, listing));
+    }
+    for (i, block) in blocks.iter().enumerate() {
+        let last = &proc.body[block.instructions.end - 1].contents.ins;
+        for (n, successor) in block.successors.iter().enumerate() {
+            match last {
+                InstructionType::Branch(_, _) => {
+                    let label = if n == 0 { "true" } else { "false" };
+                    res.push_str(&format!("    b{} -> b{} [label=\"{}\"];\n", i, successor, label));
+                }
+                _ => res.push_str(&format!("    b{} -> b{};\n", i, successor)),
+            }
+        }
+    }
+    res.push_str("}\n");
+    res
+}
+
+/// Makes text safe to put between the quotes of a dot string
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
@@ -0,0 +1,19 @@
+//! Markdown documentation for a program, made out of the doc comments on its procs
+
+use crate::ir::IRProc;
+
+/// A section for every proc in the order given, undocumented ones included
+pub fn markdown(procs: &[IRProc]) -> String {
+    let sections: Vec<_> = procs.iter()
+        .map(|proc| {
+            let mut section = format!("## {}\n\n`{}`\n", proc.name, proc.signature());
+            if let Some(doc) = &proc.doc {
+                section.push('\n');
+                section.push_str(doc);
+                section.push('\n');
+            }
+            section
+        })
+        .collect();
+    sections.join("\n")
+}
@@ -2,8 +2,11 @@
 
 use ErrorType::*;
 
+use std::any::Any;
+use std::cell::Cell;
 use std::fmt;
-use std::sync::Mutex;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Mutex, Once};
 
 #[derive(Clone)]
 pub struct Span<T: fmt::Debug> {
@@ -22,52 +25,526 @@ impl<T: fmt::Debug> fmt::Debug for Span<T> {
     }
 }
 
-#[derive(Debug)]
+/// The position and length of the source covering both spans
+pub fn merge<A: fmt::Debug, B: fmt::Debug>(first: &Span<A>, last: &Span<B>) -> (usize, usize) {
+    let end = (last.pos + last.len).max(first.pos + first.len);
+    (first.pos, end - first.pos)
+}
+
+/// A hint naming whichever candidate is closest to `name`, or nothing if none are close enough
+/// to plausibly be what was meant. A one-letter name is never suggested, since it's close to everything.
+pub fn did_you_mean<'a>(name: &str, candidates: impl IntoIterator<Item = &'a str>) -> String {
+    let max_distance = (name.chars().count() / 3).max(1);
+    candidates.into_iter()
+        .filter(|candidate| *candidate != name && candidate.chars().count() > 1)
+        .map(|candidate| (edit_distance(name, candidate), candidate))
+        .filter(|(distance, _)| *distance <= max_distance)
+        .min()
+        .map_or_else(String::new, |(_, candidate)| format!(", did you mean {}?", candidate))
+}
+
+/// The optimal string alignment distance between two strings, which is the Levenshtein distance
+/// except that swapping two letters next to each other counts as one edit rather than two
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    // distances from the first i - 2, i - 1 and i chars of `a` to every prefix of `b`
+    let mut before: Vec<usize> = vec![0; b.len() + 1];
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    let mut row = vec![0; b.len() + 1];
+    for i in 1..=a.len() {
+        row[0] = i;
+        for j in 1..=b.len() {
+            let substituted = previous[j - 1] + usize::from(a[i - 1] != b[j - 1]);
+            row[j] = substituted.min(previous[j] + 1).min(row[j - 1] + 1);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                row[j] = row[j].min(before[j - 2] + 1);
+            }
+        }
+        std::mem::swap(&mut before, &mut previous);
+        std::mem::swap(&mut previous, &mut row);
+    }
+    previous[b.len()]
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ErrorType {
     SyntaxError,
-    //TypeError,
+    TypeError,
     NameError,
+    Warning,
+    Note,
+    InternalError,
+    // only from running the IR, in interp.rs
+    RuntimeError,
 }
 
-#[derive(Debug)]
-pub struct Error {
-    typ: ErrorType,
-    msg: String,
-    pos: usize,
-    len: usize,
+impl fmt::Display for ErrorType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SyntaxError => write!(f, "syntax error"),
+            TypeError => write!(f, "type error"),
+            NameError => write!(f, "name error"),
+            Warning => write!(f, "warning"),
+            Note => write!(f, "note"),
+            InternalError => write!(f, "internal compiler error"),
+            RuntimeError => write!(f, "runtime error"),
+        }
+    }
 }
 
-pub struct Logger {
+/// What a diagnostic is about, for tools to go by instead of its message.
+/// Once a code has been given out it always means the same thing, so new ones go on the end
+/// of their group: E00 for syntax, E01 for types, E02 for names, E03 for failures at runtime,
+/// E9 for the compiler's own bugs, and W for warnings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Code {
+    UnexpectedToken,
+    UnexpectedEof,
+    UnknownOperator,
+    UnexpectedCharacter,
+    UnterminatedString,
+    InvalidEscape,
+    ReservedKeyword,
+    TooDeeplyNested,
+    UnclosedBlock,
+    AssignmentInCondition,
+    ChainedComparison,
+    InvalidAssignmentTarget,
+    InvalidMatchArm,
+    MisplacedStatement,
+    TupleTooShort,
+    InvalidCharLiteral,
+    UnclosedDelimiter,
+
+    MismatchedTypes,
+    WrongArgumentCount,
+    CannotInfer,
+    InvalidOperation,
+    InvalidMain,
+    MissingValue,
+    MissingReturn,
+    ReturnMismatch,
+    AssignToConst,
+    InvalidConstValue,
+    LiteralOutOfRange,
+    ConflictingArgumentTypes,
+    InvalidAssertMessage,
+    DuplicateMatchArm,
+    MissingTypeAnnotation,
+    NoSuchMember,
+    InvalidArrayLength,
+
+    UnknownVariable,
+    UnknownProc,
+    UnknownType,
+    UnknownModule,
+    Redefinition,
+    NoMain,
+
+    RuntimeFailure,
+
+    InternalError,
+
+    UnusedVariable,
+    UnusedProc,
+    UsedBeforeAssigned,
+    UnreachableCode,
+    ShadowedVariable,
+    FloatLiteralOverflow,
+    DanglingDocComment,
+    UnusedResult,
+}
+
+impl Code {
+    pub fn as_str(self) -> &'static str {
+        use Code::*;
+        match self {
+            UnexpectedToken => "E0001",
+            UnexpectedEof => "E0002",
+            UnknownOperator => "E0003",
+            UnexpectedCharacter => "E0004",
+            UnterminatedString => "E0005",
+            InvalidEscape => "E0006",
+            ReservedKeyword => "E0007",
+            TooDeeplyNested => "E0008",
+            UnclosedBlock => "E0009",
+            AssignmentInCondition => "E0010",
+            ChainedComparison => "E0011",
+            InvalidAssignmentTarget => "E0012",
+            InvalidMatchArm => "E0013",
+            MisplacedStatement => "E0014",
+            TupleTooShort => "E0015",
+            InvalidCharLiteral => "E0016",
+            UnclosedDelimiter => "E0017",
+
+            MismatchedTypes => "E0101",
+            WrongArgumentCount => "E0102",
+            CannotInfer => "E0103",
+            InvalidOperation => "E0104",
+            InvalidMain => "E0105",
+            MissingValue => "E0106",
+            MissingReturn => "E0107",
+            ReturnMismatch => "E0108",
+            AssignToConst => "E0109",
+            InvalidConstValue => "E0110",
+            LiteralOutOfRange => "E0111",
+            ConflictingArgumentTypes => "E0112",
+            InvalidAssertMessage => "E0113",
+            DuplicateMatchArm => "E0114",
+            MissingTypeAnnotation => "E0115",
+            NoSuchMember => "E0116",
+            InvalidArrayLength => "E0117",
+
+            UnknownVariable => "E0201",
+            UnknownProc => "E0202",
+            UnknownType => "E0203",
+            UnknownModule => "E0204",
+            Redefinition => "E0205",
+            NoMain => "E0206",
+
+            RuntimeFailure => "E0301",
+
+            InternalError => "E9001",
+
+            UnusedVariable => "W0001",
+            UnusedProc => "W0002",
+            UsedBeforeAssigned => "W0003",
+            UnreachableCode => "W0004",
+            ShadowedVariable => "W0005",
+            FloatLiteralOverflow => "W0006",
+            DanglingDocComment => "W0007",
+            UnusedResult => "W0008",
+        }
+    }
+}
+
+impl fmt::Display for Code {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// Anything the compiler has to say about the code, error or not
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub severity: ErrorType,
+    // a note belongs to the diagnostic before it, so it has no code of its own
+    pub code: Option<Code>,
+    pub message: String,
+    pub pos: usize,
+    pub len: usize,
+}
+
+thread_local! {
+    // the span of whatever this thread was last working on, which is all there is to go by if it panics
+    static WORKING_ON: Cell<Option<(usize, usize)>> = const { Cell::new(None) };
+    // whether a panic on this thread is going to be caught and handed back as a diagnostic
+    static CATCHING: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Notes that the compiler has started on whatever is at this span, so that a panic
+/// can be reported somewhere near what caused it
+pub fn working_on(pos: usize, len: usize) {
+    WORKING_ON.with(|cell| cell.set(Some((pos, len))));
+}
+
+/// Whether a panic on this thread is going to be handed back by `catching_panics`,
+/// in which case a panic hook has no need to print it
+pub fn panic_is_caught() -> bool {
+    CATCHING.with(Cell::get)
+}
+
+/// Runs `f`, handing back a panic in it as an internal compiler error.
+/// The panic hook set before the first call is kept, but only hears about panics that aren't caught this way.
+pub fn catching_panics<T>(f: impl FnOnce() -> T) -> Result<T, Diagnostic> {
+    static QUIET_HOOK: Once = Once::new();
+    QUIET_HOOK.call_once(|| {
+        let hook = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            if !panic_is_caught() {
+                hook(info)
+            }
+        }));
+    });
+    let outer = CATCHING.with(|cell| cell.replace(true));
+    let res = panic::catch_unwind(AssertUnwindSafe(f));
+    CATCHING.with(|cell| cell.set(outer));
+    res.map_err(|payload| Diagnostic::from_panic(&*payload))
+}
+
+impl Diagnostic {
+    /// A panic as an internal compiler error, at whatever the thread that caught it was last working on
+    pub fn from_panic(payload: &(dyn Any + Send)) -> Diagnostic {
+        let message = match (payload.downcast_ref::<&str>(), payload.downcast_ref::<String>()) {
+            (Some(message), _) => message,
+            (_, Some(message)) => message.as_str(),
+            _ => "no message",
+        };
+        let (pos, len) = WORKING_ON.with(Cell::get).unwrap_or((0, 0));
+        Diagnostic {
+            severity: InternalError,
+            code: Some(Code::InternalError),
+            message: format!("The compiler panicked: {}", message),
+            pos,
+            len,
+        }
+    }
+
+    /// Warnings and notes are the only things that don't stop compilation
+    pub fn is_error(&self) -> bool {
+        !matches!(self.severity, Warning | Note)
+    }
+
+    /// The severity along with the code, like `type error[E0101]`
+    fn heading(&self) -> String {
+        match self.code {
+            Some(code) => format!("{}[{}]", self.severity, code),
+            None => self.severity.to_string(),
+        }
+    }
+
+    /// A single line JSON object, with the file, line and column as well if `source` is the one it's in
+    pub fn to_json(&self, source: Option<&SourceMap>) -> String {
+        let mut fields = vec![
+            format!("\"code\":{}", self.code.map_or_else(|| "null".to_owned(), |code| json_string(code.as_str()))),
+            format!("\"severity\":{}", json_string(&self.severity.to_string())),
+            format!("\"message\":{}", json_string(&self.message)),
+            format!("\"pos\":{}", self.pos),
+            format!("\"len\":{}", self.len),
+        ];
+        if let Some(source) = source {
+            let (line, col) = source.line_col(self.pos);
+            fields.push(format!("\"file\":{}", json_string(&source.name)));
+            fields.push(format!("\"line\":{}", line));
+            fields.push(format!("\"column\":{}", col));
+        }
+        format!("{{{}}}", fields.join(","))
+    }
+}
 
+fn json_string(s: &str) -> String {
+    let mut out = String::from("\"");
+    for ch in s.chars() {
+        match ch {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            ch if (ch as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", ch as u32)),
+            ch => out.push(ch),
+        }
+    }
+    out.push('"');
+    out
 }
 
+/// Without the source to point into, which only `Logger::render` knows
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {} (at {}, length {})", self.heading(), self.message, self.pos, self.len)
+    }
+}
+
+/// How reported diagnostics are printed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ErrorFormat {
+    // pointing into the source, for people
+    #[default]
+    Human,
+    // one `Diagnostic::to_json` object per line, for editors
+    Json,
+}
 
-lazy_static! {
-    pub static ref ERRORS: Mutex<Vec<Error>> = Mutex::new(vec![]);
+/// The text of a file, which turns the byte offsets in spans into lines and columns.
+/// Every file starts where the one before it ends, so a position alone says which file it's in.
+pub struct SourceMap {
+    name: String,
+    start: usize,
+    code: String,
+    line_starts: Vec<usize>,
+}
+
+impl SourceMap {
+    pub fn new(name: &str, start: usize, code: &str) -> Self {
+        let mut line_starts = vec![0];
+        line_starts.extend(code.match_indices('\n').map(|(i, _)| i + 1));
+        SourceMap {
+            name: name.to_owned(),
+            start,
+            code: code.to_owned(),
+            line_starts,
+        }
+    }
+
+    /// The 1-based line and column of a position. Columns count chars, so a tab is a single column.
+    pub fn line_col(&self, pos: usize) -> (usize, usize) {
+        let pos = pos - self.start;
+        let line = match self.line_starts.binary_search(&pos) {
+            Ok(line) => line,
+            Err(next) => next - 1,
+        };
+        let before = self.code.get(self.line_starts[line]..pos).unwrap_or("");
+        (line + 1, before.chars().count() + 1)
+    }
+
+    /// The text of a 1-based line, without its newline
+    fn line(&self, line: usize) -> &str {
+        let start = self.line_starts[line - 1];
+        let end = self.line_starts.get(line).map_or(self.code.len(), |next| next - 1);
+        &self.code[start..end.max(start)]
+    }
+
+    /// `file:line:col: message`, then the line with the span underlined.
+    /// A span running over several lines is only underlined on its first.
+    fn render(&self, error: &Diagnostic) -> String {
+        let (line, col) = self.line_col(error.pos);
+        let text = self.line(line);
+        // copying the tabs keeps the carets lined up however wide they're shown
+        let indent: String = text.chars()
+            .take(col - 1)
+            .map(|ch| if ch == '\t' { '\t' } else { ' ' })
+            .collect();
+        let from = error.pos - self.start - self.line_starts[line - 1];
+        let underlined = text.get(from..(from + error.len).min(text.len())).unwrap_or("");
+        let width = underlined.chars().count().max(1);
+        format!(
+            "{}:{}:{}: {}: {}\n    {}\n    {}{}",
+            self.name,
+            line,
+            col,
+            error.heading(),
+            error.message,
+            text,
+            indent,
+            "^".repeat(width),
+        )
+    }
+}
+
+/// Where everything found while compiling some code is logged, to be reported once it's done,
+/// along with the files it came from and how to print it.
+/// Clones share all of that, so each stage holds on to one of its own,
+/// while code compiled alongside it on other threads logs somewhere else entirely.
+#[derive(Clone, Default)]
+pub struct Logger {
+    diagnostics: Arc<Mutex<Vec<Diagnostic>>>,
+    sources: Arc<Mutex<Vec<SourceMap>>>,
+    format: Arc<Mutex<ErrorFormat>>,
 }
 
 impl Logger {
-    pub fn log(typ: ErrorType, msg: &str, pos: usize, len: usize) {
-        ERRORS.lock().unwrap().push(Error {
-            typ,
-            msg: msg.to_owned(),
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn log(&self, severity: ErrorType, code: Option<Code>, msg: &str, pos: usize, len: usize) {
+        self.diagnostics.lock().unwrap().push(Diagnostic {
+            severity,
+            code,
+            message: msg.to_owned(),
             pos,
             len,
         });
     }
 
+    /// How `render` prints diagnostics, which is shared with every clone
+    pub fn set_format(&self, format: ErrorFormat) {
+        *self.format.lock().unwrap() = format;
+    }
+
+    pub fn format(&self) -> ErrorFormat {
+        *self.format.lock().unwrap()
+    }
+
+    /// How many errors have been logged and not yet drained
+    pub fn error_count(&self) -> usize {
+        self.diagnostics.lock().unwrap().iter().filter(|diagnostic| diagnostic.is_error()).count()
+    }
+
+    /// Adds a file for printed errors to point into,
+    /// returning the position that its first char has in spans
+    pub fn add_source(&self, name: &str, code: &str) -> usize {
+        let mut sources = self.sources.lock().unwrap();
+        // one past the end, so that the end of the file before it still points there
+        let start = sources.last().map_or(0, |last| last.start + last.code.len() + 1);
+        sources.push(SourceMap::new(name, start, code));
+        start
+    }
+
+    /// Diagnostics in source order, one per line (or several, for a human) in this logger's format,
+    /// pointing into whichever source given to `add_source` each one is in
+    pub fn render(&self, diagnostics: Vec<Diagnostic>) -> String {
+        let format = self.format();
+        let sources = self.sources.lock().unwrap();
+        in_source_order(diagnostics)
+            .iter()
+            .map(|diagnostic| {
+                let source = sources.iter().rev().find(|source| source.start <= diagnostic.pos);
+                match (format, source) {
+                    (ErrorFormat::Human, Some(source)) => format!("{}\n", source.render(diagnostic)),
+                    (ErrorFormat::Human, None) => format!("{}\n", diagnostic),
+                    (ErrorFormat::Json, source) => format!("{}\n", diagnostic.to_json(source)),
+                }
+            })
+            .collect()
+    }
+
+    /// Logs diagnostics that were found somewhere else, like by another logger
+    pub fn report(&self, diagnostics: Vec<Diagnostic>) {
+        self.diagnostics.lock().unwrap().extend(diagnostics);
+    }
+
+    /// Takes everything logged so far, in the order it was logged
+    pub fn drain(&self) -> Vec<Diagnostic> {
+        std::mem::take(&mut *self.diagnostics.lock().unwrap())
+    }
+
     #[inline]
-    pub fn name_error(msg: &str, pos: usize, len: usize) {
-        Self::log(NameError, msg, pos, len);
+    pub fn name_error(&self, code: Code, msg: &str, pos: usize, len: usize) {
+        self.log(NameError, Some(code), msg, pos, len);
     }
 
-    //#[inline]
-    //pub fn type_error(msg: &str, pos: usize, len: usize) {
-    //    Self::log(TypeError, msg, pos, len);
-    //}
+    #[inline]
+    pub fn type_error(&self, code: Code, msg: &str, pos: usize, len: usize) {
+        self.log(TypeError, Some(code), msg, pos, len);
+    }
 
+    /// Reported just like an error, but never stops compilation
+    /// and isn't counted by `error_count`
     #[inline]
-    pub fn syntax_error(msg: &str, pos: usize, len: usize) {
-        Self::log(SyntaxError, msg, pos, len);
+    pub fn warning(&self, code: Code, msg: &str, pos: usize, len: usize) {
+        self.log(Warning, Some(code), msg, pos, len);
+    }
+
+    /// Points at somewhere else the error just before it involves, like a warning it isn't counted
+    #[inline]
+    pub fn note(&self, msg: &str, pos: usize, len: usize) {
+        self.log(Note, None, msg, pos, len);
+    }
+
+    #[inline]
+    pub fn syntax_error(&self, code: Code, msg: &str, pos: usize, len: usize) {
+        self.log(SyntaxError, Some(code), msg, pos, len);
+    }
+
+    /// Something the compiler got wrong, not the code it's compiling
+    #[inline]
+    pub fn internal_error(&self, msg: &str, pos: usize, len: usize) {
+        self.log(InternalError, Some(Code::InternalError), msg, pos, len);
+    }
+}
+
+/// Puts diagnostics in the order of where they are in the source, whichever order they were logged in.
+/// Notes stay right after whatever they're about, and anything at the same place keeps its order.
+pub fn in_source_order(diagnostics: Vec<Diagnostic>) -> Vec<Diagnostic> {
+    let mut groups: Vec<Vec<Diagnostic>> = vec![];
+    for diagnostic in diagnostics {
+        match groups.last_mut() {
+            Some(group) if diagnostic.severity == Note => group.push(diagnostic),
+            _ => groups.push(vec![diagnostic]),
+        }
     }
+    groups.sort_by_key(|group| group[0].pos);
+    groups.into_iter().flatten().collect()
 }
@@ -0,0 +1,599 @@
+//! Runs the typed IR directly, for trying a program out without going through LLVM
+//! It does what the generated code would, except that anything the generated code leaves undefined,
+//! like an index out of bounds or an overflow that isn't allowed to wrap, stops it with an error
+
+use crate::errors::{Code, Diagnostic, ErrorType, Span};
+use crate::ir::{IRGlobal, IRProc, Instruction, InstructionType};
+use crate::opt::{self, Constant};
+use crate::types::Type;
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt;
+use std::io::{self, Write};
+use std::rc::Rc;
+
+/// Deep enough for any recursion that ends, a call past it is taken to be one that doesn't
+const MAX_CALL_DEPTH: usize = 100_000;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    // iN types
+    Int(i128),
+    // nN types
+    Nat(u128),
+    // every float is an f64 here, f32s are rounded after each operation
+    Float(f64),
+    Bool(bool),
+    // shared, so that pushing a literal or copying a variable doesn't copy the bytes
+    Str(Rc<str>),
+    Array(Vec<Value>),
+    // in declaration order
+    Struct(Vec<(String, Value)>),
+    // the name of the proc referred to
+    Proc(String),
+    Undefined,
+}
+
+impl Value {
+    /// What a variable without a value starts out as, the same as a global without one
+    fn zero(typ: &Type) -> Value {
+        match typ {
+            t if t.is_signed() => Value::Int(0),
+            t if t.is_integer() => Value::Nat(0),
+            t if t.is_float() => Value::Float(0.0),
+            Type::Bool => Value::Bool(false),
+            Type::Str => Value::Str(Rc::from("")),
+            Type::Array(size, element) => Value::Array(vec![Value::zero(element); *size]),
+            Type::Struct(s) => Value::Struct(s.fields.iter()
+                .map(|(field, typ)| (field.clone(), Value::zero(typ)))
+                .collect()),
+            Type::Tuple(elements) => Value::Struct(elements.iter()
+                .enumerate()
+                .map(|(i, typ)| (i.to_string(), Value::zero(typ)))
+                .collect()),
+            _ => Value::Undefined,
+        }
+    }
+
+    /// The value of a Push of type `typ`
+    fn parse(value: &str, typ: &Type) -> Option<Value> {
+        match typ {
+            t if t.is_signed() => value.parse().ok().map(Value::Int),
+            t if t.is_integer() => value.parse().ok().map(Value::Nat),
+            // an f32 literal is only as precise as an f32, and too big for one is infinite
+            t if t.is_float() => value.parse().ok().map(|v| Value::Float(opt::fit_float(v, t))),
+            Type::Bool => value.parse().ok().map(Value::Bool),
+            Type::Str => Some(Value::Str(Rc::from(value))),
+            Type::Undefined => Some(Value::Undefined),
+            _ => None,
+        }
+    }
+
+    /// The same value as the constant folding in opt.rs sees it, which is what does the arithmetic
+    fn constant(&self) -> Option<Constant> {
+        match self {
+            Value::Int(v) => Some(Constant::Int(*v)),
+            Value::Nat(v) => Some(Constant::Nat(*v)),
+            Value::Float(v) => Some(Constant::Float(*v)),
+            Value::Bool(v) => Some(Constant::Bool(*v)),
+            _ => None,
+        }
+    }
+
+    /// An array or str index, which is never in bounds if it's negative
+    fn index(&self) -> Option<usize> {
+        match self {
+            Value::Int(v) => usize::try_from(*v).ok(),
+            Value::Nat(v) => usize::try_from(*v).ok(),
+            _ => None,
+        }
+    }
+}
+
+impl From<Constant> for Value {
+    fn from(constant: Constant) -> Self {
+        match constant {
+            Constant::Int(v) => Value::Int(v),
+            Constant::Nat(v) => Value::Nat(v),
+            Constant::Float(v) => Value::Float(v),
+            Constant::Bool(v) => Value::Bool(v),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RuntimeError {
+    pub message: String,
+    pub pos: usize,
+    pub len: usize,
+}
+
+impl RuntimeError {
+    /// The same kind of diagnostic as the compiler's own, pointing at the instruction that failed
+    pub fn diagnostic(&self) -> Diagnostic {
+        Diagnostic {
+            severity: ErrorType::RuntimeError,
+            code: Some(Code::RuntimeFailure),
+            message: self.message.clone(),
+            pos: self.pos,
+            len: self.len,
+        }
+    }
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.diagnostic())
+    }
+}
+
+/// Runs `entry` to completion, which takes no arguments, and gives back what it returns.
+/// `procs` have to have been through analysis, so that every instruction has a concrete type.
+pub fn run(procs: &[IRProc], globals: &[IRGlobal], entry: &str) -> Result<Value, RuntimeError> {
+    let mut interpreter = Interpreter::new(procs, globals);
+    let entry = interpreter.procs.get(entry).copied().ok_or_else(|| RuntimeError {
+        message: format!("There is no proc named {} to run", entry),
+        pos: 0,
+        len: 0,
+    })?;
+    interpreter.go(entry)
+}
+
+struct Interpreter<'p> {
+    procs: HashMap<&'p str, &'p IRProc>,
+    // where every label of every proc is in its body
+    labels: HashMap<&'p str, HashMap<&'p str, usize>>,
+    globals: HashMap<String, Value>,
+    // one copy of every str literal in the program, which each Push of it shares
+    strings: HashMap<&'p str, Rc<str>>,
+}
+
+/// A call that's still running
+struct Frame<'p> {
+    proc: &'p IRProc,
+    // the instruction after the one being run
+    next: usize,
+    stack: Vec<Value>,
+    variables: HashMap<String, Value>,
+}
+
+/// What to do once an instruction has been run
+enum Step<'p> {
+    Next,
+    Call(&'p IRProc, Vec<Value>),
+    Return(Value),
+}
+
+impl<'p> Interpreter<'p> {
+    fn new(procs: &'p [IRProc], globals: &[IRGlobal]) -> Self {
+        let labels = procs.iter()
+            .map(|proc| {
+                let labels = proc.body.iter()
+                    .enumerate()
+                    .filter_map(|(i, ins)| match &ins.contents.ins {
+                        InstructionType::Label(label) => Some((label.as_str(), i)),
+                        _ => None,
+                    })
+                    .collect();
+                (proc.name.as_str(), labels)
+            })
+            .collect();
+        let globals = globals.iter()
+            .map(|global| {
+                let value = global.value.as_ref()
+                    .and_then(|value| Value::parse(value, &global.typ))
+                    .unwrap_or_else(|| Value::zero(&global.typ));
+                (global.ir_name(), value)
+            })
+            .collect();
+        let strings = procs.iter()
+            .flat_map(|proc| &proc.body)
+            .filter_map(|ins| match (&ins.contents.ins, &ins.contents.typ) {
+                (InstructionType::Push(value), Type::Str) => Some((value.as_str(), Rc::from(value.as_str()))),
+                _ => None,
+            })
+            .collect();
+        Interpreter {
+            procs: procs.iter().map(|proc| (proc.name.as_str(), proc)).collect(),
+            labels,
+            globals,
+            strings,
+        }
+    }
+
+    /// Calls are kept on a stack of frames rather than made in Rust,
+    /// so that deep recursion in the program doesn't overflow the interpreter's own stack
+    fn go(&mut self, entry: &'p IRProc) -> Result<Value, RuntimeError> {
+        let mut frame = Frame::new(entry, vec![]);
+        // every call that's waiting on the one in `frame`
+        let mut callers = vec![];
+        loop {
+            let ins = match frame.proc.body.get(frame.next) {
+                Some(ins) => ins,
+                // the IR builder makes sure every path through a proc ends in a return
+                None => {
+                    let last = frame.proc.body.last();
+                    return Err(RuntimeError {
+                        message: format!("Got to the end of proc {} without it returning", frame.proc.name),
+                        pos: last.map_or(0, |ins| ins.pos),
+                        len: last.map_or(0, |ins| ins.len),
+                    });
+                }
+            };
+            frame.next += 1;
+            match self.step(&mut frame, ins)? {
+                Step::Next => (),
+                Step::Call(_, _) if callers.len() >= MAX_CALL_DEPTH => {
+                    return fail(ins, format!("Went more than {} calls deep, so the stack overflowed", MAX_CALL_DEPTH));
+                }
+                Step::Call(proc, args) => callers.push(std::mem::replace(&mut frame, Frame::new(proc, args))),
+                Step::Return(value) => match callers.pop() {
+                    Some(caller) => {
+                        frame = caller;
+                        frame.stack.push(value);
+                    }
+                    None => return Ok(value),
+                },
+            }
+        }
+    }
+
+    fn step(&mut self, frame: &mut Frame<'p>, ins: &Span<Instruction>) -> Result<Step<'p>, RuntimeError> {
+        use InstructionType::*;
+        let typ = &ins.contents.typ;
+        match &ins.contents.ins {
+            Push(value) if *typ == Type::Str => match self.strings.get(value.as_str()) {
+                Some(s) => frame.stack.push(Value::Str(s.clone())),
+                None => frame.stack.push(Value::Str(Rc::from(value.as_str()))),
+            },
+            Push(value) => match Value::parse(value, typ) {
+                Some(value) => frame.stack.push(value),
+                None => return fail(ins, format!("Can't push {:?} as a {}", value, typ)),
+            },
+            Load(name) => {
+                let value = self.variable(frame, name, ins)?.clone();
+                frame.stack.push(value);
+            }
+            Store(name) => {
+                let value = pop(frame, ins)?;
+                *self.variable(frame, name, ins)? = value;
+            }
+            StoreIndexed(name, indices) => {
+                let indices = pop_many(frame, indices.len(), ins)?;
+                let value = pop(frame, ins)?;
+                // each index picks out the array the next one goes into
+                let mut target = self.variable(frame, name, ins)?;
+                for index in &indices {
+                    target = match target {
+                        Value::Array(elements) => element(elements, index, ins)?,
+                        _ => return fail(ins, format!("{} isn't an array that deep", name)),
+                    };
+                }
+                *target = value;
+            }
+            // a variable declared without a value is zeroed, so the program can't see what was there before
+            Allocate(name) => {
+                let value = match pop(frame, ins)? {
+                    Value::Undefined => Value::zero(typ),
+                    value => value,
+                };
+                frame.variables.insert(name.clone(), value);
+            }
+            Pop => {
+                pop(frame, ins)?;
+            }
+
+            Index(_) => {
+                let index = pop(frame, ins)?;
+                let value = match pop(frame, ins)? {
+                    Value::Array(mut elements) => element(&mut elements, &index, ins)?.clone(),
+                    Value::Str(s) => match index.index().and_then(|i| s.as_bytes().get(i)) {
+                        Some(&byte) => Value::Nat(byte as u128),
+                        None => return fail(ins, out_of_bounds(&index, s.len())),
+                    },
+                    _ => return fail(ins, "Only an array or a str can be indexed".to_owned()),
+                };
+                frame.stack.push(value);
+            }
+            Field(field) => {
+                let value = match pop(frame, ins)? {
+                    Value::Struct(mut fields) => struct_field(&mut fields, field, ins)?.clone(),
+                    _ => return fail(ins, format!("Only a struct has fields, like {}", field)),
+                };
+                frame.stack.push(value);
+            }
+            StoreField(name, field) => {
+                let value = pop(frame, ins)?;
+                match self.variable(frame, name, ins)? {
+                    Value::Struct(fields) => *struct_field(fields, field, ins)? = value,
+                    _ => return fail(ins, format!("{} isn't a struct", name)),
+                }
+            }
+            Construct(count) => {
+                // a tuple is a struct whose fields are named by their positions
+                let names: Vec<_> = match typ {
+                    Type::Struct(s) => s.fields.iter().map(|(field, _)| field.clone()).collect(),
+                    Type::Tuple(elements) => (0..elements.len()).map(|i| i.to_string()).collect(),
+                    _ => return fail(ins, format!("Can't construct a {}", typ)),
+                };
+                let values = pop_many(frame, *count, ins)?;
+                frame.stack.push(Value::Struct(names.into_iter().zip(values).collect()));
+            }
+            Length => match pop(frame, ins)? {
+                Value::Str(s) => frame.stack.push(Value::Nat(s.len() as u128)),
+                _ => return fail(ins, "Only a str has a length".to_owned()),
+            },
+            Cast(target) => {
+                let value = pop(frame, ins)?;
+                match cast(&value, typ, target) {
+                    Some(value) => frame.stack.push(value),
+                    None => return fail(ins, format!("Can't cast a {} to a {}", typ, target)),
+                }
+            }
+
+            Branch(body, else_body) => {
+                let target = match pop(frame, ins)? {
+                    Value::Bool(true) => body,
+                    Value::Bool(false) => else_body,
+                    _ => return fail(ins, "Can only branch on a bool".to_owned()),
+                };
+                frame.next = self.label(frame.proc, target, ins)?;
+            }
+            Jump(label) => frame.next = self.label(frame.proc, label, ins)?,
+            Label(_) => (),
+
+            Call(name) => {
+                let proc = match self.procs.get(name.as_str()) {
+                    Some(proc) => *proc,
+                    None => return fail(ins, format!("There is no proc named {}", name)),
+                };
+                let args = pop_many(frame, proc.args.len(), ins)?;
+                if proc.body.is_empty() {
+                    frame.stack.push(external(proc, &args, ins)?);
+                } else {
+                    return Ok(Step::Call(proc, args));
+                }
+            }
+            ProcRef(name) => frame.stack.push(Value::Proc(name.clone())),
+            CallIndirect(count) => {
+                let args = pop_many(frame, *count, ins)?;
+                let proc = match pop(frame, ins)? {
+                    Value::Proc(name) => self.procs.get(name.as_str()).copied(),
+                    _ => None,
+                };
+                match proc {
+                    Some(proc) => return Ok(Step::Call(proc, args)),
+                    None => return fail(ins, "Can only call a reference to a proc".to_owned()),
+                }
+            }
+            // a proc that returns nothing has nothing of its own on the stack to return
+            Return => match typ {
+                Type::Undefined => return Ok(Step::Return(Value::Undefined)),
+                _ => return Ok(Step::Return(pop(frame, ins)?)),
+            },
+            Fail(message) => return fail(ins, message.clone()),
+            Print(newline) => {
+                let text = match pop(frame, ins)? {
+                    Value::Int(v) => v.to_string(),
+                    Value::Nat(v) => v.to_string(),
+                    Value::Float(v) => printf_g(v),
+                    Value::Bool(v) => v.to_string(),
+                    Value::Str(s) => s.to_string(),
+                    _ => return fail(ins, format!("Can't print a {}", typ)),
+                };
+                let mut stdout = io::stdout();
+                // nothing a program can do about stdout going away, so neither does the compiled one
+                let _ = write!(stdout, "{}{}", text, if *newline { "\n" } else { "" });
+                let _ = stdout.flush();
+            }
+
+            Negate(_) | Plus | Not => {
+                let operand = pop(frame, ins)?;
+                let value = operand.constant().and_then(|operand| opt::unary(&ins.contents.ins, typ, operand));
+                match value {
+                    Some(value) => frame.stack.push(value.into()),
+                    None => return fail(ins, overflow(&ins.contents.ins, typ)),
+                }
+            }
+            Add(_) | Subtract(_) | Multiply(_) | IntDivide | Divide | Power
+                | BitAnd | BitOr | BitXor | Shl(_) | Shr(_) => {
+                let right = pop(frame, ins)?;
+                let left = pop(frame, ins)?;
+                let value = match (left.constant(), right.constant()) {
+                    (Some(left), Some(right)) => opt::binary(&ins.contents.ins, typ, left, right),
+                    _ => None,
+                };
+                match value {
+                    Some(value) => frame.stack.push(value.into()),
+                    None if matches!(ins.contents.ins, Shl(_) | Shr(_)) => return fail(ins, opt::shift_range(typ)),
+                    None if matches!(ins.contents.ins, Power) && matches!(right, Value::Int(v) if v < 0) => {
+                        return fail(ins, "An integer can't be raised to a negative power".to_owned())
+                    }
+                    None if matches!(right, Value::Int(0) | Value::Nat(0)) => return fail(ins, "Division by zero".to_owned()),
+                    None => return fail(ins, overflow(&ins.contents.ins, typ)),
+                }
+            }
+            Compare(comparison) => {
+                // bools have an order too, where false comes before true like it does for codegen
+                let ordered = |value: Value| match value {
+                    Value::Bool(v) => Some(Constant::Nat(v as u128)),
+                    value => value.constant(),
+                };
+                let right = pop(frame, ins)?;
+                let left = pop(frame, ins)?;
+                let value = match (ordered(left), ordered(right)) {
+                    (Some(left), Some(right)) => opt::compare(comparison, left, right),
+                    _ => None,
+                };
+                match value {
+                    Some(value) => frame.stack.push(Value::Bool(value)),
+                    None => return fail(ins, format!("Can't compare values of type {}", typ)),
+                }
+            }
+        }
+        Ok(Step::Next)
+    }
+
+    /// A local variable, or failing that a global one
+    fn variable<'v>(
+        &'v mut self,
+        frame: &'v mut Frame<'p>,
+        name: &str,
+        ins: &Span<Instruction>,
+    ) -> Result<&'v mut Value, RuntimeError> {
+        match frame.variables.get_mut(name) {
+            Some(value) => Ok(value),
+            None => match self.globals.get_mut(name) {
+                Some(value) => Ok(value),
+                None => fail(ins, format!("There is no variable named {}", name)),
+            },
+        }
+    }
+
+    /// Where to carry on from after jumping to `label`
+    fn label(&self, proc: &IRProc, label: &str, ins: &Span<Instruction>) -> Result<usize, RuntimeError> {
+        match self.labels[proc.name.as_str()].get(label) {
+            Some(&i) => Ok(i),
+            None => fail(ins, format!("There is no label {} in proc {}", label, proc.name)),
+        }
+    }
+}
+
+impl<'p> Frame<'p> {
+    fn new(proc: &'p IRProc, args: Vec<Value>) -> Self {
+        Frame {
+            proc,
+            next: 0,
+            stack: vec![],
+            variables: proc.args.iter().cloned().zip(args).collect(),
+        }
+    }
+}
+
+fn fail<T>(ins: &Span<Instruction>, message: String) -> Result<T, RuntimeError> {
+    Err(RuntimeError {
+        message,
+        pos: ins.pos,
+        len: ins.len,
+    })
+}
+
+fn pop(frame: &mut Frame, ins: &Span<Instruction>) -> Result<Value, RuntimeError> {
+    match frame.stack.pop() {
+        Some(value) => Ok(value),
+        None => fail(ins, "There's nothing on the stack".to_owned()),
+    }
+}
+
+/// The top `count` values, in the order they were pushed
+fn pop_many(frame: &mut Frame, count: usize, ins: &Span<Instruction>) -> Result<Vec<Value>, RuntimeError> {
+    if frame.stack.len() < count {
+        return fail(ins, format!("Needs {} values on the stack, but there are only {}", count, frame.stack.len()));
+    }
+    Ok(frame.stack.split_off(frame.stack.len() - count))
+}
+
+fn element<'v>(elements: &'v mut [Value], index: &Value, ins: &Span<Instruction>) -> Result<&'v mut Value, RuntimeError> {
+    let len = elements.len();
+    match index.index().and_then(move |i| elements.get_mut(i)) {
+        Some(element) => Ok(element),
+        None => fail(ins, out_of_bounds(index, len)),
+    }
+}
+
+fn out_of_bounds(index: &Value, len: usize) -> String {
+    let index = index.constant().map_or_else(|| format!("{:?}", index), |index| index.to_string());
+    format!("Index {} is out of bounds, the length is {}", index, len)
+}
+
+fn struct_field<'v>(
+    fields: &'v mut [(String, Value)],
+    field: &str,
+    ins: &Span<Instruction>,
+) -> Result<&'v mut Value, RuntimeError> {
+    match fields.iter_mut().find(|(name, _)| name == field) {
+        Some((_, value)) => Ok(value),
+        None => fail(ins, format!("There is no field named {}", field)),
+    }
+}
+
+/// Conversions between numbers, which like codegen wrap an integer that doesn't fit
+fn cast(value: &Value, from: &Type, to: &Type) -> Option<Value> {
+    if from == to {
+        return Some(value.clone());
+    }
+    let wrapped = |v: i128| match to {
+        t if t.is_signed() => opt::fit_int(None, v, to, true).map(Value::Int),
+        _ => opt::fit_nat(None, v as u128, to, true).map(Value::Nat),
+    };
+    match value {
+        Value::Int(v) if to.is_integer() => wrapped(*v),
+        Value::Nat(v) if to.is_integer() => wrapped(*v as i128),
+        Value::Int(v) if to.is_float() => Some(Value::Float(opt::fit_float(*v as f64, to))),
+        Value::Nat(v) if to.is_float() => Some(Value::Float(opt::fit_float(*v as f64, to))),
+        Value::Float(v) if to.is_float() => Some(Value::Float(opt::fit_float(*v, to))),
+        Value::Float(v) if to.is_signed() => wrapped(*v as i128),
+        Value::Float(v) if to.is_integer() => wrapped(*v as u128 as i128),
+        _ => None,
+    }
+}
+
+/// Procs without a body are written in C, so only the ones the interpreter
+/// can stand in for itself can be called
+fn external(proc: &IRProc, args: &[Value], ins: &Span<Instruction>) -> Result<Value, RuntimeError> {
+    match (proc.name.as_str(), args) {
+        ("puts", [Value::Str(s)]) => {
+            println!("{}", s);
+            // what glibc's gives back
+            Ok(Value::Int((s.len() as i128 + 1).min(i32::MAX as i128)))
+        }
+        ("print_int", [Value::Int(v)]) => {
+            println!("{}", v);
+            Ok(Value::zero(&proc.ret_type))
+        }
+        ("print_int", [Value::Nat(v)]) => {
+            println!("{}", v);
+            Ok(Value::zero(&proc.ret_type))
+        }
+        _ => fail(ins, format!("{} is an external proc, which can only be called from compiled code", proc.name)),
+    }
+}
+
+/// A float written the way printf's %g writes it, so the interpreter prints what compiled code would:
+/// six significant digits without trailing zeros, in scientific notation if the exponent is below -4 or over 5
+fn printf_g(v: f64) -> String {
+    if v.is_nan() {
+        return "nan".to_owned();
+    }
+    if v.is_infinite() {
+        return if v > 0.0 { "inf" } else { "-inf" }.to_owned();
+    }
+    let trim = |s: &str| if s.contains('.') { s.trim_end_matches('0').trim_end_matches('.').to_owned() } else { s.to_owned() };
+    // rounding to six digits first, since that can carry into the exponent
+    let scientific = format!("{:.5e}", v);
+    let (mantissa, exponent) = scientific.split_once('e').unwrap();
+    let exponent: i32 = exponent.parse().unwrap();
+    if !(-4..6).contains(&exponent) {
+        format!("{}e{}{:02}", trim(mantissa), if exponent < 0 { '-' } else { '+' }, exponent.abs())
+    } else {
+        trim(&format!("{:.*}", (5 - exponent) as usize, v))
+    }
+}
+
+/// Why an operation on numbers couldn't be done, other than dividing by zero
+fn overflow(op: &InstructionType, typ: &Type) -> String {
+    use InstructionType::*;
+    let (operation, wrapping) = match op {
+        Add(_) => ("addition", Some("+~")),
+        Subtract(_) => ("subtraction", Some("-~")),
+        Multiply(_) => ("multiplication", Some("*~")),
+        Negate(_) => ("negation", Some("-~")),
+        Power => ("exponentiation", None),
+        _ => ("division", None),
+    };
+    match wrapping {
+        Some(wrapping) => format!("The result of this {} doesn't fit in a {}, use {} if it should wrap around", operation, typ, wrapping),
+        None => format!("The result of this {} doesn't fit in a {}", operation, typ),
+    }
+}
@@ -2,26 +2,71 @@
 //! Elgin IR is the intermediate representation which is then used for type analysis in analysis.rs
 //! It is then converted into LLVM IR in the codegen phase
 
-use crate::errors::{Logger, Span};
-use crate::astgen::Node;
+use crate::cache::Cached;
+use crate::errors::{self, did_you_mean, Code, Logger, Span};
+use crate::astgen::{Node, ProcDecl};
 use crate::types::Type;
+use crate::symbol::Symbol;
+use crate::opt;
 
 use std::collections::HashMap;
 use std::fmt;
 
-type Scope = HashMap<String, Type>;
+// source name -> (name used in the IR, type)
+type Bindings = HashMap<String, (String, Type)>;
 type IRResult = Option<Vec<Span<Instruction>>>;
 
 pub struct IRBuilder<'i> {
-    ast: &'i [Span<Node>],
+    pub ast: &'i [Span<Node>],
     pub available_type_var: usize,
-    available_label_id: usize,
-    pub scopes: Vec<Scope>,
+    // numbers the hidden variables that shadowing and the like need, across the whole program
+    available_id: usize,
+    // numbers the ifs, loops and such of the current proc, which name their labels
+    constructs: usize,
+    pub scopes: Vec<Bindings>,
+    // where each parameter and variable of the current proc was declared, by its IR name,
+    // for pointing at the one a new variable shadows
+    declared: HashMap<String, (&'static str, usize, usize)>,
+    // the value of every const declared in a proc, by its IR name, which is pushed wherever it's used
+    consts: HashMap<String, String>,
     pub procs: Vec<IRProc>, 
-    pub consts: HashMap<String, Span<Node>>,
+    pub globals: Vec<IRGlobal>,
+    pub structs: HashMap<String, Type>,
+    // whether to warn about unused and shadowed variables and unused procs
+    pub warnings: bool,
+    // whether analysis explains what it's doing on stderr
+    pub trace: bool,
+    // what used files loaded from the cache declare, which is only ever added to what's built
+    pub cached: Cached,
 
-    current_loop_entrance_id: usize,
-    current_after_loop_id: usize,
+    // (entrance, exit) labels of every loop enclosing the current instruction
+    loops: Vec<(String, String)>,
+    pub logger: Logger,
+}
+
+/// A module-level const or var, whose starting value is worked out at compile time
+#[derive(Debug, Clone)]
+pub struct IRGlobal {
+    pub name: String,
+    pub typ: Type,
+    // zeroed if there isn't one
+    pub value: Option<String>,
+    pub constant: bool,
+}
+
+impl IRGlobal {
+    /// What it's called in the IR, which can't clash with any local variable
+    pub fn ir_name(&self) -> String {
+        format!("global.{}", self.name)
+    }
+
+    fn kind(&self) -> &'static str {
+        if self.constant {
+            "const"
+        } else {
+            "global variable"
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -31,6 +76,7 @@ pub struct IRProc {
     pub arg_types: Vec<Type>,
     pub ret_type: Type,
     pub body: Vec<Span<Instruction>>,
+    pub doc: Option<String>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
@@ -48,27 +94,47 @@ pub enum InstructionType {
     Push(String),     // pushes an immediate value to the stack
     Load(String),     // pushes a variable's contents to the stack
     Store(String),    // pops a value from the stack into a variable
-    StoreIndexed(String), // pops an index and a value and stores to that index of the variable
+    // pops an index of each of these types, outermost first, and a value and stores to that element of the variable
+    StoreIndexed(String, Vec<Type>),
     Allocate(String), // creates a new local variable and gives it the top value of the stack
+    Pop,              // throws away the value on the stack, like the result of a call made as a statement
 
-    Index,            // pops an index and an object and indexes in
+    Index(Type),      // pops an index of this type, which can be any integer, and an object and indexes in
+    Field(String),    // pops a struct and pushes one of its fields
+    StoreField(String, String), // pops a value and stores it to a field of the variable
+    Construct(usize), // pops that many values and builds a struct out of them
+    Length,           // pops a str and pushes how many bytes are in it
+    Cast(Type),       // converts the value on the stack, which is of the instruction's type, to this one
 
-    Branch(usize, usize), // conditional branch with if body and else body
-    Jump(usize),          // unconditional jump
+    Branch(String, String), // conditional branch with if body and else body
+    Jump(String),           // unconditional jump
 
-    Label(usize), // location for jumps and branches
+    Label(String), // location for jumps and branches, named after the construct it's part of like while3.cond
 
     Call(String), // call another proc from this one
+    ProcRef(String),     // pushes a reference to a proc, of the instruction's type
+    CallIndirect(usize), // pops that many arguments and then a proc reference, and calls it with them
     Return,       // return to the calling proc with the value on the stack
+    Fail(String), // stops the program with this message, pointing at where the instruction came from
+    Print(bool),  // pops a value of the instruction's type and writes it to stdout, with a newline if true
 
     Negate(bool), // whether or not wrapping is enabled
+    Plus,         // leaves the number on the stack as it is, it's only there to be checked to be one
+    Not,          // logical not of the bool on the stack
     Add(bool), 
     Subtract(bool),
     Multiply(bool),
     IntDivide,
     Divide,
+    Power,            // raises the number under the top of the stack to the power of the top one
+    BitAnd,
+    BitOr,
+    BitXor,
+    // the type of the amount, which can be any integer, the instruction's own type is that of what's shifted
+    Shl(Type),
+    Shr(Type),        // arithmetic for a signed integer, logical for an unsigned one
 
-    Compare(CompareType),
+    Compare(CompareType), // pops two values of the instruction's type and pushes a bool
 }
 
 
@@ -84,108 +150,524 @@ impl fmt::Debug for Instruction {
     }
 }
 
+/// How an instruction is written in a listing, a label being just its name
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.ins, &self.typ) {
+            (InstructionType::Label(l), _) => write!(f, "{}:", l),
+            // strings can hold anything, newlines included
+            (InstructionType::Push(v), t @ (Type::Str | Type::StrLiteral)) => write!(f, "push {:?}: {:?}", v, t),
+            (i, t) => write!(f, "{}: {:?}", i, t),
+        }
+    }
+}
+
+/// One instruction per line, without spans, so that it stays the same as long as the IR does
+impl fmt::Display for IRProc {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.signature())?;
+        if self.body.is_empty() {
+            return writeln!(f);
+        }
+        writeln!(f, " {{")?;
+        for ins in &self.body {
+            // labels stand out from the instructions under them
+            let indent = if matches!(ins.contents.ins, InstructionType::Label(_)) { "  " } else { "    " };
+            writeln!(f, "{}{}", indent, ins.contents)?;
+        }
+        writeln!(f, "}}")
+    }
+}
+
+impl IRProc {
+    /// How the proc would be declared, without its body
+    pub fn signature(&self) -> String {
+        let args: Vec<_> = self.args.iter()
+            .zip(&self.arg_types)
+            .map(|(arg, typ)| match typ {
+                // not written out, and not inferred yet
+                Type::Variable(_) => arg.clone(),
+                typ => format!("{}: {:?}", arg, typ),
+            })
+            .collect();
+        format!("proc {}({}): {:?}", self.name, args.join(", "), self.ret_type)
+    }
+
+    pub fn to_text(&self) -> String {
+        self.to_string()
+    }
+
+    /// Reads back a listing written by `to_text`. Spans aren't part of it so every instruction
+    /// comes back at position 0, and structs only keep their name, not their fields.
+    /// Docs aren't part of it either.
+    pub fn from_text(text: &str) -> Result<IRProc, String> {
+        let mut lines = text.lines().filter(|line| !line.trim().is_empty());
+        let header = lines.next().ok_or("Expected a proc, but the text is empty")?;
+        let (header, has_body) = match header.strip_suffix(" {") {
+            Some(header) => (header, true),
+            None => (header, false),
+        };
+        let signature = header.strip_prefix("proc ").ok_or_else(|| format!("Expected a proc, found {}", header))?;
+        let (name, rest) = signature.split_once('(').ok_or_else(|| format!("Expected arguments after {}", signature))?;
+        let (args, ret_type) = rest.rsplit_once("): ").ok_or_else(|| format!("Expected a return type in {}", header))?;
+        let mut proc = IRProc {
+            name: name.to_owned(),
+            args: vec![],
+            arg_types: vec![],
+            ret_type: type_from_text(ret_type)?,
+            body: vec![],
+            doc: None,
+        };
+        for arg in args.split(", ").filter(|arg| !arg.is_empty()) {
+            let (arg, typ) = arg.split_once(": ").ok_or_else(|| format!("Expected a type for argument {}", arg))?;
+            proc.args.push(arg.to_owned());
+            proc.arg_types.push(type_from_text(typ)?);
+        }
+        if !has_body {
+            return Ok(proc);
+        }
+
+        for line in lines {
+            let line = line.trim();
+            if line == "}" {
+                return Ok(proc);
+            }
+            let ins = match line.strip_suffix(':') {
+                Some(label) => Instruction {
+                    ins: InstructionType::Label(label_from_text(label)?),
+                    typ: Type::Undefined,
+                },
+                None => {
+                    // types never have a ": " in them, unlike the string a push might have
+                    let (ins, typ) = line.rsplit_once(": ").ok_or_else(|| format!("Expected a type for {}", line))?;
+                    let typ = type_from_text(typ)?;
+                    Instruction {
+                        ins: instruction_from_text(ins, &typ)?,
+                        typ,
+                    }
+                }
+            };
+            proc.body.push(spanned(ins, 0, 0));
+        }
+        Err(format!("Proc {} is never closed with a }}", proc.name))
+    }
+}
+
+fn label_from_text(text: &str) -> Result<String, String> {
+    if text.is_empty() || text.contains(' ') {
+        return Err(format!("Expected a label like while0.cond, found {}", text));
+    }
+    Ok(text.to_owned())
+}
+
+fn instruction_from_text(text: &str, typ: &Type) -> Result<InstructionType, String> {
+    use InstructionType::*;
+    let (op, operand) = text.split_once(' ').unwrap_or((text, ""));
+    let name = || operand.to_owned();
+    let count = || operand.parse().map_err(|_| format!("Expected a number after {}, found {}", op, operand));
+    let wrap = match operand {
+        "" => Ok(false),
+        "wrapping" => Ok(true),
+        _ => Err(format!("Expected wrapping or nothing after {}, found {}", op, operand)),
+    };
+    Ok(match op {
+        "push" if matches!(typ, Type::Str | Type::StrLiteral) => Push(unquote(operand)?),
+        "push" => Push(name()),
+        "load" => Load(name()),
+        "store" => Store(name()),
+        "store_indexed" => {
+            let (var, indices) = operand.split_once(' ').ok_or_else(|| format!("Expected a variable and index types, found {}", operand))?;
+            StoreIndexed(var.to_owned(), indices.split(' ').map(type_from_text).collect::<Result<_, _>>()?)
+        }
+        "allocate" => Allocate(name()),
+        "pop" => Pop,
+
+        "index" => Index(type_from_text(operand)?),
+        "field" => Field(name()),
+        "store_field" => {
+            let (var, field) = operand.split_once('.').ok_or_else(|| format!("Expected var.field, found {}", operand))?;
+            StoreField(var.to_owned(), field.to_owned())
+        }
+        "construct" => Construct(count()?),
+        "length" => Length,
+        "cast" => Cast(type_from_text(operand)?),
+
+        "branch" => {
+            let (body, else_body) = operand.split_once(' ').ok_or_else(|| format!("Expected two labels, found {}", operand))?;
+            Branch(label_from_text(body)?, label_from_text(else_body)?)
+        }
+        "jump" => Jump(label_from_text(operand)?),
+        "label" => Label(label_from_text(operand)?),
+
+        "call" => Call(name()),
+        "proc_ref" => ProcRef(name()),
+        "call_indirect" => CallIndirect(count()?),
+        "fail" => Fail(unquote(operand)?),
+        "print" => Print(false),
+        "println" => Print(true),
+        "return" => Return,
+
+        "negate" => Negate(wrap?),
+        "plus" => Plus,
+        "not" => Not,
+        "add" => Add(wrap?),
+        "subtract" => Subtract(wrap?),
+        "multiply" => Multiply(wrap?),
+        "int_divide" => IntDivide,
+        "divide" => Divide,
+        "power" => Power,
+        "bit_and" => BitAnd,
+        "bit_or" => BitOr,
+        "bit_xor" => BitXor,
+        "shift_left" => Shl(type_from_text(operand)?),
+        "shift_right" => Shr(type_from_text(operand)?),
+
+        "compare" => Compare(match operand {
+            "EQ" => CompareType::EQ,
+            "NE" => CompareType::NE,
+            "GT" => CompareType::GT,
+            "LT" => CompareType::LT,
+            "GE" => CompareType::GE,
+            "LE" => CompareType::LE,
+            _ => return Err(format!("There is no comparison named {}", operand)),
+        }),
+        _ => return Err(format!("There is no instruction named {}", op)),
+    })
+}
+
+/// The reverse of how the Debug impl of Type writes it
+pub fn type_from_text(text: &str) -> Result<Type, String> {
+    Ok(match text {
+        "intLiteral" => Type::IntLiteral,
+        "floatLiteral" => Type::FloatLiteral,
+        "strLiteral" => Type::StrLiteral,
+
+        "i8" => Type::I8,
+        "i16" => Type::I16,
+        "i32" => Type::I32,
+        "i64" => Type::I64,
+        "i128" => Type::I128,
+
+        "n8" => Type::N8,
+        "n16" => Type::N16,
+        "n32" => Type::N32,
+        "n64" => Type::N64,
+        "n128" => Type::N128,
+
+        "f32" => Type::F32,
+        "f64" => Type::F64,
+        "f128" => Type::F128,
+
+        "bool" => Type::Bool,
+        "str" => Type::Str,
+
+        "undefined" => Type::Undefined,
+        "noreturn" => Type::NoReturn,
+
+        _ if text.starts_with('*') => Type::Ptr(Box::new(type_from_text(&text[1..])?)),
+        _ if text.starts_with('[') => {
+            let (size, content) = text[1..].split_once(']').ok_or_else(|| format!("Expected a ] in {}", text))?;
+            let size = size.parse().map_err(|_| format!("Expected an array length, found {}", size))?;
+            Type::Array(size, Box::new(type_from_text(content)?))
+        }
+        _ if text.starts_with("proc(") => {
+            let close = closing_paren(&text[4..]).ok_or_else(|| format!("Expected a ) in {}", text))? + 4;
+            let ret = text[close + 1..].strip_prefix(':').ok_or_else(|| format!("Expected a return type in {}", text))?;
+            Type::new_proc(types_from_text(&text[5..close])?, type_from_text(ret)?)
+        }
+        _ if text.starts_with('(') && closing_paren(text) == Some(text.len() - 1) => {
+            Type::Tuple(types_from_text(&text[1..text.len() - 1])?)
+        }
+        _ if text.starts_with('$') => {
+            Type::Variable(text[1..].parse().map_err(|_| format!("Expected a type variable, found {}", text))?)
+        }
+        _ if !text.is_empty() && text.chars().all(|c| c.is_alphanumeric() || c == '_') => {
+            Type::new_struct(text.to_owned(), vec![])
+        }
+        _ => return Err(format!("There is no type named {}", text)),
+    })
+}
+
+/// Types separated by commas, only the ones that aren't inside a nested tuple or proc type
+fn types_from_text(text: &str) -> Result<Vec<Type>, String> {
+    if text.is_empty() {
+        return Ok(vec![]);
+    }
+    let mut types = vec![];
+    let (mut depth, mut start) = (0, 0);
+    for (i, c) in text.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ',' if depth == 0 => {
+                types.push(type_from_text(&text[start..i])?);
+                start = i + 1;
+            }
+            _ => (),
+        }
+    }
+    types.push(type_from_text(&text[start..])?);
+    Ok(types)
+}
+
+/// Where the ) that closes the ( `text` starts with is
+fn closing_paren(text: &str) -> Option<usize> {
+    let mut depth = 0;
+    for (i, c) in text.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' if depth == 1 => return Some(i),
+            ')' => depth -= 1,
+            _ => (),
+        }
+    }
+    None
+}
+
+/// Undoes the escaping Debug does to a string
+pub fn unquote(text: &str) -> Result<String, String> {
+    let inner = text.strip_prefix('"')
+        .and_then(|t| t.strip_suffix('"'))
+        .ok_or_else(|| format!("Expected a quoted string, found {}", text))?;
+    let mut res = String::new();
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            res.push(c);
+            continue;
+        }
+        res.push(match chars.next() {
+            Some('n') => '\n',
+            Some('t') => '\t',
+            Some('r') => '\r',
+            Some('0') => '\0',
+            Some(c @ ('\\' | '"' | '\'')) => c,
+            Some('u') => {
+                let code: String = chars.by_ref().skip(1).take_while(|c| *c != '}').collect();
+                u32::from_str_radix(&code, 16).ok()
+                    .and_then(char::from_u32)
+                    .ok_or_else(|| format!("There is no character \\u{{{}}}", code))?
+            }
+            c => return Err(format!("There is no escape \\{}", c.map(String::from).unwrap_or_default())),
+        });
+    }
+    Ok(res)
+}
+
+impl fmt::Display for InstructionType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use InstructionType::*;
+        let wrapping = |wrap: &bool| if *wrap { " wrapping" } else { "" };
+        match self {
+            Push(v) => write!(f, "push {}", v),
+            Load(v) => write!(f, "load {}", v),
+            Store(v) => write!(f, "store {}", v),
+            StoreIndexed(v, indices) => {
+                let indices: Vec<_> = indices.iter().map(|t| format!("{:?}", t)).collect();
+                write!(f, "store_indexed {} {}", v, indices.join(" "))
+            }
+            Allocate(v) => write!(f, "allocate {}", v),
+            Pop => write!(f, "pop"),
+
+            Index(typ) => write!(f, "index {:?}", typ),
+            Field(field) => write!(f, "field {}", field),
+            StoreField(v, field) => write!(f, "store_field {}.{}", v, field),
+            Construct(count) => write!(f, "construct {}", count),
+            Length => write!(f, "length"),
+            Cast(typ) => write!(f, "cast {:?}", typ),
+
+            Branch(body, else_body) => write!(f, "branch {} {}", body, else_body),
+            Jump(l) => write!(f, "jump {}", l),
+            Label(l) => write!(f, "label {}", l),
+
+            Call(name) => write!(f, "call {}", name),
+            ProcRef(name) => write!(f, "proc_ref {}", name),
+            CallIndirect(count) => write!(f, "call_indirect {}", count),
+            Return => write!(f, "return"),
+            Fail(message) => write!(f, "fail {:?}", message),
+            Print(newline) => write!(f, "print{}", if *newline { "ln" } else { "" }),
+
+            Negate(wrap) => write!(f, "negate{}", wrapping(wrap)),
+            Plus => write!(f, "plus"),
+            Not => write!(f, "not"),
+            Add(wrap) => write!(f, "add{}", wrapping(wrap)),
+            Subtract(wrap) => write!(f, "subtract{}", wrapping(wrap)),
+            Multiply(wrap) => write!(f, "multiply{}", wrapping(wrap)),
+            IntDivide => write!(f, "int_divide"),
+            Divide => write!(f, "divide"),
+            Power => write!(f, "power"),
+            BitAnd => write!(f, "bit_and"),
+            BitOr => write!(f, "bit_or"),
+            BitXor => write!(f, "bit_xor"),
+            Shl(amount) => write!(f, "shift_left {:?}", amount),
+            Shr(amount) => write!(f, "shift_right {:?}", amount),
+
+            Compare(c) => write!(f, "compare {:?}", c),
+        }
+    }
+}
+
+/// Procs every program can call without declaring them
+fn builtin_procs() -> Vec<IRProc> {
+    vec![IRProc {
+        name: "puts".to_owned(),
+        args: vec!["s".to_owned()],
+        arg_types: vec![Type::Str],
+        ret_type: Type::I32,
+        body: vec![],
+        doc: None,
+    }]
+}
+
 pub fn spanned(ins: Instruction, pos: usize, len: usize) -> Span<Instruction> {
     Span {
-        contents: ins.clone(),
+        contents: ins,
         pos,
         len,
     }
 }
 
 impl<'i> IRBuilder<'i> {
-    pub fn new(ast: &'i [Span<Node>], available_type_var: usize) -> Self {
+    pub fn new(ast: &'i [Span<Node>], available_type_var: usize, logger: &Logger) -> Self {
         IRBuilder {
             ast,
             available_type_var,
-            available_label_id: 0,
+            available_id: 0,
+            constructs: 0,
             scopes: vec![],
-            procs: vec![],
+            declared: HashMap::new(),
             consts: HashMap::new(),
+            procs: vec![],
+            globals: vec![],
+            structs: HashMap::new(),
+            warnings: true,
+            trace: false,
+            cached: Cached::default(),
 
-            current_loop_entrance_id: 0,
-            current_after_loop_id: 0,
+            loops: vec![],
+            logger: logger.clone(),
         }
     }
 
-    pub fn go(&mut self) -> Option<&Vec<IRProc>> {
+    /// Returns the procs along with how many errors were found,
+    /// a proc whose body fails to build is left as just its declaration
+    pub fn go(&mut self) -> (&Vec<IRProc>, usize) {
+        let before = self.logger.error_count();
         self.build_header();
-        // just declare all functions + constants
+        // already analyzed, so they're just there to be used
+        self.procs.extend(self.cached.procs.iter().cloned());
+        self.globals.extend(self.cached.globals.iter().cloned());
+        for typ in &self.cached.structs {
+            if let Type::Struct(s) = typ {
+                self.structs.insert(s.name.clone(), typ.clone());
+            }
+        }
+        // just declare all functions + constants,
+        // globals are evaluated right away so that every proc can use them
+        let mut declared: HashMap<String, (usize, usize)> = HashMap::new();
+        let mut to_build = vec![];
         for node in self.ast {
-            match node.clone().contents {
+            match &node.contents {
                 Node::ConstStatement {
                     name,
                     typ,
                     value,
                 } => {
-                    self.const_statement(name, typ, value, node.pos, node.len)?;
+                    self.global_statement(name.clone(), typ.clone(), value.clone(), true, node.pos, node.len);
                 }
-                Node::ProcStatement {
+                Node::VarStatement {
                     name,
-                    args,
-                    arg_types,
-                    ret_type,
-                    ..
+                    typ,
+                    value,
                 } => {
-                    self.procs.push(IRProc {
+                    self.global_statement(name.clone(), typ.clone(), value.clone(), false, node.pos, node.len);
+                }
+                Node::ProcStatement(decl) => {
+                    let ProcDecl {
                         name,
                         args,
                         arg_types,
                         ret_type,
+                        doc,
+                        ..
+                    } = &**decl;
+                    if let Some(&(first_pos, first_len)) = declared.get(name) {
+                        self.logger.name_error(Code::Redefinition, format!("There is already a proc named {}", name).as_str(), node.pos, node.len);
+                        self.logger.note(format!("{} is first defined here", name).as_str(), first_pos, first_len);
+                        continue;
+                    }
+                    if self.is_cached(name) {
+                        self.logger.name_error(Code::Redefinition, format!("There is already a proc named {}", name).as_str(), node.pos, node.len);
+                        continue;
+                    }
+                    if self.procs.iter().any(|proc| &proc.name == name) {
+                        self.logger.name_error(Code::Redefinition, format!("{} is already a builtin proc", name).as_str(), node.pos, node.len);
+                        continue;
+                    }
+                    declared.insert(name.clone(), (node.pos, node.len));
+                    to_build.push(node);
+                    self.procs.push(IRProc {
+                        name: name.clone(),
+                        args: args.clone(),
+                        arg_types: arg_types.clone(),
+                        ret_type: ret_type.clone(),
                         body: vec![],
+                        doc: doc.clone(),
                     });
                 }
+                Node::StructDecl {
+                    name,
+                    fields,
+                } => {
+                    self.structs.insert(name.clone(), Type::new_struct(name.clone(), fields.clone()));
+                }
+                Node::VarTupleStatement { .. } => {
+                    self.logger.syntax_error(Code::MisplacedStatement, "A tuple can only be unpacked inside a proc", node.pos, node.len);
+                }
+                // the files they point at have already been loaded
+                Node::UseStatement { .. } => (),
                 n => {
-                    Logger::syntax_error(
+                    self.logger.syntax_error(
+                        Code::MisplacedStatement,
                         format!("A node of type {:?} is not allowed at the top level of a module", n).as_str(),
                         node.pos,
                         node.len,
                     );
-                    return None
                 }
             }
         }
-        // then actually generate code
-        for node in self.ast {
-            match node.clone().contents {
-                Node::ConstStatement {
-                    name,
-                    typ,
-                    value,
-                } => {
-                    self.const_statement(name, typ, value, node.pos, node.len);
-                }
-                Node::ProcStatement {
-                    name,
-                    args,
-                    arg_types,
-                    ret_type,
-                    body,
-                } => {
-                    let pstat = self.proc_statement(
-                        name, args, arg_types, ret_type, body, node.pos, node.len,
-                    )?;
-                    // FIXME this is a temporary workaround (procs should really be a hashmap)
-                    for (i, proc) in self.procs.iter().enumerate() {
-                        if proc.name == pstat.name {
-                            self.procs[i] = pstat;
-                            break;
-                        }
+        // then actually generate code, now that every proc can be called from any other,
+        // globals are already done and anything else was reported in the first pass
+        for node in to_build {
+            if let Node::ProcStatement(decl) = &node.contents {
+                let pstat = match self.proc_statement(decl, node.pos, node.len) {
+                    Some(pstat) => pstat,
+                    None => continue,
+                };
+                // FIXME this is a temporary workaround (procs should really be a hashmap)
+                for (i, proc) in self.procs.iter().enumerate() {
+                    if proc.name == pstat.name {
+                        self.procs[i] = pstat;
+                        break;
                     }
                 }
-                _ => unreachable!(),
             }
         }
-        Some(&self.procs)
+        (&self.procs, self.logger.error_count() - before)
     }
 
     fn build_header(&mut self) {
-        self.procs.push(IRProc {
-            name: "puts".to_owned(),
-            args: vec!["s".to_owned()],
-            arg_types: vec![Type::Ptr(Box::new(Type::I8))],
-            ret_type: Type::I32,
-            body: vec![],
-        });
+        self.procs.extend(builtin_procs());
+    }
+
+    /// Whether a proc came from the cache, and so doesn't need to be analyzed
+    pub fn is_cached(&self, name: &str) -> bool {
+        self.cached.procs.iter().any(|proc| proc.name == name)
+    }
+
+    /// Every proc the program declares itself, which always come after the builtins
+    pub fn declared_procs(&self) -> &[IRProc] {
+        &self.procs[builtin_procs().len().min(self.procs.len())..]
     }
 
     fn node(&mut self, node: &Span<Node>) -> IRResult { 
@@ -201,17 +683,20 @@ impl<'i> IRBuilder<'i> {
             } => self.call(name, args, node.pos, node.len)?,
             InfixOp {
                 op,
+                op_span,
                 left,
                 right,
-            } => self.infix_op(op, left, right, node.pos, node.len)?,
+            } => self.infix_op(op, op_span, left, right, node.pos, node.len)?,
             PrefixOp {
                 op,
                 right,
-            } => self.prefix_op(op, right, node.pos, node.len)?,
+                ..
+            } => self.prefix_op(op, right)?,
             PostfixOp {
                 op,
+                op_span,
                 left,
-            } => self.postfix_op(op, left, node.pos, node.len)?,
+            } => self.postfix_op(op, op_span, left, node.pos, node.len)?,
             IndexOp {
                 object,
                 index,
@@ -219,15 +704,42 @@ impl<'i> IRBuilder<'i> {
             VariableRef {
                 name,
             } => self.variable_ref(name, node.pos, node.len)?,
+            FieldAccess {
+                object,
+                field,
+            } => self.field_access(object, field, node.pos, node.len)?,
+            Cast {
+                value,
+                typ,
+            } => self.cast(value, typ, node.pos, node.len)?,
+            Tuple {
+                elements,
+            } => self.tuple(elements, node.pos, node.len)?,
             IfStatement {
                 condition,
                 body,
                 else_body,
             } => self.if_statement(condition, body, else_body, node.pos, node.len)?,
+            IfExpression {
+                condition,
+                body,
+                else_body,
+            } => self.if_expression(condition, body, else_body, node.pos, node.len)?,
             WhileStatement {
                 condition,
                 body,
             } => self.while_statement(condition, body, node.pos, node.len)?,
+            MatchStatement {
+                scrutinee,
+                arms,
+                default,
+            } => self.match_statement(scrutinee, arms, default, node.pos, node.len)?,
+            ForStatement {
+                var,
+                start,
+                end,
+                body,
+            } => self.for_statement(var, start, end, body, node.pos, node.len)?,
             Block {
                 nodes,
             } => self.block(nodes, node.pos, node.len)?,
@@ -236,28 +748,38 @@ impl<'i> IRBuilder<'i> {
                 typ,
                 value,
             } => self.var_statement(name, typ, value, node.pos, node.len)?,
-            ConstStatement { .. } => {
-                Logger::syntax_error("Found const statement not at top level. This feature is NYI.", node.pos, node.len);
-                return None;
-            },
+            VarTupleStatement {
+                names,
+                value,
+            } => self.var_tuple_statement(names, value, node.pos, node.len)?,
+            ConstStatement {
+                name,
+                typ,
+                value,
+            } => self.const_statement(name, typ, value, node.pos, node.len)?,
             AssignStatement {
                 name,
                 value,
             } => self.assign_statement(name, value, node.pos, node.len)?,
-            IndexedAssignStatement {
-                name,
+            IndexAssignStatement {
+                object,
                 index,
                 value,
-            } => self.indexed_assign_statement(name, index, value, node.pos, node.len)?,
+            } => self.index_assign_statement(*object, *index, value, node.pos, node.len)?,
+            FieldAssignStatement {
+                object,
+                field,
+                value,
+            } => self.field_assign_statement(*object, field, value, node.pos, node.len)?,
+            StructDecl { .. } => {
+                self.logger.syntax_error(Code::MisplacedStatement, "Found struct declaration not at top level", node.pos, node.len);
+                return None;
+            },
             ReturnStatement {
                 val,
             } => self.return_statement(val, node.pos, node.len)?,
-            BreakStatement {
-
-            } => self.break_statement(node.pos, node.len)?,
-            ContinueStatement {
-
-            } => self.continue_statement(node.pos, node.len)?,
+            BreakStatement => self.break_statement(node.pos, node.len)?,
+            ContinueStatement => self.continue_statement(node.pos, node.len)?,
             _ => unreachable!(),
         })
     }
@@ -282,10 +804,54 @@ impl<'i> IRBuilder<'i> {
         pos: usize,
         len: usize,
     ) -> IRResult {
-        let proc = self.locate_proc(&name)?.clone();
+        if let Some(typ) = self.structs.get(&name).cloned() {
+            // `Point(1, 2)` builds a struct out of its fields in order
+            let mut res = vec![];
+            let count = args.len();
+            for arg in args {
+                res.append(&mut self.node(&arg)?);
+            }
+            res.push(spanned(Instruction {
+                ins: InstructionType::Construct(count),
+                typ,
+            }, pos, len));
+            return Some(res);
+        }
+        // a proc called len, assert, print or println takes precedence over the builtin
+        let builtin = !self.procs.iter().any(|proc| proc.name == name);
+        if name == "len" && builtin {
+            return self.len(args, pos, len);
+        }
+        if name == "assert" && builtin {
+            return self.assert(args, pos, len);
+        }
+        if (name == "print" || name == "println") && builtin {
+            return self.print(name == "println", args, pos, len);
+        }
+        if builtin && self.is_variable(&name) {
+            return self.call_indirect(name, args, pos, len);
+        }
+        // the arguments are checked even when the call itself can't be, so that mistakes in them are reported too
+        let lowered: Vec<IRResult> = args.iter().map(|arg| self.node(arg)).collect();
+        let proc = self.locate_proc(&name, pos, len)?.clone();
+        if args.len() != proc.args.len() {
+            self.logger.type_error(
+                Code::WrongArgumentCount,
+                format!(
+                    "Proc {} expects {} argument{}, found {}",
+                    proc.name,
+                    proc.args.len(),
+                    if proc.args.len() == 1 { "" } else { "s" },
+                    args.len(),
+                ).as_str(),
+                pos,
+                len,
+            );
+            return None;
+        }
         let mut res = vec![];
-        for arg in args {
-            res.append(&mut self.node(&arg)?);
+        for arg in lowered {
+            res.append(&mut arg?);
         }
         res.push(spanned(Instruction {
             ins: InstructionType::Call(proc.name),
@@ -294,14 +860,103 @@ impl<'i> IRBuilder<'i> {
         Some(res)
     }
 
+    /// `len(s)` is how many bytes are in the str `s`
+    fn len(&mut self, args: Vec<Span<Node>>, pos: usize, len: usize) -> IRResult {
+        let arg = match &args[..] {
+            [arg] => arg,
+            _ => {
+                self.logger.type_error(Code::WrongArgumentCount, format!("len expects 1 argument, found {}", args.len()).as_str(), pos, len);
+                return None;
+            }
+        };
+        let mut res = self.node(arg)?;
+        res.push(spanned(Instruction {
+            ins: InstructionType::Length,
+            typ: Type::N64,
+        }, pos, len));
+        Some(res)
+    }
+
+    /// `assert(condition)` stops the program if the condition is false, saying where the assert is.
+    /// An optional string literal after the condition is added to what it says.
+    /// It gives back nothing, like a call to a proc that doesn't return anything.
+    fn assert(&mut self, args: Vec<Span<Node>>, pos: usize, len: usize) -> IRResult {
+        let (condition, message) = match &args[..] {
+            [condition] => (condition, "Assertion failed".to_owned()),
+            [condition, Span { contents: Node::Literal { typ: Type::StrLiteral, value }, .. }] => {
+                (condition, format!("Assertion failed: {}", value))
+            }
+            [_, message] => {
+                self.logger.type_error(Code::InvalidAssertMessage, "The message of an assert has to be a string literal", message.pos, message.len);
+                return None;
+            }
+            _ => {
+                self.logger.type_error(Code::WrongArgumentCount, format!("assert expects 1 or 2 arguments, found {}", args.len()).as_str(), pos, len);
+                return None;
+            }
+        };
+        let id = self.next_construct("assert");
+        let (ok_label, fail_label) = (format!("{}.ok", id), format!("{}.fail", id));
+        let mut res = self.node(condition)?;
+        res.push(spanned(Instruction {
+            ins: InstructionType::Branch(ok_label.clone(), fail_label.clone()),
+            typ: Type::NoReturn,
+        }, condition.pos, condition.len));
+        res.push(spanned(Instruction {
+            ins: InstructionType::Label(fail_label),
+            typ: Type::Undefined,
+        }, pos, len));
+        res.push(spanned(Instruction {
+            ins: InstructionType::Fail(message),
+            typ: Type::NoReturn,
+        }, pos, len));
+        res.push(spanned(Instruction {
+            ins: InstructionType::Label(ok_label),
+            typ: Type::Undefined,
+        }, pos, len));
+        res.push(spanned(Instruction {
+            ins: InstructionType::Push("undefined".to_owned()),
+            typ: Type::Undefined,
+        }, pos, len));
+        Some(res)
+    }
+
+    /// `print(x)` writes a number, bool or str to stdout, and `println(x)` a newline after it.
+    /// Like assert, it gives back nothing.
+    fn print(&mut self, newline: bool, args: Vec<Span<Node>>, pos: usize, len: usize) -> IRResult {
+        let arg = match &args[..] {
+            [arg] => arg,
+            _ => {
+                let name = if newline { "println" } else { "print" };
+                self.logger.type_error(Code::WrongArgumentCount, format!("{} expects 1 argument, found {}", name, args.len()).as_str(), pos, len);
+                return None;
+            }
+        };
+        let mut res = self.node(arg)?;
+        res.push(spanned(Instruction {
+            ins: InstructionType::Print(newline),
+            typ: Type::Variable(self.next_type_var()),
+        }, pos, len));
+        res.push(spanned(Instruction {
+            ins: InstructionType::Push("undefined".to_owned()),
+            typ: Type::Undefined,
+        }, pos, len));
+        Some(res)
+    }
+
+    /// The operation itself is spanned at the operator, since that's what a type error in it is about
     fn infix_op(
         &mut self,
-        op: String,
+        op: Symbol,
+        op_span: Span<()>,
         left: Box<Span<Node>>,
         right: Box<Span<Node>>,
         pos: usize,
         len: usize,
     ) -> IRResult {
+        if op == "&&" || op == "||" {
+            return self.logical_op(op, left, right, pos, len);
+        }
         let mut res = vec![];
         res.append(&mut self.node(&left)?);
         res.append(&mut self.node(&right)?);
@@ -318,6 +973,13 @@ impl<'i> IRBuilder<'i> {
 
                 "//" => InstructionType::IntDivide,
                 "/" => InstructionType::Divide,
+                "**" => InstructionType::Power,
+
+                "&" => InstructionType::BitAnd,
+                "|" => InstructionType::BitOr,
+                "^" => InstructionType::BitXor,
+                "<<" => InstructionType::Shl(Type::Variable(self.next_type_var())),
+                ">>" => InstructionType::Shr(Type::Variable(self.next_type_var())),
 
                 "==" => InstructionType::Compare(CompareType::EQ),
                 "!=" => InstructionType::Compare(CompareType::NE),
@@ -328,38 +990,96 @@ impl<'i> IRBuilder<'i> {
                 _ => todo!(),
             },
             typ: Type::Variable(self.next_type_var()),
-        }, pos, len));
+        }, op_span.pos, op_span.len));
         Some(res)
     }
 
-    fn prefix_op(
+    /// `a && b` and `a || b` only evaluate `b` when they need to, so the
+    /// result travels through a hidden bool slot initialized to the
+    /// short-circuit value.
+    fn logical_op(
         &mut self,
-        op: String,
+        op: Symbol,
+        left: Box<Span<Node>>,
         right: Box<Span<Node>>,
         pos: usize,
         len: usize,
     ) -> IRResult {
-        let mut res = vec![];
+        let (name, short_circuit) = if op == "&&" { ("and", "false") } else { ("or", "true") };
+        let id = self.next_construct(name);
+        let (rhs_label, end_label) = (format!("{}.rhs", id), format!("{}.end", id));
+        let branch = if op == "&&" {
+            InstructionType::Branch(rhs_label.clone(), end_label.clone())
+        } else {
+            InstructionType::Branch(end_label.clone(), rhs_label.clone())
+        };
+        let result = format!("{}.result", id);
+
+        let mut res = vec![
+            spanned(Instruction {
+                ins: InstructionType::Push(short_circuit.to_owned()),
+                typ: Type::Bool,
+            }, pos, len),
+            spanned(Instruction {
+                ins: InstructionType::Allocate(result.clone()),
+                typ: Type::Bool,
+            }, pos, len),
+        ];
+        res.append(&mut self.node(&left)?);
+        res.push(spanned(Instruction {
+            ins: branch,
+            typ: Type::NoReturn,
+        }, pos, len));
+        res.push(spanned(Instruction {
+            ins: InstructionType::Label(rhs_label),
+            typ: Type::Undefined,
+        }, pos, len));
         res.append(&mut self.node(&right)?);
         res.push(spanned(Instruction {
-            ins: match op.as_str() {
-                "-" => InstructionType::Negate(false),
-                "-~" => InstructionType::Negate(true),
-                _ => todo!(),
-            },
-            typ: Type::Variable(self.next_type_var()),
+            ins: InstructionType::Store(result.clone()),
+            typ: Type::Bool,
+        }, pos, len));
+        res.push(spanned(Instruction {
+            ins: InstructionType::Jump(end_label.clone()),
+            typ: Type::Undefined,
+        }, pos, len));
+        res.push(spanned(Instruction {
+            ins: InstructionType::Label(end_label),
+            typ: Type::Undefined,
+        }, pos, len));
+        res.push(spanned(Instruction {
+            ins: InstructionType::Load(result),
+            typ: Type::Bool,
         }, pos, len));
         Some(res)
     }
 
+    fn prefix_op(&mut self, op: Symbol, right: Box<Span<Node>>) -> IRResult {
+        let mut res = vec![];
+        let (operand_pos, operand_len) = (right.pos, right.len);
+        res.append(&mut self.node(&right)?);
+        // an operand of the wrong type is what gets reported, so they all point at it
+        let (ins, typ) = match op.as_str() {
+            "!" => (InstructionType::Not, Type::Bool),
+            "-" => (InstructionType::Negate(false), Type::Variable(self.next_type_var())),
+            "-~" => (InstructionType::Negate(true), Type::Variable(self.next_type_var())),
+            "+" => (InstructionType::Plus, Type::Variable(self.next_type_var())),
+            // the parser only knows of these
+            _ => unreachable!(),
+        };
+        res.push(spanned(Instruction { ins, typ }, operand_pos, operand_len));
+        Some(res)
+    }
+
     fn postfix_op(
         &mut self,
-        op: String,
+        op: Symbol,
+        op_span: Span<()>,
         left: Box<Span<Node>>,
         pos: usize,
         len: usize,
     ) -> IRResult {
-        todo!("{:?} {:?} {:?} {:?}", op, left, pos, len);
+        todo!("{:?} {:?} {:?} {:?} {:?}", op, op_span, left, pos, len);
     }
 
     fn index_op(
@@ -374,67 +1094,166 @@ impl<'i> IRBuilder<'i> {
         ins.append(&mut obj); 
         ins.append(&mut self.node(&index)?);
         ins.push(spanned(Instruction {
-            ins: InstructionType::Index,
+            ins: InstructionType::Index(Type::Variable(self.next_type_var())),
             typ: Type::Variable(self.next_type_var()),
         }, pos, len));
         Some(ins)
     }
 
-    fn variable_ref(&mut self, name: String, pos: usize, len: usize) -> IRResult {
-        if self.consts.contains_key(&name) {
-            let constant = self.consts[&name].clone();
-            return self.node(&constant);
-        }
-
-        let typ = self.locate_var(&name)?;
-        Some(vec![spanned(Instruction {
-            ins: InstructionType::Load(name),
-            typ,
-        }, pos, len)])
-    }
-
-    fn if_statement(
+    fn field_access(
         &mut self,
-        condition: Box<Span<Node>>,
-        body: Box<Span<Node>>,
-        else_body: Box<Span<Node>>,
+        object: Box<Span<Node>>,
+        field: String,
         pos: usize,
         len: usize,
     ) -> IRResult {
-        let mut res = vec![];
-        let body_label = self.next_label_id();
-        let else_label = self.next_label_id();
-        let end_label = self.next_label_id();
-        let mut blocks_ending_in_return = 2;
-
-        res.append(&mut self.node(&condition)?);
-        res.push(spanned(Instruction {
-            ins: InstructionType::Branch(body_label, else_label),
-            typ: Type::NoReturn,
+        let mut ins = self.node(&object)?;
+        ins.push(spanned(Instruction {
+            ins: InstructionType::Field(field),
+            typ: Type::Variable(self.next_type_var()),
         }, pos, len));
-        res.push(spanned(Instruction {
-            ins: InstructionType::Label(body_label),
-            typ: Type::Undefined,
+        Some(ins)
+    }
+
+    /// Elements are evaluated left to right and built into one value, like a struct's fields
+    fn tuple(&mut self, elements: Vec<Span<Node>>, pos: usize, len: usize) -> IRResult {
+        let mut res = vec![];
+        let mut types = vec![];
+        for element in &elements {
+            res.append(&mut self.node(element)?);
+            types.push(Type::Variable(self.next_type_var()));
+        }
+        res.push(spanned(Instruction {
+            ins: InstructionType::Construct(elements.len()),
+            typ: Type::Tuple(types),
         }, pos, len));
-        res.append(&mut self.node(&body)?);
-        if res.last().unwrap().contents.ins != InstructionType::Return {
-            blocks_ending_in_return -= 1;
-            res.push(spanned(Instruction {
-                ins: InstructionType::Jump(end_label),
-                typ: Type::Undefined,
-            }, pos, len));
+        Some(res)
+    }
+
+    fn cast(
+        &mut self,
+        value: Box<Span<Node>>,
+        typ: Type,
+        pos: usize,
+        len: usize,
+    ) -> IRResult {
+        let mut res = self.node(&value)?;
+        // whatever the value turns out to be, codegen needs it to pick the conversion
+        res.push(spanned(Instruction {
+            ins: InstructionType::Cast(typ),
+            typ: Type::Variable(self.next_type_var()),
+        }, pos, len));
+        Some(res)
+    }
+
+    /// A const is known at compile time, so its value is used directly instead of loading it
+    fn variable_ref(&mut self, name: String, pos: usize, len: usize) -> IRResult {
+        if !self.is_variable(&name) {
+            if let Some(proc) = self.declared_procs().iter().find(|proc| proc.name == name).cloned() {
+                return self.proc_ref(proc, pos, len);
+            }
+        }
+        let (name, typ) = self.locate_var(&name, pos, len);
+        let global = self.globals.iter().find(|global| global.constant && global.ir_name() == name);
+        let ins = match (self.consts.get(&name), global) {
+            (Some(value), _) => InstructionType::Push(value.clone()),
+            (None, Some(global)) => InstructionType::Push(global.value.clone().unwrap()),
+            (None, None) => InstructionType::Load(name),
+        };
+        Some(vec![spanned(Instruction {
+            ins,
+            typ,
+        }, pos, len)])
+    }
+
+    /// A proc named without calling it, which can be stored and passed around and called later
+    fn proc_ref(&mut self, proc: IRProc, pos: usize, len: usize) -> IRResult {
+        if proc.arg_types.iter().any(|t| matches!(t, Type::Variable(_))) {
+            self.logger.type_error(
+                Code::MissingTypeAnnotation,
+                format!("Proc {} is used without being called, so the types of its arguments have to be written out", proc.name).as_str(),
+                pos,
+                len,
+            );
+            return None;
+        }
+        Some(vec![spanned(Instruction {
+            ins: InstructionType::ProcRef(proc.name),
+            typ: Type::new_proc(proc.arg_types, proc.ret_type),
+        }, pos, len)])
+    }
+
+    /// A call through a variable holding a proc reference, which is loaded before the arguments
+    fn call_indirect(&mut self, name: String, args: Vec<Span<Node>>, pos: usize, len: usize) -> IRResult {
+        let mut res = self.variable_ref(name.clone(), pos, len)?;
+        let callee = res[0].contents.typ.clone();
+        // otherwise analysis works out the type from the call, and checks it against the reference
+        let ret_type = match callee {
+            Type::Proc(callee) => {
+                if callee.args.len() != args.len() {
+                    self.logger.type_error(
+                        Code::WrongArgumentCount,
+                        format!(
+                            "{} is a {}, which takes {} argument{}, found {}",
+                            name,
+                            Type::Proc(callee.clone()),
+                            callee.args.len(),
+                            if callee.args.len() == 1 { "" } else { "s" },
+                            args.len(),
+                        ).as_str(),
+                        pos,
+                        len,
+                    );
+                    return None;
+                }
+                callee.ret
+            }
+            _ => Type::Variable(self.next_type_var()),
+        };
+        let count = args.len();
+        for arg in args {
+            res.append(&mut self.node(&arg)?);
         }
         res.push(spanned(Instruction {
-            ins: InstructionType::Label(else_label),
-            typ: Type::Undefined,
+            ins: InstructionType::CallIndirect(count),
+            typ: ret_type,
         }, pos, len));
-        res.append(&mut self.node(&else_body)?);
-        if res.last().unwrap().contents.ins != InstructionType::Return {
-            blocks_ending_in_return -= 1;
+        Some(res)
+    }
+
+    fn if_statement(
+        &mut self,
+        condition: Box<Span<Node>>,
+        body: Box<Span<Node>>,
+        else_body: Box<Span<Node>>,
+        pos: usize,
+        len: usize,
+    ) -> IRResult {
+        let mut res = vec![];
+        let id = self.next_construct("if");
+        let (body_label, else_label, end_label) = (format!("{}.body", id), format!("{}.else", id), format!("{}.end", id));
+        let mut blocks_ending_in_return = 2;
+
+        res.append(&mut self.node(&condition)?);
+        // anything wrong with the branch is really wrong with the condition
+        res.push(spanned(Instruction {
+            ins: InstructionType::Branch(body_label.clone(), else_label.clone()),
+            typ: Type::NoReturn,
+        }, condition.pos, condition.len));
+        // the label and jump around each branch belong to that branch, not the whole if
+        for (label, branch) in [(body_label, &body), (else_label, &else_body)] {
             res.push(spanned(Instruction {
-                ins: InstructionType::Jump(end_label),
+                ins: InstructionType::Label(label),
                 typ: Type::Undefined,
-            }, pos, len));
+            }, branch.pos, branch.len));
+            res.append(&mut self.node(branch)?);
+            if !ends_in_terminator(&res) {
+                blocks_ending_in_return -= 1;
+                res.push(spanned(Instruction {
+                    ins: InstructionType::Jump(end_label.clone()),
+                    typ: Type::Undefined,
+                }, branch.pos, branch.len));
+            }
         }
         if blocks_ending_in_return < 2 {
             res.push(spanned(Instruction {
@@ -445,6 +1264,107 @@ impl<'i> IRBuilder<'i> {
         Some(res)
     }
 
+    /// Both branches store their value to a hidden slot, which is loaded once
+    /// they meet up again.
+    fn if_expression(
+        &mut self,
+        condition: Box<Span<Node>>,
+        body: Box<Span<Node>>,
+        else_body: Option<Box<Span<Node>>>,
+        pos: usize,
+        len: usize,
+    ) -> IRResult {
+        let else_body = match else_body {
+            Some(else_body) => else_body,
+            None => {
+                self.logger.type_error(Code::MissingValue, "An if expression needs an else block to have a value", pos, len);
+                return None;
+            }
+        };
+        let id = self.next_construct("if");
+        let (body_label, else_label, end_label) = (format!("{}.body", id), format!("{}.else", id), format!("{}.end", id));
+        let result = format!("{}.result", id);
+        let typ = Type::Variable(self.next_type_var());
+
+        let mut res = vec![
+            spanned(Instruction {
+                ins: InstructionType::Push("undefined".to_owned()),
+                typ: Type::Undefined,
+            }, pos, len),
+            spanned(Instruction {
+                ins: InstructionType::Allocate(result.clone()),
+                typ: typ.clone(),
+            }, pos, len),
+        ];
+        res.append(&mut self.node(&condition)?);
+        // anything wrong with the branch is really wrong with the condition
+        res.push(spanned(Instruction {
+            ins: InstructionType::Branch(body_label.clone(), else_label.clone()),
+            typ: Type::NoReturn,
+        }, condition.pos, condition.len));
+        for (label, branch) in [(body_label, body), (else_label, else_body)] {
+            let (branch_pos, branch_len) = (branch.pos, branch.len);
+            res.push(spanned(Instruction {
+                ins: InstructionType::Label(label),
+                typ: Type::Undefined,
+            }, branch_pos, branch_len));
+            res.append(&mut self.if_expression_branch(*branch)?);
+            // a branch that returns or breaks never produces a value
+            if !ends_in_terminator(&res) {
+                res.push(spanned(Instruction {
+                    ins: InstructionType::Store(result.clone()),
+                    typ: typ.clone(),
+                }, branch_pos, branch_len));
+                res.push(spanned(Instruction {
+                    ins: InstructionType::Jump(end_label.clone()),
+                    typ: Type::Undefined,
+                }, branch_pos, branch_len));
+            }
+        }
+        res.push(spanned(Instruction {
+            ins: InstructionType::Label(end_label),
+            typ: Type::Undefined,
+        }, pos, len));
+        res.push(spanned(Instruction {
+            ins: InstructionType::Load(result),
+            typ,
+        }, pos, len));
+        Some(res)
+    }
+
+    fn if_expression_branch(&mut self, branch: Span<Node>) -> IRResult {
+        let nodes = match branch.contents {
+            Node::Block { nodes } => nodes,
+            _ => unreachable!(),
+        };
+        let last = match nodes.last() {
+            Some(last) => last,
+            None => {
+                self.logger.type_error(Code::MissingValue, "The block of an if expression can't be empty, it has to end in the value it evaluates to", branch.pos, branch.len);
+                return None;
+            }
+        };
+        let terminates = matches!(
+            last.contents,
+            Node::ReturnStatement { .. } | Node::BreakStatement | Node::ContinueStatement,
+        );
+        if !terminates && !is_expression(&last.contents) {
+            self.logger.type_error(Code::MissingValue, "The block of an if expression has to end in the value it evaluates to", last.pos, last.len);
+            return None;
+        }
+        // like a block, except that the value of the last node stays on the stack
+        let (last, statements) = nodes.split_last().unwrap();
+        self.scopes.push(Bindings::new());
+        let res = statements.iter().map(|node| self.statement(node)).collect::<Option<Vec<_>>>();
+        let value = self.node(last);
+        self.scopes.pop();
+        let mut res: Vec<_> = res?.into_iter().flatten().collect();
+        res.append(&mut value?);
+        Some(res)
+    }
+
+    /// The condition gets a label of its own, which the end of the body and `continue` jump back to,
+    /// so it's evaluated again before every iteration
     fn while_statement(
         &mut self,
         condition: Box<Span<Node>>,
@@ -453,32 +1373,32 @@ impl<'i> IRBuilder<'i> {
         len: usize,
     ) -> IRResult {
         let mut res = vec![];
-        let cond_label = self.next_label_id();
-        self.current_loop_entrance_id = cond_label;
-        let body_label = self.next_label_id();
-        let end_label = self.next_label_id();
-        self.current_after_loop_id = end_label;
+        let id = self.next_construct("while");
+        let (cond_label, body_label, end_label) = (format!("{}.cond", id), format!("{}.body", id), format!("{}.end", id));
         let mut blocks_ending_in_return = 1;
 
         res.push(spanned(Instruction {
-            ins: InstructionType::Jump(cond_label),
+            ins: InstructionType::Jump(cond_label.clone()),
             typ: Type::Undefined,
         }, pos, len));
         res.push(spanned(Instruction {
-            ins: InstructionType::Label(cond_label),
+            ins: InstructionType::Label(cond_label.clone()),
             typ: Type::Undefined,
         }, pos, len));
         res.append(&mut self.node(&condition)?);
         res.push(spanned(Instruction {
-            ins: InstructionType::Branch(body_label, end_label),
+            ins: InstructionType::Branch(body_label.clone(), end_label.clone()),
             typ: Type::NoReturn,
-        }, pos, len));
+        }, condition.pos, condition.len));
         res.push(spanned(Instruction {
             ins: InstructionType::Label(body_label),
             typ: Type::Undefined,
         }, pos, len));
-        res.append(&mut self.node(&body)?);
-        if res.last().unwrap().contents.ins != InstructionType::Return {
+        self.loops.push((cond_label.clone(), end_label.clone()));
+        let body = self.node(&body);
+        self.loops.pop();
+        res.append(&mut body?);
+        if !ends_in_terminator(&res) {
             blocks_ending_in_return -= 1;
             res.push(spanned(Instruction {
                 ins: InstructionType::Jump(cond_label),
@@ -494,14 +1414,230 @@ impl<'i> IRBuilder<'i> {
         Some(res)
     }
 
-    fn block(&mut self, nodes: Vec<Span<Node>>, _pos: usize, _len: usize) -> IRResult {
+    /// The scrutinee is evaluated once into a hidden slot, then `dispatch` jumps
+    /// to the arm that matches it, or to the else block when none do
+    fn match_statement(
+        &mut self,
+        scrutinee: Box<Span<Node>>,
+        arms: Vec<(Span<Node>, Span<Node>)>,
+        default: Option<Box<Span<Node>>>,
+        pos: usize,
+        len: usize,
+    ) -> IRResult {
+        // an arm that's already been matched earlier could never run
+        let mut seen: HashMap<String, (usize, usize)> = HashMap::new();
+        let mut duplicated = false;
+        for (pattern, _) in &arms {
+            if let Node::Literal { value, .. } = &pattern.contents {
+                if let Some(&(first_pos, first_len)) = seen.get(&literal_key(value)) {
+                    self.logger.type_error(Code::DuplicateMatchArm, format!("There is already an arm for {} in this match", value).as_str(), pattern.pos, pattern.len);
+                    self.logger.note(format!("{} is first matched here", value).as_str(), first_pos, first_len);
+                    duplicated = true;
+                } else {
+                    seen.insert(literal_key(value), (pattern.pos, pattern.len));
+                }
+            }
+        }
+        if duplicated {
+            return None;
+        }
+
+        let id = self.next_construct("match");
+        let (default_label, end_label) = (format!("{}.default", id), format!("{}.end", id));
+        let slot = format!("{}.value", id);
+        let typ = Type::Variable(self.next_type_var());
+        let mut res = self.node(&scrutinee)?;
+        res.push(spanned(Instruction {
+            ins: InstructionType::Allocate(slot.clone()),
+            typ: typ.clone(),
+        }, scrutinee.pos, scrutinee.len));
+        let arms: Vec<_> = arms.into_iter()
+            .enumerate()
+            .map(|(i, (pattern, body))| (pattern, body, format!("{}.arm{}", id, i)))
+            .collect();
+        let targets: Vec<_> = arms.iter().map(|(pattern, _, label)| (pattern, label.as_str())).collect();
+        res.append(&mut self.dispatch(&slot, &typ, &targets, &default_label, pos, len));
+
+        let mut reaches_end = false;
+        let default = default.map(|default| *default);
+        let bodies = std::iter::once((default_label, default.as_ref()))
+            .chain(arms.iter().map(|(_, body, label)| (label.clone(), Some(body))));
+        for (label, body) in bodies {
+            res.push(spanned(Instruction {
+                ins: InstructionType::Label(label),
+                typ: Type::Undefined,
+            }, pos, len));
+            if let Some(body) = body {
+                res.append(&mut self.node(body)?);
+            }
+            if !ends_in_terminator(&res) {
+                reaches_end = true;
+                res.push(spanned(Instruction {
+                    ins: InstructionType::Jump(end_label.clone()),
+                    typ: Type::Undefined,
+                }, pos, len));
+            }
+        }
+        if reaches_end {
+            res.push(spanned(Instruction {
+                ins: InstructionType::Label(end_label),
+                typ: Type::Undefined,
+            }, pos, len));
+        }
+        Some(res)
+    }
+
+    /// Jumps to the label of the first arm whose literal equals the value in `slot`,
+    /// or to `default` if none of them do. Comparing against each in turn is all it
+    /// does for now, but it's the only part of a match that would change for a jump table.
+    fn dispatch(
+        &mut self,
+        slot: &str,
+        typ: &Type,
+        arms: &[(&Span<Node>, &str)],
+        default: &str,
+        pos: usize,
+        len: usize,
+    ) -> Vec<Span<Instruction>> {
         let mut res = vec![];
-        for node in nodes {
-            res.append(&mut self.node(&node)?);
+        if arms.is_empty() {
+            res.push(spanned(Instruction {
+                ins: InstructionType::Jump(default.to_owned()),
+                typ: Type::Undefined,
+            }, pos, len));
+        }
+        for (i, &(pattern, label)) in arms.iter().enumerate() {
+            let (literal_type, value) = match &pattern.contents {
+                Node::Literal { typ, value } => (typ.clone(), value.clone()),
+                _ => unreachable!(),
+            };
+            // each arm after the first is tested at a label of its own
+            let next = match arms.get(i + 1) {
+                Some((_, next_arm)) => format!("{}.test", next_arm),
+                None => default.to_owned(),
+            };
+            // a literal of the wrong type is what gets blamed for a mismatch
+            let at = |ins, typ| spanned(Instruction { ins, typ }, pattern.pos, pattern.len);
+            res.push(at(InstructionType::Load(slot.to_owned()), typ.clone()));
+            res.push(at(InstructionType::Push(value), literal_type));
+            res.push(at(InstructionType::Compare(CompareType::EQ), Type::Variable(self.next_type_var())));
+            res.push(at(InstructionType::Branch(label.to_owned(), next.clone()), Type::NoReturn));
+            if next != default {
+                res.push(at(InstructionType::Label(next), Type::Undefined));
+            }
+        }
+        res
+    }
+
+    /// `for i = start .. end { body }` is lowered as if it were
+    /// `var i = start; while i < end { body; i = i + 1 }`, except that
+    /// `continue` still goes through the increment and `i` only exists
+    /// inside the loop, shadowing anything else with the same name.
+    fn for_statement(
+        &mut self,
+        var: String,
+        start: Box<Span<Node>>,
+        end: Box<Span<Node>>,
+        body: Box<Span<Node>>,
+        pos: usize,
+        len: usize,
+    ) -> IRResult {
+        let mut res = self.node(&start)?;
+        let id = self.next_construct("for");
+        let (cond_label, body_label) = (format!("{}.cond", id), format!("{}.body", id));
+        let (step_label, end_label) = (format!("{}.step", id), format!("{}.end", id));
+        let counter = format!("{}.{}", var, id);
+        let typ = Type::Variable(self.next_type_var());
+
+        let mut scope = Bindings::new();
+        scope.insert(var, (counter.clone(), typ.clone()));
+        self.scopes.push(scope);
+        let ins = |ins| spanned(Instruction {
+            ins,
+            typ: Type::Undefined,
+        }, pos, len);
+        let typed = |ins, typ: &Type| spanned(Instruction {
+            ins,
+            typ: typ.clone(),
+        }, pos, len);
+
+        res.push(typed(InstructionType::Allocate(counter.clone()), &typ));
+        res.push(ins(InstructionType::Jump(cond_label.clone())));
+        res.push(ins(InstructionType::Label(cond_label.clone())));
+        res.push(typed(InstructionType::Load(counter.clone()), &typ));
+        let end = self.node(&end);
+        let end = match end {
+            Some(end) => end,
+            None => {
+                self.scopes.pop();
+                return None;
+            }
+        };
+        res.extend(end);
+        res.push(typed(InstructionType::Compare(CompareType::LT), &Type::Variable(self.next_type_var())));
+        res.push(typed(InstructionType::Branch(body_label.clone(), end_label.clone()), &Type::NoReturn));
+        res.push(ins(InstructionType::Label(body_label)));
+
+        self.loops.push((step_label.clone(), end_label.clone()));
+        let body = self.node(&body);
+        self.loops.pop();
+        self.scopes.pop();
+        res.append(&mut body?);
+
+        if !ends_in_terminator(&res) {
+            res.push(ins(InstructionType::Jump(step_label.clone())));
         }
+        res.push(ins(InstructionType::Label(step_label)));
+        res.push(typed(InstructionType::Load(counter.clone()), &typ));
+        res.push(typed(InstructionType::Push("1".to_owned()), &Type::IntLiteral));
+        res.push(typed(InstructionType::Add(false), &Type::Variable(self.next_type_var())));
+        res.push(typed(InstructionType::Store(counter), &typ));
+        res.push(ins(InstructionType::Jump(cond_label)));
+        res.push(ins(InstructionType::Label(end_label)));
         Some(res)
     }
 
+    /// An expression used as a statement has its value thrown away. What a proc returns is warned about
+    /// unless that's been asked for with `_ = `, since otherwise it's probably a mistake,
+    /// but not for builtins like puts, whose results hardly ever matter.
+    fn statement(&mut self, node: &Span<Node>) -> IRResult {
+        errors::working_on(node.pos, node.len);
+        let mut res = self.node(node)?;
+        if !is_expression(&node.contents) {
+            return Some(res);
+        }
+        if let Node::Call { name, .. } = &node.contents {
+            let returns_value = !self.structs.contains_key(name)
+                && self.declared_procs().iter().any(|proc| proc.name == *name && proc.ret_type != Type::Undefined);
+            if self.warnings && returns_value {
+                self.logger.warning(
+                    Code::UnusedResult,
+                    format!("The value returned by {} is never used, assign it to _ to throw it away on purpose", name).as_str(),
+                    node.pos,
+                    node.len,
+                );
+            }
+        }
+        res.push(self.pop(node.pos, node.len));
+        Some(res)
+    }
+
+    /// Takes whatever's on top of the stack off it, which can be of any type
+    fn pop(&self, pos: usize, len: usize) -> Span<Instruction> {
+        spanned(Instruction {
+            ins: InstructionType::Pop,
+            typ: Type::Undefined,
+        }, pos, len)
+    }
+
+    /// Variables declared in a block go away at the end of it
+    fn block(&mut self, nodes: Vec<Span<Node>>, _pos: usize, _len: usize) -> IRResult {
+        self.scopes.push(Bindings::new());
+        let res = nodes.iter().map(|node| self.statement(node)).collect::<Option<Vec<_>>>();
+        self.scopes.pop();
+        Some(res?.into_iter().flatten().collect())
+    }
+
     fn var_statement(
         &mut self,
         name: String,
@@ -510,46 +1646,196 @@ impl<'i> IRBuilder<'i> {
         pos: usize,
         len: usize,
     ) -> IRResult {
+        // the value is built first so that `var x = x + 1` can still see the outer x
+        let value = self.node(&value);
+        let ir_name = self.declare(name, typ.clone(), pos, len);
+        let mut res = value?;
+        res.push(spanned(Instruction {
+            ins: InstructionType::Allocate(ir_name),
+            typ,
+        }, pos, len));
+        Some(res)
+    }
+
+    /// The tuple goes into a hidden slot of a tuple type as long as the list of names,
+    /// which is where a tuple of the wrong length gets caught, then each name gets its element
+    fn var_tuple_statement(
+        &mut self,
+        names: Vec<String>,
+        value: Box<Span<Node>>,
+        pos: usize,
+        len: usize,
+    ) -> IRResult {
+        let mut res = self.node(&value)?;
+        let elements: Vec<_> = names.iter().map(|_| Type::Variable(self.next_type_var())).collect();
+        let tuple = Type::Tuple(elements.clone());
+        let slot = format!("tuple.{}", self.next_id());
+        let typed = |ins, typ: &Type| spanned(Instruction { ins, typ: typ.clone() }, pos, len);
+        res.push(typed(InstructionType::Allocate(slot.clone()), &tuple));
+        for (i, (name, typ)) in names.into_iter().zip(elements).enumerate() {
+            res.push(typed(InstructionType::Load(slot.clone()), &tuple));
+            res.push(typed(InstructionType::Field(i.to_string()), &typ));
+            let ir_name = self.declare(name, typ.clone(), pos, len);
+            res.push(typed(InstructionType::Allocate(ir_name), &typ));
+        }
+        Some(res)
+    }
+
+    /// Adds a variable to the innermost scope, handing back the name the IR knows it by.
+    /// Any earlier variable with the same name is only shadowed, even a parameter or one in the same scope.
+    fn declare(&mut self, name: String, typ: Type, pos: usize, len: usize) -> String {
+        let earlier = self.scopes.iter().rev().find_map(|scope| scope.get(&name)).map(|(ir_name, _)| ir_name.clone());
+        let shadows = earlier.is_some();
+        if let (true, Some(earlier)) = (self.warnings, earlier) {
+            // one that was only ever used without being declared has already been reported
+            let declared = self.declared.get(&earlier).copied();
+            let message = match declared {
+                Some(("parameter", _, _)) => format!("The variable {} shadows the parameter with the same name", name),
+                Some(_) if self.scopes.last().unwrap().contains_key(&name) => {
+                    format!("The variable {} shadows one with the same name declared earlier in this scope", name)
+                }
+                _ => format!("The variable {} shadows an earlier variable with the same name", name),
+            };
+            self.logger.warning(Code::ShadowedVariable, message.as_str(), pos, len);
+            if let Some((kind, earlier_pos, earlier_len)) = declared {
+                self.logger.note(format!("The {} {} is declared here", kind, name).as_str(), earlier_pos, earlier_len);
+            }
+        }
+        // the outer variable has to survive being shadowed, so the new one gets a name of its own
+        let ir_name = if shadows {
+            format!("{}.{}", name, self.next_id())
+        } else {
+            name.clone()
+        };
+        self.declared.insert(ir_name.clone(), ("variable", pos, len));
+        self.scopes
+            .last_mut()
+            .unwrap()
+            .insert(name, (ir_name.clone(), typ));
+        ir_name
+    }
+
+    /// A const in a proc takes up no space, it's only a name for its value
+    /// for the rest of the scope it's declared in
+    fn const_statement(
+        &mut self,
+        name: String,
+        typ: Type,
+        value: Box<Span<Node>>,
+        pos: usize,
+        len: usize,
+    ) -> IRResult {
+        let annotation = match typ {
+            Type::Variable(_) => None,
+            t => Some(t),
+        };
+        let evaluated = self.evaluate_const(&name, annotation, &value, "const", pos, len);
+        let ir_name = format!("const.{}.{}", name, self.next_id());
+        // one that can't be worked out is still declared, so that using it isn't another error
+        let typ = match &evaluated {
+            Some((typ, value)) => {
+                self.consts.insert(ir_name.clone(), value.clone());
+                typ.clone()
+            }
+            None => Type::Variable(self.next_type_var()),
+        };
         self.scopes
             .last_mut()
             .unwrap()
-            .insert(name.clone(), typ.clone());
+            .insert(name, (ir_name, typ));
+        evaluated?;
+        Some(vec![])
+    }
+
+    fn assign_statement(
+        &mut self,
+        name: String,
+        value: Box<Span<Node>>,
+        pos: usize,
+        len: usize,
+    ) -> IRResult {
+        // `_ = f(x)` calls f just for what else it does
+        if name == "_" {
+            let mut res = self.node(&value)?;
+            res.push(self.pop(pos, len));
+            return Some(res);
+        }
+        self.ensure_not_const(&name, pos, len)?;
         let mut res = self.node(&value)?;
+        let (name, typ) = self.locate_var(&name, pos, len);
         res.push(spanned(Instruction {
-            ins: InstructionType::Allocate(name.clone()),
+            ins: InstructionType::Store(name),
             typ,
         }, pos, len));
         Some(res)
     }
 
-    fn assign_statement(
+    fn index_assign_statement(
         &mut self,
-        name: String,
+        object: Span<Node>,
+        index: Span<Node>,
         value: Box<Span<Node>>,
         pos: usize,
         len: usize,
     ) -> IRResult {
+        // StoreIndexed writes through a variable, so `m[i][j] = x` is a store
+        // to m with both indices on the stack, outermost first
+        let mut indices = vec![index];
+        let mut object = object;
+        while let Node::IndexOp { object: inner, index } = object.contents {
+            indices.push(*index);
+            object = *inner;
+        }
+        indices.reverse();
+        let name = if let Node::VariableRef { name } = object.contents {
+            name
+        } else {
+            self.logger.syntax_error(
+                Code::InvalidAssignmentTarget,
+                "Unsupported lvalue, only elements of array variables can be assigned to",
+                object.pos,
+                object.len,
+            );
+            return None;
+        };
+        self.ensure_not_const(&name, pos, len)?;
         let mut res = self.node(&value)?;
+        for index in &indices {
+            res.append(&mut self.node(index)?);
+        }
+        let (name, typ) = self.locate_var(&name, object.pos, object.len);
         res.push(spanned(Instruction {
-            ins: InstructionType::Store(name.clone()),
-            typ: self.locate_var(&name)?,
+            ins: InstructionType::StoreIndexed(name, indices.iter().map(|_| Type::Variable(self.next_type_var())).collect()),
+            typ,
         }, pos, len));
         Some(res)
     }
 
-    fn indexed_assign_statement(
+    fn field_assign_statement(
         &mut self,
-        name: String,
-        index: Box<Span<Node>>,
+        object: Span<Node>,
+        field: String,
         value: Box<Span<Node>>,
         pos: usize,
         len: usize,
     ) -> IRResult {
+        let name = if let Node::VariableRef { name } = object.contents {
+            name
+        } else {
+            self.logger.syntax_error(
+                Code::InvalidAssignmentTarget,
+                "Unsupported lvalue, only fields of struct variables can be assigned to",
+                object.pos,
+                object.len,
+            );
+            return None;
+        };
+        self.ensure_not_const(&name, pos, len)?;
         let mut res = self.node(&value)?;
-        res.append(&mut self.node(&index)?);
+        let (name, typ) = self.locate_var(&name, object.pos, object.len);
         res.push(spanned(Instruction {
-            ins: InstructionType::StoreIndexed(name.clone()),
-            typ: self.locate_var(&name)?,
+            ins: InstructionType::StoreField(name, field),
+            typ,
         }, pos, len));
         Some(res)
     }
@@ -571,58 +1857,198 @@ impl<'i> IRBuilder<'i> {
     }
 
     fn break_statement(&mut self, pos: usize, len: usize) -> IRResult {
+        let (_, exit) = self.innermost_loop("break", pos, len)?;
         Some(vec![
             spanned(Instruction {
-                ins: InstructionType::Jump(self.current_after_loop_id),
+                ins: InstructionType::Jump(exit),
                 typ: Type::NoReturn,
             }, pos, len)
         ])
     }
 
     fn continue_statement(&mut self, pos: usize, len: usize) -> IRResult {
+        let (entrance, _) = self.innermost_loop("continue", pos, len)?;
         Some(vec![
             spanned(Instruction {
-                ins: InstructionType::Jump(self.current_loop_entrance_id),
+                ins: InstructionType::Jump(entrance),
                 typ: Type::NoReturn,
             }, pos, len)
         ])
     }
 
-    fn const_statement(
+    fn innermost_loop(&self, keyword: &str, pos: usize, len: usize) -> Option<(String, String)> {
+        let lp = self.loops.last().cloned();
+        if lp.is_none() {
+            self.logger.syntax_error(
+                Code::MisplacedStatement,
+                format!("Found a {} statement outside of a loop", keyword).as_str(),
+                pos,
+                len,
+            );
+        }
+        lp
+    }
+
+    /// Top-level consts and vars, both of which need a value known at compile time
+    fn global_statement(
         &mut self,
         name: String,
-        _typ: Type,
+        typ: Type,
         value: Box<Span<Node>>,
-        _pos: usize,
-        _len: usize,
+        constant: bool,
+        pos: usize,
+        len: usize,
     ) -> Option<()> {
-        // TODO: Actual verification that this is a const expression
-        self.consts.insert(name, *value.clone());
+        let kind = if constant { "const" } else { "global variable" };
+        if let Some(existing) = self.globals.iter().find(|global| global.name == name) {
+            self.logger.name_error(
+                Code::Redefinition,
+                format!("There is already a {} named {}", existing.kind(), name).as_str(),
+                pos,
+                len,
+            );
+            return None;
+        }
+        let annotation = match typ {
+            Type::Variable(_) => None,
+            t => Some(t),
+        };
+        // a var without a value starts out zeroed
+        if let Node::Literal { typ: Type::Undefined, .. } = value.contents {
+            return match annotation {
+                Some(typ) => {
+                    self.globals.push(IRGlobal {
+                        name,
+                        typ,
+                        value: None,
+                        constant,
+                    });
+                    Some(())
+                }
+                None => {
+                    self.logger.type_error(
+                        Code::MissingTypeAnnotation,
+                        format!("The {} {} needs either a type or a value", kind, name).as_str(),
+                        pos,
+                        len,
+                    );
+                    None
+                }
+            };
+        }
+
+        // there are no variables to refer to, but unknown ones still need somewhere to go
+        self.scopes.push(Bindings::new());
+        let evaluated = self.evaluate_const(&name, annotation, &value, kind, pos, len);
+        self.scopes.pop();
+        let (typ, literal) = evaluated?;
+        self.globals.push(IRGlobal {
+            name,
+            typ,
+            value: Some(literal),
+            constant,
+        });
         Some(())
     }
 
-    fn proc_statement(
+    /// The type and value of something that has to be known at compile time, which is made out of
+    /// literals and consts that come before it
+    fn evaluate_const(
         &mut self,
-        name: String,
-        args: Vec<String>,
-        arg_types: Vec<Type>,
-        ret_type: Type,
-        body: Box<Span<Node>>,
+        name: &str,
+        annotation: Option<Type>,
+        value: &Span<Node>,
+        kind: &str,
         pos: usize,
         len: usize,
-    ) -> Option<IRProc> {
+    ) -> Option<(Type, String)> {
+        // consts are already known, so their values are pushed instead of loaded
+        let ins = self.node(value)?;
+        let value = match opt::evaluate(&ins, annotation.as_ref()) {
+            Some(value) => value,
+            None => {
+                // arithmetic on nothing but literals only stays unfolded if it can't be done
+                let arithmetic = ins.iter().all(|ins| {
+                    use InstructionType::*;
+                    matches!(
+                        ins.contents.ins,
+                        Push(_) | Negate(_) | Plus | Add(_) | Subtract(_) | Multiply(_) | IntDivide | Divide | Power
+                            | BitAnd | BitOr | BitXor | Shl(_) | Shr(_)
+                    )
+                });
+                let message = if arithmetic {
+                    format!("Working out the value of {} {} overflows, or divides by zero", kind, name)
+                } else {
+                    format!("The value of {} {} has to be made out of literals and consts", kind, name)
+                };
+                self.logger.type_error(Code::InvalidConstValue, message.as_str(), value.pos, value.len);
+                return None;
+            }
+        };
+        if let Some(expected) = annotation {
+            if expected != value.contents.typ {
+                self.logger.type_error(
+                    Code::MismatchedTypes,
+                    format!("Mismatched types: expected {}, found {}", expected, value.contents.typ).as_str(),
+                    pos,
+                    len,
+                );
+                return None;
+            }
+        }
+        if !opt::fits(&value) {
+            self.logger.type_error(
+                Code::InvalidConstValue,
+                format!("The value of {} {} doesn't fit in a {}", kind, name, value.contents.typ).as_str(),
+                pos,
+                len,
+            );
+            return None;
+        }
+        match value.contents.ins {
+            InstructionType::Push(literal) => Some((value.contents.typ, literal)),
+            _ => unreachable!(),
+        }
+    }
+
+    fn proc_statement(&mut self, decl: &ProcDecl, pos: usize, len: usize) -> Option<IRProc> {
+        let ProcDecl {
+            name,
+            args,
+            arg_types,
+            arg_spans,
+            ret_type,
+            body,
+            doc,
+        } = decl;
         let mut ins = vec![];
+        let depth = self.scopes.len();
         self.scopes.push(HashMap::new());
         let scope = self.scopes.last_mut().unwrap();
+        self.declared.clear();
+        self.constructs = 0;
         for (i, arg) in args.iter().enumerate() {
             let t = arg_types[i].clone();
-            scope.insert(arg.clone(), t);
+            scope.insert(arg.clone(), (arg.clone(), t));
+            let (arg_pos, arg_len) = arg_spans[i];
+            self.declared.insert(arg.clone(), ("parameter", arg_pos, arg_len));
         }
-        if let Node::Block { nodes, .. } = body.contents {
-            for node in &nodes {
-                ins.append(&mut self.node(&node)?);
+        if let Node::Block { nodes, .. } = &body.contents {
+            // every statement is still built so that all of its errors get reported
+            let mut failed = false;
+            for node in nodes {
+                match self.statement(node) {
+                    Some(mut node_ins) => ins.append(&mut node_ins),
+                    None => failed = true,
+                }
+            }
+            // a failed statement may not have cleaned up its own scopes
+            self.scopes.truncate(depth);
+            if failed {
+                return None;
             }
-            if ret_type == Type::Undefined && nodes.len() > 0 {
+            // an empty body would make it look like a declaration of something from C
+            if *ret_type == Type::Undefined {
                 ins.push(spanned(Instruction {
                     ins: InstructionType::Push("undefined".to_owned()),
                     typ: Type::Undefined,
@@ -631,13 +2057,22 @@ impl<'i> IRBuilder<'i> {
                     ins: InstructionType::Return,
                     typ: Type::Undefined,
                 }, pos, len));
+            } else if falls_off_end(&ins) {
+                self.logger.type_error(
+                    Code::MissingReturn,
+                    format!("Not all paths through proc {} return a value", name).as_str(),
+                    pos,
+                    len,
+                );
+                return None;
             }
             Some(IRProc {
-                name,
-                args,
-                arg_types,
-                ret_type,
+                name: name.clone(),
+                args: args.clone(),
+                arg_types: arg_types.clone(),
+                ret_type: ret_type.clone(),
                 body: ins,
+                doc: doc.clone(),
             })
         } else {
             panic!()
@@ -649,41 +2084,166 @@ impl<'i> IRBuilder<'i> {
         self.available_type_var - 1
     }
 
-    fn next_label_id(&mut self) -> usize {
-        self.available_label_id += 1;
-        self.available_label_id - 1
+    fn next_id(&mut self) -> usize {
+        self.available_id += 1;
+        self.available_id - 1
+    }
+
+    /// A name like while3 for the next construct in the proc, which its labels and hidden slots go under,
+    /// so that they come out the same however the procs before it were lowered
+    fn next_construct(&mut self, kind: &str) -> String {
+        self.constructs += 1;
+        format!("{}{}", kind, self.constructs - 1)
     }
 
-    pub fn locate_var(&self, name: &String) -> Option<Type> {
-        //let mut scope_index = self.scopes.len() - 1;
-        //while scope_index >= 0 {
+    /// Finds the IR name and type that a source-level variable refers to.
+    /// Locals shadow globals, and an unknown variable is reported and then declared with a type to infer,
+    /// so that the rest of the proc still gets checked without it being reported again.
+    pub fn locate_var(&mut self, name: &str, pos: usize, len: usize) -> (String, Type) {
         for scope in self.scopes.iter().rev() {
-            if let Some(typ) = scope.get(name) {
-                return Some(typ.clone());
+            if let Some(binding) = scope.get(name) {
+                return binding.clone();
             }
-            //if scope_index == 0 {
-            //    break;
-            //}
-            //scope_index -= 1
+        }
+        if let Some(global) = self.locate_global(name) {
+            return (global.ir_name(), global.typ.clone());
         }
 
-        Logger::name_error(
-            format!("Can't find a variable named {} in the current scope", name).as_str(),
-            0, 0,
+        let candidates = self.scopes.iter()
+            .flat_map(|scope| scope.keys())
+            .chain(self.globals.iter().map(|global| &global.name))
+            .map(String::as_str);
+        let suggestion = did_you_mean(name, candidates);
+        self.logger.name_error(
+            Code::UnknownVariable,
+            format!("Can't find a variable named {} in the current scope{}", name, suggestion).as_str(),
+            pos, len,
         );
-        None
+        let binding = (name.to_owned(), Type::Variable(self.next_type_var()));
+        self.scopes.last_mut().unwrap().insert(name.to_owned(), binding.clone());
+        binding
+    }
+
+    /// Whether `name` is a variable in scope or a global, without reporting anything if it isn't
+    pub fn is_variable(&self, name: &str) -> bool {
+        self.scopes.iter().any(|scope| scope.contains_key(name)) || self.locate_global(name).is_some()
+    }
+
+    pub fn locate_global(&self, name: &str) -> Option<&IRGlobal> {
+        self.globals.iter().find(|global| global.name == name)
+    }
+
+    fn ensure_not_const(&self, name: &str, pos: usize, len: usize) -> Option<()> {
+        let constant = match self.scopes.iter().rev().find_map(|scope| scope.get(name)) {
+            Some((ir_name, _)) => self.consts.contains_key(ir_name),
+            None => self.locate_global(name).is_some_and(|global| global.constant),
+        };
+        if constant {
+            self.logger.type_error(Code::AssignToConst, format!("{} is a const, so it can't be assigned to", name).as_str(), pos, len);
+            return None;
+        }
+        Some(())
+    }
+
+    pub fn locate_proc(&self, name: &str, pos: usize, len: usize) -> Option<&IRProc> {
+        locate_proc(&self.procs, name, pos, len, &self.logger)
+    }
+}
+
+/// Two spellings of the same number, like 07 and 7 or -0 and 0, would match the same values
+fn literal_key(value: &str) -> String {
+    let (negative, digits) = match value.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, value),
+    };
+    match digits.parse::<u128>() {
+        Ok(0) => "0".to_owned(),
+        Ok(magnitude) if negative => format!("-{}", magnitude),
+        Ok(magnitude) => magnitude.to_string(),
+        Err(_) => value.to_owned(),
     }
+}
 
-    pub fn locate_proc(&self, name: &String) -> Option<&IRProc> {
-        for proc in &self.procs {
-            if proc.name == *name {
-                return Some(proc);
+/// Whether a node leaves a value on the stack
+fn is_expression(node: &Node) -> bool {
+    matches!(
+        node,
+        Node::Literal { .. }
+            | Node::Call { .. }
+            | Node::InfixOp { .. }
+            | Node::PrefixOp { .. }
+            | Node::PostfixOp { .. }
+            | Node::IndexOp { .. }
+            | Node::Cast { .. }
+            | Node::VariableRef { .. }
+            | Node::FieldAccess { .. }
+            | Node::Tuple { .. }
+            | Node::IfExpression { .. }
+    )
+}
+
+/// Whether control can't fall through the end of these instructions
+fn ends_in_terminator(ins: &[Span<Instruction>]) -> bool {
+    match ins.last() {
+        Some(last) => matches!(last.contents.ins, InstructionType::Return | InstructionType::Jump(_) | InstructionType::Fail(_)),
+        None => false,
+    }
+}
+
+/// Whether control can reach the end of a body without returning on the way.
+/// Labels only jumped to from later on can't make the end reachable, since a loop
+/// always starts at a label that's fallen into. A branch on `true` or `false` only
+/// ever goes one way, which is what makes `loop` without a `break` never end.
+fn falls_off_end(body: &[Span<Instruction>]) -> bool {
+    use InstructionType::*;
+    let mut live = true;
+    let mut reached = std::collections::HashSet::new();
+    for (i, ins) in body.iter().enumerate() {
+        match ins.contents.ins {
+            Label(ref label) => live = live || reached.contains(label),
+            _ if !live => (),
+            Jump(ref label) => {
+                reached.insert(label);
+                live = false;
+            }
+            Branch(ref body_label, ref else_label) => {
+                let condition = i.checked_sub(1).map(|prev| &body[prev].contents);
+                match condition {
+                    Some(Instruction { ins: Push(value), typ: Type::Bool }) if value == "true" => {
+                        reached.insert(body_label);
+                    }
+                    Some(Instruction { ins: Push(value), typ: Type::Bool }) if value == "false" => {
+                        reached.insert(else_label);
+                    }
+                    _ => {
+                        reached.insert(body_label);
+                        reached.insert(else_label);
+                    }
+                }
+                live = false;
             }
+            Return | Fail(_) => live = false,
+            _ => (),
+        }
+    }
+    live
+}
+
+/// The procs that come with the language instead of being declared, unless a proc of the same name is
+pub const BUILTIN_PROCS: [&str; 4] = ["print", "println", "len", "assert"];
+
+pub fn locate_proc<'a>(procs: &'a [IRProc], name: &str, pos: usize, len: usize, logger: &Logger) -> Option<&'a IRProc> {
+    for proc in procs {
+        if proc.name == name {
+            return Some(proc);
         }
-        Logger::name_error(
-            format!("Can't find a procedure named {} in the current module", name).as_str(),
-            0, 0,
-        );
-        None
     }
+    let candidates = procs.iter().map(|proc| proc.name.as_str()).chain(BUILTIN_PROCS);
+    let suggestion = did_you_mean(name, candidates);
+    logger.name_error(
+        Code::UnknownProc,
+        format!("Can't find a procedure named {} in the current module{}", name, suggestion).as_str(),
+        pos, len,
+    );
+    None
 }
@@ -2,22 +2,44 @@
 
 use std::fmt;
 
-use crate::errors::{Logger, Span};
+use crate::errors::{Code, Logger, Span};
+use crate::symbol::Symbol;
 
 const SPECIAL_CHARS: [char; 9] = ['(', ')', '[', ']', '{', '}', ',', '=', ':'];
 
+/// Every operator there is, an operator is always the longest of these that fits
+const OPERATORS: [&str; 30] = [
+    "+", "-", "*", "/", "//", "+~", "-~", "*~", "**",
+    "==", "!=", "<", ">", "<=", ">=",
+    "!", "&&", "||",
+    "&", "|", "^", "<<", ">>",
+    "+=", "-=", "*=", "/=", "//=",
+    ".", "..",
+];
+
+/// The operators a line can start with to carry on the expression from the line before.
+/// Anything infix is here, including + and -, so a line can't start with a unary + or -.
+const CONTINUING: [&str; 24] = [
+    "+", "-", "*", "/", "//", "+~", "-~", "*~", "**",
+    "==", "!=", "<", ">", "<=", ">=",
+    "&&", "||",
+    "&", "|", "^", "<<", ">>",
+    ".", "..",
+];
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum Token {
     // literals
     IntLiteral(String),
     FloatLiteral(String),
     StrLiteral(String),
+    CharLiteral(char),
 
     // identifier
-    Ident(String),
+    Ident(Symbol),
 
     // operator
-    Op(String),
+    Op(Symbol),
 
     // documentation comment
     DocComment(String),
@@ -28,6 +50,8 @@ pub enum Token {
     Elif,
     Else,
     While,
+    Match,
+    For,
     Loop,
     Var,
     Const,
@@ -35,6 +59,10 @@ pub enum Token {
     Use,
     Break,
     Continue,
+    Struct,
+    True,
+    False,
+    As,
 
     // special characters
     LParen,
@@ -54,47 +82,134 @@ pub enum Token {
     EOF,
 }
 
+impl Token {
+    /// How a keyword is spelled, or None for any other token
+    pub fn keyword(&self) -> Option<&'static str> {
+        use Token::*;
+        Some(match self {
+            Proc => "proc",
+            If => "if",
+            Elif => "elif",
+            Else => "else",
+            While => "while",
+            Match => "match",
+            For => "for",
+            Loop => "loop",
+            Var => "var",
+            Const => "const",
+            Return => "return",
+            Use => "use",
+            Break => "break",
+            Continue => "continue",
+            Struct => "struct",
+            True => "true",
+            False => "false",
+            As => "as",
+            _ => return None,
+        })
+    }
+}
+
+/// How a token reads in an error message, with an article where one's needed,
+/// so that it fits right after "found" or "expected"
 impl fmt::Display for Token {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{:?}", self)
+        use Token::*;
+        let text = match self {
+            IntLiteral(v) | FloatLiteral(v) => return write!(f, "the number {}", v),
+            StrLiteral(v) => return write!(f, "the string {:?}", v),
+            CharLiteral(ch) => return write!(f, "the character {:?}", ch),
+            Ident(id) => return write!(f, "the identifier {}", id),
+            Op(op) => return write!(f, "'{}'", op),
+            DocComment(_) => return write!(f, "a doc comment"),
+            Newline => return write!(f, "a newline"),
+            EOF => return write!(f, "the end of the file"),
+
+            LParen => "(",
+            RParen => ")",
+            LBracket => "[",
+            RBracket => "]",
+            LBrace => "{",
+            RBrace => "}",
+            Comma => ",",
+            Equals => "=",
+            Colon => ":",
+            // all that's left are the keywords
+            keyword => keyword.keyword().unwrap(),
+        };
+        write!(f, "'{}'", text)
     }
 }
 
+/// Lexes lazily, one token at a time, so that the parser can pull tokens as it needs them.
+/// Positions are byte offsets into the source.
 pub struct Lexer<'l> {
-    code: &'l [char],
+    code: &'l str,
+    // the byte the next char starts at
     index: usize,
+    // how many ( and [ are open, inside of which a newline doesn't end anything
     nesting: usize,
+    // the nesting outside of each open {, since a block inside parentheses still has its statements
+    // on lines of their own, and an unclosed ( shouldn't go on past the end of its block
+    outer_nesting: Vec<usize>,
+    // where the token currently being lexed starts
+    start: usize,
+    // added to every position, which says what file they're in
+    offset: usize,
+    // the last token handed out, which decides whether a newline ends a statement
+    last: Option<Token>,
+    // whether anything was wrong with the source so far
+    failed: bool,
+    // set once lexing can't go any further, even if the end of the source hasn't been reached
+    done: bool,
+    logger: Logger,
 }
 
 impl<'l> Lexer<'l> {
-    pub fn new(code: &'l [char]) -> Self {
+    pub fn new(code: &'l str, logger: &Logger) -> Self {
         Lexer {
             code,
             index: 0,
             nesting: 0,
+            outer_nesting: vec![],
+            start: 0,
+            offset: 0,
+            last: None,
+            failed: false,
+            done: false,
+            logger: logger.clone(),
         }
     }
 
-    fn peek(&self) -> char {
-        if self.index >= self.code.len() {
-            return '\0';
+    /// For a file other than the first, whose positions start at `offset`
+    pub fn with_offset(code: &'l str, offset: usize, logger: &Logger) -> Self {
+        Lexer {
+            offset,
+            ..Lexer::new(code, logger)
         }
-        self.code[self.index]
     }
 
-    fn next(&mut self) -> char {
-        self.index += 1;
-        if self.index >= self.code.len() {
-            return '\0';
+    fn peek(&self) -> char {
+        self.code[self.index..].chars().next().unwrap_or('\0')
+    }
+
+    fn peek2(&self) -> char {
+        self.code[self.index..].chars().nth(1).unwrap_or('\0')
+    }
+
+    fn bump(&mut self) -> char {
+        let ch = self.peek();
+        // stays put at the end, which peek marks with a \0 that isn't really there
+        if self.index < self.code.len() {
+            self.index += ch.len_utf8();
         }
-        let ch = self.code[self.index - 1];
         ch
     }
 
     fn ident_str(&mut self) -> String {
         let mut ident = String::new();
         while is_ident(self.peek()) {
-            ident.push(self.next());
+            ident.push(self.bump());
         }
         ident
     }
@@ -104,7 +219,10 @@ impl<'l> Lexer<'l> {
         let mut decimal_passed = false;
 
         while is_number(self.peek(), decimal_passed) {
-            number.push(match self.next() {
+            if self.peek() == '.' && self.peek2() == '.' {
+                break; // the start of a range, not a decimal point
+            }
+            number.push(match self.bump() {
                 '.' => {
                     decimal_passed = true;
                     '.'
@@ -112,6 +230,10 @@ impl<'l> Lexer<'l> {
                 c => c,
             });
         }
+        // a type written straight after the digits, like 42i64, is kept for the parser to pick apart
+        if is_ident_start(self.peek()) {
+            number.push_str(&self.ident_str());
+        }
         if decimal_passed {
             Token::FloatLiteral(number)
         } else {
@@ -119,36 +241,174 @@ impl<'l> Lexer<'l> {
         }
     }
 
-    fn operator(&mut self) -> Token {
-        let mut op = String::new();
-        while is_op(self.peek()) {
-            op.push(self.next());
+    /// Whatever comes after the operator is left for the next token, so `a+-b` is `a + -b`
+    fn operator(&mut self) -> Option<Token> {
+        let rest = &self.code[self.index..];
+        let op = OPERATORS.iter()
+            .filter(|op| rest.starts_with(*op))
+            .max_by_key(|op| op.len());
+        match op {
+            Some(op) => {
+                self.index += op.len();
+                Some(Token::Op(Symbol::intern(op)))
+            }
+            None => {
+                self.logger.syntax_error(
+                    Code::UnknownOperator,
+                    format!("There is no operator starting with {}", self.peek()).as_str(),
+                    self.offset + self.index,
+                    1,
+                );
+                self.bump();
+                None
+            }
         }
-        Token::Op(op)
     }
 
-    fn string(&mut self) -> Option<Token> {
+    /// A string literal along with any that come right after it with nothing but whitespace
+    /// in between, which is how a long one is split over several lines
+    fn strings(&mut self) -> Option<Token> {
+        let mut joined = String::new();
+        loop {
+            let (string, closed) = self.string()?;
+            joined.push_str(&string);
+            let rest = self.code[self.index..].trim_start_matches(|ch: char| ch.is_ascii_whitespace());
+            if !closed || !rest.starts_with('"') {
+                return Some(Token::StrLiteral(joined));
+            }
+            self.index = self.code.len() - rest.len();
+        }
+    }
+
+    /// The contents of one string literal, and whether it was closed before the end of its line.
+    /// One that wasn't is reported, and lexing carries on from the next line.
+    fn string(&mut self) -> Option<(String, bool)> {
         let mut string = String::new();
-        self.next(); // skip "
+        // pointing at where the string starts, since that's what never got closed
+        let start = self.offset + self.index;
+        self.bump(); // skip "
         while self.peek() != '"' {
-            if self.peek() == '\0' {
-                Logger::syntax_error("Encountered end of file while parsing string literal", self.index, string.len());
+            match self.peek() {
+                '\0' => {
+                    self.logger.syntax_error(Code::UnexpectedEof, "Encountered end of file while parsing string literal", start, 1);
+                    return None
+                }
+                '\n' => {
+                    self.logger.syntax_error(
+                        Code::UnterminatedString,
+                        "Unterminated string literal, use \\n or adjacent literals to have it go over several lines",
+                        start,
+                        1,
+                    );
+                    self.failed = true;
+                    return Some((string, false));
+                }
+                // a bad escape is reported by itself and left out, the rest of the string is still fine
+                '\\' => match self.escape() {
+                    Some(ch) => string.push(ch),
+                    None => self.failed = true,
+                },
+                _ => string.push(self.bump()),
+            }
+        }
+        self.bump(); // skip "
+        Some((string, true))
+    }
+
+    /// Decodes the escape sequence whose backslash is under the cursor.
+    /// A bad one is reported without going past the end of the line or a quote that isn't part of it,
+    /// so whatever it's in can still be closed.
+    fn escape(&mut self) -> Option<char> {
+        let start = self.index;
+        self.bump(); // skip \
+        let ch = self.peek();
+        if matches!(ch, '\n' | '\0') {
+            self.logger.syntax_error(Code::InvalidEscape, "Expected an escape sequence after \\", self.offset + start, 1);
+            return None
+        }
+        self.bump();
+        Some(match ch {
+            'n' => '\n',
+            't' => '\t',
+            'r' => '\r',
+            '\\' => '\\',
+            '"' => '"',
+            '\'' => '\'',
+            '0' => '\0',
+            'x' => {
+                let mut value = 0;
+                let mut digits = 0;
+                while digits < 2 && self.peek().is_ascii_hexdigit() {
+                    value = value * 16 + self.bump().to_digit(16).unwrap();
+                    digits += 1;
+                }
+                if digits < 2 || value >= 0x80 {
+                    self.logger.syntax_error(
+                        Code::InvalidEscape,
+                        "Expected two hex digits between 00 and 7f after \\x",
+                        self.offset + start,
+                        self.index - start,
+                    );
+                    return None
+                }
+                char::from(value as u8)
+            }
+            ch => {
+                self.logger.syntax_error(
+                    Code::InvalidEscape,
+                    format!("Unknown escape sequence \\{}", ch).as_str(),
+                    self.offset + start,
+                    self.index - start,
+                );
                 return None
             }
-            string.push(self.next());
+        })
+    }
+
+    /// A character between single quotes, which has to be a single ASCII character
+    /// or escape sequence since it's an n8
+    fn char_literal(&mut self) -> Option<Token> {
+        let start = self.index;
+        self.bump(); // skip '
+        let mut chars = vec![];
+        // a bad escape is reported by itself, but the rest of the literal still has to be skipped
+        let mut valid = true;
+        while !matches!(self.peek(), '\'' | '\n' | '\0') {
+            match self.peek() {
+                '\\' => match self.escape() {
+                    Some(ch) => chars.push(ch),
+                    None => valid = false,
+                },
+                _ => chars.push(self.bump()),
+            }
         }
-        self.next(); // skip "
-        Some(Token::StrLiteral(string))
+        if self.peek() != '\'' {
+            self.logger.syntax_error(Code::InvalidCharLiteral, "Unterminated character literal", self.offset + start, 1);
+            return None;
+        }
+        self.bump(); // skip '
+        let problem = match chars[..] {
+            _ if !valid => return None,
+            [ch] if ch.is_ascii() => return Some(Token::CharLiteral(ch)),
+            [ch] => format!("{:?} isn't an ASCII character, so it doesn't fit in an n8", ch),
+            [] => "A character literal can't be empty".to_owned(),
+            _ => "A character literal can only have one character in it, a str goes between double quotes".to_owned(),
+        };
+        self.logger.syntax_error(Code::InvalidCharLiteral, problem.as_str(), self.offset + start, self.index - start);
+        None
     }
 
     fn special(&mut self) -> Token {
         match self.peek() {
             '(' | '[' => self.nesting += 1,
-            ')' | ']' => self.nesting -= 1,
-            ',' | '=' | ':' | '{' | '}' => (),
+            // an unmatched bracket is the parser's problem
+            ')' | ']' => self.nesting = self.nesting.saturating_sub(1),
+            '{' => self.outer_nesting.push(std::mem::take(&mut self.nesting)),
+            '}' => self.nesting = self.outer_nesting.pop().unwrap_or(0),
+            ',' | '=' | ':' => (),
             _ => unreachable!(),
         };
-        match self.next() {
+        match self.bump() {
             '(' => Token::LParen,
             ')' => Token::RParen,
             '[' => Token::LBracket,
@@ -162,117 +422,188 @@ impl<'l> Lexer<'l> {
         }
     }
 
+    /// The newline at the end is left alone, it may still end a statement
     fn comment(&mut self) {
-        self.next(); // throwaway initial #
+        self.bump(); // throwaway initial #
         while self.peek() != '\n' && self.peek() != '\0' {
-            self.next();
+            self.bump();
         }
-        self.next();
     }
 
     fn doc_comment(&mut self) -> Token {
-        self.next(); // throwaway initial #
-        self.next(); // throwaway initial :
+        self.bump(); // throwaway initial #
+        self.bump(); // throwaway initial :
         let mut doc_comment = String::new();
         while self.peek() != '\n' && self.peek() != '\0' {
-            doc_comment.push(self.next());
+            doc_comment.push(self.bump());
         }
-        self.next();
         Token::DocComment(doc_comment)
     }
 
+    /// Lexes everything at once, which fails if any of it couldn't be lexed
     pub fn go(&mut self) -> Option<Vec<Span<Token>>> {
-        let mut tokens = vec![];
+        let tokens: Vec<_> = self.by_ref().collect();
+        if self.failed {
+            return None;
+        }
+        Some(tokens)
+    }
+
+    /// The next token, skipping over whitespace, comments and any newlines that don't end a statement
+    fn token(&mut self) -> Option<Span<Token>> {
         loop {
-            match self.peek() {
+            self.start = self.index;
+            let token = match self.peek() {
                 ch if is_ident_start(ch) => {
                     let id = self.ident_str();
-                    tokens.push(
-                        self.spanned(str_to_keyword(&id).unwrap_or_else(|| str_to_ident(&id))),
-                    );
+                    str_to_keyword(&id).unwrap_or_else(|| str_to_ident(&id))
                 }
                 '.' => {
-                    if is_number(self.code[self.index + 1], false) {
-                        let number = self.number();
-                        tokens.push(self.spanned(number));
+                    if self.peek2() == '.' {
+                        self.bump();
+                        self.bump();
+                        Token::Op(Symbol::intern(".."))
+                    } else if is_number(self.peek2(), false) {
+                        self.number()
                     } else {
-                        tokens.push(self.spanned(Token::Op(".".to_owned())));
-                        self.next();
+                        self.bump();
+                        Token::Op(Symbol::intern("."))
                     }
                 }
-                ch if is_number(ch, false) => {
-                    let number = self.number();
-                    tokens.push(self.spanned(number));
-                }
+                ch if is_number(ch, false) => self.number(),
                 '=' => {
-                    if self.code[self.index + 1] == '=' {
-                        let operator = self.operator();
-                        tokens.push(self.spanned(operator));
+                    if self.peek2() == '=' {
+                        // there's always an operator here
+                        self.operator().unwrap()
                     } else {
-                        let special = self.special();
-                        tokens.push(self.spanned(special));
+                        self.special()
                     }
                 }
                 '#' => {
-                    if self.code[self.index + 1] == ':' {
-                        let doc_comment = self.doc_comment();
-                        tokens.push(self.spanned(doc_comment));
+                    if self.peek2() == ':' {
+                        self.doc_comment()
                     } else {
                         self.comment();
+                        continue;
                     }
                 }
-                ch if is_special(ch) => {
-                    let special = self.special();
-                    tokens.push(self.spanned(special));
-                }
-                '"' => {
-                    let string = self.string()?;
-                    tokens.push(self.spanned(string));
-                }
-                ch if is_op(ch) => {
-                    let operator = self.operator();
-                    tokens.push(self.spanned(operator));
+                ch if is_special(ch) => self.special(),
+                '"' => match self.strings() {
+                    Some(string) => string,
+                    None => {
+                        // the rest of the source can't be told apart from the inside of the string
+                        self.failed = true;
+                        self.done = true;
+                        return None;
+                    }
+                },
+                // has to come before operators, which a lone ' would otherwise be taken for
+                '\'' => match self.char_literal() {
+                    Some(ch) => ch,
+                    None => {
+                        self.failed = true;
+                        continue;
+                    }
+                },
+                ';' => {
+                    // separates statements just like a newline does
+                    self.bump();
+                    if self.last == Some(Token::Newline) {
+                        continue;
+                    }
+                    Token::Newline
                 }
-                ch if ch == '\n' => {
-                    // token::proc doesn't matter, just needs to be
-                    // something that doesn't trigger newline suppression
-                    if tokens.len() > 0 && tokens.last().unwrap().contents == Token::Newline {
-                        self.next(); // skip consecutive newlines
-                    } else {
-                        match tokens
-                            .last()
-                            .unwrap_or(&Span {
-                                contents: Token::Proc,
-                                pos: 0,
-                                len: 0,
-                            })
-                            .contents
-                        {
-                            Token::Op(_) | Token::Comma => self.next(),
-                            _ if self.nesting != 0 => self.next(),
-                            _ => {
-                                tokens.push(self.spanned(Token::Newline));
-                                self.next()
-                            }
-                        };
+                ch if is_op(ch) => match self.operator() {
+                    Some(operator) => operator,
+                    None => {
+                        self.failed = true;
+                        continue;
                     }
+                },
+                '\n' => {
+                    let continues = matches!(self.last, Some(Token::Newline | Token::Op(_) | Token::Comma));
+                    if continues || self.nesting != 0 || self.next_line_continues() {
+                        self.bump();
+                        continue;
+                    }
+                    let newline = self.spanned(Token::Newline);
+                    self.bump();
+                    return Some(newline);
                 }
                 ch if ch.is_ascii_whitespace() => {
-                    self.next();
+                    self.bump();
+                    continue;
+                }
+                '\0' => return None,
+                ch => {
+                    self.logger.syntax_error(
+                        Code::UnexpectedCharacter,
+                        format!("Unexpected character {:?}", ch).as_str(),
+                        self.offset + self.index,
+                        ch.len_utf8(),
+                    );
+                    self.failed = true;
+                    self.bump();
+                    continue;
                 }
-                '\0' => break,
-                _ => unreachable!(),
+            };
+            return Some(self.spanned(token));
+        }
+    }
+
+    /// Whether the next line with a token on it starts with an operator that can only
+    /// carry on from the one before, like the `+ b` in `a\n+ b`
+    fn next_line_continues(&self) -> bool {
+        let mut rest = &self.code[self.index..];
+        loop {
+            rest = rest.trim_start_matches(|ch: char| ch.is_ascii_whitespace());
+            // a doc comment is a token of its own
+            if rest.starts_with('#') && !rest.starts_with("#:") {
+                rest = rest.find('\n').map_or("", |end| &rest[end..]);
+            } else {
+                break;
             }
         }
-        Some(tokens)
+        let op = OPERATORS.iter()
+            .filter(|op| rest.starts_with(*op))
+            .max_by_key(|op| op.len());
+        match op {
+            // .5 is a number
+            Some(&".") => !rest[1..].starts_with(|ch: char| ch.is_ascii_digit()),
+            Some(op) => CONTINUING.contains(op),
+            None => false,
+        }
     }
 
+    /// Spans the source consumed since the token started, or the token's own
+    /// length if the token is spanned before it's consumed
     fn spanned(&mut self, token: Token) -> Span<Token> {
+        let len = if self.index > self.start {
+            self.index - self.start
+        } else {
+            token_len(&token)
+        };
         Span {
             contents: token.clone(),
-            pos: self.index,
-            len: token_len(&token),
+            pos: self.offset + self.start,
+            len,
+        }
+    }
+}
+
+impl<'l> Iterator for Lexer<'l> {
+    type Item = Span<Token>;
+
+    fn next(&mut self) -> Option<Span<Token>> {
+        if self.done {
+            return None;
+        }
+        let token = self.token();
+        match &token {
+            Some(token) => self.last = Some(token.contents.clone()),
+            None => self.done = true,
         }
+        token
     }
 }
 
@@ -308,6 +639,8 @@ fn str_to_keyword(s: &str) -> Option<Token> {
         "else" => Token::Else,
         "elif" => Token::Elif,
         "while" => Token::While,
+        "match" => Token::Match,
+        "for" => Token::For,
         "loop" => Token::Loop,
         "var" => Token::Var,
         "const" => Token::Const,
@@ -315,13 +648,17 @@ fn str_to_keyword(s: &str) -> Option<Token> {
         "use" => Token::Use,
         "break" => Token::Break,
         "continue" => Token::Continue,
+        "struct" => Token::Struct,
+        "true" => Token::True,
+        "false" => Token::False,
+        "as" => Token::As,
         _ => return None,
     })
 }
 
 #[inline]
 fn str_to_ident(s: &str) -> Token {
-    Token::Ident(s.to_owned())
+    Token::Ident(Symbol::intern(s))
 }
 
 fn token_len(t: &Token) -> usize {
@@ -329,9 +666,10 @@ fn token_len(t: &Token) -> usize {
         Token::IntLiteral(s) => s.len(),
         Token::FloatLiteral(s) => s.len(),
         Token::StrLiteral(s) => s.len(),
+        Token::CharLiteral(_) => 3,
 
-        Token::Ident(s) => s.len(),
-        Token::Op(s) => s.len(),
+        Token::Ident(s) => s.as_str().len(),
+        Token::Op(s) => s.as_str().len(),
 
         Token::DocComment(s) => s.len() + 2,
 
@@ -340,6 +678,8 @@ fn token_len(t: &Token) -> usize {
         Token::Else => 4,
         Token::Elif => 4,
         Token::While => 5,
+        Token::Match => 5,
+        Token::For => 3,
         Token::Loop => 4,
         Token::Var => 3,
         Token::Const => 5,
@@ -347,6 +687,10 @@ fn token_len(t: &Token) -> usize {
         Token::Use => 3,
         Token::Break => 5,
         Token::Continue => 8,
+        Token::Struct => 6,
+        Token::True => 4,
+        Token::False => 5,
+        Token::As => 2,
 
         Token::LParen
         | Token::RParen
@@ -0,0 +1,89 @@
+//! The Elgin compiler as a library, for tools that want its results without anything being printed.
+//! Each entry point takes the text of a single file and hands back
+//! either what that stage produced or every diagnostic found on the way, in source order.
+//! Every call logs to a `Logger` of its own, so any number of them can run at once on different threads.
+
+#[macro_use]
+extern crate lazy_static;
+
+pub mod errors;
+pub mod types;
+pub mod symbol;
+
+pub mod lexer;
+pub mod parser;
+pub mod astgen;
+pub mod ast_printer;
+pub mod docs;
+pub mod ir;
+pub mod verify;
+pub mod cfg;
+pub mod analysis;
+pub mod opt;
+pub mod interp;
+pub mod llvm;
+pub mod modules;
+pub mod cache;
+
+use astgen::Node;
+use errors::{catching_panics, in_source_order, Diagnostic, Logger, Span};
+use ir::{IRBuilder, IRProc};
+use lexer::{Lexer, Token};
+use parser::{BufferedSource, Parser};
+
+pub fn lex(src: &str) -> Result<Vec<Span<Token>>, Vec<Diagnostic>> {
+    checked(|logger| Lexer::new(src, logger).go())
+}
+
+pub fn parse(src: &str) -> Result<Vec<Span<Node>>, Vec<Diagnostic>> {
+    checked(|logger| parse_file(src, logger).map(|(nodes, _)| nodes))
+}
+
+/// The typed IR of every proc, after the same passes the command line runs by default
+pub fn compile_to_ir(src: &str) -> Result<Vec<IRProc>, Vec<Diagnostic>> {
+    checked(|logger| {
+        let (nodes, available_type_var) = parse_file(src, logger)?;
+        let mut irbuilder = IRBuilder::new(&nodes, available_type_var, logger);
+        let (_, build_errors) = irbuilder.go();
+        if build_errors + irbuilder.analyze() > 0 {
+            return None;
+        }
+        opt::optimize(&mut irbuilder.procs);
+        opt::remove_dead_code(&mut irbuilder.procs, irbuilder.warnings, logger);
+        opt::tidy_labels(&mut irbuilder.procs);
+        Some(irbuilder.procs)
+    })
+}
+
+/// Markdown listing every proc's signature along with its doc comments
+pub fn extract_docs(src: &str) -> Result<String, Vec<Diagnostic>> {
+    checked(|logger| {
+        let (nodes, available_type_var) = parse_file(src, logger)?;
+        let mut irbuilder = IRBuilder::new(&nodes, available_type_var, logger);
+        irbuilder.go();
+        Some(docs::markdown(irbuilder.declared_procs()))
+    })
+}
+
+/// The nodes along with the next free type variable, which IR generation carries on from
+fn parse_file(src: &str, logger: &Logger) -> Option<(Vec<Span<Node>>, usize)> {
+    // lexing errors are still caught by `checked`, the parser just never sees the bad tokens
+    let mut parser = Parser::from_source(BufferedSource::new(Lexer::new(src, logger)), logger);
+    let (nodes, _) = parser.go();
+    Some((nodes, parser.available_type_var))
+}
+
+/// Runs a stage with a logger of its own, which only succeeds if nothing it logged is an error.
+/// Warnings are dropped when it does. A panic in the compiler comes back as an internal compiler error
+/// rather than unwinding into the caller or being printed by the panic hook.
+fn checked<T>(f: impl FnOnce(&Logger) -> Option<T>) -> Result<T, Vec<Diagnostic>> {
+    let logger = Logger::new();
+    match catching_panics(|| f(&logger)) {
+        Ok(Some(res)) if logger.error_count() == 0 => Ok(res),
+        Ok(_) => Err(in_source_order(logger.drain())),
+        Err(ice) => {
+            logger.report(vec![ice]);
+            Err(in_source_order(logger.drain()))
+        }
+    }
+}
@@ -4,38 +4,75 @@ extern crate llvm_sys as llvm;
 
 use llvm::core::*;
 use llvm::prelude::*;
+use llvm::target::*;
+use llvm::target_machine::*;
 
 use std::collections::HashMap;
-use std::ffi::CString;
+use std::ffi::{CStr, CString};
 
-use crate::ir::{CompareType, IRProc, Instruction, InstructionType};
-use crate::types::Type;
-use crate::errors::Span;
+use crate::opt::{self, Constant};
+use crate::ir::{CompareType, IRGlobal, IRProc, Instruction, InstructionType};
+use crate::types::{StructType, Type};
+use crate::errors::{self, Code, Diagnostic, ErrorType, Logger, Span};
 
 pub struct Generator<'g> {
     procs: &'g [IRProc],
+    globals: &'g [IRGlobal],
 
     context: *mut llvm::LLVMContext,
     builder: *mut llvm::LLVMBuilder,
     module: *mut llvm::LLVMModule,
 
     strings: Vec<CString>,
+    // every string constant emitted so far, so that each one only goes in the module once
+    string_constants: HashMap<String, LLVMValueRef>,
 
     stack: Vec<LLVMValueRef>,
     lookup: HashMap<String, LLVMValueRef>,
-    labels: HashMap<usize, LLVMBasicBlockRef>,
+    labels: HashMap<String, LLVMBasicBlockRef>,
     llvm_procs: HashMap<String, LLVMValueRef>,
+    structs: HashMap<String, (StructType, LLVMTypeRef)>,
 
     current_proc: LLVMValueRef,
+    // what the module is compiled for, null until set_target has been called
+    machine: LLVMTargetMachineRef,
+
+    // whether indexing checks that the index is in bounds first, aborting if it isn't
+    pub bounds_checks: bool,
+    // what a failed check's message points into the source with, and is formatted like
+    pub logger: Logger,
+}
+
+/// The name a proc's function gets in the object file. A proc without a body is one from outside,
+/// and keeps its name to be linked against. Every other one is prefixed so that it can't clash
+/// with anything it's linked with, like the C library functions used for bounds checks.
+/// Procs are all in one namespace however many files they come from, so the prefix is all it takes.
+/// main keeps its name as the entry point, unless it needs c_main to be one for it.
+fn symbol(proc: &IRProc) -> String {
+    if proc.body.is_empty() || proc.name == "main" && proc.ret_type == Type::I32 {
+        proc.name.clone()
+    } else {
+        format!("elgin_{}", proc.name)
+    }
+}
+
+/// The machine to generate code for, where anything left out is the one the compiler runs on.
+/// A cpu of "native" is the host's own CPU, along with every feature it has.
+#[derive(Default)]
+pub struct Target {
+    pub triple: Option<String>,
+    pub cpu: Option<String>,
+    // like +avx2,-sse4.1, added to what the cpu has anyway
+    pub features: Option<String>,
 }
 
 impl<'g> Generator<'g> {
-    pub fn new(procs: &'g [IRProc], module_name: &str, file_name: &str) -> Self {
+    pub fn new(procs: &'g [IRProc], globals: &'g [IRGlobal], module_name: &str, file_name: &str) -> Self {
         let context = unsafe { LLVMContextCreate() };
         let builder = unsafe { LLVMCreateBuilderInContext(context) };
-        let module = unsafe {
-            LLVMModuleCreateWithNameInContext(module_name.as_bytes().as_ptr() as *const _, context)
-        };
+        // LLVM copies the name, it only has to live until the module exists
+        let module_name = CString::new(module_name).unwrap();
+        let module = unsafe { LLVMModuleCreateWithNameInContext(module_name.as_ptr(), context) };
         unsafe {
             LLVMSetSourceFileName(
                 module,
@@ -46,52 +83,66 @@ impl<'g> Generator<'g> {
 
         Generator {
             procs,
+            globals,
 
             context,
             builder,
             module,
 
             strings: vec![],
+            string_constants: HashMap::new(),
 
             stack: vec![],
             lookup: HashMap::new(),
             labels: HashMap::new(),
             llvm_procs: HashMap::new(),
+            structs: HashMap::new(),
 
             current_proc: 0 as LLVMValueRef,
+            machine: std::ptr::null_mut(),
+
+            bounds_checks: true,
+            logger: Logger::new(),
         }
     }
 
     pub fn go(&mut self) {
+        self.declare_structs();
+        self.declare_globals();
         // Create declarations first
         for proc in self.procs {
             unsafe {
-                let mut llvm_arg_types: Vec<_> =
-                    proc.arg_types.iter().map(|t| self.llvm_type(&t)).collect();
+                let mut llvm_arg_types: Vec<_> = if proc.body.is_empty() {
+                    proc.arg_types.iter().map(|t| self.extern_type(t)).collect()
+                } else {
+                    proc.arg_types.iter().map(|t| self.llvm_type(t)).collect()
+                };
                 let proc_type = LLVMFunctionType(
                     self.llvm_type(&proc.ret_type),
                     llvm_arg_types.as_mut_ptr(),
                     llvm_arg_types.len() as u32,
                     0,
                     );
-                let this_proc = LLVMAddFunction(self.module, self.cstr(&proc.name), proc_type);
+                let this_proc = LLVMAddFunction(self.module, self.cstr(&symbol(proc)), proc_type);
                 self.llvm_procs.insert(proc.name.clone(), this_proc);
             }
         }
+        if let Some(main) = self.procs.iter().find(|proc| proc.name == "main" && proc.ret_type != Type::I32) {
+            self.c_main(main);
+        }
         // Then evaluate bodies
         for proc in self.procs {
             unsafe {
+                // index labels before starting, they're only unique within a proc
+                self.labels.clear();
                 for ins in &proc.body {
-                    // index labels before starting
-                    if let InstructionType::Label(label) = ins.contents.ins {
-                        let mut lbl = "lbl".to_string();
-                        lbl.push_str(&label.to_string());
-                        let bb = LLVMCreateBasicBlockInContext(self.context, self.cstr(&lbl));
-                        self.labels.insert(label, bb);
+                    if let InstructionType::Label(label) = &ins.contents.ins {
+                        let bb = LLVMCreateBasicBlockInContext(self.context, self.cstr(label));
+                        self.labels.insert(label.clone(), bb);
                     }
                 }
 
-                if proc.body.len() == 0 { // this is a declaration, not a definition
+                if proc.body.is_empty() { // this is a declaration, not a definition
                     continue 
                 }
 
@@ -110,35 +161,113 @@ impl<'g> Generator<'g> {
             for ins in &proc.body {
                 self.ins(&ins.clone());
             }
+            // the IR builder made sure nothing gets here, like the end of a loop without a break
+            unsafe {
+                if LLVMGetBasicBlockTerminator(LLVMGetInsertBlock(self.builder)).is_null() {
+                    LLVMBuildUnreachable(self.builder);
+                }
+            }
+        }
+    }
+
+    /// Consts and top-level vars become globals, which are loaded from and stored to just like local variables
+    fn declare_globals(&mut self) {
+        for global in self.globals {
+            let typ = self.llvm_type(&global.typ);
+            let value = match &global.value {
+                Some(value) => {
+                    self.push(value.clone(), global.typ.clone());
+                    self.stack.pop().unwrap()
+                }
+                None => unsafe { LLVMConstNull(typ) },
+            };
+            unsafe {
+                let global_ref = LLVMAddGlobal(self.module, typ, self.cstr(&global.name));
+                LLVMSetInitializer(global_ref, value);
+                if global.constant {
+                    LLVMSetGlobalConstant(global_ref, 1);
+                }
+                self.lookup.insert(global.ir_name(), global_ref);
+            }
+        }
+    }
+
+    /// Every struct that shows up anywhere becomes a named LLVM struct, which
+    /// is also how `Field` finds out which struct it's looking at.
+    fn declare_structs(&mut self) {
+        let mut found = vec![];
+        for proc in self.procs {
+            for t in proc.arg_types.iter().chain(Some(&proc.ret_type)) {
+                find_structs(t, &mut found);
+            }
+            for ins in &proc.body {
+                find_structs(&ins.contents.typ, &mut found);
+            }
+        }
+        // all of them have to exist before any of their bodies can refer to each other
+        for s in &found {
+            unsafe {
+                let name = self.cstr(&s.name);
+                let llvm_struct = LLVMStructCreateNamed(self.context, name);
+                self.structs.insert(s.name.clone(), (s.clone(), llvm_struct));
+            }
+        }
+        for s in &found {
+            unsafe {
+                let mut field_types: Vec<_> = s.fields.iter().map(|(_, t)| self.llvm_type(t)).collect();
+                LLVMStructSetBody(self.structs[&s.name].1, field_types.as_mut_ptr(), field_types.len() as u32, 0);
+            }
         }
     }
 
     fn ins(&mut self, ins: &Span<Instruction>) {
         use crate::ir::InstructionType::*;
+        errors::working_on(ins.pos, ins.len);
         let typ = ins.contents.typ.clone();
         match ins.clone().contents.ins {
             Push(s) => self.push(s, typ),
             Load(s) => self.load(s, typ),
             Store(s) => self.store(s, typ),
-            StoreIndexed(s) => self.store_indexed(s, typ),
+            StoreIndexed(s, indices) => self.store_indexed(s, &indices, ins),
             Allocate(s) => self.allocate(s, typ),
+            // whatever computed the value has already been built, so there's nothing left to do with it
+            Pop => {
+                self.stack.pop();
+            }
 
-            Index => self.index(typ),
+            Index(index) => self.index(&index, ins),
+            Field(f) => self.field(f),
+            StoreField(s, f) => self.store_field(s, f, typ),
+            Construct(count) => self.construct(count, typ),
+            Length => self.length(),
+            Cast(target) => self.cast(typ, target),
 
             Branch(b, e) => self.branch(b, e),
             Jump(l) => self.jump(l),
             Label(l) => self.label(l),
 
             Call(pn) => self.call(pn),
+            // a proc is already a pointer to its code
+            ProcRef(pn) => self.stack.push(self.llvm_procs[&pn]),
+            CallIndirect(count) => self.call_indirect(count, typ),
             Return => self.return_(typ),
+            Fail(message) => self.fail(&message, ins),
+            Print(newline) => self.print(typ, newline),
 
             Negate(wrap) => self.negate(typ, wrap),
+            Not => self.not(),
+            // the value is already on the stack, and analysis made sure it's a number
+            Plus => (),
             Add(wrap) => self.add(typ, wrap),
             Subtract(wrap) => self.subtract(typ, wrap),
             Multiply(wrap) => self.multiply(typ, wrap),
             IntDivide => self.int_divide(typ),
 
             Divide => self.divide(typ),
+            Power => self.power(typ, ins),
+            BitAnd | BitOr | BitXor => self.bitwise(&ins.contents.ins),
+            Shl(amount) => self.shift(typ, amount, true, ins),
+            Shr(amount) => self.shift(typ, amount, false, ins),
 
             Compare(m) => self.compare(m, typ),
         }
@@ -147,19 +276,16 @@ impl<'g> Generator<'g> {
     fn push(&mut self, s: String, typ: Type) {
         unsafe {
             let obj = match typ {
-                Type::I8
-                | Type::I16
-                | Type::I32
-                | Type::I64
-                | Type::I128 => {
-                    LLVMConstInt(self.llvm_type(&typ), s.parse().unwrap(), 0)
-                }
-                Type::N8
-                | Type::N16
-                | Type::N32
-                | Type::N64
-                | Type::N128 => {
-                    LLVMConstInt(self.llvm_type(&typ), s.parse().unwrap(), 0)
+                _ if typ.is_integer() => {
+                    // constant folding can leave negative values behind, which are stored
+                    // as their two's complement, cut down to however many bits the type has
+                    let bits = match opt::int_literal(&s, &typ) {
+                        Ok(Constant::Int(value)) => value as u128,
+                        Ok(Constant::Nat(value)) => value,
+                        _ => panic!("{} got past analysis as a {}", s, typ),
+                    };
+                    let words = [bits as u64, (bits >> 64) as u64];
+                    LLVMConstIntOfArbitraryPrecision(self.llvm_type(&typ), words.len() as u32, words.as_ptr())
                 }
                 Type::F32
                 | Type::F64
@@ -169,8 +295,12 @@ impl<'g> Generator<'g> {
                 Type::Undefined => {
                     LLVMGetUndef(self.llvm_type(&Type::I8))
                 }
-                Type::StrLiteral => {
-                    LLVMBuildGlobalStringPtr(self.builder, self.cstr(&s), self.cstr("tmpstr"))
+                Type::Str => {
+                    let mut parts = [
+                        self.global_string(&s),
+                        LLVMConstInt(LLVMInt64TypeInContext(self.context), s.len() as u64, 0),
+                    ];
+                    LLVMConstStructInContext(self.context, parts.as_mut_ptr(), parts.len() as u32, 0)
                 }
                 Type::Bool => {
                     let boolean = match s.as_str() {
@@ -186,6 +316,33 @@ impl<'g> Generator<'g> {
         }
     }
 
+    /// Emits a constant, null-terminated copy of `s` and returns an i8* to it.
+    /// Unlike LLVMBuildGlobalStringPtr this copes with embedded \0s.
+    /// The same string used again, wherever it's from, points at the copy that's already there.
+    fn global_string(&mut self, s: &str) -> LLVMValueRef {
+        if let Some(&existing) = self.string_constants.get(s) {
+            return existing;
+        }
+        unsafe {
+            let init = LLVMConstStringInContext(
+                self.context,
+                s.as_ptr() as *const _,
+                s.len() as u32,
+                0,
+            );
+            let global = LLVMAddGlobal(self.module, LLVMTypeOf(init), self.cstr("tmpstr"));
+            LLVMSetInitializer(global, init);
+            LLVMSetGlobalConstant(global, 1);
+            LLVMSetLinkage(global, llvm::LLVMLinkage::LLVMPrivateLinkage);
+            LLVMSetUnnamedAddress(global, llvm::LLVMUnnamedAddr::LLVMGlobalUnnamedAddr);
+            let zero = LLVMConstInt(LLVMInt32TypeInContext(self.context), 0, 0);
+            let mut indices = [zero, zero];
+            let ptr = LLVMConstInBoundsGEP(global, indices.as_mut_ptr(), indices.len() as u32);
+            self.string_constants.insert(s.to_owned(), ptr);
+            ptr
+        }
+    }
+
     fn load(&mut self, s: String, typ: Type) {
         let var = self.lookup.get(&s).unwrap();
         unsafe {
@@ -210,12 +367,22 @@ impl<'g> Generator<'g> {
         }
     }
 
-    fn store_indexed(&mut self, s: String, _typ: Type) {
+    fn store_indexed(&mut self, s: String, index_types: &[Type], ins: &Span<Instruction>) {
         unsafe {
+            let indices = self.stack.split_off(self.stack.len() - index_types.len());
+            let indices: Vec<_> = indices.into_iter().zip(index_types).map(|(index, typ)| self.index_value(index, typ)).collect();
             let value = self.stack.pop().unwrap();
-            let index = self.stack.pop().unwrap();
+            // one GEP through every level, with each index checked against the array it goes into
+            let mut typ = &ins.contents.typ;
+            for &index in &indices {
+                if let Type::Array(size, element) = typ {
+                    let length = LLVMConstInt(LLVMInt64TypeInContext(self.context), *size as u64, 0);
+                    self.check_bounds(index, length, ins);
+                    typ = element;
+                }
+            }
             let zero = LLVMConstInt(LLVMInt32TypeInContext(self.context), 0, 0);
-            let mut indices = vec![zero, index];
+            let mut indices: Vec<_> = std::iter::once(zero).chain(indices).collect();
             let gep = LLVMBuildGEP(self.builder, self.lookup[&s], indices.as_mut_ptr(), indices.len() as u32, self.cstr("tmpgep"));
             LLVMBuildStore(self.builder, value, gep);
         }
@@ -223,24 +390,58 @@ impl<'g> Generator<'g> {
 
     fn allocate(&mut self, s: String, typ: Type) {
         unsafe {
+            // every variable gets its slot at the start of the proc, otherwise one
+            // declared inside a loop would take up more of the stack each time around
+            let current = LLVMGetInsertBlock(self.builder);
+            let entry = LLVMGetEntryBasicBlock(self.current_proc);
+            let first = LLVMGetFirstInstruction(entry);
+            if first.is_null() {
+                LLVMPositionBuilderAtEnd(self.builder, entry);
+            } else {
+                LLVMPositionBuilderBefore(self.builder, first);
+            }
             let name = self.cstr(&s);
             let alloca = LLVMBuildAlloca(self.builder, self.llvm_type(&typ), name);
+            LLVMPositionBuilderAtEnd(self.builder, current);
             self.lookup.insert(s.clone(), alloca);
             let val = self.stack.pop().unwrap();
+            // a variable declared without a value is zeroed, like the interpreter does
             if LLVMIsUndef(val) == 0 {
                 LLVMBuildStore(self.builder, val, alloca);
             } else {
-                LLVMBuildStore(self.builder, LLVMGetUndef(self.llvm_type(&typ)), alloca);
+                LLVMBuildStore(self.builder, LLVMConstNull(self.llvm_type(&typ)), alloca);
             }
         }
     }
 
-    fn index(&mut self, _typ: Type) {
+    fn index(&mut self, index_type: &Type, ins: &Span<Instruction>) {
         unsafe {
             let index = self.stack.pop().unwrap();
+            let index = self.index_value(index, index_type);
             let object = self.stack.pop().unwrap();
+            // only a str is a struct here, every other indexable thing is an array
+            if LLVMGetTypeKind(LLVMTypeOf(object)) == llvm::LLVMTypeKind::LLVMStructTypeKind {
+                let length = LLVMBuildExtractValue(self.builder, object, 1, self.cstr("tmplen"));
+                self.check_bounds(index, length, ins);
+                let bytes = LLVMBuildExtractValue(self.builder, object, 0, self.cstr("tmpbytes"));
+                let mut indices = vec![index];
+                let gep = LLVMBuildGEP(self.builder, bytes, indices.as_mut_ptr(), indices.len() as u32, self.cstr("tmpgep"));
+                let ld = LLVMBuildLoad(self.builder, gep, self.cstr("tmpload"));
+                self.stack.push(ld);
+                return;
+            }
+            let length = LLVMConstInt(LLVMInt64TypeInContext(self.context), LLVMGetArrayLength(LLVMTypeOf(object)) as u64, 0);
+            self.check_bounds(index, length, ins);
             let zero = LLVMConstInt(LLVMInt32TypeInContext(self.context), 0, 0);
-            let pointer = LLVMGetOperand(object, 0); // semi-temporary workaround
+            // an array loaded from a variable is indexed where it is,
+            // any other array, like one returned from a proc, is put somewhere first
+            let pointer = if LLVMIsALoadInst(object).is_null() {
+                let pointer = self.entry_alloca(LLVMTypeOf(object));
+                LLVMBuildStore(self.builder, object, pointer);
+                pointer
+            } else {
+                LLVMGetOperand(object, 0)
+            };
             let mut indices = vec![zero, index];
             let gep = LLVMBuildGEP(self.builder, pointer, indices.as_mut_ptr(), indices.len() as u32, self.cstr("tmpgep"));
             let ld = LLVMBuildLoad(self.builder, gep, self.cstr("tmpload"));
@@ -248,6 +449,226 @@ impl<'g> Generator<'g> {
         }
     }
 
+    /// An index of any integer type as an i64, which is what the GEP that uses it treats it as,
+    /// so that an unsigned one too big for its signed counterpart isn't taken to be negative
+    fn index_value(&mut self, index: LLVMValueRef, typ: &Type) -> LLVMValueRef {
+        unsafe {
+            let i64_type = LLVMInt64TypeInContext(self.context);
+            LLVMBuildIntCast2(self.builder, index, i64_type, typ.is_signed() as i32, self.cstr("tmpindex"))
+        }
+    }
+
+    /// Carries on in a new block if `index` is less than `length`, both i64s, and otherwise prints
+    /// an error pointing at `ins` and aborts
+    fn check_bounds(&mut self, index: LLVMValueRef, length: LLVMValueRef, ins: &Span<Instruction>) {
+        if !self.bounds_checks {
+            return;
+        }
+        unsafe {
+            // a negative index is bigger than any length as far as an unsigned comparison goes
+            let in_bounds = LLVMBuildICmp(self.builder, llvm::LLVMIntPredicate::LLVMIntULT, index, length, self.cstr("tmpbounds"));
+            let ok = LLVMAppendBasicBlockInContext(self.context, self.current_proc, self.cstr("inbounds"));
+            let fail = LLVMAppendBasicBlockInContext(self.context, self.current_proc, self.cstr("outofbounds"));
+            LLVMBuildCondBr(self.builder, in_bounds, ok, fail);
+
+            LLVMPositionBuilderAtEnd(self.builder, fail);
+            self.fail("Index out of bounds", ins);
+            LLVMPositionBuilderAtEnd(self.builder, ok);
+        }
+    }
+
+    /// Prints an error pointing at `ins` to stderr and aborts, which ends the current block
+    fn fail(&mut self, message: &str, ins: &Span<Instruction>) {
+        // the diagnostic is known in full already, down to the line it points at
+        let message = Diagnostic {
+            severity: ErrorType::RuntimeError,
+            code: Some(Code::RuntimeFailure),
+            message: message.to_owned(),
+            pos: ins.pos,
+            len: ins.len,
+        };
+        let message = self.logger.render(vec![message]);
+        unsafe {
+            let i32_type = LLVMInt32TypeInContext(self.context);
+            let i64_type = LLVMInt64TypeInContext(self.context);
+            let i8_ptr = LLVMPointerType(LLVMInt8TypeInContext(self.context), 0);
+            // abort doesn't flush stdout, so whatever the program printed before this would be lost
+            let fflush = self.c_proc("fflush", i32_type, &mut [i8_ptr]);
+            LLVMBuildCall(self.builder, fflush, [LLVMConstNull(i8_ptr)].as_mut_ptr(), 1, self.cstr(""));
+            let write = self.c_proc("write", i64_type, &mut [i32_type, i8_ptr, i64_type]);
+            let mut args = [
+                LLVMConstInt(i32_type, 2, 0),
+                self.global_string(&message),
+                LLVMConstInt(i64_type, message.len() as u64, 0),
+            ];
+            LLVMBuildCall(self.builder, write, args.as_mut_ptr(), args.len() as u32, self.cstr(""));
+            let abort = self.c_proc("abort", LLVMVoidTypeInContext(self.context), &mut []);
+            LLVMBuildCall(self.builder, abort, [].as_mut_ptr(), 0, self.cstr(""));
+            LLVMBuildUnreachable(self.builder);
+        }
+    }
+
+    /// Goes through printf, with whatever conversion it needs for the type.
+    /// Numbers are widened to what %lld, %llu and %g take, and a str is written by its length since it has no terminator.
+    fn print(&mut self, typ: Type, newline: bool) {
+        let newline = if newline { "\n" } else { "" };
+        unsafe {
+            let value = self.stack.pop().unwrap();
+            let i32_type = LLVMInt32TypeInContext(self.context);
+            let i64_type = LLVMInt64TypeInContext(self.context);
+            let (format, mut args) = if typ.is_integer() {
+                let wide = LLVMBuildIntCast2(self.builder, value, i64_type, typ.is_signed() as i32, self.cstr("tmpwide"));
+                (if typ.is_signed() { "%lld" } else { "%llu" }, vec![wide])
+            } else if typ.is_float() {
+                let wide = LLVMBuildFPCast(self.builder, value, LLVMDoubleTypeInContext(self.context), self.cstr("tmpwide"));
+                ("%g", vec![wide])
+            } else if typ == Type::Bool {
+                let (t, f) = (self.global_string("true"), self.global_string("false"));
+                ("%s", vec![LLVMBuildSelect(self.builder, value, t, f, self.cstr("tmpbool"))])
+            } else {
+                let ptr = LLVMBuildExtractValue(self.builder, value, 0, self.cstr("tmpptr"));
+                let len = LLVMBuildExtractValue(self.builder, value, 1, self.cstr("tmplen"));
+                let len = LLVMBuildTrunc(self.builder, len, i32_type, self.cstr("tmplen"));
+                ("%.*s", vec![len, ptr])
+            };
+            args.insert(0, self.global_string(&format!("{}{}", format, newline)));
+
+            let name = self.cstr("printf");
+            let mut printf = LLVMGetNamedFunction(self.module, name);
+            if printf.is_null() {
+                let mut arg_types = [LLVMPointerType(LLVMInt8TypeInContext(self.context), 0)];
+                let printf_type = LLVMFunctionType(i32_type, arg_types.as_mut_ptr(), arg_types.len() as u32, 1);
+                printf = LLVMAddFunction(self.module, name, printf_type);
+            }
+            LLVMBuildCall(self.builder, printf, args.as_mut_ptr(), args.len() as u32, self.cstr(""));
+        }
+    }
+
+    /// The C entry point for a main that doesn't return an i32 itself, which calls it
+    /// and returns what it did as an i32, or 0 if it returns nothing
+    fn c_main(&mut self, main: &IRProc) {
+        unsafe {
+            let i32_type = LLVMInt32TypeInContext(self.context);
+            let proc_type = LLVMFunctionType(i32_type, [].as_mut_ptr(), 0, 0);
+            let c_main = LLVMAddFunction(self.module, self.cstr("main"), proc_type);
+            let bb = LLVMAppendBasicBlockInContext(self.context, c_main, self.cstr("entry"));
+            LLVMPositionBuilderAtEnd(self.builder, bb);
+            let inner = self.llvm_procs["main"];
+            if main.ret_type == Type::Undefined {
+                LLVMBuildCall(self.builder, inner, [].as_mut_ptr(), 0, self.cstr(""));
+                LLVMBuildRet(self.builder, LLVMConstInt(i32_type, 0, 0));
+            } else {
+                let value = LLVMBuildCall(self.builder, inner, [].as_mut_ptr(), 0, self.cstr("tmpcall"));
+                let signed = main.ret_type.is_signed() as i32;
+                let value = LLVMBuildIntCast2(self.builder, value, i32_type, signed, self.cstr("tmpcast"));
+                LLVMBuildRet(self.builder, value);
+            }
+        }
+    }
+
+    /// A function from the C library, declared the first time it's needed
+    fn c_proc(&mut self, name: &str, ret_type: LLVMTypeRef, arg_types: &mut [LLVMTypeRef]) -> LLVMValueRef {
+        unsafe {
+            let existing = LLVMGetNamedFunction(self.module, self.cstr(name));
+            if !existing.is_null() {
+                return existing;
+            }
+            let proc_type = LLVMFunctionType(ret_type, arg_types.as_mut_ptr(), arg_types.len() as u32, 0);
+            LLVMAddFunction(self.module, self.cstr(name), proc_type)
+        }
+    }
+
+    /// Space for a temporary at the start of the proc, so that it's only made once however often it's used
+    fn entry_alloca(&mut self, typ: LLVMTypeRef) -> LLVMValueRef {
+        unsafe {
+            let entry = LLVMGetEntryBasicBlock(self.current_proc);
+            let builder = LLVMCreateBuilderInContext(self.context);
+            let first = LLVMGetFirstInstruction(entry);
+            if first.is_null() {
+                LLVMPositionBuilderAtEnd(builder, entry);
+            } else {
+                LLVMPositionBuilderBefore(builder, first);
+            }
+            let alloca = LLVMBuildAlloca(builder, typ, self.cstr("tmparray"));
+            LLVMDisposeBuilder(builder);
+            alloca
+        }
+    }
+
+    fn field(&mut self, field: String) {
+        unsafe {
+            let object = self.stack.pop().unwrap();
+            let object_type = LLVMTypeOf(object);
+            // a tuple is a struct without a name, and its fields are named by their positions
+            let index = if LLVMIsLiteralStruct(object_type) != 0 {
+                field.parse().unwrap()
+            } else {
+                let struct_name = CStr::from_ptr(LLVMGetStructName(object_type)).to_string_lossy();
+                let (struct_type, _) = &self.structs[struct_name.as_ref()];
+                struct_type.fields.iter().position(|(f, _)| *f == field).unwrap()
+            };
+            let value = LLVMBuildExtractValue(self.builder, object, index as u32, self.cstr("tmpfield"));
+            self.stack.push(value);
+        }
+    }
+
+    fn store_field(&mut self, s: String, field: String, typ: Type) {
+        unsafe {
+            let value = self.stack.pop().unwrap();
+            let (index, _) = typ.field(&field).unwrap();
+            let gep = LLVMBuildStructGEP(self.builder, self.lookup[&s], index as u32, self.cstr("tmpgep"));
+            LLVMBuildStore(self.builder, value, gep);
+        }
+    }
+
+    fn construct(&mut self, count: usize, typ: Type) {
+        unsafe {
+            let values = self.stack.split_off(self.stack.len() - count);
+            let mut object = LLVMGetUndef(self.llvm_type(&typ));
+            for (i, value) in values.into_iter().enumerate() {
+                object = LLVMBuildInsertValue(self.builder, object, value, i as u32, self.cstr("tmpstruct"));
+            }
+            self.stack.push(object);
+        }
+    }
+
+    fn length(&mut self) {
+        unsafe {
+            let object = self.stack.pop().unwrap();
+            let length = LLVMBuildExtractValue(self.builder, object, 1, self.cstr("tmplen"));
+            self.stack.push(length);
+        }
+    }
+
+    /// `from` can only be a different type if both are numbers
+    fn cast(&mut self, from: Type, to: Type) {
+        unsafe {
+            let value = self.stack.pop().unwrap();
+            let target = self.llvm_type(&to);
+            let name = self.cstr("tmpcast");
+            let signed = from.is_signed();
+            let cast = if from == to {
+                value
+            } else if from.is_integer() && to.is_integer() {
+                // only the source decides between sign and zero extension
+                LLVMBuildIntCast2(self.builder, value, target, signed as i32, name)
+            } else if from.is_float() && to.is_float() {
+                LLVMBuildFPCast(self.builder, value, target, name)
+            } else if from.is_integer() {
+                if signed {
+                    LLVMBuildSIToFP(self.builder, value, target, name)
+                } else {
+                    LLVMBuildUIToFP(self.builder, value, target, name)
+                }
+            } else if to.is_signed() {
+                LLVMBuildFPToSI(self.builder, value, target, name)
+            } else {
+                LLVMBuildFPToUI(self.builder, value, target, name)
+            };
+            self.stack.push(cast);
+        }
+    }
+
     fn call(&mut self, proc_name: String) {
         unsafe {
             let proc = self.llvm_procs[&proc_name];
@@ -256,8 +677,28 @@ impl<'g> Generator<'g> {
             for _ in 0..arg_count {
                 args.insert(0, self.stack.pop().unwrap());
             }
-            let call = LLVMBuildCall(self.builder, proc, args.as_mut_ptr(), args.len() as u32, self.cstr("tmpcall"));
-            // FIXME calls to functions that return void need to not be named HOW TO DO THIS?
+            // procs without a body come from C, which only wants the bytes of a str
+            let ir_proc = self.procs.iter().find(|p| p.name == proc_name).unwrap();
+            if ir_proc.body.is_empty() {
+                for (arg, typ) in args.iter_mut().zip(&ir_proc.arg_types) {
+                    if *typ == Type::Str {
+                        *arg = LLVMBuildExtractValue(self.builder, *arg, 0, self.cstr("tmpbytes"));
+                    }
+                }
+            }
+            // a call that returns nothing can't be given a name
+            let name = if ir_proc.ret_type == Type::Undefined { "" } else { "tmpcall" };
+            let call = LLVMBuildCall(self.builder, proc, args.as_mut_ptr(), args.len() as u32, self.cstr(name));
+            self.stack.push(call);
+        }
+    }
+
+    fn call_indirect(&mut self, count: usize, ret_type: Type) {
+        unsafe {
+            let mut args = self.stack.split_off(self.stack.len() - count);
+            let proc = self.stack.pop().unwrap();
+            let name = if ret_type == Type::Undefined { "" } else { "tmpcall" };
+            let call = LLVMBuildCall(self.builder, proc, args.as_mut_ptr(), args.len() as u32, self.cstr(name));
             self.stack.push(call);
         }
     }
@@ -318,6 +759,17 @@ impl<'g> Generator<'g> {
         }
     }
 
+    fn not(&mut self) {
+        unsafe {
+            let not = LLVMBuildNot(
+                self.builder,
+                self.stack.pop().unwrap(),
+                self.cstr("tmpnot"),
+            );
+            self.stack.push(not);
+        }
+    }
+
     fn add(&mut self, typ: Type, wrap: bool) {
         unsafe {
             let add = match typ {
@@ -475,20 +927,22 @@ impl<'g> Generator<'g> {
 
     fn int_divide(&mut self, typ: Type) {
         unsafe {
-            let mul = match typ {
+            let v1 = self.stack.pop().unwrap();
+            let v2 = self.stack.pop().unwrap();
+            let div = match typ {
                 Type::I8 | Type::I16 | Type::I32 | Type::I64 | Type::I128 => {
                     LLVMBuildSDiv(
                             self.builder,
-                            self.stack.pop().unwrap(),
-                            self.stack.pop().unwrap(),
+                            v2,
+                            v1,
                             self.cstr("tmpdiv"),
                     )
                 },
                 Type::N8 | Type::N16 | Type::N32 | Type::N64 | Type::N128 => {
                     LLVMBuildUDiv(
                             self.builder,
-                            self.stack.pop().unwrap(),
-                            self.stack.pop().unwrap(),
+                            v2,
+                            v1,
                             self.cstr("tmpdiv"),
                     )
                 },
@@ -497,13 +951,15 @@ impl<'g> Generator<'g> {
                     | Type::F128 => unreachable!(),
                 _ => unreachable!(),
             };
-            self.stack.push(mul);
+            self.stack.push(div);
         }
     }
 
     fn divide(&mut self, typ: Type) {
         unsafe {
-            let mul = match typ {
+            let v1 = self.stack.pop().unwrap();
+            let v2 = self.stack.pop().unwrap();
+            let div = match typ {
                 Type::I8 | Type::I16 | Type::I32 | Type::I64 | Type::I128 |
                 Type::N8 | Type::N16 | Type::N32 | Type::N64 | Type::N128 => unreachable!(),
                 Type::F32
@@ -511,80 +967,178 @@ impl<'g> Generator<'g> {
                     | Type::F128 => {
                         LLVMBuildFDiv(
                                 self.builder,
-                                self.stack.pop().unwrap(),
-                                self.stack.pop().unwrap(),
+                                v2,
+                                v1,
                                 self.cstr("tmpdiv"),
                         )
                     },
                 _ => unreachable!(),
             };
-            self.stack.push(mul);
+            self.stack.push(div);
+        }
+    }
+
+    /// Floats have an intrinsic for it, an integer is multiplied by itself in a loop
+    /// so that every step is checked like any other multiplication
+    fn power(&mut self, typ: Type, ins: &Span<Instruction>) {
+        unsafe {
+            let exponent = self.stack.pop().unwrap();
+            let base = self.stack.pop().unwrap();
+            let llvm_type = self.llvm_type(&typ);
+            if typ.is_float() {
+                let name = match typ {
+                    Type::F32 => "llvm.pow.f32",
+                    Type::F64 => "llvm.pow.f64",
+                    _ => "llvm.pow.f128",
+                };
+                let pow = self.c_proc(name, llvm_type, &mut [llvm_type, llvm_type]);
+                let mut args = [base, exponent];
+                let res = LLVMBuildCall(self.builder, pow, args.as_mut_ptr(), args.len() as u32, self.cstr("tmppow"));
+                self.stack.push(res);
+                return;
+            }
+
+            let zero = LLVMConstInt(llvm_type, 0, 0);
+            if typ.is_signed() {
+                let negative = LLVMBuildICmp(self.builder, llvm::LLVMIntPredicate::LLVMIntSLT, exponent, zero, self.cstr("tmpneg"));
+                let ok = LLVMAppendBasicBlockInContext(self.context, self.current_proc, self.cstr("powpositive"));
+                let fail = LLVMAppendBasicBlockInContext(self.context, self.current_proc, self.cstr("pownegative"));
+                LLVMBuildCondBr(self.builder, negative, fail, ok);
+                LLVMPositionBuilderAtEnd(self.builder, fail);
+                self.fail("An integer can't be raised to a negative power", ins);
+                LLVMPositionBuilderAtEnd(self.builder, ok);
+            }
+            let result = self.entry_alloca(llvm_type);
+            let left = self.entry_alloca(llvm_type);
+            LLVMBuildStore(self.builder, LLVMConstInt(llvm_type, 1, 0), result);
+            LLVMBuildStore(self.builder, exponent, left);
+            let check = LLVMAppendBasicBlockInContext(self.context, self.current_proc, self.cstr("powcheck"));
+            let body = LLVMAppendBasicBlockInContext(self.context, self.current_proc, self.cstr("powbody"));
+            let done = LLVMAppendBasicBlockInContext(self.context, self.current_proc, self.cstr("powdone"));
+            LLVMBuildBr(self.builder, check);
+
+            LLVMPositionBuilderAtEnd(self.builder, check);
+            let remaining = LLVMBuildLoad(self.builder, left, self.cstr("tmpleft"));
+            let more = LLVMBuildICmp(self.builder, llvm::LLVMIntPredicate::LLVMIntNE, remaining, zero, self.cstr("tmpmore"));
+            LLVMBuildCondBr(self.builder, more, body, done);
+
+            LLVMPositionBuilderAtEnd(self.builder, body);
+            let so_far = LLVMBuildLoad(self.builder, result, self.cstr("tmpresult"));
+            self.stack.push(so_far);
+            self.stack.push(base);
+            self.multiply(typ, false);
+            LLVMBuildStore(self.builder, self.stack.pop().unwrap(), result);
+            let one = LLVMConstInt(llvm_type, 1, 0);
+            LLVMBuildStore(self.builder, LLVMBuildSub(self.builder, remaining, one, self.cstr("tmpleft")), left);
+            LLVMBuildBr(self.builder, check);
+
+            LLVMPositionBuilderAtEnd(self.builder, done);
+            let res = LLVMBuildLoad(self.builder, result, self.cstr("tmppow"));
+            self.stack.push(res);
+        }
+    }
+
+    fn bitwise(&mut self, op: &InstructionType) {
+        unsafe {
+            let v1 = self.stack.pop().unwrap();
+            let v2 = self.stack.pop().unwrap();
+            let name = self.cstr("tmpbits");
+            let res = match op {
+                InstructionType::BitAnd => LLVMBuildAnd(self.builder, v2, v1, name),
+                InstructionType::BitOr => LLVMBuildOr(self.builder, v2, v1, name),
+                _ => LLVMBuildXor(self.builder, v2, v1, name),
+            };
+            self.stack.push(res);
+        }
+    }
+
+    /// Shifting by as many bits as the type has or more isn't defined in LLVM, so it's checked first.
+    /// The amount is compared as unsigned in its own type, where a negative one is too big as well.
+    fn shift(&mut self, typ: Type, amount_type: Type, left: bool, ins: &Span<Instruction>) {
+        unsafe {
+            let amount = self.stack.pop().unwrap();
+            let value = self.stack.pop().unwrap();
+            let limit = LLVMConstInt(self.llvm_type(&amount_type), opt::bits(&typ) as u64, 0);
+            let in_range = LLVMBuildICmp(self.builder, llvm::LLVMIntPredicate::LLVMIntULT, amount, limit, self.cstr("tmprange"));
+            let ok = LLVMAppendBasicBlockInContext(self.context, self.current_proc, self.cstr("shiftinrange"));
+            let fail = LLVMAppendBasicBlockInContext(self.context, self.current_proc, self.cstr("shiftoutofrange"));
+            LLVMBuildCondBr(self.builder, in_range, ok, fail);
+            LLVMPositionBuilderAtEnd(self.builder, fail);
+            self.fail(&opt::shift_range(&typ), ins);
+            LLVMPositionBuilderAtEnd(self.builder, ok);
+
+            // it's in range, so it fits in the value's type however it's extended or truncated
+            let amount = LLVMBuildIntCast2(self.builder, amount, self.llvm_type(&typ), 0, self.cstr("tmpamount"));
+            let name = self.cstr("tmpshift");
+            let res = if left {
+                LLVMBuildShl(self.builder, value, amount, name)
+            } else if typ.is_signed() {
+                LLVMBuildAShr(self.builder, value, amount, name)
+            } else {
+                LLVMBuildLShr(self.builder, value, amount, name)
+            };
+            self.stack.push(res);
         }
     }
 
-    fn compare(&mut self, comptype: CompareType, _typ: Type) {
+    fn compare(&mut self, comptype: CompareType, typ: Type) {
         unsafe {
             use llvm::LLVMIntPredicate::*;
             use llvm::LLVMRealPredicate::*;
-            use llvm::LLVMTypeKind::*;
             let v1 = self.stack.pop().unwrap();
             let v2 = self.stack.pop().unwrap();
-            let cmp = match LLVMGetTypeKind(LLVMTypeOf(v1)) {
-                LLVMIntegerTypeKind => {
-                    LLVMBuildICmp(
-                        self.builder,
-                        match comptype {
-                            CompareType::EQ => LLVMIntEQ,
-                            CompareType::NE => LLVMIntNE,
-                            CompareType::LT => LLVMIntSLT,
-                            CompareType::GT => LLVMIntSGT,
-                            CompareType::LE => LLVMIntSLE,
-                            CompareType::GE => LLVMIntSGE,
-                        },
-                        v2,
-                        v1,
-                        self.cstr("tmpcmp"),
-                    )
-                },
-                //Type::N8 | Type::N16 | Type::N32 | Type::N64 | Type::N128 => {
-                //    LLVMBuildICmp(
-                //        self.builder,
-                //        match comptype {
-                //            CompareType::EQ => LLVMIntEQ,
-                //            CompareType::NE => LLVMIntNE,
-                //            CompareType::LT => LLVMIntUGT,
-                //            CompareType::GT => LLVMIntUGT,
-                //            CompareType::LE => LLVMIntULE,
-                //            CompareType::GE => LLVMIntUGE,
-                //        },
-                //        self.stack.pop().unwrap(),
-                //        self.stack.pop().unwrap(),
-                //        self.cstr("tmpcmp"),
-                //    )
-                //},
-                LLVMFloatTypeKind => {
-                        LLVMBuildFCmp(
-                            self.builder,
-                            match comptype {
-                                CompareType::EQ => LLVMRealOEQ,
-                                CompareType::NE => LLVMRealONE,
-                                CompareType::LT => LLVMRealOGT,
-                                CompareType::GT => LLVMRealOGT,
-                                CompareType::LE => LLVMRealOLE,
-                                CompareType::GE => LLVMRealOGE,
-                            },
-                            v2,
-                            v1,
-                            self.cstr("tmpcmp"),
-                        )
-                    }
-                t => unreachable!("{:?}", t),
+            let cmp = if typ.is_float() {
+                LLVMBuildFCmp(
+                    self.builder,
+                    match comptype {
+                        CompareType::EQ => LLVMRealOEQ,
+                        CompareType::NE => LLVMRealONE,
+                        CompareType::LT => LLVMRealOLT,
+                        CompareType::GT => LLVMRealOGT,
+                        CompareType::LE => LLVMRealOLE,
+                        CompareType::GE => LLVMRealOGE,
+                    },
+                    v2,
+                    v1,
+                    self.cstr("tmpcmp"),
+                )
+            } else if typ.is_signed() {
+                LLVMBuildICmp(
+                    self.builder,
+                    match comptype {
+                        CompareType::EQ => LLVMIntEQ,
+                        CompareType::NE => LLVMIntNE,
+                        CompareType::LT => LLVMIntSLT,
+                        CompareType::GT => LLVMIntSGT,
+                        CompareType::LE => LLVMIntSLE,
+                        CompareType::GE => LLVMIntSGE,
+                    },
+                    v2,
+                    v1,
+                    self.cstr("tmpcmp"),
+                )
+            } else {
+                // nN types, and bools, where false comes before true
+                LLVMBuildICmp(
+                    self.builder,
+                    match comptype {
+                        CompareType::EQ => LLVMIntEQ,
+                        CompareType::NE => LLVMIntNE,
+                        CompareType::LT => LLVMIntULT,
+                        CompareType::GT => LLVMIntUGT,
+                        CompareType::LE => LLVMIntULE,
+                        CompareType::GE => LLVMIntUGE,
+                    },
+                    v2,
+                    v1,
+                    self.cstr("tmpcmp"),
+                )
             };
             self.stack.push(cmp);
         }
     }
 
-    fn branch(&mut self, then_label: usize, else_label: usize) {
+    fn branch(&mut self, then_label: String, else_label: String) {
         unsafe {
             let br = LLVMBuildCondBr(
                 self.builder,
@@ -596,20 +1150,28 @@ impl<'g> Generator<'g> {
         }
     }
 
-    fn jump(&mut self, label: usize) {
+    fn jump(&mut self, label: String) {
         unsafe {
             let jmp = LLVMBuildBr(self.builder, self.labels[&label]);
             self.stack.push(jmp);
         }
     }
 
-    fn label(&mut self, label: usize) {
+    fn label(&mut self, label: String) {
         unsafe {
             LLVMAppendExistingBasicBlock(self.current_proc, self.labels[&label]);
             LLVMPositionBuilderAtEnd(self.builder, self.labels[&label]);
         }
     }
 
+    /// How a type is passed to a proc declared in C, which knows nothing about strs
+    fn extern_type(&self, t: &Type) -> LLVMTypeRef {
+        match t {
+            Type::Str => self.llvm_type(&Type::Ptr(Box::new(Type::I8))),
+            t => self.llvm_type(t),
+        }
+    }
+
     fn llvm_type(&self, t: &Type) -> LLVMTypeRef {
         unsafe {
             match t {
@@ -626,13 +1188,33 @@ impl<'g> Generator<'g> {
                 Type::N128 => LLVMInt128TypeInContext(self.context),
 
                 Type::F32 => LLVMFloatTypeInContext(self.context),
-                Type::F64 => LLVMFloatTypeInContext(self.context),
-                Type::F128 => LLVMFloatTypeInContext(self.context),
+                Type::F64 => LLVMDoubleTypeInContext(self.context),
+                Type::F128 => LLVMFP128TypeInContext(self.context),
 
                 Type::Bool => LLVMInt1TypeInContext(self.context),
 
-                Type::Ptr(t) => LLVMPointerType(self.llvm_type(&t), 0),
-                Type::Array(size, t) => LLVMArrayType(self.llvm_type(&t), *size as u32),
+                Type::Ptr(t) => LLVMPointerType(self.llvm_type(t), 0),
+                // arrays are values like any other, so they're copied into the procs they're passed to
+                // and out of the ones that return them, however big they are
+                Type::Array(size, t) => LLVMArrayType(self.llvm_type(t), *size as u32),
+                Type::Struct(s) => self.structs[&s.name].1,
+                // returned by value like any other struct, which is how a proc hands back several values
+                Type::Tuple(elements) => {
+                    let mut parts: Vec<_> = elements.iter().map(|t| self.llvm_type(t)).collect();
+                    LLVMStructTypeInContext(self.context, parts.as_mut_ptr(), parts.len() as u32, 0)
+                }
+                Type::Proc(p) => {
+                    let mut args: Vec<_> = p.args.iter().map(|t| self.llvm_type(t)).collect();
+                    let proc_type = LLVMFunctionType(self.llvm_type(&p.ret), args.as_mut_ptr(), args.len() as u32, 0);
+                    LLVMPointerType(proc_type, 0)
+                }
+                Type::Str => {
+                    let mut parts = [
+                        self.llvm_type(&Type::Ptr(Box::new(Type::I8))),
+                        LLVMInt64TypeInContext(self.context),
+                    ];
+                    LLVMStructTypeInContext(self.context, parts.as_mut_ptr(), parts.len() as u32, 0)
+                }
 
                 Type::Undefined => LLVMVoidTypeInContext(self.context),
                 _ => unreachable!(),
@@ -640,18 +1222,109 @@ impl<'g> Generator<'g> {
         }
     }
 
-    pub fn dump_to_file(&mut self, file: &str) -> bool {
+    /// Picks the machine the module is for, which sets its triple and data layout.
+    /// Done before generating any code, so that nothing assumes the layout of another machine.
+    pub fn set_target(&mut self, target: &Target) -> Result<(), String> {
         unsafe {
-            let mut error_msg: *mut i8 = ""
-                .as_bytes()
-                .iter()
-                .map(|b| *b as i8)
-                .collect::<Vec<_>>()
-                .as_mut_ptr();
-            LLVMPrintModuleToFile(self.module, self.cstr(file), &mut error_msg) == 1
+            LLVM_InitializeAllTargetInfos();
+            LLVM_InitializeAllTargets();
+            LLVM_InitializeAllTargetMCs();
+            LLVM_InitializeAllAsmPrinters();
+
+            let triple = match &target.triple {
+                Some(triple) => {
+                    let normalized = LLVMNormalizeTargetTriple(self.cstr(triple));
+                    take_message(normalized)
+                }
+                None => take_message(LLVMGetDefaultTargetTriple()),
+            };
+            let (cpu, features) = match target.cpu.as_deref() {
+                Some("native") => (take_message(LLVMGetHostCPUName()), take_message(LLVMGetHostCPUFeatures())),
+                Some(cpu) => (cpu.to_owned(), String::new()),
+                None => (String::new(), String::new()),
+            };
+            let features = match &target.features {
+                Some(extra) if features.is_empty() => extra.clone(),
+                Some(extra) => format!("{},{}", features, extra),
+                None => features,
+            };
+
+            let mut llvm_target = std::ptr::null_mut();
+            let mut error_msg = std::ptr::null_mut();
+            if LLVMGetTargetFromTriple(self.cstr(&triple), &mut llvm_target, &mut error_msg) != 0 {
+                LLVMDisposeMessage(error_msg);
+                return Err(format!(
+                    "LLVM can't generate code for {}, the targets it was built with are {}",
+                    triple,
+                    supported_targets().join(", "),
+                ));
+            }
+            // position independent, since that's what cc links by default
+            let machine = LLVMCreateTargetMachine(
+                llvm_target,
+                self.cstr(&triple),
+                self.cstr(&cpu),
+                self.cstr(&features),
+                LLVMCodeGenOptLevel::LLVMCodeGenLevelDefault,
+                LLVMRelocMode::LLVMRelocPIC,
+                LLVMCodeModel::LLVMCodeModelDefault,
+            );
+            if !self.machine.is_null() {
+                LLVMDisposeTargetMachine(self.machine);
+            }
+            self.machine = machine;
+            LLVMSetTarget(self.module, self.cstr(&triple));
+            let layout = LLVMCreateTargetDataLayout(machine);
+            LLVMSetModuleDataLayout(self.module, layout);
+            LLVMDisposeTargetData(layout);
+            Ok(())
+        }
+    }
+
+    /// Writes the module out as textual LLVM IR, or to stdout if the file is -
+    pub fn dump_to_file(&mut self, file: &str) -> Result<(), String> {
+        self.ensure_target()?;
+        unsafe {
+            if file == "-" {
+                print!("{}", take_message(LLVMPrintModuleToString(self.module)));
+                return Ok(());
+            }
+            let mut error_msg = std::ptr::null_mut();
+            if LLVMPrintModuleToFile(self.module, self.cstr(file), &mut error_msg) != 0 {
+                return Err(take_message(error_msg));
+            }
+            Ok(())
         }
     }
 
+    /// Writes the module out as an object file for its target
+    pub fn emit_object(&mut self, file: &str) -> Result<(), String> {
+        self.ensure_target()?;
+        unsafe {
+            let mut error_msg = std::ptr::null_mut();
+            let file = self.cstr(file) as *mut _;
+            let failed = LLVMTargetMachineEmitToFile(
+                self.machine,
+                self.module,
+                file,
+                LLVMCodeGenFileType::LLVMObjectFile,
+                &mut error_msg,
+            ) != 0;
+            if failed {
+                return Err(take_message(error_msg));
+            }
+            Ok(())
+        }
+    }
+
+    /// Anything that was never given a target is for the host
+    fn ensure_target(&mut self) -> Result<(), String> {
+        if self.machine.is_null() {
+            self.set_target(&Target::default())?;
+        }
+        Ok(())
+    }
+
     fn cstr(&mut self, s: &str) -> *const i8 {
         let cstring = CString::new(s).unwrap();
         let ptr = cstring.as_ptr() as *const _;
@@ -660,12 +1333,54 @@ impl<'g> Generator<'g> {
     }
 }
 
+/// The names of every target LLVM was built with, like x86-64 or aarch64
+fn supported_targets() -> Vec<String> {
+    let mut names = vec![];
+    unsafe {
+        let mut target = LLVMGetFirstTarget();
+        while !target.is_null() {
+            names.push(CStr::from_ptr(LLVMGetTargetName(target)).to_string_lossy().into_owned());
+            target = LLVMGetNextTarget(target);
+        }
+    }
+    names.sort();
+    names
+}
+
+/// Copies out a message LLVM allocated and frees it
+unsafe fn take_message(msg: *mut i8) -> String {
+    if msg.is_null() {
+        return "unknown LLVM error".to_owned();
+    }
+    let res = CStr::from_ptr(msg).to_string_lossy().into_owned();
+    LLVMDisposeMessage(msg);
+    res
+}
+
 impl<'g> Drop for Generator<'g> {
     fn drop(&mut self) {
         unsafe {
+            if !self.machine.is_null() {
+                LLVMDisposeTargetMachine(self.machine);
+            }
             LLVMDisposeBuilder(self.builder);
             LLVMDisposeModule(self.module);
             LLVMContextDispose(self.context);
         }
     }
 }
+
+fn find_structs(t: &Type, found: &mut Vec<StructType>) {
+    match t {
+        Type::Ptr(t) | Type::Array(_, t) => find_structs(t, found),
+        Type::Tuple(elements) => elements.iter().for_each(|t| find_structs(t, found)),
+        Type::Proc(p) => p.args.iter().chain(std::iter::once(&p.ret)).for_each(|t| find_structs(t, found)),
+        Type::Struct(s) if found.iter().all(|f| f.name != s.name) => {
+            found.push((**s).clone());
+            for (_, field_type) in &s.fields {
+                find_structs(field_type, found);
+            }
+        }
+        _ => (),
+    }
+}
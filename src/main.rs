@@ -1,82 +1,310 @@
-#[macro_use]
-extern crate lazy_static;
+use elgin::{ast_printer, cache, cfg, docs, errors, interp, ir, llvm, modules, opt};
 
-mod errors;
-mod types;
+use std::env;
+use std::fs;
+use std::panic;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command};
 
-mod lexer;
-mod parser;
-mod astgen;
-mod ir;
-mod analysis;
-mod llvm;
+/// How far through the pipeline to go, and so what gets written out
+#[derive(Clone, Copy, PartialEq)]
+enum Emit {
+    Ast,
+    Docs,
+    Ir,
+    Llvm,
+    Obj,
+    Exe,
+}
+
+struct Options {
+    path: String,
+    // where the output goes, by default next to the input or to stdout for the text dumps
+    output: Option<String>,
+    emit: Emit,
+    // print constraint solving to stderr
+    trace_types: bool,
+    // whether to run the passes in opt.rs
+    optimize: bool,
+    // run main with interp.rs instead of writing anything out
+    run: bool,
+    // whether compiled code checks array and str indices, which it does unless asked not to
+    bounds_checks: bool,
+    // the machine to compile for, by default the one compiling
+    target: llvm::Target,
+    // how diagnostics are printed to stderr
+    error_format: errors::ErrorFormat,
+    // whether used files that haven't changed are loaded from .elgin-cache next to FILE
+    cache: bool,
+    // write a Graphviz file of each proc's basic blocks right after analysis instead of compiling
+    dump_cfg: bool,
+}
 
-use std::io::prelude::*;
-use std::{env, fs};
+const USAGE: &str = "usage: elgin FILE [-o OUTPUT|-] [--emit ast|docs|ir|llvm|obj|exe] [-O0|-O1] [--[no-]bounds-checks] \
+[--target TRIPLE] [--cpu CPU|native] [--features +FEATURE,-FEATURE] [--error-format human|json] [--trace-types] [--run] [--no-cache] \
+[--dump-cfg]";
 
 fn main() {
-    if let Some(_) = env::args().nth(1) {
-        file();
-    } else {
-        panic!("Expected File")
+    let options = parse_args(env::args().skip(1)).unwrap_or_else(|msg| {
+        eprintln!("{}", msg);
+        eprintln!("{}", USAGE);
+        process::exit(2)
+    });
+    // every stage after lexing keeps going past errors,
+    // so that as many of them as possible get reported at once
+    let logger = errors::Logger::new();
+    logger.set_format(options.error_format);
+    report_panics(options.path.clone(), logger.clone());
+    file(options, &logger);
+}
+
+/// A panic is a bug in the compiler, so instead of just a backtrace it gets reported as one,
+/// pointing at whatever the compiler was working on when it happened
+fn report_panics(path: String, logger: errors::Logger) {
+    panic::set_hook(Box::new(move |info| {
+        // one that's caught is reported like any other diagnostic
+        if errors::panic_is_caught() {
+            return;
+        }
+        eprintln!("internal compiler error — please report this");
+        eprintln!("elgin {} compiling {}", env!("CARGO_PKG_VERSION"), path);
+        if let Some(location) = info.location() {
+            eprintln!("panicked at {}", location);
+        }
+        let diagnostic = errors::Diagnostic::from_panic(info.payload());
+        eprint!("{}", logger.render(vec![diagnostic]));
+    }));
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let mut path = None;
+    let mut output = None;
+    let mut emit = Emit::Exe;
+    let mut trace_types = false;
+    let mut optimize = true;
+    let mut run = false;
+    let mut bounds_checks = true;
+    let mut target = llvm::Target::default();
+    let mut error_format = errors::ErrorFormat::Human;
+    let mut cache = true;
+    let mut dump_cfg = false;
+    while let Some(arg) = args.next() {
+        // options with a value take it either as the next argument or after an =
+        let (flag, value) = match arg.split_once('=') {
+            Some((flag, value)) if flag.starts_with("--") => (flag.to_owned(), Some(value.to_owned())),
+            _ => (arg.clone(), None),
+        };
+        let mut value = || value.clone().or_else(|| args.next()).ok_or(format!("{} needs a value", flag));
+        match flag.as_str() {
+            "-o" => output = Some(value()?),
+            "--emit" => emit = match value()?.as_str() {
+                "ast" => Emit::Ast,
+                "docs" => Emit::Docs,
+                "ir" => Emit::Ir,
+                "llvm" => Emit::Llvm,
+                "obj" => Emit::Obj,
+                "exe" => Emit::Exe,
+                other => return Err(format!("Can't emit {}, expected one of ast, docs, ir, llvm, obj or exe", other)),
+            },
+            "--error-format" => error_format = match value()?.as_str() {
+                "human" => errors::ErrorFormat::Human,
+                "json" => errors::ErrorFormat::Json,
+                other => return Err(format!("There is no error format {}, expected human or json", other)),
+            },
+            "--trace-types" => trace_types = true,
+            "-O0" => optimize = false,
+            "-O1" => optimize = true,
+            "--run" => run = true,
+            "--bounds-checks" => bounds_checks = true,
+            "--no-bounds-checks" => bounds_checks = false,
+            "--cache" => cache = true,
+            "--no-cache" => cache = false,
+            "--dump-cfg" => dump_cfg = true,
+            "--target" => target.triple = Some(value()?),
+            "--cpu" => target.cpu = Some(value()?),
+            "--features" => target.features = Some(value()?),
+            _ if flag.starts_with('-') => return Err(format!("There is no option {}", arg)),
+            _ if path.is_some() => return Err(format!("Only one file can be compiled at a time, found {} too", arg)),
+            _ => path = Some(arg),
+        }
     }
+    Ok(Options {
+        path: path.ok_or("Expected a file to compile")?,
+        output,
+        emit,
+        trace_types,
+        optimize,
+        run,
+        bounds_checks,
+        target,
+        error_format,
+        cache,
+        dump_cfg,
+    })
 }
 
-fn file() {
-    let mut file = fs::File::open(env::args().nth(1).unwrap()).unwrap();
-    let mut input = String::new();
-    file.read_to_string(&mut input).unwrap();
-
-    let chars = &input.chars().collect::<Vec<_>>()[..];
-
-    let mut lexer = lexer::Lexer::new(chars);
-    let lex_results_option = lexer.go();
-    println!("______________________");
-    println!("lex errors:");
-    println!("{:#?}", errors::ERRORS.lock().unwrap());
-    let lex_results = lex_results_option.unwrap();
-    println!("______________________");
-    println!("lexer output:");
-    lex_results.iter().map(|t| println!("{:?}", t)).for_each(drop);
-
-    let mut parser = parser::Parser::new(&lex_results);
-    let parse_results = parser.go();
-    println!("______________________");
-    println!("parse errors:");
-    println!("{:#?}", errors::ERRORS.lock().unwrap());
-    println!("______________________");
-    println!("parser output:");
-    println!("{:#?}", parse_results);
-
-    let unwrapped = parse_results.unwrap();
-    let mut irbuilder = ir::IRBuilder::new(&unwrapped, parser.available_type_var);
-    let ir_results = irbuilder.go();
-    println!("______________________");
-    println!("IR gen errors:");
-    println!("{:#?}", errors::ERRORS.lock().unwrap());
-    println!("______________________");
-    println!("IR output:");
-    println!("{:#?}", *ir_results.unwrap());
-
-    println!("______________________");
-    println!("analysis output:");
-    let analysis_option = irbuilder.analyze();
-    println!("______________________");
-    println!("analysis errors:");
-    println!("{:#?}", errors::ERRORS.lock().unwrap());
-    analysis_option.unwrap();
-
-    let mut generator = llvm::Generator::new(&irbuilder.procs, "elgin", &env::args().nth(1).unwrap());
+fn file(options: Options, logger: &errors::Logger) {
+    let mut loader = modules::Loader::new(logger);
+    // the AST only has the files that were parsed in it
+    if options.cache && options.emit != Emit::Ast {
+        let dir = Path::new(&options.path).parent().unwrap_or_else(|| Path::new(""));
+        loader.cache = Some(cache::Cache::new(&dir.join(".elgin-cache")));
+    }
+    if let Err(e) = loader.load(Path::new(&options.path)) {
+        fail(format!("Can't read {}: {}", options.path, e));
+    }
+    if options.emit == Emit::Ast {
+        stop_on_errors(logger);
+        write_text(&options, &ast_printer::print_ast(&loader.nodes));
+        return;
+    }
+
+    let cached = std::mem::take(&mut loader.cached);
+    let mut irbuilder = ir::IRBuilder::new(&loader.nodes, loader.available_type_var, logger);
+    irbuilder.cached = cached;
+    irbuilder.trace = options.trace_types;
+    irbuilder.go();
+    if options.emit == Emit::Docs {
+        stop_on_errors(logger);
+        write_text(&options, &docs::markdown(irbuilder.declared_procs()));
+        return;
+    }
+    irbuilder.analyze();
+
+    // including any from lexing and parsing, or from a use statement that went nowhere
+    stop_on_errors(logger);
+    // only once everything has been checked, so that nothing with errors is cached
+    loader.save_cache(&irbuilder);
+    if options.dump_cfg {
+        dump_cfg(&options, &irbuilder.procs);
+        return;
+    }
+    if options.optimize {
+        opt::optimize(&mut irbuilder.procs);
+    }
+    opt::remove_dead_code(&mut irbuilder.procs, irbuilder.warnings, logger);
+    report(logger);
+    if options.optimize {
+        opt::tidy_labels(&mut irbuilder.procs);
+    }
+    if options.run {
+        match interp::run(&irbuilder.procs, &irbuilder.globals, "main") {
+            // exiting with what main returns, like the executable would
+            Ok(value) => process::exit(match value {
+                interp::Value::Int(v) => v as i32,
+                interp::Value::Nat(v) => v as i32,
+                interp::Value::Bool(v) => v as i32,
+                _ => 0,
+            }),
+            Err(e) => {
+                eprint!("{}", logger.render(vec![e.diagnostic()]));
+                process::exit(1)
+            }
+        }
+    }
+    if options.emit == Emit::Ir {
+        let mut listing = String::new();
+        for proc in &irbuilder.procs {
+            let text = proc.to_text();
+            // the listing is only useful for comparing against if it holds everything
+            debug_assert_eq!(ir::IRProc::from_text(&text).map(|p| p.to_text()), Ok(text.clone()));
+            listing.push_str(&text);
+            listing.push('\n');
+        }
+        write_text(&options, &listing);
+        return;
+    }
+
+    let mut generator = llvm::Generator::new(&irbuilder.procs, &irbuilder.globals, "elgin", &options.path);
+    generator.bounds_checks = options.bounds_checks;
+    generator.logger = logger.clone();
+    if let Err(e) = generator.set_target(&options.target) {
+        fail(e);
+    }
     generator.go();
-    println!("______________________");
-    println!("codegen output:");
-    println!("Dumping to file...");
-    let mut file_name = env::args().nth(1).unwrap();
-    file_name.push_str(".ll");
-    generator.dump_to_file(&file_name);
-    println!("File done!");
-
-    println!("______________________");
-    println!("Errors:");
-    println!("{:#?}", errors::ERRORS.lock().unwrap());
+    let output = output_path(&options);
+    let written = match options.emit {
+        Emit::Llvm => generator.dump_to_file(&output),
+        Emit::Obj => generator.emit_object(&output),
+        _ => {
+            let object = env::temp_dir().join(format!("elgin-{}.o", process::id()));
+            let object = object.to_string_lossy();
+            generator.emit_object(&object).and_then(|()| {
+                // libm is where a float ** ends up
+                let linked = Command::new("cc").arg(&*object).arg("-o").arg(&output).arg("-lm").status();
+                let _ = fs::remove_file(&*object);
+                match linked {
+                    Ok(status) if status.success() => Ok(()),
+                    Ok(status) => Err(format!("cc failed with {}", status)),
+                    Err(e) => Err(format!("Can't run cc: {}", e)),
+                }
+            })
+        }
+    };
+    if let Err(e) = written {
+        fail(format!("Can't write {}: {}", output, e));
+    }
+}
+
+/// Writes FILE.PROC.dot for every proc with a body, into OUTPUT if that's given as a directory
+fn dump_cfg(options: &Options, procs: &[ir::IRProc]) {
+    let input = Path::new(&options.path);
+    let dir = match &options.output {
+        Some(output) => Path::new(output),
+        None => input.parent().unwrap_or_else(|| Path::new("")),
+    };
+    let stem = input.file_stem().unwrap_or_default().to_string_lossy();
+    for proc in procs.iter().filter(|proc| !proc.body.is_empty()) {
+        let path = dir.join(format!("{}.{}.dot", stem, proc.name));
+        if let Err(e) = fs::write(&path, cfg::to_dot(proc)) {
+            fail(format!("Can't write {}: {}", path.display(), e));
+        }
+    }
+}
+
+/// Where to put a file that isn't a text dump, which defaults to the input with its extension swapped
+fn output_path(options: &Options) -> String {
+    if let Some(output) = &options.output {
+        return output.clone();
+    }
+    let extension = match options.emit {
+        Emit::Llvm => "ll",
+        Emit::Obj => "o",
+        _ => "",
+    };
+    PathBuf::from(&options.path).with_extension(extension).to_string_lossy().into_owned()
+}
+
+/// The AST, docs and IR are for reading, so they go to stdout unless asked otherwise
+fn write_text(options: &Options, text: &str) {
+    match &options.output {
+        Some(output) if output != "-" => {
+            if let Err(e) = fs::write(output, text) {
+                fail(format!("Can't write {}: {}", output, e));
+            }
+        }
+        _ => print!("{}", text),
+    }
+}
+
+/// Prints everything logged so far, in the order it's in the source
+fn report(logger: &errors::Logger) {
+    eprint!("{}", logger.render(logger.drain()));
+}
+
+fn stop_on_errors(logger: &errors::Logger) {
+    let errors = logger.error_count();
+    report(logger);
+    match errors {
+        0 => {}
+        // nothing but diagnostics goes to stderr, so every line of it can be parsed
+        _ if logger.format() == errors::ErrorFormat::Json => process::exit(1),
+        1 => fail("Found 1 error, no code was generated".to_owned()),
+        n => fail(format!("Found {} errors, no code was generated", n)),
+    }
+}
+
+fn fail(msg: String) -> ! {
+    eprintln!("{}", msg);
+    process::exit(1)
 }
@@ -0,0 +1,277 @@
+//! Finding every file that a program is made of through its use statements
+
+use crate::astgen::Node;
+use crate::cache::{self, Cache, Cached, Entry};
+use crate::errors::{Code, Logger, Span};
+use crate::ir::{IRBuilder, InstructionType};
+use crate::lexer::{Lexer, Token};
+use crate::parser::{BufferedSource, Parser};
+use crate::types::Type;
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Where every file's top-level nodes end up, with a used file's coming before
+/// those of the file that uses it so that its structs are known in time
+#[derive(Default)]
+pub struct Loader {
+    pub nodes: Vec<Span<Node>>,
+    pub available_type_var: usize,
+    // shared between parsers, since a struct can be used from any file after the one declaring it
+    structs: HashMap<String, Type>,
+    // the same goes for consts that an array's length can be
+    consts: HashMap<String, i128>,
+    // files that have been started on, which is what stops a cycle of uses from going on forever
+    visited: HashSet<PathBuf>,
+    logger: Logger,
+    // where used files that haven't changed since the last build are loaded from, if anywhere
+    pub cache: Option<Cache>,
+    pub cached: Cached,
+    // the line that every declaration made by the files loaded so far is known by in the cache
+    declarations: HashSet<String>,
+    // used files that were parsed, to be cached once the whole program has been analyzed
+    parsed: Vec<Parsed>,
+    // every file that was lexed and parsed rather than loaded from the cache, in the order it was
+    pub parsed_files: Vec<PathBuf>,
+}
+
+/// A used file that wasn't loaded from the cache, with what's needed to make an entry for it
+struct Parsed {
+    hash: u64,
+    offset: usize,
+    uses: Vec<(String, usize, usize)>,
+    // every ident in the file, which includes the name of anything from another file it uses
+    idents: HashSet<String>,
+    // what the file declares, by name
+    declared: HashSet<String>,
+    // whether everything the file declares has a line, which a proc doesn't until its argument types
+    // are written out, since otherwise they depend on how it's called from other files
+    cacheable: bool,
+}
+
+impl Loader {
+    pub fn new(logger: &Logger) -> Self {
+        Loader {
+            nodes: vec![],
+            available_type_var: 0,
+            structs: HashMap::new(),
+            consts: HashMap::new(),
+            visited: HashSet::new(),
+            logger: logger.clone(),
+            cache: None,
+            cached: Cached::default(),
+            declarations: HashSet::new(),
+            parsed: vec![],
+            parsed_files: vec![],
+        }
+    }
+
+    /// Loads the file at `path` and everything it uses, unless that already happened.
+    /// Only fails if the file can't be read, anything wrong with its contents is logged instead.
+    pub fn load(&mut self, path: &Path) -> Result<(), std::io::Error> {
+        self.load_file(path, false)
+    }
+
+    /// Only a used file is ever cached, the one being compiled is the one most likely to change
+    fn load_file(&mut self, path: &Path, used: bool) -> Result<(), std::io::Error> {
+        let input = fs::read_to_string(path)?;
+        if !self.visited.insert(fs::canonicalize(path)?) {
+            return Ok(());
+        }
+
+        let offset = self.logger.add_source(&path.to_string_lossy(), &input);
+        let dir = path.parent().unwrap_or_else(|| Path::new(""));
+        let hash = cache::hash(&input);
+        if used && self.load_cached(dir, hash, offset) {
+            return Ok(());
+        }
+        self.parsed_files.push(path.to_owned());
+
+        // tokens go straight from the lexer to the parser, only the idents are kept along the way
+        let mut idents = HashSet::new();
+        let tokens = Lexer::with_offset(&input, offset, &self.logger).inspect(|token| {
+            if let Token::Ident(id) = &token.contents {
+                idents.insert(id.as_str().to_owned());
+            }
+        });
+        let mut parser = Parser::from_source(BufferedSource::new(tokens), &self.logger);
+        parser.available_type_var = self.available_type_var;
+        parser.structs = std::mem::take(&mut self.structs);
+        parser.consts = std::mem::take(&mut self.consts);
+        let mut uses = vec![];
+        let (mut nodes, _) = parser.go_with(|parser, node| {
+            let Node::UseStatement { path: name } = &node.contents else {
+                return;
+            };
+            // the used file carries on from everything this one has declared so far
+            self.available_type_var = parser.available_type_var;
+            self.structs = std::mem::take(&mut parser.structs);
+            self.consts = std::mem::take(&mut parser.consts);
+            let used = module_path(dir, name);
+            if self.load_file(&used, true).is_err() {
+                self.logger.name_error(
+                    Code::UnknownModule,
+                    format!("Can't find a module named {}, it should be at {}", name, used.display()).as_str(),
+                    node.pos,
+                    node.len,
+                );
+            }
+            parser.available_type_var = self.available_type_var;
+            parser.structs = std::mem::take(&mut self.structs);
+            parser.consts = std::mem::take(&mut self.consts);
+            uses.push((name.clone(), node.pos, node.len));
+        });
+        self.available_type_var = parser.available_type_var;
+        self.structs = std::mem::take(&mut parser.structs);
+        self.consts = std::mem::take(&mut parser.consts);
+        drop(parser);
+
+        let mut declared = HashSet::new();
+        for node in &nodes {
+            if let Some((name, line)) = cache::node_declaration(&node.contents, &self.consts) {
+                declared.insert(name);
+                self.declarations.insert(line);
+            }
+        }
+        let cacheable = nodes.iter().all(|node| match &node.contents {
+            Node::UseStatement { .. } => true,
+            node => cache::node_declaration(node, &self.consts).is_some(),
+        });
+        if used {
+            self.parsed.push(Parsed {
+                hash,
+                offset,
+                uses: uses.into_iter().map(|(name, pos, len)| (name, pos - offset, len)).collect(),
+                idents,
+                declared,
+                cacheable,
+            });
+        }
+        self.nodes.append(&mut nodes);
+        Ok(())
+    }
+
+    /// Loads a used file's entry in place of the file itself, and everything it uses.
+    /// Returns false if there isn't one, or it depends on something from another file that's changed,
+    /// in which case the file has to be parsed after all.
+    fn load_cached(&mut self, dir: &Path, hash: u64, offset: usize) -> bool {
+        let Some(entry) = self.cache.as_ref().and_then(|cache| cache.load(hash)) else {
+            return false;
+        };
+        // a module that can't be found is reported when the file is parsed
+        for (name, _, _) in &entry.uses {
+            if self.load_file(&module_path(dir, name), true).is_err() {
+                return false;
+            }
+        }
+        if !entry.needs.iter().all(|line| self.declarations.contains(line)) {
+            return false;
+        }
+
+        // structs go first, since everything else can have them in its types
+        for typ in entry.structs {
+            let typ = cache::with_structs(&typ, &self.structs);
+            self.declarations.insert(cache::struct_line(&typ));
+            if let Type::Struct(s) = &typ {
+                self.structs.insert(s.name.clone(), typ.clone());
+            }
+            self.cached.structs.push(typ);
+        }
+        for mut global in entry.globals {
+            global.typ = cache::with_structs(&global.typ, &self.structs);
+            let value = global.value.as_ref().and_then(|value| value.parse().ok());
+            if let (true, true, Some(value)) = (global.constant, global.typ.is_integer(), value) {
+                self.consts.insert(global.name.clone(), value);
+            }
+            self.declarations.insert(cache::global_line(&global));
+            self.cached.globals.push(global);
+        }
+        for mut proc in entry.procs {
+            proc.arg_types = proc.arg_types.iter().map(|t| cache::with_structs(t, &self.structs)).collect();
+            proc.ret_type = cache::with_structs(&proc.ret_type, &self.structs);
+            for ins in &mut proc.body {
+                ins.pos += offset;
+                ins.contents.typ = cache::with_structs(&ins.contents.typ, &self.structs);
+                if let InstructionType::Cast(target) = &mut ins.contents.ins {
+                    *target = cache::with_structs(target, &self.structs);
+                }
+            }
+            self.declarations.insert(proc.signature());
+            self.cached.procs.push(proc);
+        }
+        true
+    }
+
+    /// Writes an entry for every used file that was parsed and can be cached,
+    /// so that next time it can be loaded instead. `irbuilder` has to have analyzed
+    /// the whole program without any errors.
+    pub fn save_cache(&self, irbuilder: &IRBuilder) {
+        let Some(cache) = &self.cache else {
+            return;
+        };
+        for file in self.parsed.iter().filter(|file| file.cacheable) {
+            let procs: Vec<_> = irbuilder.procs.iter()
+                .filter(|proc| file.declared.contains(&proc.name))
+                .collect();
+            let globals: Vec<_> = irbuilder.globals.iter()
+                .filter(|global| file.declared.contains(&global.name))
+                .cloned()
+                .collect();
+            let structs: Vec<_> = file.declared.iter()
+                .filter_map(|name| irbuilder.structs.get(name))
+                .cloned()
+                .collect();
+            let types = || procs.iter().flat_map(|proc| {
+                proc.arg_types.iter()
+                    .chain(std::iter::once(&proc.ret_type))
+                    .chain(proc.body.iter().map(|ins| &ins.contents.typ))
+            });
+            if types().any(cache::has_variable) {
+                continue;
+            }
+
+            // anything from another file has to be named in this one to be used,
+            // except for the structs that come along with what is
+            let mut names: Vec<_> = file.idents.iter().cloned().collect();
+            for typ in types().chain(&structs).chain(globals.iter().map(|global| &global.typ)) {
+                cache::struct_names(typ, &mut names);
+            }
+            let mut needs = vec![];
+            for name in names.iter().filter(|name| !file.declared.contains(*name)) {
+                if let Some(proc) = irbuilder.declared_procs().iter().find(|proc| proc.name == *name) {
+                    needs.push(proc.signature());
+                }
+                if let Some(typ) = irbuilder.structs.get(name) {
+                    needs.push(cache::struct_line(typ));
+                }
+                if let Some(global) = irbuilder.locate_global(name) {
+                    needs.push(cache::global_line(global));
+                }
+            }
+            needs.sort();
+
+            let entry = Entry {
+                uses: file.uses.clone(),
+                needs,
+                structs,
+                globals,
+                procs: procs.into_iter()
+                    .map(|proc| {
+                        let mut proc = proc.clone();
+                        for ins in &mut proc.body {
+                            ins.pos = ins.pos.saturating_sub(file.offset);
+                        }
+                        proc
+                    })
+                    .collect(),
+            };
+            // the cache only ever saves time, so not being able to write to it changes nothing
+            let _ = cache.store(file.hash, &entry);
+        }
+    }
+}
+
+fn module_path(dir: &Path, name: &str) -> PathBuf {
+    dir.join(name.replace('.', "/")).with_extension("eln")
+}
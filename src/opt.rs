@@ -0,0 +1,531 @@
+//! Optimizations over the typed IR
+//! These run once analysis is done, so every instruction already has a concrete type
+
+use crate::errors::{merge, Code, Logger, Span};
+use crate::ir::{spanned, CompareType, IRProc, Instruction, InstructionType};
+use crate::types::Type;
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt;
+
+pub fn optimize(procs: &mut [IRProc]) {
+    for proc in procs {
+        proc.body = fold_constants(&proc.body);
+        if let Some(body) = eliminate_tail_calls(proc) {
+            proc.body = body;
+        }
+    }
+}
+
+/// Turns a proc calling itself right before it returns into a loop, so that recursing
+/// as deep as it likes doesn't use up the stack. The arguments of such a call are stored
+/// over the proc's own, last one first as that's the top of the stack, and then it jumps
+/// back to the start. Any other call is left alone, or None if there wasn't one to change.
+fn eliminate_tail_calls(proc: &IRProc) -> Option<Vec<Span<Instruction>>> {
+    use InstructionType::*;
+    let is_tail_call = |pair: &[Span<Instruction>]| {
+        matches!((&pair[0].contents.ins, &pair[1].contents.ins), (Call(name), Return) if *name == proc.name)
+    };
+    if !proc.body.windows(2).any(is_tail_call) {
+        return None;
+    }
+
+    let (pos, len) = proc.body.first().map_or((0, 0), |ins| (ins.pos, ins.len));
+    // no construct is named without a number after it, so this can't be taken already
+    let start = "tail.start".to_owned();
+    let mut res = vec![
+        spanned(Instruction { ins: Jump(start.clone()), typ: Type::Undefined }, pos, len),
+        spanned(Instruction { ins: Label(start.clone()), typ: Type::Undefined }, pos, len),
+    ];
+    let mut i = 0;
+    while i < proc.body.len() {
+        let ins = &proc.body[i];
+        if i + 1 < proc.body.len() && is_tail_call(&proc.body[i..i + 2]) {
+            for (arg, typ) in proc.args.iter().zip(&proc.arg_types).rev() {
+                res.push(spanned(Instruction { ins: Store(arg.clone()), typ: typ.clone() }, ins.pos, ins.len));
+            }
+            res.push(spanned(Instruction { ins: Jump(start.clone()), typ: Type::Undefined }, ins.pos, ins.len));
+            // the Return goes too
+            i += 2;
+            continue;
+        }
+        res.push(ins.clone());
+        i += 1;
+    }
+    Some(res)
+}
+
+/// Unlike the optimizations this always runs, so that codegen never sees unreachable instructions
+pub fn remove_dead_code(procs: &mut [IRProc], warnings: bool, logger: &Logger) {
+    for proc in procs {
+        proc.body = live_instructions(&proc.body, warnings, logger);
+    }
+}
+
+/// Drops everything between an instruction that never falls through and the next Label,
+/// since nothing can jump there. Labels always stay, something else may still branch to them.
+fn live_instructions(body: &[Span<Instruction>], warnings: bool, logger: &Logger) -> Vec<Span<Instruction>> {
+    use InstructionType::*;
+    let mut res = Vec::with_capacity(body.len());
+    // where the source of the last terminator ends, while inside dead code
+    let mut dead_after = None;
+    let mut warned = false;
+    for ins in body {
+        match (&ins.contents.ins, dead_after) {
+            (Label(_), _) => {
+                dead_after = None;
+                res.push(ins.clone());
+            }
+            (_, Some(end)) => {
+                // the IR builder adds instructions of its own after returns, which point back
+                // at the statement they belong to instead of at anything after the return
+                if warnings && !warned && ins.pos >= end {
+                    logger.warning(Code::UnreachableCode, "This code is unreachable", ins.pos, ins.len);
+                    warned = true;
+                }
+            }
+            (Jump(_) | Branch(_, _) | Return | Fail(_), None) => {
+                dead_after = Some(ins.pos + ins.len);
+                warned = false;
+                res.push(ins.clone());
+            }
+            (_, None) => res.push(ins.clone()),
+        }
+    }
+    res
+}
+
+/// Cuts down on the labels and jumps that lowering if statements and loops leaves behind.
+/// Runs after dead code is removed, so every Label is either jumped to or right after a terminator.
+pub fn tidy_labels(procs: &mut [IRProc]) {
+    for proc in procs {
+        let mut body = proc.body.clone();
+        // each round can leave something for the next, like a label that's no longer used
+        while let Some(tidier) = tidy_round(&body) {
+            body = tidier;
+        }
+        proc.body = body;
+    }
+}
+
+/// One go over the body, or None if there was nothing to tidy.
+/// A label that leads straight into another label or a jump stands for wherever that goes,
+/// a jump to the very next label is dropped along with it if nothing else uses the label,
+/// and a label nothing uses goes away, along with the code after it if that's unreachable.
+fn tidy_round(body: &[Span<Instruction>]) -> Option<Vec<Span<Instruction>>> {
+    use InstructionType::*;
+    let mut changed = false;
+
+    let mut targets = HashMap::new();
+    for pair in body.windows(2) {
+        if let (Label(from), Label(to) | Jump(to)) = (&pair[0].contents.ins, &pair[1].contents.ins) {
+            if from != to {
+                targets.insert(from, to);
+            }
+        }
+    }
+    let target = |label: &String| {
+        let mut current = label;
+        // a loop of jumps that never gets anywhere is left as it is
+        for _ in 0..=targets.len() {
+            match targets.get(current) {
+                Some(&next) if next != label => current = next,
+                Some(_) => return label.clone(),
+                None => return current.clone(),
+            }
+        }
+        label.clone()
+    };
+    let mut body: Vec<_> = body.iter()
+        .map(|ins| {
+            let redirected = match &ins.contents.ins {
+                Jump(label) => Jump(target(label)),
+                Branch(body, else_body) => Branch(target(body), target(else_body)),
+                other => other.clone(),
+            };
+            changed |= redirected != ins.contents.ins;
+            spanned(Instruction {
+                ins: redirected,
+                typ: ins.contents.typ.clone(),
+            }, ins.pos, ins.len)
+        })
+        .collect();
+
+    let mut uses: HashMap<String, usize> = HashMap::new();
+    for ins in &body {
+        match &ins.contents.ins {
+            Jump(label) => *uses.entry(label.clone()).or_default() += 1,
+            Branch(body, else_body) => {
+                *uses.entry(body.clone()).or_default() += 1;
+                *uses.entry(else_body.clone()).or_default() += 1;
+            }
+            _ => (),
+        }
+    }
+    let mut res: Vec<Span<Instruction>> = Vec::with_capacity(body.len());
+    let mut dead = false;
+    for ins in body.drain(..) {
+        match &ins.contents.ins {
+            Label(label) => match uses.get(label).copied().unwrap_or(0) {
+                0 => {
+                    // without a jump in, it can only be got to by falling into it
+                    dead = res.last().is_some_and(|last| matches!(last.contents.ins, Jump(_) | Branch(_, _) | Return | Fail(_)));
+                    changed = true;
+                    continue;
+                }
+                1 if !dead && res.last().is_some_and(|last| matches!(&last.contents.ins, Jump(to) if to == label)) => {
+                    res.pop();
+                    changed = true;
+                    continue;
+                }
+                _ => dead = false,
+            },
+            _ if dead => {
+                changed = true;
+                continue;
+            }
+            _ => (),
+        }
+        res.push(ins);
+    }
+    changed.then_some(res)
+}
+
+/// Works out the value of instructions that only operate on constants, leaving a single Push.
+/// Literals take on `typ` if they can, or become their default type otherwise.
+pub fn evaluate(ins: &[Span<Instruction>], typ: Option<&Type>) -> Option<Span<Instruction>> {
+    let typed: Vec<_> = ins.iter()
+        .map(|ins| {
+            let literal_type = match (&ins.contents.typ, typ) {
+                (Type::IntLiteral, Some(t)) if t.is_integer() || t.is_float() => t.clone(),
+                (Type::IntLiteral, _) => Type::I32,
+                (Type::FloatLiteral, Some(t)) if t.is_float() => t.clone(),
+                (Type::FloatLiteral, _) => Type::F64,
+                (t, _) => t.clone(),
+            };
+            spanned(Instruction {
+                ins: ins.contents.ins.clone(),
+                typ: literal_type,
+            }, ins.pos, ins.len)
+        })
+        .collect();
+    match &fold_constants(&typed)[..] {
+        [value] if constant(value).is_some() => Some(value.clone()),
+        _ => None,
+    }
+}
+
+/// Whether the value of a Push is in range for its type
+pub fn fits(ins: &Span<Instruction>) -> bool {
+    let typ = &ins.contents.typ;
+    match constant(ins) {
+        Some(Constant::Int(v)) => fit_int(Some(v), v, typ, false).is_some(),
+        Some(Constant::Nat(v)) => fit_nat(Some(v), v, typ, false).is_some(),
+        _ => true,
+    }
+}
+
+/// The value of a Push, or of an expression made out of nothing but Pushes
+#[derive(Clone, Copy)]
+pub enum Constant {
+    Int(i128),
+    Nat(u128),
+    Float(f64),
+    Bool(bool),
+}
+
+impl fmt::Display for Constant {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Constant::Int(v) => write!(f, "{}", v),
+            Constant::Nat(v) => write!(f, "{}", v),
+            Constant::Float(v) => write!(f, "{}", v),
+            Constant::Bool(v) => write!(f, "{}", v),
+        }
+    }
+}
+
+/// The value of an integer literal as a `typ`, or why it isn't one. Anything after analysis can
+/// rely on this succeeding, since analysis reports every literal it fails for.
+pub fn int_literal(literal: &str, typ: &Type) -> Result<Constant, String> {
+    let (negative, digits) = match literal.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, literal),
+    };
+    let magnitude = match digits.parse::<u128>() {
+        Ok(magnitude) => magnitude,
+        Err(_) if !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit()) => {
+            return Err(format!("The literal {} is too big for any integer type, the biggest of which is 128 bits", literal));
+        }
+        Err(_) => return Err(format!("{} isn't an integer", literal)),
+    };
+    if !typ.is_signed() {
+        return match magnitude {
+            0 => Ok(Constant::Nat(0)),
+            _ if negative => Err(format!("The literal {} is negative, but a {} can't be", literal, typ)),
+            _ if magnitude <= u128::MAX >> (128 - bits(typ)) => Ok(Constant::Nat(magnitude)),
+            _ => Err(format!("The literal {} doesn't fit in a {}", literal, typ)),
+        };
+    }
+    // the smallest value has one more than the biggest
+    let max = (1u128 << (bits(typ) - 1)) - 1 + negative as u128;
+    if magnitude > max {
+        return Err(format!("The literal {} doesn't fit in a {}", literal, typ));
+    }
+    // -2^127 is the one value whose magnitude isn't an i128
+    Ok(Constant::Int(if negative { (magnitude as i128).wrapping_neg() } else { magnitude as i128 }))
+}
+
+/// Replaces operations on constants with the constant they compute, one instruction at a time,
+/// so that a whole constant expression collapses into a single Push.
+/// A Branch on a constant condition becomes a Jump to whichever side it would have taken.
+fn fold_constants(body: &[Span<Instruction>]) -> Vec<Span<Instruction>> {
+    use InstructionType::*;
+    let mut res: Vec<Span<Instruction>> = Vec::with_capacity(body.len());
+    for ins in body {
+        // a constant that's thrown away might as well never have been pushed
+        if ins.contents.ins == Pop && res.last().and_then(constant).is_some() {
+            res.pop();
+            continue;
+        }
+        let arity = match ins.contents.ins {
+            Negate(_) | Plus | Not | Branch(_, _) => 1,
+            Add(_) | Subtract(_) | Multiply(_) | IntDivide | Divide | Power | Compare(_) => 2,
+            BitAnd | BitOr | BitXor | Shl(_) | Shr(_) => 2,
+            _ => 0,
+        };
+        let operands = if arity > 0 && res.len() >= arity {
+            res[res.len() - arity..].iter().map(constant).collect::<Option<Vec<_>>>()
+        } else {
+            None
+        };
+        let operands = match operands {
+            Some(operands) => operands,
+            None => {
+                res.push(ins.clone());
+                continue;
+            }
+        };
+
+        // the operation is spanned at its operator, which may come before its operands
+        let first = &res[res.len() - arity];
+        let (pos, len) = merge(first, &res[res.len() - 1]);
+        let end = (pos + len).max(ins.pos + ins.len);
+        let pos = pos.min(ins.pos);
+        let len = end - pos;
+        let typ = first.contents.typ.clone();
+        let folded = match (&ins.contents.ins, &operands[..]) {
+            (Branch(body, else_body), [Constant::Bool(condition)]) => {
+                let target = if *condition { body } else { else_body }.clone();
+                Some(spanned(Instruction {
+                    ins: Jump(target),
+                    typ: Type::Undefined,
+                }, ins.pos, ins.len))
+            }
+            (op, [operand]) => unary(op, &typ, *operand).map(|value| push(value, &typ, pos, len)),
+            (Compare(comparison), [left, right]) => compare(comparison, *left, *right)
+                .map(|value| push(Constant::Bool(value), &Type::Bool, pos, len)),
+            (op, [left, right]) => binary(op, &typ, *left, *right).map(|value| push(value, &typ, pos, len)),
+            _ => None,
+        };
+        match folded {
+            Some(folded) => {
+                res.truncate(res.len() - arity);
+                res.push(folded);
+            }
+            None => res.push(ins.clone()),
+        }
+    }
+    res
+}
+
+fn constant(ins: &Span<Instruction>) -> Option<Constant> {
+    let value = match &ins.contents.ins {
+        InstructionType::Push(value) => value,
+        _ => return None,
+    };
+    match ins.contents.typ {
+        Type::I8 | Type::I16 | Type::I32 | Type::I64 | Type::I128 => value.parse().ok().map(Constant::Int),
+        Type::N8 | Type::N16 | Type::N32 | Type::N64 | Type::N128 => value.parse().ok().map(Constant::Nat),
+        // an f64 can't hold every f128
+        Type::F32 | Type::F64 => value.parse().ok().map(Constant::Float),
+        Type::Bool => value.parse().ok().map(Constant::Bool),
+        _ => None,
+    }
+}
+
+fn push(value: Constant, typ: &Type, pos: usize, len: usize) -> Span<Instruction> {
+    spanned(Instruction {
+        ins: InstructionType::Push(value.to_string()),
+        typ: typ.clone(),
+    }, pos, len)
+}
+
+/// How wide a number type is, anything that isn't a number counts as 128
+pub fn bits(typ: &Type) -> u32 {
+    match typ {
+        Type::I8 | Type::N8 => 8,
+        Type::I16 | Type::N16 => 16,
+        Type::I32 | Type::N32 | Type::F32 => 32,
+        Type::I64 | Type::N64 | Type::F64 => 64,
+        _ => 128,
+    }
+}
+
+/// `exact` if it fits in the type, otherwise `wrapped` cut down to size if wrapping is allowed.
+/// Anything else is left for the program to do at runtime.
+pub fn fit_int(exact: Option<i128>, wrapped: i128, typ: &Type, wrap: bool) -> Option<i128> {
+    let shift = 128 - bits(typ);
+    // shifting up and back down sign extends from the type's top bit
+    let truncate = |v: i128| (v << shift) >> shift;
+    match exact {
+        Some(v) if truncate(v) == v => Some(v),
+        _ if wrap => Some(truncate(wrapped)),
+        _ => None,
+    }
+}
+
+pub fn fit_nat(exact: Option<u128>, wrapped: u128, typ: &Type, wrap: bool) -> Option<u128> {
+    let mask = u128::MAX >> (128 - bits(typ));
+    match exact {
+        Some(v) if v & mask == v => Some(v),
+        _ if wrap => Some(wrapped & mask),
+        _ => None,
+    }
+}
+
+/// Floats are computed as f64, which rounds an f32 result exactly like f32 arithmetic would
+pub fn fit_float(value: f64, typ: &Type) -> f64 {
+    if *typ == Type::F32 {
+        value as f32 as f64
+    } else {
+        value
+    }
+}
+
+/// The result of a Negate, Plus or Not of type `typ`, or None if it has to be left for runtime
+pub fn unary(op: &InstructionType, typ: &Type, operand: Constant) -> Option<Constant> {
+    use Constant::*;
+    match (op, operand) {
+        (InstructionType::Not, Bool(v)) => Some(Bool(!v)),
+        (InstructionType::Negate(wrap), Int(v)) => fit_int(v.checked_neg(), v.wrapping_neg(), typ, *wrap).map(Int),
+        (InstructionType::Negate(wrap), Nat(v)) => fit_nat(v.checked_neg(), v.wrapping_neg(), typ, *wrap).map(Nat),
+        (InstructionType::Negate(_), Float(v)) => Some(Float(-v)),
+        (InstructionType::Plus, v @ (Int(_) | Nat(_) | Float(_))) => Some(v),
+        _ => None,
+    }
+}
+
+/// Likewise for arithmetic, which only ever has operands of the same type, except for the amount of a shift
+pub fn binary(op: &InstructionType, typ: &Type, left: Constant, right: Constant) -> Option<Constant> {
+    use Constant::*;
+    use InstructionType::*;
+    if let Shl(_) | Shr(_) = op {
+        return shift(op, typ, left, right);
+    }
+    match (left, right) {
+        (Int(a), Int(b)) => match op {
+            Add(wrap) => fit_int(a.checked_add(b), a.wrapping_add(b), typ, *wrap),
+            Subtract(wrap) => fit_int(a.checked_sub(b), a.wrapping_sub(b), typ, *wrap),
+            Multiply(wrap) => fit_int(a.checked_mul(b), a.wrapping_mul(b), typ, *wrap),
+            // dividing by zero is left for runtime to deal with
+            IntDivide => fit_int(a.checked_div(b), 0, typ, false),
+            // like dividing by zero, a negative power of an integer is left for runtime to report
+            Power => fit_int(int_power(a, b), 0, typ, false),
+            BitAnd => Some(a & b),
+            BitOr => Some(a | b),
+            BitXor => Some(a ^ b),
+            _ => None,
+        }.map(Int),
+        (Nat(a), Nat(b)) => match op {
+            Add(wrap) => fit_nat(a.checked_add(b), a.wrapping_add(b), typ, *wrap),
+            Subtract(wrap) => fit_nat(a.checked_sub(b), a.wrapping_sub(b), typ, *wrap),
+            Multiply(wrap) => fit_nat(a.checked_mul(b), a.wrapping_mul(b), typ, *wrap),
+            IntDivide => fit_nat(a.checked_div(b), 0, typ, false),
+            Power => fit_nat(nat_power(a, b), 0, typ, false),
+            BitAnd => Some(a & b),
+            BitOr => Some(a | b),
+            BitXor => Some(a ^ b),
+            _ => None,
+        }.map(Nat),
+        (Float(a), Float(b)) => match op {
+            Add(_) => Some(a + b),
+            Subtract(_) => Some(a - b),
+            Multiply(_) => Some(a * b),
+            Divide => Some(a / b),
+            Power => Some(a.powf(b)),
+            _ => None,
+        }.map(|v| Float(fit_float(v, typ))),
+        _ => None,
+    }
+}
+
+/// `a << b` or `a >> b`, or None if `b` isn't from 0 up to the number of bits in `typ`,
+/// which is left for runtime to report. Bits shifted out of the top are lost.
+fn shift(op: &InstructionType, typ: &Type, left: Constant, right: Constant) -> Option<Constant> {
+    use Constant::*;
+    let amount = match right {
+        Int(b) => u32::try_from(b).ok()?,
+        Nat(b) => u32::try_from(b).ok()?,
+        _ => return None,
+    };
+    if amount >= bits(typ) {
+        return None;
+    }
+    match (op, left) {
+        (InstructionType::Shl(_), Int(a)) => fit_int(None, a << amount, typ, true).map(Int),
+        (InstructionType::Shl(_), Nat(a)) => fit_nat(None, a << amount, typ, true).map(Nat),
+        // an i128 shifts in copies of its sign bit, a u128 zeroes
+        (InstructionType::Shr(_), Int(a)) => Some(Int(a >> amount)),
+        (InstructionType::Shr(_), Nat(a)) => Some(Nat(a >> amount)),
+        _ => None,
+    }
+}
+
+/// Why a shift can't be done, for both the interpreter and compiled code to say
+pub fn shift_range(typ: &Type) -> String {
+    format!("A {} can only be shifted by 0 to {} bits", typ, bits(typ) - 1)
+}
+
+/// `a ** b`, or None if that doesn't fit in an i128 or `b` is negative.
+/// Only 0, 1 and -1 can be raised to a power too big for checked_pow without overflowing.
+fn int_power(a: i128, b: i128) -> Option<i128> {
+    match a {
+        _ if b < 0 => None,
+        0 | 1 => Some(if b == 0 { 1 } else { a }),
+        -1 => Some(if b % 2 == 0 { 1 } else { -1 }),
+        _ => a.checked_pow(u32::try_from(b).ok()?),
+    }
+}
+
+fn nat_power(a: u128, b: u128) -> Option<u128> {
+    match a {
+        0 | 1 => Some(if b == 0 { 1 } else { a }),
+        _ => a.checked_pow(u32::try_from(b).ok()?),
+    }
+}
+
+pub fn compare(comparison: &CompareType, left: Constant, right: Constant) -> Option<bool> {
+    use Constant::*;
+    fn holds<T: PartialOrd>(comparison: &CompareType, a: T, b: T) -> bool {
+        match comparison {
+            CompareType::EQ => a == b,
+            CompareType::NE => a != b,
+            CompareType::GT => a > b,
+            CompareType::LT => a < b,
+            CompareType::GE => a >= b,
+            CompareType::LE => a <= b,
+        }
+    }
+    match (left, right) {
+        (Int(a), Int(b)) => Some(holds(comparison, a, b)),
+        (Nat(a), Nat(b)) => Some(holds(comparison, a, b)),
+        (Float(a), Float(b)) => Some(holds(comparison, a, b)),
+        // bools only have an order as far as codegen is concerned
+        (Bool(a), Bool(b)) if matches!(comparison, CompareType::EQ | CompareType::NE) => {
+            Some(holds(comparison, a, b))
+        }
+        _ => None,
+    }
+}
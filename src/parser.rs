@@ -1,45 +1,193 @@
 //! Parser
 
-use crate::errors::{Logger, Span};
+use crate::errors::{Code, Logger, Span};
 use crate::lexer::Token;
 use crate::types::Type;
 
-pub struct Parser<'p> {
-    pub tokens: &'p [Span<Token>],
-    pub index: usize,
-    pub available_type_var: usize,
+use std::collections::{HashMap, VecDeque};
+use std::convert::TryFrom;
+
+/// How deeply expressions, blocks and types can be nested inside each other in total.
+/// Parsing them is recursive, so without a limit enough parentheses would overflow the stack.
+/// This many still fit in the 2 MiB a spawned thread gets, even in a debug build.
+const MAX_NESTING: usize = 128;
+
+/// Codegen gives an array type its length as a 32-bit number
+const MAX_ARRAY_LENGTH: u32 = u32::MAX;
+
+/// Anything the parser can pull tokens from. The parser never needs more than
+/// two tokens of lookahead, so a source only has to buffer that much.
+/// Past the end of the input, every method keeps returning `Token::EOF`.
+/// Looking ahead only borrows the token, it's only handed over once it's consumed.
+pub trait TokenSource {
+    fn next(&mut self) -> Span<Token>;
+    fn peek(&mut self) -> &Span<Token>;
+    fn peek2(&mut self) -> &Span<Token>;
+}
+
+/// Reports a keyword that's been used as a name, handing back its spelling so that parsing
+/// can carry on as if it were one. Anything that isn't a keyword is left for the caller to deal with.
+pub fn keyword_as_ident(token: &Span<Token>, logger: &Logger) -> Option<&'static str> {
+    let keyword = token.contents.keyword()?;
+    logger.syntax_error(
+        Code::ReservedKeyword,
+        format!("'{}' is a reserved keyword and cannot be used as an identifier", keyword).as_str(),
+        token.pos,
+        token.len,
+    );
+    Some(keyword)
+}
+
+fn eof_after(last: Option<&Span<Token>>) -> Span<Token> {
+    let (pos, len) = last.map_or((0, 0), |t| (t.pos, t.len));
+    Span {
+        contents: Token::EOF,
+        pos,
+        len,
+    }
+}
+
+/// A fully lexed token slice
+pub struct SliceSource<'t> {
+    tokens: &'t [Span<Token>],
+    index: usize,
+    eof: Span<Token>,
 }
 
-impl<'p> Parser<'p> {
-    pub fn new(tokens: &'p [Span<Token>]) -> Self {
-        Parser { 
-            tokens, 
+impl<'t> SliceSource<'t> {
+    pub fn new(tokens: &'t [Span<Token>]) -> Self {
+        SliceSource {
+            tokens,
             index: 0,
-            available_type_var: 0,
+            eof: eof_after(tokens.last()),
         }
     }
 
-    pub fn next(&mut self) -> Span<Token> {
+    fn get(&self, index: usize) -> &Span<Token> {
+        self.tokens.get(index).unwrap_or(&self.eof)
+    }
+}
+
+impl<'t> TokenSource for SliceSource<'t> {
+    fn next(&mut self) -> Span<Token> {
+        let t = self.get(self.index).clone();
         self.index += 1;
-        if self.index >= self.tokens.len() {
-            let last = self.tokens.last().unwrap();
-            return Span {
-                contents: Token::EOF,
-                pos: last.pos,
-                len: last.len,
-            };
+        t
+    }
+
+    fn peek(&mut self) -> &Span<Token> {
+        self.get(self.index)
+    }
+
+    fn peek2(&mut self) -> &Span<Token> {
+        self.get(self.index + 1)
+    }
+}
+
+/// Pulls tokens lazily out of an iterator (normally the lexer), keeping only
+/// the two-token lookahead window in memory.
+pub struct BufferedSource<I: Iterator<Item = Span<Token>>> {
+    iter: I,
+    window: VecDeque<Span<Token>>,
+    // what's handed out once the iterator runs dry, at the last token it gave
+    eof: Span<Token>,
+}
+
+impl<I: Iterator<Item = Span<Token>>> BufferedSource<I> {
+    pub fn new(iter: I) -> Self {
+        BufferedSource {
+            iter,
+            window: VecDeque::with_capacity(2),
+            eof: eof_after(None),
         }
-        self.tokens[self.index - 1].clone() }
-    pub fn peek(&mut self) -> Span<Token> {
-        if self.index >= self.tokens.len() {
-            let last = self.tokens.last().unwrap();
-            return Span {
-                contents: Token::EOF,
-                pos: last.pos,
-                len: last.len,
-            };
+    }
+
+    fn fill(&mut self, n: usize) {
+        while self.window.len() < n {
+            match self.iter.next() {
+                Some(t) => {
+                    self.eof = eof_after(Some(&t));
+                    self.window.push_back(t);
+                }
+                None => break,
+            }
         }
-        self.tokens[self.index].clone()
+    }
+
+    fn get(&mut self, offset: usize) -> &Span<Token> {
+        self.fill(offset + 1);
+        self.window.get(offset).unwrap_or(&self.eof)
+    }
+}
+
+impl<I: Iterator<Item = Span<Token>>> TokenSource for BufferedSource<I> {
+    fn next(&mut self) -> Span<Token> {
+        self.fill(1);
+        self.window.pop_front().unwrap_or_else(|| self.eof.clone())
+    }
+
+    fn peek(&mut self) -> &Span<Token> {
+        self.get(0)
+    }
+
+    fn peek2(&mut self) -> &Span<Token> {
+        self.get(1)
+    }
+}
+
+pub struct Parser<S: TokenSource> {
+    tokens: S,
+    pub available_type_var: usize,
+    // structs declared so far, since a type has to be declared before it's used
+    pub structs: HashMap<String, Type>,
+    // top-level consts declared so far whose values are integers, which can be the length of an array
+    pub consts: HashMap<String, i128>,
+    // where the most recently consumed token ends
+    last_end: usize,
+    // how many expressions, blocks and types the parser is inside of
+    depth: usize,
+    pub logger: Logger,
+}
+
+impl<'t> Parser<SliceSource<'t>> {
+    pub fn new(tokens: &'t [Span<Token>], logger: &Logger) -> Self {
+        Self::from_source(SliceSource::new(tokens), logger)
+    }
+}
+
+impl<S: TokenSource> Parser<S> {
+    pub fn from_source(tokens: S, logger: &Logger) -> Self {
+        Parser {
+            tokens,
+            available_type_var: 0,
+            structs: HashMap::new(),
+            consts: HashMap::new(),
+            last_end: 0,
+            depth: 0,
+            logger: logger.clone(),
+        }
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Span<Token> {
+        let t = self.tokens.next();
+        if t.contents != Token::EOF {
+            self.last_end = t.pos + t.len;
+        }
+        t
+    }
+
+    /// The position and length of everything from `start` up to the last consumed token
+    pub fn span_since(&self, start: &Span<Token>) -> (usize, usize) {
+        (start.pos, self.last_end.max(start.pos + start.len) - start.pos)
+    }
+
+    pub fn peek(&mut self) -> &Span<Token> {
+        self.tokens.peek()
+    }
+
+    pub fn peek2(&mut self) -> &Span<Token> {
+        self.tokens.peek2()
     }
 
     pub fn ensure_next(&mut self, t: Token) -> Option<()> {
@@ -47,15 +195,38 @@ impl<'p> Parser<'p> {
             self.next();
             Some(())
         } else {
-            Logger::syntax_error(
-                format!("Expected a {:?} token, but found a {:?} instead", t, self.peek().contents.clone()).as_str(),
-                self.peek().pos,
-                self.peek().len,
+            let next = self.peek().clone();
+            self.logger.syntax_error(
+                Code::UnexpectedToken,
+                format!("Expected {}, but found {} instead", t, next.contents).as_str(),
+                next.pos,
+                next.len,
             );
             None
         }
     }
 
+    /// Like ensure_next for the ) or ] that closes `open`. Running into the end of a block or of the file
+    /// first means it was never closed, which is reported at `open` since that's what needs fixing.
+    pub fn ensure_closed(&mut self, close: Token, open: &Span<Token>) -> Option<()> {
+        if self.past_end_of_statement() {
+            self.logger.syntax_error(
+                Code::UnclosedDelimiter,
+                format!("This {} is never closed with a {}", open.contents, close).as_str(),
+                open.pos,
+                open.len,
+            );
+            return None;
+        }
+        self.ensure_next(close)
+    }
+
+    /// Whether the next token can't be part of the statement being parsed, even inside parentheses
+    pub fn past_end_of_statement(&mut self) -> bool {
+        matches!(self.peek().contents, Token::RBrace | Token::Newline | Token::EOF | Token::Proc)
+    }
+
+    /// Like ensure_next, but for syntax that's optional, so nothing is reported if `t` isn't next
     pub fn try_next(&mut self, t: Token) -> Option<()> {
         if self.peek().contents == t {
             self.next();
@@ -68,18 +239,81 @@ impl<'p> Parser<'p> {
     pub fn ensure_ident(&mut self) -> Option<String> {
         if let Token::Ident(id) = self.peek().contents.clone() {
             self.next();
-            Some(id)
+            Some(id.as_str().to_owned())
+        } else if let Some(keyword) = keyword_as_ident(self.tokens.peek(), &self.logger) {
+            self.next();
+            Some(keyword.to_owned())
         } else {
-            Logger::syntax_error(
-                format!("Expected an identifier, but found a {:?} token instead", self.peek().contents.clone()).as_str(),
-                self.peek().pos,
-                self.peek().len,
+            let next = self.peek().clone();
+            self.logger.syntax_error(
+                Code::UnexpectedToken,
+                format!("Expected an identifier, but found {} instead", next.contents).as_str(),
+                next.pos,
+                next.len,
             );
             None
         }
     }
 
     pub fn ensure_type(&mut self) -> Option<Type> {
+        self.nested("type", Self::type_inner)
+    }
+
+    /// Runs `f` one level of nesting further in, unless that's too deep,
+    /// in which case the `what` starting at the next token is reported instead
+    pub fn nested<T>(&mut self, what: &str, f: impl FnOnce(&mut Self) -> Option<T>) -> Option<T> {
+        if self.depth >= MAX_NESTING {
+            let next = self.peek().clone();
+            self.logger.syntax_error(
+                Code::TooDeeplyNested,
+                format!("This {} is nested too deeply, the limit is {} levels", what, MAX_NESTING).as_str(),
+                next.pos,
+                next.len,
+            );
+            return None;
+        }
+        self.depth += 1;
+        let res = f(self);
+        self.depth -= 1;
+        res
+    }
+
+    /// An integer literal or a const, from 1 up to `MAX_ARRAY_LENGTH`.
+    /// A suffix says nothing about how long an array is, so one isn't allowed.
+    fn array_length(&mut self) -> Option<usize> {
+        let (length, name) = match self.peek().contents.clone() {
+            // digits too many for an i128 are still a length, just far too long a one
+            Token::IntLiteral(digits) if digits.bytes().all(|b| b.is_ascii_digit()) => (digits.parse().ok(), None),
+            Token::Ident(name) if self.consts.contains_key(name.as_str()) => (Some(self.consts[name.as_str()]), Some(name)),
+            found => {
+                let next = self.peek().clone();
+                self.logger.syntax_error(
+                    Code::UnexpectedToken,
+                    format!("Expected an integer or a const as the length of an array, but found {} instead", found).as_str(),
+                    next.pos,
+                    next.len,
+                );
+                return None;
+            }
+        };
+        let problem = match length {
+            Some(length) if (1..=i128::from(MAX_ARRAY_LENGTH)).contains(&length) => {
+                self.next();
+                return usize::try_from(length).ok();
+            }
+            Some(length) if length < 1 => "has to be at least 1".to_owned(),
+            _ => format!("can be at most {}", MAX_ARRAY_LENGTH),
+        };
+        let message = match (name, length) {
+            (Some(name), Some(length)) => format!("The length of an array {}, but {} is {}", problem, name, length),
+            _ => format!("The length of an array {}, not {}", problem, self.peek().contents),
+        };
+        let next = self.peek().clone();
+        self.logger.type_error(Code::InvalidArrayLength, message.as_str(), next.pos, next.len);
+        None
+    }
+
+    fn type_inner(&mut self) -> Option<Type> {
         match self.peek().contents.clone() {
             Token::Ident(id) => {
                 let typ = match id.as_str() {
@@ -100,14 +334,20 @@ impl<'p> Parser<'p> {
                     "f128" => Type::F128,
 
                     "bool" => Type::Bool,
+                    "str" => Type::Str,
 
+                    _ if self.structs.contains_key(id.as_str()) => self.structs[id.as_str()].clone(),
                     _ => {
-                        Logger::syntax_error(
-                            format!("Expected a type, but found a {:?} instead", self.peek().contents.clone()).as_str(),
-                            self.peek().pos,
-                            self.peek().len,
+                        // it's still clearly meant to be a type, so carry on
+                        // as if it were one that has to be inferred
+                        let next = self.peek().clone();
+                        self.logger.name_error(
+                            Code::UnknownType,
+                            format!("There is no type named {}", id).as_str(),
+                            next.pos,
+                            next.len,
                         );
-                        return None
+                        Type::Variable(self.next_type_var())
                     }
                 };
                 self.next();
@@ -119,26 +359,52 @@ impl<'p> Parser<'p> {
                 Some(Type::Ptr(Box::new(content_type)))
             },
             Token::LBracket => {
-                self.next(); // skip the LBracket
-                if let Token::IntLiteral(size) = self.peek().contents {
-                    self.next();
-                    self.ensure_next(Token::RBracket)?;
-                    let content_type = self.ensure_type()?; 
-                    Some(Type::Array(size.parse().unwrap(), Box::new(content_type)))
-                } else {
-                    Logger::syntax_error(
-                        format!("Expect an integer as the length of an array, but found a {:?} token instead", self.peek().contents).as_str(),
-                        self.peek().pos,
-                        self.peek().len,
-                    );
-                    None
+                let open = self.next();
+                let size = self.array_length()?;
+                self.ensure_closed(Token::RBracket, &open)?;
+                let content_type = self.ensure_type()?;
+                Some(Type::Array(size, Box::new(content_type)))
+            },
+            Token::LParen => {
+                let open = self.next();
+                let mut elements = vec![self.ensure_type()?];
+                while self.try_next(Token::Comma).is_some() {
+                    elements.push(self.ensure_type()?);
+                }
+                self.ensure_closed(Token::RParen, &open)?;
+                // just like in an expression, (T) only groups
+                if elements.len() == 1 {
+                    return elements.pop();
+                }
+                Some(Type::Tuple(elements))
+            },
+            // written like the head of a proc declaration without the names
+            Token::Proc => {
+                self.next();
+                let open = self.peek().clone();
+                self.ensure_next(Token::LParen)?;
+                let mut args = vec![];
+                while self.peek().contents != Token::RParen && !self.past_end_of_statement() {
+                    args.push(self.ensure_type()?);
+                    if self.try_next(Token::Comma).is_none() {
+                        break;
+                    }
                 }
+                self.ensure_closed(Token::RParen, &open)?;
+                let ret = if self.try_next(Token::Colon).is_some() {
+                    self.ensure_type()?
+                } else {
+                    Type::Undefined
+                };
+                Some(Type::new_proc(args, ret))
             },
             _ => {
-                Logger::syntax_error(
-                    format!("Expected a type, but found a {:?} instead", self.peek().contents.clone()).as_str(),
-                    self.peek().pos,
-                    self.peek().len,
+                let next = self.peek().clone();
+                self.logger.syntax_error(
+                    Code::UnexpectedToken,
+                    format!("Expected a type, but found {} instead", next.contents).as_str(),
+                    next.pos,
+                    next.len,
                 );
                 None
             },
@@ -0,0 +1,66 @@
+//! Interned strings for the identifiers and operators in the token stream, so that copying
+//! and comparing them doesn't cost an allocation or a walk over their bytes
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Mutex;
+
+/// A name that's been interned, equal to another only if they were interned from the same text
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Symbol(u32);
+
+#[derive(Default)]
+struct Interner {
+    names: Vec<&'static str>,
+    ids: HashMap<&'static str, Symbol>,
+}
+
+lazy_static! {
+    // shared by every compilation in the process, since names are only ever added to it
+    static ref INTERNER: Mutex<Interner> = Mutex::new(Interner::default());
+}
+
+impl Symbol {
+    pub fn intern(name: &str) -> Symbol {
+        let mut interner = INTERNER.lock().unwrap();
+        if let Some(&symbol) = interner.ids.get(name) {
+            return symbol;
+        }
+        // never freed, there's only ever as many of these as there are different names
+        let name: &'static str = Box::leak(name.to_owned().into_boxed_str());
+        let symbol = Symbol(interner.names.len() as u32);
+        interner.names.push(name);
+        interner.ids.insert(name, symbol);
+        symbol
+    }
+
+    /// The text this was interned from
+    pub fn as_str(self) -> &'static str {
+        INTERNER.lock().unwrap().names[self.0 as usize]
+    }
+}
+
+impl PartialEq<str> for Symbol {
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl PartialEq<&str> for Symbol {
+    fn eq(&self, other: &&str) -> bool {
+        self.as_str() == *other
+    }
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// The same as the string's, so that tokens print just like they did before being interned
+impl fmt::Debug for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.as_str())
+    }
+}
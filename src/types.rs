@@ -26,6 +26,9 @@ pub enum Type {
 
     Bool,
 
+    // a pointer to the bytes along with how many there are
+    Str,
+
     Variable(usize),
 
     Undefined,
@@ -34,6 +37,67 @@ pub enum Type {
     Ptr(Box<Type>),
 
     Array(usize, Box<Type>),
+
+    // boxed so that every other type stays small
+    Struct(Box<StructType>),
+
+    // at least two, laid out like a struct whose fields are numbered
+    Tuple(Vec<Type>),
+
+    // a reference to a proc, boxed for the same reason
+    Proc(Box<ProcType>),
+}
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct StructType {
+    pub name: String,
+    // in declaration order, which is also the order they're laid out in
+    pub fields: Vec<(String, Type)>,
+}
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct ProcType {
+    pub args: Vec<Type>,
+    pub ret: Type,
+}
+
+impl Type {
+    pub fn new_proc(args: Vec<Type>, ret: Type) -> Self {
+        Type::Proc(Box::new(ProcType { args, ret }))
+    }
+
+    pub fn new_struct(name: String, fields: Vec<(String, Type)>) -> Self {
+        Type::Struct(Box::new(StructType { name, fields }))
+    }
+
+    pub fn is_integer(&self) -> bool {
+        use Type::*;
+        matches!(self, I8 | I16 | I32 | I64 | I128 | N8 | N16 | N32 | N64 | N128)
+    }
+
+    /// Only the iN types, nN types are unsigned
+    pub fn is_signed(&self) -> bool {
+        use Type::*;
+        matches!(self, I8 | I16 | I32 | I64 | I128)
+    }
+
+    pub fn is_float(&self) -> bool {
+        matches!(self, Type::F32 | Type::F64 | Type::F128)
+    }
+
+    /// The type and position of a struct's field, or of a tuple's element, whose name is its position
+    pub fn field(&self, name: &str) -> Option<(usize, Type)> {
+        match self {
+            Type::Struct(s) => s.fields.iter()
+                .position(|(field, _)| field == name)
+                .map(|i| (i, s.fields[i].1.clone())),
+            Type::Tuple(elements) => {
+                let i: usize = name.parse().ok()?;
+                elements.get(i).map(|t| (i, t.clone()))
+            }
+            _ => None,
+        }
+    }
 }
 
 impl fmt::Debug for Type {
@@ -61,9 +125,20 @@ impl fmt::Debug for Type {
             F128 => write!(f, "f128"),
 
             Bool => write!(f, "bool"),
+            Str => write!(f, "str"),
 
             Ptr(t) => write!(f, "*{:?}", t),
             Array(size, t) => write!(f, "[{}]{:?}", size, t),
+            Struct(s) => write!(f, "{}", s.name),
+            // no spaces, the IR listing splits on ", "
+            Tuple(elements) => {
+                let elements: Vec<_> = elements.iter().map(|t| format!("{:?}", t)).collect();
+                write!(f, "({})", elements.join(","))
+            }
+            Proc(p) => {
+                let args: Vec<_> = p.args.iter().map(|t| format!("{:?}", t)).collect();
+                write!(f, "proc({}):{:?}", args.join(","), p.ret)
+            }
 
             Variable(n) => write!(f, "${}", n),
 
@@ -74,3 +149,35 @@ impl fmt::Debug for Type {
     }
 }
 
+/// How a type is written in Elgin, which is what error messages show.
+/// The Debug form is the one the IR listing uses, and has to stay exact.
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use Type::*;
+        match self {
+            IntLiteral => write!(f, "integer literal"),
+            FloatLiteral => write!(f, "float literal"),
+            StrLiteral => write!(f, "string literal"),
+
+            Ptr(t) => write!(f, "*{}", t),
+            Array(size, t) => write!(f, "[{}]{}", size, t),
+            Tuple(elements) => {
+                let elements: Vec<_> = elements.iter().map(|t| t.to_string()).collect();
+                write!(f, "({})", elements.join(", "))
+            }
+            Proc(p) => {
+                let args: Vec<_> = p.args.iter().map(|t| t.to_string()).collect();
+                match p.ret {
+                    Undefined => write!(f, "proc({})", args.join(", ")),
+                    _ => write!(f, "proc({}): {}", args.join(", "), p.ret),
+                }
+            }
+
+            // only ever seen when inference couldn't work something out
+            Variable(n) => write!(f, "?T{}", n),
+
+            Undefined => write!(f, "nothing"),
+            t => write!(f, "{:?}", t),
+        }
+    }
+}
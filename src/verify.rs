@@ -0,0 +1,103 @@
+//! Checks that the IR builder produced something analysis can work with
+//! Anything caught here is a bug in the compiler rather than in the program being compiled
+
+use crate::errors::{Code, Diagnostic, ErrorType};
+use crate::ir::{IRGlobal, IRProc, InstructionType};
+
+use std::collections::HashSet;
+
+/// The first problem in `proc`, as an internal compiler error for the caller to report.
+/// The stack is followed in instruction order, the same way analysis follows it.
+pub fn verify(proc: &IRProc, procs: &[IRProc], globals: &[IRGlobal]) -> Result<(), Diagnostic> {
+    check(proc, procs, globals).map_err(|(index, msg)| {
+        let ins = &proc.body[index];
+        Diagnostic {
+            severity: ErrorType::InternalError,
+            code: Some(Code::InternalError),
+            message: format!("In proc {}, instruction {} ({}): {}", proc.name, index, ins.contents.ins, msg),
+            pos: ins.pos,
+            len: ins.len,
+        }
+    })
+}
+
+/// The index of the first instruction that's wrong, and what's wrong with it
+fn check(proc: &IRProc, procs: &[IRProc], globals: &[IRGlobal]) -> Result<(), (usize, String)> {
+    use InstructionType::*;
+    let labels: HashSet<_> = proc.body.iter()
+        .filter_map(|ins| match &ins.contents.ins {
+            Label(label) => Some(label),
+            _ => None,
+        })
+        .collect();
+    // a variable can be used before the Allocate that comes first in the listing,
+    // a loop's condition comes after its body for one
+    let variables: HashSet<_> = proc.body.iter()
+        .filter_map(|ins| match &ins.contents.ins {
+            Allocate(name) => Some(name.clone()),
+            _ => None,
+        })
+        .chain(proc.args.iter().cloned())
+        .chain(globals.iter().map(IRGlobal::ir_name))
+        .collect();
+
+    let mut depth = 0;
+    for (i, ins) in proc.body.iter().enumerate() {
+        let fail = |msg: String| Err((i, msg));
+        match &ins.contents.ins {
+            Load(name) | Store(name) | StoreIndexed(name, _) | StoreField(name, _) if !variables.contains(name) => {
+                return fail(format!("There is no variable named {}", name));
+            }
+            Branch(body, else_body) if !labels.contains(body) || !labels.contains(else_body) => {
+                return fail(format!("Branches to a label that isn't in proc {}", proc.name));
+            }
+            Jump(label) if !labels.contains(label) => {
+                return fail(format!("Jumps to a label that isn't in proc {}", proc.name));
+            }
+            Call(name) if !procs.iter().any(|proc| proc.name == *name) => {
+                return fail(format!("Calls a proc named {} that doesn't exist", name));
+            }
+            ProcRef(name) if !procs.iter().any(|proc| proc.name == *name) => {
+                return fail(format!("Refers to a proc named {} that doesn't exist", name));
+            }
+            _ => (),
+        }
+        let (pops, pushes) = stack_effect(&ins.contents.ins, procs);
+        if depth < pops {
+            let values = if pops == 1 { "value" } else { "values" };
+            return fail(format!("Needs {} {} on the stack, but there are only {}", pops, values, depth));
+        }
+        depth = depth - pops + pushes;
+    }
+    Ok(())
+}
+
+/// How many values an instruction takes off the stack, and how many it puts back on
+fn stack_effect(ins: &InstructionType, procs: &[IRProc]) -> (usize, usize) {
+    use InstructionType::*;
+    match ins {
+        Push(_) | Load(_) => (0, 1),
+        Store(_) | Allocate(_) | StoreField(_, _) | Pop => (1, 0),
+        StoreIndexed(_, indices) => (indices.len() + 1, 0),
+
+        Index(_) => (2, 1),
+        Field(_) | Length | Cast(_) => (1, 1),
+        Construct(count) => (*count, 1),
+
+        Branch(_, _) => (1, 0),
+        Jump(_) | Label(_) | Fail(_) => (0, 0),
+        Print(_) => (1, 0),
+
+        Call(name) => {
+            let args = procs.iter().find(|proc| proc.name == *name).map_or(0, |proc| proc.args.len());
+            (args, 1)
+        }
+        ProcRef(_) => (0, 1),
+        CallIndirect(count) => (count + 1, 1),
+        Return => (1, 0),
+
+        Negate(_) | Plus | Not => (1, 1),
+        Add(_) | Subtract(_) | Multiply(_) | IntDivide | Divide | Power | Compare(_) => (2, 1),
+        BitAnd | BitOr | BitXor | Shl(_) | Shr(_) => (2, 1),
+    }
+}
@@ -0,0 +1,33 @@
+//! Each tests/ast/NAME.eln is parsed and the tree that --emit ast would print compared against NAME.ast,
+//! so that how the parser nests things and where it puts their spans shows up as a diff.
+//! Setting ELGIN_BLESS writes the trees out instead, for when a change to them is intended.
+
+use elgin::ast_printer;
+
+use std::env;
+use std::fs;
+use std::path::Path;
+
+#[test]
+fn printed_trees() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/ast");
+    let bless = env::var_os("ELGIN_BLESS").is_some();
+    let mut checked = 0;
+    let mut failures = vec![];
+    let mut entries: Vec<_> = fs::read_dir(&dir).unwrap().map(|e| e.unwrap().path()).collect();
+    entries.sort();
+    for path in entries.iter().filter(|p| p.extension().is_some_and(|e| e == "eln")) {
+        let src = fs::read_to_string(path).unwrap();
+        let nodes = elgin::parse(&src).unwrap_or_else(|diags| panic!("expected no errors, got {:?}", diags));
+        let actual = ast_printer::print_ast(&nodes);
+        let expected_path = path.with_extension("ast");
+        if bless {
+            fs::write(&expected_path, &actual).unwrap();
+        } else if fs::read_to_string(&expected_path).ok().as_deref() != Some(actual.as_str()) {
+            failures.push(format!("{} doesn't match, it's now:\n{}", expected_path.display(), actual));
+        }
+        checked += 1;
+    }
+    assert!(checked >= 4, "only found {} trees in {}", checked, dir.display());
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
@@ -0,0 +1,67 @@
+//! Building a program made of several files twice, where the second build should only parse what it has to
+
+use elgin::cache::Cache;
+use elgin::errors::Logger;
+use elgin::ir::IRBuilder;
+use elgin::modules::Loader;
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Builds `main.eln` in `dir` the way the command line does, giving back the files that were parsed
+fn build(dir: &Path) -> Vec<String> {
+    let logger = Logger::new();
+    let mut loader = Loader::new(&logger);
+    loader.cache = Some(Cache::new(&dir.join(".elgin-cache")));
+    loader.load(&dir.join("main.eln")).unwrap();
+    let mut irbuilder = IRBuilder::new(&loader.nodes, loader.available_type_var, &logger);
+    irbuilder.cached = std::mem::take(&mut loader.cached);
+    irbuilder.go();
+    irbuilder.analyze();
+    assert_eq!(logger.error_count(), 0, "{:?}", logger.drain());
+    loader.save_cache(&irbuilder);
+    loader.parsed_files.iter().map(|path| path.file_name().unwrap().to_string_lossy().into_owned()).collect()
+}
+
+fn project(name: &str, files: &[(&str, &str)]) -> PathBuf {
+    let dir = env::temp_dir().join(format!("elgin-cache-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    for (file, src) in files {
+        fs::write(dir.join(file), src).unwrap();
+    }
+    dir
+}
+
+#[test]
+fn unchanged_file_isnt_parsed_again() {
+    let dir = project("unchanged", &[
+        ("main.eln", "use util\n\nproc main(): i32 {\n    return square(3)\n}\n"),
+        ("util.eln", "proc square(x: i32): i32 {\n    return x * x\n}\n"),
+    ]);
+    assert_eq!(build(&dir), ["main.eln", "util.eln"]);
+    assert_eq!(build(&dir), ["main.eln"]);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn changed_signature_invalidates_users() {
+    let dir = project("signature", &[
+        ("main.eln", "use shapes\n\nproc main(): i32 {\n    return area(3) as i32\n}\n"),
+        ("shapes.eln", "use util\n\nproc area(x: i32): i64 {\n    return square(x) as i64\n}\n"),
+        ("util.eln", "proc square(x: i32): i32 {\n    return x * x\n}\n"),
+    ]);
+    assert_eq!(build(&dir), ["main.eln", "shapes.eln", "util.eln"]);
+    assert_eq!(build(&dir), ["main.eln"]);
+
+    // shapes.eln hasn't changed, but what it calls has
+    fs::write(dir.join("util.eln"), "proc square(x: i32): i64 {\n    return x as i64 * x as i64\n}\n").unwrap();
+    assert_eq!(build(&dir), ["main.eln", "util.eln", "shapes.eln"]);
+    assert_eq!(build(&dir), ["main.eln"]);
+
+    // a change that leaves the signature alone doesn't make shapes.eln be parsed again
+    fs::write(dir.join("util.eln"), "proc square(x: i32): i64 {\n    return (x * x) as i64\n}\n").unwrap();
+    assert_eq!(build(&dir), ["main.eln", "util.eln"]);
+    fs::remove_dir_all(&dir).unwrap();
+}
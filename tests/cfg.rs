@@ -0,0 +1,63 @@
+//! Each tests/cfg/NAME.eln is built and the control flow graph of each of its procs, as --dump-cfg
+//! writes it, compared against NAME.PROC.dot.
+//! Setting ELGIN_BLESS writes the graphs out instead, for when a change to them is intended.
+
+use elgin::cfg;
+use elgin::errors::Logger;
+use elgin::ir::{IRBuilder, IRProc};
+use elgin::lexer::Lexer;
+use elgin::parser::{BufferedSource, Parser};
+
+use std::env;
+use std::fs;
+use std::path::Path;
+
+/// The procs in `src` once they're analyzed, which is when --dump-cfg writes them out
+fn analyzed(src: &str) -> Vec<IRProc> {
+    let logger = Logger::new();
+    let mut parser = Parser::from_source(BufferedSource::new(Lexer::new(src, &logger)), &logger);
+    let (nodes, _) = parser.go();
+    let mut irbuilder = IRBuilder::new(&nodes, parser.available_type_var, &logger);
+    irbuilder.go();
+    irbuilder.analyze();
+    assert_eq!(logger.error_count(), 0, "{:?}", logger.drain());
+    irbuilder.procs
+}
+
+#[test]
+fn graphs() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/cfg");
+    let bless = env::var_os("ELGIN_BLESS").is_some();
+    let mut checked = 0;
+    let mut failures = vec![];
+    let mut entries: Vec<_> = fs::read_dir(&dir).unwrap().map(|e| e.unwrap().path()).collect();
+    entries.sort();
+    for path in entries.iter().filter(|p| p.extension().is_some_and(|e| e == "eln")) {
+        let stem = path.file_stem().unwrap().to_string_lossy();
+        for proc in analyzed(&fs::read_to_string(path).unwrap()).iter().filter(|proc| !proc.body.is_empty()) {
+            let actual = cfg::to_dot(proc);
+            let expected_path = dir.join(format!("{}.{}.dot", stem, proc.name));
+            if bless {
+                fs::write(&expected_path, &actual).unwrap();
+            } else if fs::read_to_string(&expected_path).ok().as_deref() != Some(actual.as_str()) {
+                failures.push(format!("{} doesn't match, it's now:\n{}", expected_path.display(), actual));
+            }
+            checked += 1;
+        }
+    }
+    assert!(checked >= 2, "only found {} graphs in {}", checked, dir.display());
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
+
+#[test]
+fn labels_are_the_same_every_time() {
+    let src = fs::read_to_string(Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/cfg/loop_with_elif.eln")).unwrap();
+    let listing = |procs: Vec<IRProc>| procs.iter().map(|proc| proc.to_text()).collect::<String>();
+    let first = listing(analyzed(&src));
+    // building something else in between doesn't move them along
+    analyzed("proc main(): i32 {\n    while true {\n        if false {\n            return 1\n        }\n    }\n}\n");
+    assert_eq!(listing(analyzed(&src)), first);
+    for label in ["while0.cond:", "while0.end:", "if1.body:", "if1.else:", "if2.body:", "if2.end:"] {
+        assert!(first.contains(label), "no {} in\n{}", label, first);
+    }
+}
@@ -0,0 +1,267 @@
+//! Running the elgin binary itself, for what only the command line does
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+/// Writes `src` to a file of its own and compiles it with `args`, giving back where the file was
+fn elgin(name: &str, src: &str, args: &[&str]) -> (Output, PathBuf) {
+    let dir = env::temp_dir().join(format!("elgin-cli-{}-{}", name, std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join(format!("{}.eln", name));
+    fs::write(&path, src).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_elgin")).arg(&path).args(args).output().unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+    (output, path)
+}
+
+/// Compiles `src` to an executable with `args` and runs it, giving back what the compiler and then the program did
+fn build_and_run(name: &str, src: &str, args: &[&str]) -> (Output, Output) {
+    let dir = env::temp_dir().join(format!("elgin-cli-{}-{}", name, std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join(format!("{}.eln", name));
+    fs::write(&path, src).unwrap();
+    let exe = dir.join(name);
+    let compiled = Command::new(env!("CARGO_BIN_EXE_elgin")).arg(&path).arg("-o").arg(&exe).args(args).output().unwrap();
+    assert!(compiled.status.success(), "{}", String::from_utf8_lossy(&compiled.stderr));
+    let ran = Command::new(&exe).output().unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+    (compiled, ran)
+}
+
+#[test]
+fn json_diagnostics() {
+    let src = "proc main(): i32 {\n    var s = \"a \\\"quote\\\"\"\n    return y + z\n}\n";
+    let (output, path) = elgin("json", src, &["--error-format", "json", "--no-cache"]);
+    assert_eq!(output.status.code(), Some(1));
+    let file = path.to_string_lossy().replace('\\', "\\\\");
+    let expected = format!(
+        "{{\"code\":\"E0201\",\"severity\":\"name error\",\"message\":\"Can't find a variable named y in the current scope\",\
+\"pos\":56,\"len\":1,\"file\":\"{0}\",\"line\":3,\"column\":12}}\n\
+{{\"code\":\"E0201\",\"severity\":\"name error\",\"message\":\"Can't find a variable named z in the current scope\",\
+\"pos\":60,\"len\":1,\"file\":\"{0}\",\"line\":3,\"column\":16}}\n",
+        file,
+    );
+    // nothing else goes to stderr, not even the count of errors
+    assert_eq!(String::from_utf8(output.stderr).unwrap(), expected);
+}
+
+#[test]
+fn human_diagnostics_have_codes() {
+    let (output, _) = elgin("human", "proc main(): i32 {\n    return y\n}\n", &["--no-cache"]);
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("name error[E0201]: Can't find a variable named y"), "{}", stderr);
+}
+
+#[cfg(unix)]
+#[test]
+fn out_of_bounds_store_traps_by_default() {
+    use std::os::unix::process::ExitStatusExt;
+
+    let cases = [
+        ("store", "proc main(): i32 {\n    var a: [4]i32\n    var i = 4\n    a[i] = 1\n    return a[0]\n}\n", "a[i] = 1"),
+        ("nested", "proc main(): i32 {\n    var m: [2][3]i32\n    var i = 1\n    var j = 3\n    m[i][j] = 5\n    return m[0][0]\n}\n", "m[i][j] = 5"),
+    ];
+    for (name, src, line) in cases {
+        let (_, ran) = build_and_run(name, src, &[]);
+        // abort() is how a failed check ends the program
+        assert_eq!(ran.status.signal(), Some(6), "{:?}", ran.status);
+        let stderr = String::from_utf8(ran.stderr).unwrap();
+        assert!(stderr.contains("runtime error[E0301]: Index out of bounds"), "{}", stderr);
+        assert!(stderr.contains(line), "{}", stderr);
+    }
+}
+
+#[test]
+fn f32_literals_run_the_same_as_compiled() {
+    let src = "proc main(): i32 {\n    var big: f32 = 1000000000000000000000000000000000000000.0\n    println(big)\n    \
+var third: f32 = 0.1\n    println(third * 3.0 == 0.3)\n    var wide = 0.1\n    println(wide * 3.0 == 0.3)\n    return 0\n}\n";
+    let (interpreted, _) = elgin("f32_run", src, &["--run", "--no-cache"]);
+    let (_, compiled) = build_and_run("f32_native", src, &["--no-cache"]);
+    assert_eq!(String::from_utf8(interpreted.stdout).unwrap(), "inf\ntrue\nfalse\n");
+    assert_eq!(String::from_utf8(compiled.stdout).unwrap(), "inf\ntrue\nfalse\n");
+}
+
+#[test]
+fn tail_recursion_runs_a_million_deep() {
+    let src = "proc count_down(n: i64, total: i64): i64 {\n    if n == 0 {\n        return total\n    }\n    return count_down(n - 1, total + 2)\n}\n\
+proc factorial(n: i64): i64 {\n    if n <= 1 {\n        return 1\n    }\n    return n * factorial(n - 1)\n}\n\
+proc main(): i32 {\n    println(count_down(1000000, 0))\n    println(factorial(20))\n    return 0\n}\n";
+    let (_, ran) = build_and_run("tail_calls", src, &["-O1", "--no-cache"]);
+    assert!(ran.status.success(), "{:?}", ran.status);
+    assert_eq!(String::from_utf8(ran.stdout).unwrap(), "2000000\n2432902008176640000\n");
+}
+
+#[test]
+fn str_length_and_indexing_compiled() {
+    let src = "proc count(s: str, c: n8): n64 {\n    var n = 0\n    for i = 0 .. len(s) {\n        if s[i] == c {\n            n += 1\n        }\n    }\n    return n\n}\n\
+proc main(): i32 {\n    var s = \"hello world\"\n    var first = s[0]\n    println(first)\n    println(len(s))\n    println(count(s, s[2]))\n    return 0\n}\n";
+    let (interpreted, _) = elgin("str_run", src, &["--run", "--no-cache"]);
+    let (_, compiled) = build_and_run("str_native", src, &["--no-cache"]);
+    // h is 104
+    assert_eq!(String::from_utf8(interpreted.stdout).unwrap(), "104\n11\n3\n");
+    assert_eq!(String::from_utf8(compiled.stdout).unwrap(), "104\n11\n3\n");
+}
+
+#[test]
+fn casts_extend_by_signedness() {
+    let src = "proc main(): i32 {\n    var a: i8 = -1\n    var b: n8 = 255\n    var big: i64 = 300\n    var f = -2.75\n    \
+println(a as i64)\n    println(b as i64)\n    println(a as n16)\n    println(big as n8)\n    println(f as i32)\n    println(b as f64)\n    return 0\n}\n";
+    // sign extended, zero extended, wrapped, truncated and rounded towards zero
+    let expected = "-1\n255\n65535\n44\n-2\n255\n";
+    let (interpreted, _) = elgin("casts_run", src, &["--run", "--no-cache"]);
+    let (_, compiled) = build_and_run("casts_native", src, &["--no-cache"]);
+    assert_eq!(String::from_utf8(interpreted.stdout).unwrap(), expected);
+    assert_eq!(String::from_utf8(compiled.stdout).unwrap(), expected);
+}
+
+#[test]
+fn used_file_compiles_and_runs() {
+    let dir = env::temp_dir().join(format!("elgin-cli-modules-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("main.eln"), "use util\n\nproc main(): i32 {\n    println(square(3))\n    return square(4) + OFFSET\n}\n").unwrap();
+    fs::write(dir.join("util.eln"), "const OFFSET: i32 = 2\n\nproc square(x: i32): i32 {\n    return x * x\n}\n").unwrap();
+    let main = dir.join("main.eln");
+    let exe = dir.join("main");
+
+    let interpreted = Command::new(env!("CARGO_BIN_EXE_elgin")).arg(&main).args(["--run", "--no-cache"]).output().unwrap();
+    let compiled = Command::new(env!("CARGO_BIN_EXE_elgin")).arg(&main).arg("-o").arg(&exe).arg("--no-cache").output().unwrap();
+    assert!(compiled.status.success(), "{}", String::from_utf8_lossy(&compiled.stderr));
+    let ran = Command::new(&exe).output().unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+
+    for output in [interpreted, ran] {
+        assert_eq!(output.status.code(), Some(18));
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), "9\n");
+    }
+}
+
+#[test]
+fn arrays_pass_to_and_from_procs_by_value() {
+    let src = "proc sum(a: [4]i32): i32 {\n    var total = 0\n    for i = 0 .. 4 {\n        total += a[i]\n    }\n    return total\n}\n\
+proc clear(a: [4]i32): i32 {\n    a[0] = 0\n    return a[0]\n}\n\
+proc make(x: i32): [4]i32 {\n    var a: [4]i32\n    for i = 0 .. 4 {\n        a[i] = x + i\n    }\n    return a\n}\n\
+proc main(): i32 {\n    var a: [4]i32\n    a[0] = 1\n    a[1] = 2\n    a[2] = 3\n    a[3] = 4\n    println(sum(a))\n    \
+println(clear(a))\n    println(a[0])\n    println(sum(make(5)))\n    println(make(7)[3])\n    return 0\n}\n";
+    // the copy clear() gets is its own
+    let expected = "10\n0\n1\n26\n10\n";
+    let (interpreted, _) = elgin("arrays_run", src, &["--run", "--no-cache"]);
+    let (_, compiled) = build_and_run("arrays_native", src, &["--no-cache"]);
+    assert_eq!(String::from_utf8(interpreted.stdout).unwrap(), expected);
+    assert_eq!(String::from_utf8(compiled.stdout).unwrap(), expected);
+}
+
+#[test]
+fn negating_floats_and_signed_ints() {
+    let src = "proc main(): i32 {\n    var x = 2.5\n    var y: f32 = -0.5\n    var n: i16 = 300\n    println(-x)\n    println(-(-x))\n    println(+x)\n    \
+println(-y)\n    println(-n)\n    println(-x * 2.0)\n    return 0\n}\n";
+    let expected = "-2.5\n2.5\n2.5\n0.5\n-300\n-5\n";
+    let (interpreted, _) = elgin("negate_run", src, &["--run", "--no-cache"]);
+    let (_, compiled) = build_and_run("negate_native", src, &["--no-cache"]);
+    assert_eq!(String::from_utf8(interpreted.stdout).unwrap(), expected);
+    assert_eq!(String::from_utf8(compiled.stdout).unwrap(), expected);
+}
+
+#[test]
+fn procs_named_like_c_functions() {
+    // abort is what a failed bounds check calls, and printf is what println calls
+    let src = "proc abort(code: n8): n8 {\n    return code + 1\n}\nproc printf(x: i32): i32 {\n    return x * 2\n}\n\
+proc main(): n8 {\n    var values: [3]n8\n    values[2] = 30\n    var i = 2\n    println(printf(21))\n    return abort(values[i])\n}\n";
+    let (interpreted, _) = elgin("symbols_run", src, &["--run", "--no-cache"]);
+    let (_, compiled) = build_and_run("symbols_native", src, &["--no-cache"]);
+    for output in [interpreted, compiled] {
+        assert_eq!(output.status.code(), Some(31));
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), "42\n");
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn failed_assert_points_at_itself() {
+    use std::os::unix::process::ExitStatusExt;
+
+    let src = "proc main(): i32 {\n    var x = 3\n    assert(x == 3)\n    println(x)\n    assert(x > 5, \"x is too small\")\n    println(0)\n    return 0\n}\n";
+    let (interpreted, _) = elgin("assert_run", src, &["--run", "--no-cache"]);
+    let (_, compiled) = build_and_run("assert_native", src, &["--no-cache"]);
+    assert_eq!(interpreted.status.code(), Some(1));
+    assert_eq!(compiled.status.signal(), Some(6), "{:?}", compiled.status);
+    for output in [interpreted, compiled] {
+        // the assert that holds does nothing, the one that doesn't stops the program
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), "3\n");
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert!(stderr.contains(":5:5: runtime error[E0301]: Assertion failed: x is too small"), "{}", stderr);
+        assert!(stderr.contains(&format!("assert(x > 5, \"x is too small\")\n        {}\n", "^".repeat(31))), "{}", stderr);
+    }
+}
+
+#[test]
+fn powers() {
+    let src = "proc main(): i32 {\n    var two: i64 = 2\n    var n: i64 = 10\n    println(two ** n)\n    println(2 ** 3 ** 2)\n    println(-2 ** 2)\n    \
+var x = 1.5\n    println(x ** 2.0)\n    println(x ** 0.0)\n    return 0\n}\n";
+    let expected = "1024\n512\n-4\n2.25\n1\n";
+    let (interpreted, _) = elgin("power_run", src, &["--run", "--no-cache"]);
+    let (_, compiled) = build_and_run("power_native", src, &["--no-cache"]);
+    assert_eq!(String::from_utf8(interpreted.stdout).unwrap(), expected);
+    assert_eq!(String::from_utf8(compiled.stdout).unwrap(), expected);
+}
+
+#[test]
+fn tuples_hand_back_several_values() {
+    let src = "proc divmod(x: i32, y: i32): (i32, i32) {\n    return (x // y, x - x // y * y)\n}\n\
+proc nested(x: i64): ((i64, bool), i64) {\n    return ((x * 2, x > 0), -x)\n}\n\
+proc main(): i32 {\n    var (q, r) = divmod(17, 5)\n    println(q)\n    println(r)\n    var (inner, negated) = nested(4)\n    var (doubled, positive) = inner\n    \
+println(doubled)\n    println(positive)\n    println(negated)\n    return 0\n}\n";
+    let expected = "3\n2\n8\ntrue\n-4\n";
+    let (interpreted, _) = elgin("tuples_run", src, &["--run", "--no-cache"]);
+    let (_, compiled) = build_and_run("tuples_native", src, &["--no-cache"]);
+    assert_eq!(String::from_utf8(interpreted.stdout).unwrap(), expected);
+    assert_eq!(String::from_utf8(compiled.stdout).unwrap(), expected);
+}
+
+#[test]
+fn bitwise_operators() {
+    let src = "proc main(): i32 {\n    var x: i32 = 1000\n    println(x & 255)\n    println(x | 7)\n    println(x ^ x)\n    var amount: n8 = 4\n    \
+var negative: i32 = -64\n    println(negative >> amount)\n    var unsigned: n32 = 4294967232\n    println(unsigned >> amount)\n    \
+var wide: i64 = 3\n    println(wide << amount)\n    var top: n8 = 129\n    println(top << 1)\n    return 0\n}\n";
+    // >> keeps the sign of a signed integer and fills an unsigned one with zeroes
+    let expected = "232\n1007\n0\n-4\n268435452\n48\n2\n";
+    let (interpreted, _) = elgin("bitwise_run", src, &["--run", "--no-cache"]);
+    let (_, compiled) = build_and_run("bitwise_native", src, &["--no-cache"]);
+    assert_eq!(String::from_utf8(interpreted.stdout).unwrap(), expected);
+    assert_eq!(String::from_utf8(compiled.stdout).unwrap(), expected);
+}
+
+#[test]
+fn grids_fill_and_read_back() {
+    let src = "proc main(): i32 {\n    var grid: [3][3]i32\n    for i = 0 .. 3 {\n        for j = 0 .. 3 {\n            grid[i][j] = i * 10 + j\n        }\n    }\n    \
+var row = grid[2]\n    println(grid[1][2])\n    println(row[0] + grid[0][1])\n    var total = 0\n    for i = 0 .. 3 {\n        total += grid[i][i]\n    }\n    println(total)\n    return 0\n}\n";
+    let expected = "12\n21\n33\n";
+    let (interpreted, _) = elgin("grid_run", src, &["--run", "--no-cache"]);
+    let (_, compiled) = build_and_run("grid_native", src, &["--no-cache"]);
+    assert_eq!(String::from_utf8(interpreted.stdout).unwrap(), expected);
+    assert_eq!(String::from_utf8(compiled.stdout).unwrap(), expected);
+}
+
+#[test]
+fn print_numbers() {
+    let src = "proc main(): i32 {\n    var n: i32 = -42\n    var f = 2.5\n    print(n)\n    print(\" \")\n    println(f)\n    println(f * 4.0)\n    println(n > 0)\n    return 0\n}\n";
+    let expected = "-42 2.5\n10\nfalse\n";
+    let (interpreted, _) = elgin("print_run", src, &["--run", "--no-cache"]);
+    let (_, compiled) = build_and_run("print_native", src, &["--no-cache"]);
+    assert_eq!(String::from_utf8(interpreted.stdout).unwrap(), expected);
+    assert_eq!(String::from_utf8(compiled.stdout).unwrap(), expected);
+}
+
+#[test]
+fn comparators_passed_to_procs() {
+    let src = "proc larger(a: i32, b: i32): bool {\n    return a > b\n}\nproc smaller(a: i32, b: i32): bool {\n    return a < b\n}\n\
+proc max_by(a: i32, b: i32, better: proc(i32, i32): bool): i32 {\n    if better(a, b) {\n        return a\n    }\n    return b\n}\n\
+proc main(): i32 {\n    println(max_by(3, 17, larger))\n    var pick = larger\n    pick = smaller\n    println(max_by(3, 17, pick))\n    return 0\n}\n";
+    let expected = "17\n3\n";
+    let (interpreted, _) = elgin("comparator_run", src, &["--run", "--no-cache"]);
+    let (_, compiled) = build_and_run("comparator_native", src, &["--no-cache"]);
+    assert_eq!(String::from_utf8(interpreted.stdout).unwrap(), expected);
+    assert_eq!(String::from_utf8(compiled.stdout).unwrap(), expected);
+}
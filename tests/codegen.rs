@@ -0,0 +1,107 @@
+//! What goes into the LLVM module
+
+use elgin::interp::{self, Value};
+use elgin::llvm::{Generator, Target};
+
+use std::env;
+use std::fs;
+
+/// The module as LLVM's text format
+fn llvm_ir(name: &str, src: &str) -> String {
+    llvm_ir_for(name, src, &Target::default()).unwrap()
+}
+
+/// The module as LLVM's text format when generating code for `target`, or why that can't be done
+fn llvm_ir_for(name: &str, src: &str, target: &Target) -> Result<String, String> {
+    let procs = elgin::compile_to_ir(src).unwrap_or_else(|diags| panic!("expected no errors, got {:?}", diags));
+    let mut generator = Generator::new(&procs, &[], "elgin", &format!("{}.eln", name));
+    generator.set_target(target)?;
+    generator.go();
+    let path = env::temp_dir().join(format!("elgin-codegen-{}-{}.ll", name, std::process::id()));
+    generator.dump_to_file(&path.to_string_lossy()).unwrap();
+    let text = fs::read_to_string(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+    Ok(text)
+}
+
+/// What the module says it's for, as its triple and datalayout
+fn triple_and_layout(text: &str) -> (&str, &str) {
+    let quoted = |key: &str| {
+        let line = text.lines().find(|line| line.starts_with(key)).unwrap_or_else(|| panic!("no {} in\n{}", key, text));
+        line.split('"').nth(1).unwrap()
+    };
+    (quoted("target triple"), quoted("target datalayout"))
+}
+
+#[test]
+fn repeated_string_literal_is_emitted_once() {
+    let mut src = String::from("proc main(): i32 {\n");
+    for i in 0..100 {
+        if i % 2 == 0 {
+            src.push_str("    println(\"the same literal\")\n");
+        } else {
+            src.push_str("    var s = \"the same literal\"\n    print(s)\n");
+        }
+    }
+    src.push_str("    return 0\n}\n");
+    let text = llvm_ir("repeated", &src);
+    assert_eq!(text.matches("the same literal").count(), 1, "{}", text);
+}
+
+#[test]
+fn targets_set_the_triple_and_datalayout() {
+    let src = "proc main(): i32 {\n    println(\"hi\")\n    return 0\n}\n";
+    let host = llvm_ir_for("host", src, &Target::default()).unwrap();
+    let (triple, layout) = triple_and_layout(&host);
+    assert!(triple.starts_with(std::env::consts::ARCH), "{}", triple);
+    assert!(!layout.is_empty());
+
+    // an LLVM may be built without either of these, but at least one of them is expected
+    let mut crossed = 0;
+    for (triple, pointers) in [("aarch64-unknown-linux-gnu", None), ("wasm32-unknown-unknown", Some("p:32:32"))] {
+        let target = Target { triple: Some(triple.to_owned()), ..Target::default() };
+        let text = match llvm_ir_for(triple, src, &target) {
+            Ok(text) => text,
+            Err(message) if message.contains("the targets it was built with are") => continue,
+            Err(message) => panic!("{}", message),
+        };
+        let (found, layout) = triple_and_layout(&text);
+        assert_eq!(found, triple);
+        assert_ne!(layout, triple_and_layout(&host).1);
+        // 64 bit pointers are the default, so only 32 bit ones are written out
+        match pointers {
+            Some(pointers) => assert!(layout.contains(pointers), "{}", layout),
+            None => assert!(!layout.contains("p:32:32"), "{}", layout),
+        }
+        crossed += 1;
+    }
+    assert!(crossed > 0);
+
+    let target = Target { triple: Some("nonsense-none-nowhere".to_owned()), ..Target::default() };
+    let message = llvm_ir_for("nonsense", src, &target).unwrap_err();
+    assert!(message.contains("the targets it was built with are"), "{}", message);
+}
+
+#[test]
+fn unsigned_compares_and_divides_unsigned() {
+    let src = "proc check(x: n8, y: n8): bool {\n    return x > y && x // y == 2\n}\n\
+proc main(): i32 {\n    if check(200, 100) {\n        return 1\n    }\n    return 0\n}\n";
+    let text = llvm_ir("unsigned", src);
+    assert!(text.contains("icmp ugt i8"), "{}", text);
+    assert!(text.contains("udiv i8"), "{}", text);
+    assert!(!text.contains("icmp sgt") && !text.contains("sdiv"), "{}", text);
+    // 200 as an i8 is -56, which wouldn't be more than 100
+    let procs = elgin::compile_to_ir(src).unwrap();
+    assert!(matches!(interp::run(&procs, &[], "main"), Ok(Value::Int(1))));
+}
+
+#[test]
+fn procs_without_a_value_return_void() {
+    let src = "proc early(x: i32) {\n    if x > 5 {\n        return\n    }\n    println(x)\n}\n\
+proc main(): i32 {\n    early(1)\n    return 0\n}\n";
+    let text = llvm_ir("void", src);
+    let start = text.find("define void @elgin_early").unwrap_or_else(|| panic!("no void early in\n{}", text));
+    let body = &text[start..start + text[start..].find("\n}").unwrap()];
+    assert_eq!(body.matches("ret void").count(), 2, "{}", body);
+    assert!(!body.contains("ret i"), "{}", body);
+}
@@ -0,0 +1,809 @@
+//! What the compiler says about programs that are wrong, going by codes and spans rather than wording
+
+use elgin::errors::{Code, Diagnostic, ErrorType, Logger};
+use elgin::interp::{self, Value};
+use elgin::ir::IRBuilder;
+use elgin::lexer::Lexer;
+use elgin::parser::{BufferedSource, Parser};
+use elgin::types::Type;
+
+fn diagnostics(src: &str) -> Vec<Diagnostic> {
+    match elgin::compile_to_ir(src) {
+        Ok(_) => vec![],
+        Err(diags) => diags,
+    }
+}
+
+/// Warnings as well, which the library drops when there aren't any errors
+fn with_warnings(src: &str) -> Vec<Diagnostic> {
+    let logger = Logger::new();
+    let mut parser = Parser::from_source(BufferedSource::new(Lexer::new(src, &logger)), &logger);
+    let (nodes, _) = parser.go();
+    let mut irbuilder = IRBuilder::new(&nodes, parser.available_type_var, &logger);
+    irbuilder.go();
+    irbuilder.analyze();
+    logger.drain()
+}
+
+fn codes(src: &str) -> Vec<Code> {
+    diagnostics(src).iter().filter_map(|d| d.code).collect()
+}
+
+#[test]
+fn unknown_proc_suggests_builtin() {
+    let diags = diagnostics("proc main(): i32 {\n    pront(1)\n    return 0\n}\n");
+    assert_eq!(diags.len(), 1, "{:?}", diags);
+    assert_eq!(diags[0].code, Some(Code::UnknownProc));
+    assert!(diags[0].message.contains("print"), "{}", diags[0].message);
+}
+
+#[test]
+fn unknown_proc_still_checks_arguments() {
+    let src = "proc main(): i32 {\n    pront2(zz)\n    return 0\n}\n";
+    assert_eq!(codes(src), vec![Code::UnknownProc, Code::UnknownVariable]);
+    let zz = diagnostics(src).into_iter().find(|d| d.code == Some(Code::UnknownVariable)).unwrap();
+    assert_eq!(&src[zz.pos..zz.pos + zz.len], "zz");
+}
+
+/// One broken program for each of a handful of codes, so that renumbering them can't go unnoticed
+#[test]
+fn codes_of_broken_programs() {
+    let cases = [
+        ("proc main(): i32 {\n    return )\n}\n", vec!["E0001"]),
+        ("proc main(): i32 {\n    var s = \"abc\n    return 0\n}\n", vec!["E0005"]),
+        ("proc main(): i32 {\n    println(\"\\q\")\n    return 0\n}\n", vec!["E0006"]),
+        ("proc main(): i32 {\n    var x = 1\n    if x = 2 {\n        return 1\n    }\n    return 0\n}\n", vec!["E0010"]),
+        ("proc main(): i32 {\n    return 1 + true\n}\n", vec!["E0101"]),
+        ("proc f(a: i32): i32 {\n    return a\n}\nproc main(): i32 {\n    return f(1, 2)\n}\n", vec!["E0102"]),
+        ("proc f(a: i32): i32 {\n    if a > 0 {\n        return a\n    }\n}\nproc main(): i32 {\n    return f(1)\n}\n", vec!["E0107"]),
+        ("proc main(): i32 {\n    return y\n}\n", vec!["E0201"]),
+        ("proc main(): i32 {\n    return f()\n}\n", vec!["E0202"]),
+        ("proc main(): i32 {\n    var x: i33 = 1\n    return 0\n}\n", vec!["E0203"]),
+        ("proc f(): i32 {\n    return 1\n}\nproc f(): i32 {\n    return 2\n}\nproc main(): i32 {\n    return f()\n}\n", vec!["E0205"]),
+        ("proc main(): i32 {\n    #: about nothing\n    return y\n}\n", vec!["W0007", "E0201"]),
+    ];
+    for (src, expected) in cases {
+        let found: Vec<&str> = codes(src).into_iter().map(Code::as_str).collect();
+        assert_eq!(found, expected, "in\n{}", src);
+    }
+}
+
+#[test]
+fn var_without_type_or_value_cannot_be_inferred() {
+    let src = "proc main(): i32 {\n    var x\n    return 0\n}\n";
+    let diags = diagnostics(src);
+    assert_eq!(diags.len(), 1, "{:?}", diags);
+    assert_eq!(diags[0].code, Some(Code::CannotInfer));
+    assert_eq!(&src[diags[0].pos..diags[0].pos + diags[0].len], "var x");
+}
+
+#[test]
+fn read_before_assignment_warns() {
+    let src = "proc f(c: bool): i32 {\n    var x\n    if c {\n        x = 1\n    }\n    return x\n}\n\
+proc main(): i32 {\n    return f(true)\n}\n";
+    let diags = with_warnings(src);
+    assert_eq!(diags.len(), 1, "{:?}", diags);
+    assert_eq!(diags[0].code, Some(Code::UsedBeforeAssigned));
+    assert_eq!(diags[0].pos, src.find("return x").unwrap() + "return ".len());
+    assert_eq!(diags[0].len, 1);
+}
+
+#[test]
+fn var_with_type_and_no_value_is_zero() {
+    let sources = [
+        "proc main(): i32 {\n    var x: i32\n    return x\n}\n",
+        "proc main(): i32 {\n    var a: [4]i32\n    for i = 1 .. 4 {\n        a[i] = i\n    }\n    return a[0] + a[3]\n}\n",
+        "struct P {\n    x: i32\n    y: i32\n}\nproc main(): i32 {\n    var p: P\n    p.y = 3\n    return p.x + p.y\n}\n",
+    ];
+    let results = [0, 3, 3];
+    for (src, result) in sources.iter().zip(results) {
+        assert!(with_warnings(src).is_empty(), "{:?} in\n{}", with_warnings(src), src);
+        let procs = elgin::compile_to_ir(src).unwrap();
+        match interp::run(&procs, &[], "main") {
+            Ok(Value::Int(value)) => assert_eq!(value, result),
+            _ => panic!("main didn't return an int in\n{}", src),
+        }
+    }
+}
+
+#[test]
+fn returning_from_both_branches_is_enough() {
+    let src = "proc max(a: i32, b: i32): i32 { if a > b { return a } else { return b } }\n\
+proc main(): i32 {\n    return max(3, 8)\n}\n";
+    assert!(with_warnings(src).is_empty(), "{:?}", with_warnings(src));
+    let procs = elgin::compile_to_ir(src).unwrap();
+    assert!(matches!(interp::run(&procs, &[], "main"), Ok(Value::Int(8))));
+
+    let src = "proc max(a: i32, b: i32): i32 { if a > b { return a } }\n\
+proc main(): i32 {\n    return max(3, 8)\n}\n";
+    let diags = diagnostics(src);
+    assert_eq!(diags.len(), 1, "{:?}", diags);
+    assert_eq!(diags[0].code, Some(Code::MissingReturn));
+}
+
+#[test]
+fn duplicate_match_arm_points_at_both() {
+    let src = "proc main(): i32 {\n    var x = 2\n    match x {\n        1 { return 1 }\n        2 { return 2 }\n        01 { return 3 }\n    }\n    return 0\n}\n";
+    let diags = diagnostics(src);
+    assert_eq!(diags.len(), 2, "{:?}", diags);
+    assert_eq!(diags[0].code, Some(Code::DuplicateMatchArm));
+    assert_eq!(&src[diags[0].pos..diags[0].pos + diags[0].len], "01");
+    assert_eq!(diags[1].severity, ErrorType::Note);
+    assert_eq!(diags[1].pos, src.find("1 { return 1 }").unwrap());
+    assert_eq!(diags[1].len, 1);
+}
+
+#[test]
+fn match_runs_the_right_arm() {
+    let src = "proc pick(x: i32): i32 {\n    match x {\n        0 { return 10 }\n        -1 { return 20 }\n        else { return 30 }\n    }\n}\n\
+proc main(): i32 {\n    return pick(0) + pick(-1) * 2 + pick(7) * 3\n}\n";
+    let procs = elgin::compile_to_ir(src).unwrap();
+    assert!(matches!(interp::run(&procs, &[], "main"), Ok(Value::Int(140))));
+}
+
+#[test]
+fn vars_only_assigned_to_each_other_cannot_be_inferred() {
+    let src = "proc main(): i32 {\n    var a\n    var b\n    a = b\n    b = a\n    return 0\n}\n";
+    let diags = diagnostics(src);
+    assert!(matches!(diags.len(), 1 | 2), "{:?}", diags);
+    assert!(diags.iter().all(|d| d.code == Some(Code::CannotInfer)), "{:?}", diags);
+    assert_eq!(&src[diags[0].pos..diags[0].pos + diags[0].len], "var a");
+}
+
+#[test]
+fn errors_in_different_procs_are_all_reported() {
+    let src = "proc f(x: i32): i33 {\n    return x\n}\nproc main(): i32 {\n    var count = 1\n    return cuont\n}\n";
+    let diags = diagnostics(src);
+    let found: Vec<(Option<Code>, &str)> = diags.iter().map(|d| (d.code, &src[d.pos..d.pos + d.len])).collect();
+    assert_eq!(found, [(Some(Code::UnknownType), "i33"), (Some(Code::UnknownVariable), "cuont")]);
+}
+
+#[test]
+fn swapped_letters_suggest_the_variable() {
+    let src = "proc main(): i32 {\n    var count = 1\n    return cuont\n}\n";
+    let diags = diagnostics(src);
+    assert_eq!(diags.len(), 1, "{:?}", diags);
+    assert!(diags[0].message.ends_with("did you mean count?"), "{}", diags[0].message);
+    // a one-letter name is a single edit away from every other one
+    let diags = diagnostics("proc main(): i32 {\n    var s = 1\n    return y\n}\n");
+    assert_eq!(diags.len(), 1, "{:?}", diags);
+    assert!(!diags[0].message.contains("did you mean"), "{}", diags[0].message);
+}
+
+#[test]
+fn proc_without_annotations_is_clean() {
+    let src = "proc average(a, b) {\n    var sum = a + b\n    println(sum)\n}\nproc main(): i32 {\n    average(2.5, 4.0)\n    return 0\n}\n";
+    assert!(with_warnings(src).is_empty(), "{:?}", with_warnings(src));
+}
+
+#[test]
+fn non_bool_elif_condition() {
+    let src = "proc main(): i32 {\n    var x = 3\n    if x > 5 {\n        return 1\n    } elif x + 1 {\n        return 2\n    }\n    return 0\n}\n";
+    let diags = diagnostics(src);
+    // along with a note saying where the i32 came from
+    assert_eq!(diags.iter().filter(|d| d.severity != ErrorType::Note).count(), 1, "{:?}", diags);
+    assert_eq!(diags[0].code, Some(Code::MismatchedTypes));
+    assert_eq!(&src[diags[0].pos..diags[0].pos + diags[0].len], "x + 1");
+}
+
+#[test]
+fn assignment_in_condition_suggests_comparison() {
+    for keyword in ["if", "elif", "while"] {
+        let src = match keyword {
+            "elif" => "proc main(): i32 {\n    var x = 3\n    if x > 5 {\n        return 1\n    } elif x = 5 {\n        return 2\n    }\n    return 0\n}\n".to_owned(),
+            _ => format!("proc main(): i32 {{\n    var x = 3\n    {} x = 5 {{\n        return 2\n    }}\n    return 0\n}}\n", keyword),
+        };
+        let diags = diagnostics(&src);
+        assert_eq!(diags.len(), 1, "{:?} in\n{}", diags, src);
+        assert_eq!(diags[0].code, Some(Code::AssignmentInCondition));
+        assert!(diags[0].message.contains("did you mean =="), "{}", diags[0].message);
+        assert_eq!(diags[0].pos, src.find("x = 5").unwrap() + 2);
+        assert_eq!(diags[0].len, 1);
+    }
+}
+
+#[test]
+fn slash_on_integers_points_to_int_divide() {
+    let cases = [
+        ("proc main(): i32 {\n    var x = 10\n    return x / 4\n}\n", "/"),
+        ("proc main(): i32 {\n    var x: i32 = 10\n    x /= 4\n    return x\n}\n", "/="),
+    ];
+    for (src, op) in cases {
+        let diags = diagnostics(src);
+        assert_eq!(diags.len(), 1, "{:?}", diags);
+        assert_eq!(diags[0].code, Some(Code::InvalidOperation));
+        assert_eq!(diags[0].message, "Can't use / on a i32, use // to divide integers");
+        assert_eq!(&src[diags[0].pos..diags[0].pos + diags[0].len], op);
+    }
+    // floats still divide with /
+    assert!(diagnostics("proc main(): i32 {\n    var x = 10.0\n    x /= 4\n    return (x / 2) as i32\n}\n").is_empty());
+}
+
+/// The text of the first W0005 and of the note after it, which says where the shadowed one is declared
+fn shadowing(src: &str) -> (&str, &str) {
+    let diags = with_warnings(src);
+    let i = diags.iter().position(|d| d.code == Some(Code::ShadowedVariable)).expect("no W0005");
+    let (warning, note) = (&diags[i], &diags[i + 1]);
+    assert_eq!(note.severity, ErrorType::Note, "{:?}", diags);
+    (&src[warning.pos..warning.pos + warning.len], &src[note.pos..note.pos + note.len])
+}
+
+#[test]
+fn shadowed_parameter_points_at_the_parameter() {
+    let src = "proc twice(count: i32, n: i32): i32 {\n    var n = count * 2\n    return n\n}\n\
+proc main(): i32 {\n    return twice(4, 1)\n}\n";
+    let (warning, note) = shadowing(src);
+    assert_eq!((warning, note), ("var n = count * 2", "n"));
+    // not the n in count
+    let note = with_warnings(src).into_iter().find(|d| d.severity == ErrorType::Note).unwrap();
+    assert_eq!(note.pos, src.find("n: i32").unwrap());
+}
+
+#[test]
+fn redeclaration_in_the_same_scope_points_at_the_first() {
+    let src = "proc main(): i32 {\n    var x = 1\n    var x = x + 1\n    return x\n}\n";
+    let (warning, note) = shadowing(src);
+    assert_eq!((warning, note), ("var x = x + 1", "var x = 1"));
+}
+
+#[test]
+fn notes_name_operators_as_written() {
+    for op in ["//", "<<", ">>", "+~"] {
+        let src = format!("proc main(): i32 {{\n    var x: i64 = 1\n    var s: str = x {} 2\n    return 0\n}}\n", op);
+        let diags = diagnostics(&src);
+        assert_eq!(diags[0].code, Some(Code::MismatchedTypes), "{:?}", diags);
+        assert_eq!(diags[1].severity, ErrorType::Note);
+        assert_eq!(diags[1].message, format!("Found to be i64 because of the result of {} here", op));
+        assert_eq!(&src[diags[1].pos..diags[1].pos + diags[1].len], op);
+    }
+}
+
+#[test]
+fn break_and_continue_outside_a_loop() {
+    for keyword in ["break", "continue"] {
+        let src = format!("proc main(): i32 {{\n    var x = 0\n    {}\n    return x\n}}\n", keyword);
+        let diags = diagnostics(&src);
+        assert_eq!(diags.len(), 1, "{:?}", diags);
+        assert_eq!(diags[0].code, Some(Code::MisplacedStatement));
+        assert_eq!(&src[diags[0].pos..diags[0].pos + diags[0].len], keyword);
+    }
+}
+
+#[test]
+fn assigning_to_an_expression() {
+    let src = "proc main(): i32 {\n    var a: [4]i32\n    a[0] + 1 = 5\n    return a[0]\n}\n";
+    let diags = diagnostics(src);
+    assert_eq!(diags.len(), 1, "{:?}", diags);
+    assert_eq!(diags[0].code, Some(Code::InvalidAssignmentTarget));
+    assert_eq!(&src[diags[0].pos..diags[0].pos + diags[0].len], "a[0] + 1");
+}
+
+#[test]
+fn struct_fields_and_construction() {
+    let point = "struct Point {\n    x: i32\n    y: i32\n}\n";
+    let src = format!("{}proc main(): i32 {{\n    var p = Point(1, 2)\n    return p.z\n}}\n", point);
+    let diags = diagnostics(&src);
+    assert_eq!(diags.len(), 1, "{:?}", diags);
+    assert_eq!(diags[0].code, Some(Code::NoSuchMember));
+    assert_eq!(&src[diags[0].pos..diags[0].pos + diags[0].len], "z");
+
+    let src = format!("{}proc main(): i32 {{\n    var p = Point(1)\n    return p.x\n}}\n", point);
+    let diags = diagnostics(&src);
+    assert_eq!(diags.len(), 1, "{:?}", diags);
+    assert_eq!(diags[0].code, Some(Code::WrongArgumentCount));
+    assert_eq!(&src[diags[0].pos..diags[0].pos + diags[0].len], "Point(1)");
+}
+
+#[test]
+fn if_expression_values() {
+    let src = "proc main(): i32 {\n    var x = 3\n    var y = if x > 2 { 1 }\n    return y\n}\n";
+    let diags = diagnostics(src);
+    assert_eq!(diags.len(), 1, "{:?}", diags);
+    assert_eq!(diags[0].code, Some(Code::MissingValue));
+    assert_eq!(&src[diags[0].pos..diags[0].pos + diags[0].len], "if x > 2 { 1 }");
+
+    // both branches have to have the same type, and it's the second that's reported
+    let src = "proc main(): i32 {\n    var x = 3\n    var y = if x > 2 { 1 } else { true }\n    return 0\n}\n";
+    let diags = diagnostics(src);
+    assert_eq!(diags[0].code, Some(Code::MismatchedTypes), "{:?}", diags);
+    assert_eq!(&src[diags[0].pos..diags[0].pos + diags[0].len], "{ true }");
+}
+
+#[test]
+fn every_syntax_error_is_reported() {
+    let src = "proc main(): i32 {\n    var x = 1 var y = 2\n    var z = )\n    x = 3 4\n    return x\n}\n";
+    let diags = diagnostics(src);
+    let found: Vec<(Option<Code>, &str)> = diags.iter().map(|d| (d.code, &src[d.pos..d.pos + d.len])).collect();
+    assert_eq!(found, [
+        (Some(Code::UnexpectedToken), "var"),
+        (Some(Code::UnexpectedToken), ")"),
+        (Some(Code::UnexpectedToken), "4"),
+    ]);
+    // saying which statement wasn't ended
+    assert!(diags[0].message.contains("to end this var statement"), "{}", diags[0].message);
+    assert!(diags[2].message.contains("to end this assignment"), "{}", diags[2].message);
+}
+
+#[test]
+fn type_errors_in_nested_blocks_point_into_them() {
+    let src = "proc main(): i32 {\n    var x = 1\n    while x < 10 {\n        if x > 2 {\n            var y: bool = x * 2\n        }\n        x += 1\n    }\n    return x\n}\n";
+    let diags = diagnostics(src);
+    assert_eq!(diags[0].code, Some(Code::MismatchedTypes), "{:?}", diags);
+    assert_eq!(&src[diags[0].pos..diags[0].pos + diags[0].len], "var y: bool = x * 2");
+    let statement = src.find("var y").unwrap();
+    assert!(diags.iter().all(|d| d.pos >= statement && d.pos + d.len <= statement + "var y: bool = x * 2".len()), "{:?}", diags);
+}
+
+#[test]
+fn calls_with_the_wrong_number_of_arguments() {
+    let src = "proc add(a: i32, b: i32): i32 {\n    return a + b\n}\nproc zero(): i32 {\n    return 0\n}\n\
+proc main(): i32 {\n    var a = add(1)\n    var b = add(1, 2, 3)\n    var c = zero(4)\n    var d = zero()\n    return nope(1)\n}\n";
+    let diags = diagnostics(src);
+    let found: Vec<(Option<Code>, &str, &str)> = diags.iter()
+        .map(|d| (d.code, d.message.as_str(), &src[d.pos..d.pos + d.len]))
+        .collect();
+    assert_eq!(found, [
+        (Some(Code::WrongArgumentCount), "Proc add expects 2 arguments, found 1", "add(1)"),
+        (Some(Code::WrongArgumentCount), "Proc add expects 2 arguments, found 3", "add(1, 2, 3)"),
+        (Some(Code::WrongArgumentCount), "Proc zero expects 0 arguments, found 1", "zero(4)"),
+        (Some(Code::UnknownProc), "Can't find a procedure named nope in the current module", "nope(1)"),
+    ]);
+}
+
+#[test]
+fn variables_declared_in_a_block_end_with_it() {
+    let src = "proc main(): i32 {\n    var x = 1\n    if x > 0 {\n        var inner = 2\n        x += inner\n    }\n    return inner\n}\n";
+    let diags = diagnostics(src);
+    assert_eq!(diags.len(), 1, "{:?}", diags);
+    assert_eq!(diags[0].code, Some(Code::UnknownVariable));
+    assert_eq!(diags[0].pos, src.rfind("inner").unwrap());
+}
+
+#[test]
+fn consts_stay_constant() {
+    let src = "const SIZE: i32 = 8\nproc main(): i32 {\n    SIZE = 9\n    SIZE += 1\n    return SIZE\n}\n";
+    let diags = diagnostics(src);
+    let found: Vec<(Option<Code>, &str)> = diags.iter().map(|d| (d.code, &src[d.pos..d.pos + d.len])).collect();
+    assert_eq!(found, [(Some(Code::AssignToConst), "SIZE = 9"), (Some(Code::AssignToConst), "SIZE += 1")]);
+
+    // the const is known to be 8 when checking the index
+    let src = "const SIZE: i32 = 8\nproc main(): i32 {\n    var a: [SIZE]i32\n    return a[SIZE]\n}\n";
+    let diags = diagnostics(src);
+    assert_eq!(diags.len(), 1, "{:?}", diags);
+    assert_eq!(diags[0].code, Some(Code::NoSuchMember));
+    assert_eq!(diags[0].message, "Index 8 is out of bounds for a [8]i32");
+}
+
+#[test]
+fn casting_between_kinds() {
+    let src = "proc main(): i32 {\n    var b = true\n    var f = b as f64\n    return 0\n}\n";
+    let diags = diagnostics(src);
+    assert_eq!(diags.len(), 1, "{:?}", diags);
+    assert_eq!(diags[0].code, Some(Code::InvalidOperation));
+    assert_eq!(diags[0].message, "Can't cast a bool to a f64");
+    assert_eq!(&src[diags[0].pos..diags[0].pos + diags[0].len], "b as f64");
+}
+
+#[test]
+fn mixing_signed_and_unsigned_needs_a_cast() {
+    let src = "proc main(): i32 {\n    var a: i32 = 1\n    var b: n32 = 2\n    var c = a + b\n    return 0\n}\n";
+    let diags = diagnostics(src);
+    assert_eq!(diags.len(), 1, "{:?}", diags);
+    assert_eq!(diags[0].code, Some(Code::MismatchedTypes));
+    assert!(diags[0].message.contains("cast the right one with as i32"), "{}", diags[0].message);
+    assert_eq!(&src[diags[0].pos..diags[0].pos + diags[0].len], "+");
+}
+
+#[test]
+fn duplicate_procs_point_at_both() {
+    let src = "proc f(): i32 {\n    return 1\n}\nproc f(x: i32): i32 {\n    return x\n}\nproc main(): i32 {\n    return f()\n}\n";
+    let diags = diagnostics(src);
+    assert_eq!(diags.len(), 2, "{:?}", diags);
+    assert_eq!(diags[0].code, Some(Code::Redefinition));
+    assert_eq!(diags[0].pos, src.rfind("proc f").unwrap());
+    assert_eq!(diags[1].severity, ErrorType::Note);
+    assert_eq!(diags[1].pos, 0);
+}
+
+#[test]
+fn chained_comparisons() {
+    for (src, chain) in [
+        ("proc f(a: i32, b: i32, c: i32): bool {\n    return a < b < c\n}\n", "< b <"),
+        ("proc f(a: i32, b: i32, c: i32, d: i32): bool {\n    return a <= b == c > d\n}\n", "<= b =="),
+    ] {
+        let diags = diagnostics(src);
+        assert_eq!(diags.len(), 1, "{:?}", diags);
+        assert_eq!(diags[0].code, Some(Code::ChainedComparison));
+        assert_eq!(&src[diags[0].pos..diags[0].pos + diags[0].len], chain);
+    }
+    // with brackets or && in between, it's clear what's meant
+    for src in [
+        "proc f(a: i32, b: i32, c: bool): bool {\n    return (a < b) == c\n}\n",
+        "proc f(a: i32, b: i32, c: i32): bool {\n    return a < b && b < c\n}\n",
+    ] {
+        let diags = diagnostics(&format!("{}proc main(): i32 {{\n    return 0\n}}\n", src));
+        assert!(diags.is_empty(), "{:?}", diags);
+    }
+}
+
+#[test]
+fn returns_must_match_the_proc() {
+    let cases = [
+        ("proc f(): i32 {\n    return\n}\n", Code::ReturnMismatch),
+        ("proc f(x: i32) {\n    if x > 0 {\n        return x\n    }\n}\n", Code::ReturnMismatch),
+        ("proc f(x: i32): i32 {\n    if x > 0 {\n        return 1\n    }\n}\n", Code::MissingReturn),
+        ("proc f(x: i32): i32 {\n    while x > 0 {\n        return 1\n    }\n}\n", Code::MissingReturn),
+    ];
+    let main = "proc main(): i32 {\n    return 0\n}\n";
+    for (src, code) in cases {
+        assert_eq!(codes(&format!("{}{}", src, main)), vec![code], "in\n{}", src);
+    }
+    // every way through these ends in a return
+    let fine = [
+        "proc f(x: i32): i32 {\n    if x > 0 {\n        return 1\n    } else {\n        return 2\n    }\n}\n",
+        "proc f(): i32 {\n    loop {\n        return 7\n    }\n}\n",
+        "proc f(x: i32) {\n    if x > 0 {\n        return\n    }\n}\n",
+    ];
+    for src in fine {
+        let diags = diagnostics(&format!("{}{}", src, main));
+        assert!(diags.is_empty(), "{:?} in\n{}", diags, src);
+    }
+}
+
+#[test]
+fn recursive_proc_without_a_return_type() {
+    let src = "proc fact(n: i32) {\n    if n <= 1 {\n        return 1\n    }\n    return n * fact(n - 1)\n}\n\
+proc main(): i32 {\n    fact(3)\n    return 0\n}\n";
+    let diags = diagnostics(src);
+    assert!(!diags.is_empty());
+    assert!(diags.iter().all(|d| d.code == Some(Code::ReturnMismatch)), "{:?}", diags);
+    assert!(diags[0].message.contains("explicit return type"), "{}", diags[0].message);
+    assert_eq!(&src[diags[0].pos..diags[0].pos + diags[0].len], "return 1");
+}
+
+#[test]
+fn messages_show_types_and_tokens_as_written() {
+    let cases = [
+        "proc main(): i32 {\n    return )\n}\n",
+        "proc main() {\n    var x = (1\n}\n",
+        "proc main(): i32 {\n    var x: = 1\n    return x\n}\n",
+        "proc main(): i32 {\n    return 0\n",
+        "proc f(x i32) {}\n",
+        "proc main(): i32 {\n    var a: [4]i32\n    var b: [3]i32\n    a = b\n    return 0\n}\n",
+        "proc main(): i32 {\n    var a: [2][4]n8\n    return a\n}\n",
+        "proc f(x: f64): bool {\n    return x\n}\nproc main(): i32 {\n    return 0\n}\n",
+        "proc main(): i32 {\n    var s = \"abc\"\n    return s + 1\n}\n",
+    ];
+    let messages: Vec<String> = cases.iter().flat_map(|src| diagnostics(src)).map(|d| d.message).collect();
+    let expected = [
+        "Expected an expression, but found ')' instead",
+        "This '(' is never closed with a ')'",
+        "Expected a type, but found '=' instead",
+        "This block is never closed with a }",
+        "Expected ')', but found the identifier i32 instead",
+        "Mismatched types: expected [4]i32, found [3]i32, which is a different length",
+        "Mismatched types: expected i32, found [2][4]n8",
+        "Mismatched types: expected bool, found f64",
+        "Mismatched types: expected i32, found str",
+        "Found to be str because of the result of + here",
+        "Mismatched types: expected str, found i32",
+        "Expected to be str because of the declaration of s here",
+    ];
+    assert_eq!(messages, expected);
+
+    // neither of these can be written down, but both turn up when inference goes wrong
+    let ptr = Type::Ptr(Box::new(Type::Array(4, Box::new(Type::I32))));
+    assert_eq!(ptr.to_string(), "*[4]i32");
+    assert_eq!(Type::Variable(7).to_string(), "?T7");
+}
+
+#[test]
+fn literals_have_to_fit_their_type() {
+    let cases = [
+        ("i8", "127", true),
+        ("i8", "128", false),
+        ("i8", "-128", true),
+        ("i8", "-129", false),
+        ("n8", "255", true),
+        ("n8", "256", false),
+        ("n8", "-1", false),
+        ("i64", "-9223372036854775808", true),
+        ("n128", "340282366920938463463374607431768211455", true),
+        ("n128", "340282366920938463463374607431768211456", false),
+        ("i128", "-170141183460469231731687303715884105729", false),
+    ];
+    for (typ, literal, fits) in cases {
+        let src = format!("proc main(): i32 {{\n    var x: {} = {}\n    return 0\n}}\n", typ, literal);
+        let diags = diagnostics(&src);
+        if fits {
+            assert!(diags.is_empty(), "{} as {}: {:?}", literal, typ, diags);
+        } else {
+            assert_eq!(diags.len(), 1, "{} as {}: {:?}", literal, typ, diags);
+            assert_eq!(diags[0].code, Some(Code::LiteralOutOfRange));
+            assert_eq!(&src[diags[0].pos..diags[0].pos + diags[0].len], literal);
+        }
+    }
+
+    // a float too big for an f32 still works, it's just infinity
+    let src = "proc main(): i32 {\n    var x: f32 = 1000000000000000000000000000000000000000.0\n    println(x)\n    return 0\n}\n";
+    assert_eq!(with_warnings(src).iter().map(|d| d.code).collect::<Vec<_>>(), [Some(Code::FloatLiteralOverflow)]);
+}
+
+#[test]
+fn unannotated_arguments() {
+    let src = "proc double(x): i64 {\n    return x as i64 * 2\n}\n\
+proc main(): i32 {\n    var a: i32 = 1\n    var b: n8 = 2\n    var total = double(a) + double(b)\n    return total as i32\n}\n";
+    let diags = diagnostics(src);
+    assert_eq!(diags.len(), 2, "{:?}", diags);
+    assert_eq!(diags[0].code, Some(Code::ConflictingArgumentTypes));
+    assert_eq!(&src[diags[0].pos..diags[0].pos + diags[0].len], "double(b)");
+    assert_eq!(diags[1].severity, ErrorType::Note);
+    assert_eq!(&src[diags[1].pos..diags[1].pos + diags[1].len], "double(a)");
+
+    let src = "proc double(x): i64 {\n    return x + x\n}\nproc main(): i32 {\n    return 0\n}\n";
+    assert_eq!(codes(src), vec![Code::CannotInfer]);
+    // calls that agree are fine
+    let src = "proc double(x): i64 {\n    return x + x\n}\nproc main(): i32 {\n    var a: i64 = 1\n    var b: i64 = 2\n    return (double(a) + double(b)) as i32\n}\n";
+    assert!(diagnostics(src).is_empty(), "{:?}", diagnostics(src));
+}
+
+#[test]
+fn array_argument_of_the_wrong_length() {
+    let src = "proc sum(a: [4]i32): i32 {\n    return a[0]\n}\nproc main(): i32 {\n    var a: [3]i32\n    return sum(a)\n}\n";
+    let diags = diagnostics(src);
+    assert_eq!(diags[0].code, Some(Code::MismatchedTypes), "{:?}", diags);
+    assert!(diags[0].message.contains("expected [4]i32, found [3]i32"), "{}", diags[0].message);
+}
+
+#[test]
+fn negating_what_cant_be_negated() {
+    let cases = [
+        ("proc f(x: n32): n32 {\n    return -x\n}\n", "x", "cast it to a signed type"),
+        ("proc f(x: bool): bool {\n    return -x\n}\n", "x", "type bool"),
+        ("proc f(x: bool): bool {\n    return +x\n}\n", "x", "only on numbers"),
+    ];
+    for (src, operand, message) in cases {
+        let diags = diagnostics(&format!("{}proc main(): i32 {{\n    return 0\n}}\n", src));
+        assert_eq!(diags.len(), 1, "{:?}", diags);
+        assert_eq!(diags[0].code, Some(Code::InvalidOperation));
+        assert_eq!(&src[diags[0].pos..diags[0].pos + diags[0].len], operand);
+        assert!(diags[0].message.contains(message), "{}", diags[0].message);
+    }
+}
+
+#[test]
+fn suffixed_literals_are_range_checked() {
+    let src = "proc main(): i32 {\n    var byte = 300n8\n    var small = -129i8\n    return 0\n}\n";
+    let diags = diagnostics(src);
+    let spans: Vec<&str> = diags.iter().map(|d| &src[d.pos..d.pos + d.len]).collect();
+    assert_eq!(codes(src), vec![Code::LiteralOutOfRange, Code::LiteralOutOfRange]);
+    assert_eq!(spans, ["300n8", "-129i8"]);
+}
+
+#[test]
+fn main_has_to_be_startable() {
+    assert_eq!(codes("proc start(): i32 {\n    return 0\n}\n"), vec![Code::NoMain]);
+    for src in [
+        "proc main(argc: i32): i32 {\n    return argc\n}\n",
+        "proc main(): i64 {\n    return 0\n}\n",
+        "proc main(): bool {\n    return true\n}\n",
+    ] {
+        let diags = diagnostics(src);
+        assert_eq!(diags.len(), 1, "{:?}", diags);
+        assert_eq!(diags[0].code, Some(Code::InvalidMain));
+        assert_eq!(diags[0].pos, 0, "in\n{}", src);
+    }
+    // an exit code or nothing
+    for src in ["proc main(): n8 {\n    return 0\n}\n", "proc main() {}\n"] {
+        let diags = diagnostics(src);
+        assert!(diags.is_empty(), "{:?}", diags);
+    }
+}
+
+#[test]
+fn assert_takes_a_bool() {
+    let src = "proc main(): i32 {\n    var x = 3\n    assert(x)\n    return 0\n}\n";
+    let diags = diagnostics(src);
+    assert_eq!(diags[0].code, Some(Code::MismatchedTypes), "{:?}", diags);
+    assert!(diags[0].message.contains("expected bool, found i32"), "{}", diags[0].message);
+}
+
+#[test]
+fn consts_in_a_proc_are_known_at_compile_time() {
+    let cases = [
+        ("proc main(): i32 {\n    const N = 4\n    N = 5\n    return N\n}\n", Code::AssignToConst, "N = 5"),
+        ("proc main(): i32 {\n    var x = 4\n    const N = x + 1\n    return N\n}\n", Code::InvalidConstValue, "x + 1"),
+        ("proc main(): i32 {\n    const N: i8 = 100 + 100\n    return N as i32\n}\n", Code::InvalidConstValue, "100 + 100"),
+        ("proc main(): i32 {\n    const N = 1 // 0\n    return N\n}\n", Code::InvalidConstValue, "1 // 0"),
+    ];
+    for (src, code, span) in cases {
+        let diags = diagnostics(src);
+        assert_eq!(diags.len(), 1, "{:?}", diags);
+        assert_eq!(diags[0].code, Some(code), "{:?}", diags);
+        assert_eq!(&src[diags[0].pos..diags[0].pos + diags[0].len], span);
+    }
+}
+
+#[test]
+fn array_lengths_out_of_range() {
+    let cases = [
+        ("99999999999999999999999999999999999999999999", "can be at most 4294967295"),
+        ("18446744073709551615", "can be at most 4294967295"),
+        ("4294967296", "can be at most 4294967295"),
+        ("0", "has to be at least 1"),
+        ("EMPTY", "has to be at least 1, but EMPTY is 0"),
+        ("4i64", "Expected an integer or a const"),
+    ];
+    for (length, message) in cases {
+        let src = format!("const EMPTY = 0\nproc main(): i32 {{\n    var a: [{}]i8\n    return 0\n}}\n", length);
+        let diags = diagnostics(&src);
+        assert_eq!(diags.len(), 1, "{:?}", diags);
+        assert!(diags[0].message.contains(message), "{}", diags[0].message);
+        assert_eq!(&src[diags[0].pos..diags[0].pos + diags[0].len], length);
+    }
+    // the longest there can be is fine, as long as nothing has to make room for it
+    let src = "proc f(a: *[4294967295]n8): n8 {\n    return 0\n}\nproc main(): i32 {\n    return 0\n}\n";
+    assert!(diagnostics(src).is_empty(), "{:?}", diagnostics(src));
+}
+
+#[test]
+fn tuples_of_the_wrong_shape() {
+    let src = "proc pair(): (i32, bool) {\n    return (1, 2)\n}\nproc main(): i32 {\n    return 0\n}\n";
+    let diags = diagnostics(src);
+    assert_eq!(diags[0].code, Some(Code::MismatchedTypes), "{:?}", diags);
+    assert!(diags[0].message.contains("expected bool, found i32"), "{}", diags[0].message);
+    assert_eq!(&src[diags[0].pos..diags[0].pos + diags[0].len], "return (1, 2)");
+
+    let src = "proc pair(): (i32, bool) {\n    return (1, true)\n}\nproc main(): i32 {\n    var (a, b, c) = pair()\n    return a\n}\n";
+    let diags = diagnostics(src);
+    assert_eq!(diags.len(), 1, "{:?}", diags);
+    assert_eq!(&src[diags[0].pos..diags[0].pos + diags[0].len], "var (a, b, c) = pair()");
+}
+
+#[test]
+fn bitwise_operators_need_integers() {
+    let cases = [
+        ("proc f(x: f64): f64 {\n    return x & 1.0\n}\n", "only work on integers, not on a f64"),
+        ("proc f(x: f64): f64 {\n    return x << 1\n}\n", "Can't shift a f64"),
+        ("proc f(x: i32): i32 {\n    return x >> 1.5\n}\n", "Can't shift by a f64"),
+        ("proc f(x: bool): bool {\n    return x | true\n}\n", "use || for bools"),
+    ];
+    for (src, message) in cases {
+        let diags = diagnostics(&format!("{}proc main(): i32 {{\n    return 0\n}}\n", src));
+        assert_eq!(diags.len(), 1, "{:?}", diags);
+        assert_eq!(diags[0].code, Some(Code::InvalidOperation));
+        assert!(diags[0].message.contains(message), "{}", diags[0].message);
+    }
+}
+
+#[test]
+fn indexing_one_level_too_far() {
+    let src = "proc main(): i32 {\n    var grid: [3][3]i32\n    return grid[0][1][2]\n}\n";
+    let diags = diagnostics(src);
+    assert_eq!(diags.len(), 1, "{:?}", diags);
+    assert_eq!(diags[0].message, "Type i32 can't be indexed");
+    assert_eq!(&src[diags[0].pos..diags[0].pos + diags[0].len], "grid[0][1][2]");
+
+    let src = "proc main(): i32 {\n    var x = 1\n    x[0] = 2\n    return x\n}\n";
+    assert_eq!(diagnostics(src).len(), 1, "{:?}", diagnostics(src));
+}
+
+#[test]
+fn indices_have_to_be_integers_in_range() {
+    let cases = [
+        ("return a[1.5]", "Can't index with a f64"),
+        ("return a[true]", "Can't index with a bool"),
+        ("a[1.5] = 2\n    return 0", "Can't index with a f64"),
+        ("a[false] = 2\n    return 0", "Can't index with a bool"),
+        ("return a[-1]", "out of bounds"),
+        ("a[-1] = 2\n    return 0", "out of bounds"),
+    ];
+    for (statement, message) in cases {
+        let src = format!("proc main(): i32 {{\n    var a: [4]i32\n    {}\n}}\n", statement);
+        let diags = diagnostics(&src);
+        assert_eq!(diags.len(), 1, "{:?}", diags);
+        assert!(diags[0].message.contains(message), "{}", diags[0].message);
+    }
+    let src = "proc main(): i32 {\n    var a: [4]i32\n    var i: n64 = 3\n    a[i] = 1\n    var j: i8 = 3\n    return a[j]\n}\n";
+    assert!(diagnostics(src).is_empty(), "{:?}", diagnostics(src));
+}
+
+#[test]
+fn unused_results() {
+    // the thrown away i64 mustn't throw off the types of what comes after it
+    let src = "proc big(x: i32): i64 {\n    return x as i64 * 1000\n}\n\
+proc main(): i32 {\n    big(1)\n    var small: i32 = 2\n    _ = big(3)\n    return small + big(4) as i32\n}\n";
+    let diags = with_warnings(src);
+    assert_eq!(diags.len(), 1, "{:?}", diags);
+    assert_eq!(diags[0].code, Some(Code::UnusedResult));
+    assert!(diags[0].message.contains("big"), "{}", diags[0].message);
+    assert_eq!(&src[diags[0].pos..diags[0].pos + diags[0].len], "big(1)");
+    // nothing's left over from a proc that doesn't return anything
+    let src = "proc nothing() {}\nproc main(): i32 {\n    nothing()\n    return 0\n}\n";
+    assert!(with_warnings(src).is_empty(), "{:?}", with_warnings(src));
+}
+
+#[test]
+fn calls_without_parentheses() {
+    for (src, arg, span) in [
+        ("proc main(): i32 {\n    var x = 1\n    print x\n    return 0\n}\n", "x", "print x"),
+        ("proc main(): i32 {\n    println \"hello\"\n    return 0\n}\n", "\"hello\"", "println \"hello\""),
+    ] {
+        let diags = diagnostics(src);
+        assert_eq!(diags.len(), 1, "{:?}", diags);
+        assert_eq!(diags[0].code, Some(Code::UnexpectedToken));
+        let name = span.split(' ').next().unwrap();
+        assert_eq!(diags[0].message, format!("Calls require parentheses: did you mean {}({})?", name, arg));
+        assert_eq!(&src[diags[0].pos..diags[0].pos + diags[0].len], span);
+    }
+}
+
+#[test]
+fn mixing_number_types() {
+    let ok = ["a + b", "a + 1", "1 + a", "(x + 1.5) as i32", "(1 + 2) as i32 + a"];
+    let mixed = [
+        ("a + big", "the left operand of + is i32 and the right one is i64, cast the right one with as i32 or the left one with as i64"),
+        ("(x + y) as i32", "the left operand of + is f32 and the right one is f64, cast the right one with as f32 or the left one with as f64"),
+    ];
+    let with = |expr: &str| {
+        format!("proc main(): i32 {{\n    var a: i32 = 1\n    var b: i32 = 2\n    var big: i64 = 3\n    var x: f32 = 0.5\n    var y: f64 = 0.5\n    return {}\n}}\n", expr)
+    };
+    for expr in ok {
+        let diags = diagnostics(&with(expr));
+        assert!(diags.is_empty(), "{}: {:?}", expr, diags);
+    }
+    for (expr, message) in mixed {
+        let src = with(expr);
+        let diags = diagnostics(&src);
+        assert_eq!(diags[0].code, Some(Code::MismatchedTypes), "{}: {:?}", expr, diags);
+        assert_eq!(diags[0].message, format!("Mismatched types: {}", message));
+    }
+    // on their own, literals are an i32
+    for (src, allocated) in [
+        ("proc main(): i32 {\n    var plain = 1 + 2\n    println(plain)\n    return 0\n}\n", "allocate plain: i32"),
+        ("proc main(): i32 {\n    var plain = 1 + 2\n    var wide: i64 = plain\n    println(wide)\n    return 0\n}\n", "allocate plain: i64"),
+    ] {
+        let procs = elgin::compile_to_ir(src).unwrap_or_else(|diags| panic!("{:?}", diags));
+        let main = procs.iter().find(|proc| proc.name == "main").unwrap().to_text();
+        assert!(main.contains(allocated), "{}", main);
+    }
+}
+
+#[test]
+fn never_closed() {
+    // the } meant for a closes the if instead, which isn't found out until proc b starts
+    let src = "proc a(): i32 {\n    if true {\n        return 1\n    return 2\n}\nproc b(): i32 {\n    return )\n}\n";
+    let diags = diagnostics(src);
+    assert_eq!(diags.len(), 3, "{:?}", diags);
+    assert_eq!((diags[0].code, diags[0].pos), (Some(Code::UnclosedBlock), src.find('{').unwrap()));
+    assert_eq!((diags[1].severity, diags[1].pos), (ErrorType::Note, src.find("proc b").unwrap()));
+    // b is still parsed
+    assert_eq!((diags[2].code, diags[2].pos), (Some(Code::UnexpectedToken), src.rfind(')').unwrap()));
+
+    // running into the end of the file is what shows a bracket was never closed
+    for (src, open) in [
+        ("proc b(): i32 {\n    return 0\n}\nproc a(): i32 {\n    return (1 + 2\n", "(1"),
+        ("proc b(): i32 {\n    return 0\n}\nproc a(xs: [4]i32): i32 {\n    return xs[1 + 2\n", "[1"),
+        ("proc b(): i32 {\n    return 0\n}\nproc a(x: [4", "[4"),
+    ] {
+        let diags = diagnostics(src);
+        let unclosed = diags.iter().find(|d| d.code == Some(Code::UnclosedDelimiter)).unwrap_or_else(|| panic!("{:?}", diags));
+        assert_eq!(unclosed.pos, src.find(open).unwrap(), "{:?}", diags);
+    }
+}
+
+#[test]
+fn calling_through_a_proc_with_the_wrong_arity() {
+    let src = "proc larger(a: i32, b: i32): bool {\n    return a > b\n}\nproc main(): i32 {\n    var pick = larger\n    assert(pick(1))\n    return 0\n}\n";
+    let diags = diagnostics(src);
+    assert_eq!(diags[0].code, Some(Code::MismatchedTypes), "{:?}", diags);
+    assert!(diags[0].message.contains("takes 2 arguments, but it's given 1"), "{}", diags[0].message);
+    assert!(src[diags[0].pos..].starts_with("pick(1)"), "{:?}", diags[0]);
+
+    // passing a proc where one with a different arity is expected
+    let src = "proc larger(a: i32, b: i32): bool {\n    return a > b\n}\nproc check(f: proc(i32): bool): bool {\n    return f(1)\n}\n\
+proc main(): i32 {\n    assert(check(larger))\n    return 0\n}\n";
+    let diags = diagnostics(src);
+    assert_eq!(diags[0].code, Some(Code::MismatchedTypes), "{:?}", diags);
+    assert!(diags[0].message.contains("different number of arguments"), "{}", diags[0].message);
+}
@@ -0,0 +1,39 @@
+//! Each tests/golden/NAME.eln is compiled and the typed IR of its procs compared against NAME.ir,
+//! so that what the constraint solver decides shows up as a diff.
+//! Setting ELGIN_BLESS writes the listings out instead, for when a change to them is intended.
+
+use std::env;
+use std::fs;
+use std::path::Path;
+
+fn listing(src: &str) -> String {
+    let procs = elgin::compile_to_ir(src).unwrap_or_else(|diags| panic!("expected no errors, got {:?}", diags));
+    let mut listing = String::new();
+    for proc in &procs {
+        listing.push_str(&proc.to_text());
+        listing.push('\n');
+    }
+    listing
+}
+
+#[test]
+fn golden_ir() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden");
+    let bless = env::var_os("ELGIN_BLESS").is_some();
+    let mut checked = 0;
+    let mut failures = vec![];
+    let mut entries: Vec<_> = fs::read_dir(&dir).unwrap().map(|e| e.unwrap().path()).collect();
+    entries.sort();
+    for path in entries.iter().filter(|p| p.extension().is_some_and(|e| e == "eln")) {
+        let actual = listing(&fs::read_to_string(path).unwrap());
+        let expected_path = path.with_extension("ir");
+        if bless {
+            fs::write(&expected_path, &actual).unwrap();
+        } else if fs::read_to_string(&expected_path).ok().as_deref() != Some(actual.as_str()) {
+            failures.push(format!("{} doesn't match, it's now:\n{}", expected_path.display(), actual));
+        }
+        checked += 1;
+    }
+    assert!(checked >= 5, "only found {} golden tests in {}", checked, dir.display());
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
@@ -0,0 +1,241 @@
+//! Running programs with the interpreter
+
+use elgin::errors::{Code, Logger};
+use elgin::interp::{self, Value};
+use elgin::ir::{IRBuilder, InstructionType};
+use elgin::lexer::Lexer;
+use elgin::opt;
+use elgin::parser::{BufferedSource, Parser};
+use elgin::types::Type;
+
+/// What main returns, which has to be a signed integer
+fn int(src: &str) -> i128 {
+    let procs = elgin::compile_to_ir(src).unwrap_or_else(|diags| panic!("expected no errors, got {:?}", diags));
+    match interp::run(&procs, &[], "main") {
+        Ok(Value::Int(v)) => v,
+        other => panic!("expected an integer, got {:?}", other),
+    }
+}
+
+#[test]
+fn constant_index_out_of_bounds_is_rejected() {
+    let src = "proc main(): i32 {\n    var a: [4]i32\n    return a[10]\n}\n";
+    let diags = elgin::compile_to_ir(src).unwrap_err();
+    assert_eq!(diags.len(), 1, "{:?}", diags);
+    assert_eq!(diags[0].code, Some(Code::NoSuchMember));
+    assert_eq!(diags[0].message, "Index 10 is out of bounds for a [4]i32");
+    assert_eq!(&src[diags[0].pos..diags[0].pos + diags[0].len], "a[10]");
+}
+
+#[test]
+fn variable_index_out_of_bounds_traps() {
+    let src = "proc get(a: [4]i32, i: i32): i32 {\n    return a[i]\n}\n\
+proc main(): i32 {\n    var a: [4]i32\n    a[3] = 5\n    return get(a, 3) + get(a, 4)\n}\n";
+    let procs = elgin::compile_to_ir(src).unwrap();
+    let error = match interp::run(&procs, &[], "main") {
+        Err(error) => error,
+        Ok(value) => panic!("expected a trap, got {:?}", value),
+    };
+    assert_eq!(error.message, "Index 4 is out of bounds, the length is 4");
+    assert_eq!(&src[error.pos..error.pos + error.len], "a[i]");
+    assert_eq!(error.diagnostic().code, Some(Code::RuntimeFailure));
+
+    // and the same program is fine when the index is in bounds
+    let src = src.replace("get(a, 4)", "get(a, 0)");
+    let procs = elgin::compile_to_ir(&src).unwrap();
+    assert!(matches!(interp::run(&procs, &[], "main"), Ok(Value::Int(5))));
+}
+
+/// Runs main of a program with globals, which `compile_to_ir` leaves out, checking that it does the same
+/// once optimized
+fn run_with_globals(src: &str) -> Value {
+    let logger = Logger::new();
+    let mut parser = Parser::from_source(BufferedSource::new(Lexer::new(src, &logger)), &logger);
+    let (nodes, _) = parser.go();
+    let mut irbuilder = IRBuilder::new(&nodes, parser.available_type_var, &logger);
+    irbuilder.go();
+    irbuilder.analyze();
+    assert_eq!(logger.error_count(), 0, "{:?}", logger.drain());
+    let value = interp::run(&irbuilder.procs, &irbuilder.globals, "main").unwrap();
+    opt::optimize(&mut irbuilder.procs);
+    let optimized = interp::run(&irbuilder.procs, &irbuilder.globals, "main").unwrap();
+    assert_eq!(format!("{:?}", optimized), format!("{:?}", value));
+    value
+}
+
+#[test]
+fn while_condition_runs_once_more_than_the_body() {
+    for iterations in [0, 1, 4, 100] {
+        let src = format!(
+            "var checks: i32 = 0\nvar left: i32 = {}\n\
+proc has_next(): bool {{\n    checks += 1\n    left -= 1\n    return left >= 0\n}}\n\
+proc main(): i32 {{\n    var body: i32 = 0\n    while has_next() {{\n        body += 1\n    }}\n    return checks * 1000 + body\n}}\n",
+            iterations,
+        );
+        let expected = (iterations + 1) * 1000 + iterations;
+        assert!(matches!(run_with_globals(&src), Value::Int(v) if v == expected), "for {} iterations", iterations);
+    }
+}
+
+#[test]
+fn break_and_continue_target_the_innermost_loop() {
+    let src = "proc main(): i32 {\n    var x = 0\n    loop {\n        if x > 10 {\n            break\n        }\n        x = x + 1\n    }\n    \
+var pairs = 0\n    var i = 0\n    while i < 5 {\n        i += 1\n        if i == 2 {\n            continue\n        }\n        var j = 0\n        \
+loop {\n            j += 1\n            if j > i {\n                break\n            }\n            if j == 1 {\n                continue\n            }\n            \
+pairs += 1\n        }\n    }\n    return x * 100 + pairs\n}\n";
+    // i of 1, 3, 4 and 5 count every j from 2 up to i
+    assert_eq!(int(src), 1100 + 2 + 3 + 4);
+}
+
+#[test]
+fn for_loops_count_up_to_the_end() {
+    let src = "proc main(): i32 {\n    var i = 1000\n    var sum = 0\n    for i = 0 .. 10 {\n        sum += i\n    }\n    \
+for j = 5 .. 5 {\n        sum += 100\n    }\n    return sum * 10000 + i\n}\n";
+    // the end is left out, an empty range runs nothing, and the outer i is untouched
+    assert_eq!(int(src), 45 * 10000 + 1000);
+}
+
+#[test]
+fn bool_literals_are_bools() {
+    let src = "proc main(): i32 {\n    var flag = true\n    var n = 0\n    while true {\n        n += 1\n        if n == 3 {\n            break\n        }\n    }\n    \
+while false {\n        n += 100\n    }\n    if flag && !false {\n        return n\n    }\n    return 0\n}\n";
+    assert_eq!(int(src), 3);
+    let procs = elgin::compile_to_ir(src).unwrap();
+    let flag = procs.iter()
+        .flat_map(|proc| &proc.body)
+        .find(|ins| matches!(&ins.contents.ins, InstructionType::Allocate(name) if name == "flag"))
+        .unwrap();
+    assert_eq!(flag.contents.typ, Type::Bool);
+}
+
+#[test]
+fn assigning_to_array_elements() {
+    let src = "proc main(): i32 {\n    var a: [4]i32\n    var m: [2][3]i32\n    for i = 0 .. 4 {\n        a[i] = i * i\n    }\n    \
+a[0] = 7\n    m[1][2] = a[3]\n    m[0][1] += 2\n    return a[0] * 1000 + a[1] * 100 + m[1][2] * 10 + m[0][1]\n}\n";
+    assert_eq!(int(src), 7000 + 100 + 90 + 2);
+}
+
+#[test]
+fn struct_fields_round_trip() {
+    let src = "struct Point {\n    x: i32\n    y: i32\n}\nstruct Line { start: Point, end: Point }\n\
+proc main(): i32 {\n    var p = Point(1, 2)\n    p.x = 4\n    p.y += 3\n    var l = Line(Point(6, 7), p)\n    \
+return p.x * 1000 + p.y * 100 + l.start.y * 10 + l.end.x\n}\n";
+    assert_eq!(int(src), 4000 + 500 + 70 + 4);
+}
+
+#[test]
+fn if_expressions_in_call_arguments() {
+    let src = "proc add(a: i32, b: i32): i32 {\n    return a + b\n}\n\
+proc main(): i32 {\n    var x = 3\n    var y = add(if x > 2 { 10 } else { 20 }, if x > 5 { 1 } elif x > 1 { 2 } else { 3 })\n    \
+return add(y, if y == 12 { 100 } else { 0 })\n}\n";
+    assert_eq!(int(src), 112);
+}
+
+#[test]
+fn semicolons_separate_statements() {
+    let src = "proc main(): i32 {\n    var x = 1; var y = 2;\n    x += y; y *= 10\n    return x + y;\n}\n";
+    assert_eq!(int(src), 23);
+}
+
+#[test]
+fn blocks_have_scopes_of_their_own() {
+    let src = "proc main(): i32 {\n    var x = 1\n    var seen = 0\n    if x > 0 {\n        var x = 50\n        seen += x\n    } else {\n        var x = 60\n        seen += x\n    }\n    \
+while seen < 52 {\n        var x = 1\n        seen += x\n    }\n    return seen * 10 + x\n}\n";
+    assert_eq!(int(src), 521);
+}
+
+#[test]
+fn top_level_consts_bound_arrays() {
+    let src = "const SIZE: i32 = 8\nconst LAST = SIZE - 1\n\
+proc fill(): [SIZE]i32 {\n    var a: [SIZE]i32\n    for i = 0 .. SIZE {\n        a[i] = i * 2\n    }\n    return a\n}\n\
+proc main(): i32 {\n    var a = fill()\n    var i = 0\n    var total = 0\n    while i < SIZE {\n        total += a[i]\n        i += 1\n    }\n    return total * 100 + a[LAST]\n}\n";
+    assert!(matches!(run_with_globals(src), Value::Int(5614)));
+}
+
+#[test]
+fn globals_are_shared_between_procs() {
+    let src = "var counter: i32 = 5\nvar calls = 0\n\
+proc bump(by: i32) {\n    counter += by\n    calls += 1\n}\nproc read(): i32 {\n    return counter\n}\n\
+proc main(): i32 {\n    bump(1)\n    bump(10)\n    var before = read()\n    counter = 100\n    return before * 1000 + read() + calls\n}\n";
+    assert!(matches!(run_with_globals(src), Value::Int(16102)));
+}
+
+#[test]
+fn indexing_a_str_gives_an_n8() {
+    let src = "proc main(): i32 {\n    var s = \"hi\"\n    var first = s[0]\n    var n = len(s)\n    return first as i32 + n as i32\n}\n";
+    assert_eq!(int(src), 104 + 2);
+    let procs = elgin::compile_to_ir(src).unwrap();
+    let allocated = |name: &str| procs.iter()
+        .flat_map(|proc| &proc.body)
+        .find(|ins| matches!(&ins.contents.ins, InstructionType::Allocate(n) if n == name))
+        .map(|ins| ins.contents.typ.clone())
+        .unwrap();
+    assert_eq!(allocated("s"), Type::Str);
+    assert_eq!(allocated("first"), Type::N8);
+    assert!(allocated("n").is_integer());
+}
+
+#[test]
+fn procs_can_call_ones_declared_after_them() {
+    let src = "proc main(): i32 {\n    if is_even(10) && is_odd(7) && !is_even(3) {\n        return 1\n    }\n    return 0\n}\n\
+proc is_even(n: i32): bool {\n    if n == 0 {\n        return true\n    }\n    return is_odd(n - 1)\n}\n\
+proc is_odd(n: i32): bool {\n    if n == 0 {\n        return false\n    }\n    return is_even(n - 1)\n}\n";
+    assert_eq!(int(src), 1);
+}
+
+#[test]
+fn bare_return_leaves_early() {
+    let src = "var calls: i32 = 0\n\
+proc bump(x: i32) {\n    calls += 1\n    if x > 5 {\n        return\n    }\n    calls += 10\n}\n\
+proc main(): i32 {\n    bump(10)\n    bump(1)\n    return calls\n}\n";
+    assert!(matches!(run_with_globals(src), Value::Int(12)));
+}
+
+#[test]
+fn recursive_procs_use_their_own_signature() {
+    let src = "proc fact(n: i32): i64 {\n    if n <= 1 {\n        return 1\n    }\n    return n as i64 * fact(n - 1)\n}\n\
+proc fib(n: i32): i32 {\n    if n < 2 {\n        return n\n    }\n    return fib(n - 1) + fib(n - 2)\n}\n\
+proc main(): i32 {\n    return (fact(10) // 100000) as i32 + fib(15)\n}\n";
+    // 3628800 / 100000 is 36, the 15th fibonacci number is 610
+    assert_eq!(int(src), 646);
+}
+
+#[test]
+fn most_negative_literals() {
+    let src = "proc main(): i32 {\n    var x: i64 = -9223372036854775808\n    var y: i8 = -128\n    if x < 0 && x -~ 1 > 0 {\n        return y as i32\n    }\n    return 0\n}\n";
+    // one less than the smallest i64 wraps around to the biggest with -~
+    assert_eq!(int(src), -128);
+    let diags = elgin::compile_to_ir("proc main(): i32 {\n    var x: i64 = -9223372036854775809\n    return 0\n}\n").unwrap_err();
+    assert_eq!(diags[0].code, Some(Code::LiteralOutOfRange));
+}
+
+#[test]
+fn consts_in_a_proc() {
+    let src = "const SIZE = 8\n\
+proc main(): i32 {\n    const N = 4\n    const LAST: i32 = SIZE - N // 4 - 1\n    var a: [SIZE]i32\n    a[LAST] = N\n    var seen = 0\n    \
+if true {\n        var N = 100\n        seen += N\n    }\n    return seen + a[6] * 10 + N\n}\n";
+    // the var hides the const only inside the if
+    assert_eq!(int(src), 144);
+}
+
+#[test]
+fn discarded_results_still_run() {
+    let src = "var total: i32 = 0\nproc add(x: i32): i32 {\n    total += x\n    return total\n}\n\
+proc main(): i32 {\n    add(1)\n    _ = add(2)\n    var doubled = add(3) * 2\n    return total * 100 + doubled\n}\n";
+    assert!(matches!(run_with_globals(src), Value::Int(612)));
+}
+
+#[test]
+fn chars_compare_with_str_bytes() {
+    let src = "proc main(): i32 {\n    var s = \"hi\\tthere\"\n    var n = 0\n    if s[0] == 'h' {\n        n += 1\n    }\n    if s[2] == '\\t' {\n        n += 10\n    }\n    \
+if s[1] != 'x' {\n        n += 100\n    }\n    return n + ('b' - 'a') as i32 * 1000\n}\n";
+    assert_eq!(int(src), 1111);
+}
+
+#[test]
+fn comparators_passed_to_procs() {
+    let src = "proc larger(a: i32, b: i32): bool {\n    return a > b\n}\nproc smaller(a: i32, b: i32): bool {\n    return a < b\n}\n\
+proc max_by(a: i32, b: i32, better: proc(i32, i32): bool): i32 {\n    if better(a, b) {\n        return a\n    }\n    return b\n}\n\
+proc main(): i32 {\n    var pick = smaller\n    return max_by(3, 17, larger) * 100 + max_by(3, 17, pick)\n}\n";
+    assert_eq!(int(src), 1703);
+}
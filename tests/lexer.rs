@@ -0,0 +1,130 @@
+//! Tokens and spans straight out of the lexer
+
+use elgin::errors::Code;
+use elgin::lexer::Token;
+
+#[test]
+fn bad_escape_keeps_lexing() {
+    let src = "var x = \"a\\qb\"\nvar y = $\n";
+    let diags = elgin::lex(src).unwrap_err();
+    assert_eq!(diags.len(), 2, "{:?}", diags);
+    assert_eq!(diags[0].code, Some(Code::InvalidEscape));
+    assert_eq!(&src[diags[0].pos..diags[0].pos + diags[0].len], "\\q");
+    assert_eq!(&src[diags[1].pos..diags[1].pos + diags[1].len], "$");
+}
+
+#[test]
+fn short_hex_escape_leaves_the_quote() {
+    let src = "var x = \"\\x4\"\nvar y = $\n";
+    let diags = elgin::lex(src).unwrap_err();
+    assert_eq!(diags.len(), 2, "{:?}", diags);
+    assert_eq!(diags[0].code, Some(Code::InvalidEscape));
+    assert_eq!(&src[diags[1].pos..diags[1].pos + diags[1].len], "$");
+}
+
+#[test]
+fn spans_cover_each_token() {
+    let src = "#: doc\nproc f(x: i32): str {\n    var s = \"a\\n\" # note\n    return s + 'c' // 2.5\n}\n";
+    let tokens = elgin::lex(src).unwrap();
+    let spans: Vec<(usize, usize)> = tokens.iter().map(|token| (token.pos, token.len)).collect();
+    let expected = [
+        (0, 6),   // #: doc
+        (6, 1),   // newline
+        (7, 4),   // proc
+        (12, 1),  // f
+        (13, 1),  // (
+        (14, 1),  // x
+        (15, 1),  // :
+        (17, 3),  // i32
+        (20, 1),  // )
+        (21, 1),  // :
+        (23, 3),  // str
+        (27, 1),  // {
+        (28, 1),  // newline
+        (33, 3),  // var
+        (37, 1),  // s
+        (39, 1),  // =
+        (41, 5),  // "a\n"
+        (53, 1),  // newline after the comment
+        (58, 6),  // return
+        (65, 1),  // s
+        (67, 1),  // +
+        (69, 3),  // 'c'
+        (73, 2),  // //
+        (76, 3),  // 2.5
+        (79, 1),  // newline
+        (80, 1),  // }
+        (81, 1),  // newline
+    ];
+    assert_eq!(spans, expected);
+    let texts: Vec<&str> = tokens.iter().map(|token| &src[token.pos..token.pos + token.len]).collect();
+    assert_eq!(texts[0], "#: doc");
+    assert_eq!(texts[16], "\"a\\n\"");
+    assert_eq!(texts[21], "'c'");
+}
+
+#[test]
+fn blank_lines_inside_a_block() {
+    let src = "proc f(): i32 {\n    var x = 1\n\n\n\n    return x\n\n\n}\n";
+    let newlines = elgin::lex(src).unwrap().iter().filter(|token| &src[token.pos..token.pos + token.len] == "\n").count();
+    // a run of blank lines is a single newline token
+    assert_eq!(newlines, 4);
+    assert!(elgin::parse(src).is_ok(), "{:?}", elgin::parse(src));
+}
+
+#[test]
+fn unknown_operator_is_a_syntax_error() {
+    let src = "var x = a @ b\nvar y = a+-b\n";
+    let diags = elgin::lex(src).unwrap_err();
+    assert_eq!(diags.len(), 1, "{:?}", diags);
+    assert_eq!(diags[0].code, Some(Code::UnknownOperator));
+    assert_eq!(&src[diags[0].pos..diags[0].pos + diags[0].len], "@");
+}
+
+#[test]
+fn newline_in_a_string_is_an_error() {
+    let src = "var s = \"abc\nvar t = 1\n";
+    let diags = elgin::lex(src).unwrap_err();
+    assert_eq!(diags.len(), 1, "{:?}", diags);
+    assert_eq!(diags[0].code, Some(Code::UnterminatedString));
+    assert_eq!(diags[0].pos, 8);
+    assert_eq!(diags[0].len, 1);
+}
+
+#[test]
+fn adjacent_strings_are_joined() {
+    let src = "var s = \"hello, \"\n    \"world\" \"!\"\nputs(\"in \"\n    \"parens\")\n";
+    let tokens = elgin::lex(src).unwrap();
+    let strings: Vec<(&str, &str)> = tokens.iter()
+        .filter_map(|token| match &token.contents {
+            Token::StrLiteral(s) => Some((s.as_str(), &src[token.pos..token.pos + token.len])),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(strings, [("hello, world!", "\"hello, \"\n    \"world\" \"!\""), ("in parens", "\"in \"\n    \"parens\"")]);
+    // the newlines in between are part of the literal, so the statement doesn't end early
+    let newlines = tokens.iter().filter(|token| token.contents == Token::Newline).count();
+    assert_eq!(newlines, 2);
+}
+
+#[test]
+fn char_literals_and_their_escapes() {
+    let src = r"'a' '\n' '\t' '\\' '\'' '\x41' '0'";
+    let chars: Vec<char> = elgin::lex(src).unwrap().iter()
+        .filter_map(|token| match token.contents {
+            Token::CharLiteral(c) => Some(c),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(chars, ['a', '\n', '\t', '\\', '\'', 'A', '0']);
+}
+
+#[test]
+fn malformed_char_literals() {
+    for (src, span) in [("var c = 'ab'\n", "'ab'"), ("var c = ''\n", "''"), ("var c = 'a\n", "'")] {
+        let diags = elgin::lex(src).unwrap_err();
+        assert_eq!(diags.len(), 1, "{:?}", diags);
+        assert_eq!(diags[0].code, Some(Code::InvalidCharLiteral), "{:?}", diags);
+        assert_eq!(&src[diags[0].pos..diags[0].pos + diags[0].len], span);
+    }
+}
@@ -0,0 +1,91 @@
+//! Lexing a large file lazily shouldn't take memory in proportion to it.
+//! The lexer used to collect the whole file into a Vec<char> first, four bytes for every byte of source,
+//! and then every token into a Vec before the parser saw any of them.
+//! This is its own test binary, since the allocator counts what every thread in it allocates,
+//! and the tests in it take turns for the same reason.
+
+use elgin::errors::Logger;
+use elgin::lexer::Lexer;
+use elgin::modules::Loader;
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::env;
+use std::fs;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+struct Counting;
+
+static IN_USE: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let in_use = IN_USE.fetch_add(layout.size(), Ordering::SeqCst) + layout.size();
+        PEAK.fetch_max(in_use, Ordering::SeqCst);
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        IN_USE.fetch_sub(layout.size(), Ordering::SeqCst);
+        unsafe { System.dealloc(ptr, layout) }
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
+
+static ONE_AT_A_TIME: Mutex<()> = Mutex::new(());
+
+fn megabyte() -> String {
+    let chunk = include_str!("../examples/quicksort.eln");
+    chunk.repeat((1 << 20) / chunk.len() + 1)
+}
+
+#[test]
+fn peak_memory_of_a_megabyte() {
+    let _turn = ONE_AT_A_TIME.lock().unwrap();
+    let src = megabyte();
+    let logger = Logger::new();
+
+    let before = IN_USE.load(Ordering::SeqCst);
+    PEAK.store(before, Ordering::SeqCst);
+    let tokens = Lexer::new(&src, &logger).count();
+    let peak = PEAK.load(Ordering::SeqCst) - before;
+
+    assert!(tokens > 100_000, "only {} tokens", tokens);
+    // a Vec<char> of it alone would be 4 MB
+    assert!(peak < src.len() / 16, "lexing {} bytes took {} bytes at its peak", src.len(), peak);
+}
+
+#[test]
+fn loading_a_megabyte_keeps_no_tokens() {
+    let _turn = ONE_AT_A_TIME.lock().unwrap();
+    let dir = env::temp_dir().join(format!("elgin-memory-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let src = megabyte();
+    fs::write(dir.join("big.eln"), &src).unwrap();
+    fs::write(dir.join("main.eln"), "use big\n").unwrap();
+    let logger = Logger::new();
+
+    let before = IN_USE.load(Ordering::SeqCst);
+    PEAK.store(before, Ordering::SeqCst);
+    let mut loader = Loader::new(&logger);
+    loader.load(&dir.join("main.eln")).unwrap();
+    let peak = PEAK.load(Ordering::SeqCst) - before;
+    let kept = IN_USE.load(Ordering::SeqCst) - before;
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(logger.error_count(), 0);
+    assert!(loader.nodes.len() > 1000, "only {} nodes", loader.nodes.len());
+    // besides what's kept, which is mostly the nodes, little more than the source itself is needed
+    // along the way, while a Vec of every token would be over ten times its size
+    let along_the_way = peak - kept;
+    assert!(
+        along_the_way < src.len() * 2,
+        "loading {} bytes took {} bytes on top of the {} kept",
+        src.len(),
+        along_the_way,
+        kept,
+    );
+}
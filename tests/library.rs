@@ -0,0 +1,63 @@
+//! The library entry points, as a tool would call them
+
+use elgin::errors::{Code, Diagnostic, ErrorFormat, ErrorType, Logger};
+
+use std::sync::{Arc, Barrier};
+use std::thread;
+
+#[test]
+fn concurrent_compiles_keep_their_own_diagnostics() {
+    let modules = [
+        ("alpha", "proc main(): i32 {\n    return alpha\n}\n"),
+        ("beta", "proc helper(x: i32): i32 {\n    return x\n}\nproc main(): i32 {\n    return helper(beta) + gamma\n}\n"),
+    ];
+    let barrier = Arc::new(Barrier::new(modules.len()));
+    let threads: Vec<_> = modules.iter().map(|&(name, src)| {
+        let barrier = barrier.clone();
+        thread::spawn(move || {
+            barrier.wait();
+            for _ in 0..50 {
+                let diags = elgin::compile_to_ir(src).unwrap_err();
+                let messages: Vec<&str> = diags.iter().map(|d| d.message.as_str()).collect();
+                match name {
+                    "alpha" => assert_eq!(messages, ["Can't find a variable named alpha in the current scope"]),
+                    _ => assert_eq!(messages, [
+                        "Can't find a variable named beta in the current scope",
+                        "Can't find a variable named gamma in the current scope",
+                    ]),
+                }
+            }
+        })
+    }).collect();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+}
+
+#[test]
+fn loggers_point_into_their_own_sources() {
+    let one = Logger::new();
+    let two = Logger::new();
+    two.set_format(ErrorFormat::Json);
+    // both files start at position 0, since neither logger knows about the other's
+    assert_eq!(one.add_source("one.eln", "proc main(): i32 {\n    return x\n}\n"), 0);
+    assert_eq!(two.add_source("two.eln", "\n\nproc main(): i32 {\n    return y\n}\n"), 0);
+    let diagnostic = |pos| Diagnostic {
+        severity: ErrorType::NameError,
+        code: Some(Code::UnknownVariable),
+        message: "Can't find it".to_owned(),
+        pos,
+        len: 1,
+    };
+    assert_eq!(
+        one.render(vec![diagnostic(30)]),
+        "one.eln:2:12: name error[E0201]: Can't find it\n        return x\n               ^\n",
+    );
+    assert_eq!(
+        two.render(vec![diagnostic(32)]),
+        "{\"code\":\"E0201\",\"severity\":\"name error\",\"message\":\"Can't find it\",\
+\"pos\":32,\"len\":1,\"file\":\"two.eln\",\"line\":4,\"column\":12}\n",
+    );
+    // without any source, there's only the position to go by
+    assert_eq!(Logger::new().render(vec![diagnostic(30)]), "name error[E0201]: Can't find it (at 30, length 1)\n");
+}
@@ -0,0 +1,172 @@
+//! Whatever the input, the compiler reports what's wrong with it rather than panicking
+
+use elgin::errors::Code;
+
+const CORPUS: &[&str] = &[
+    "",
+    "}",
+    "proc",
+    "proc main(",
+    "proc main(): i32 {",
+    "proc main(): i32 { return }",
+    "proc main(): i32 { return : }",
+    "proc main(): i32 { var x = } }",
+    "proc main(): i32 { var = 1 }",
+    "proc main(): i32 { x( }",
+    "proc main(): i32 { [1, 2 }",
+    "proc main(): i32 { return (1, }",
+    "proc main(): i32 { if { } }",
+    "proc main(): i32 { while }",
+    "proc main(): i32 { for i in { } }",
+    "proc main(): i32 { match 1 { } }",
+    "proc main(): i32 { match { 1 => } }",
+    "proc main(): i32 { return é }",
+    "proc main(): i32 { return \"\\",
+    "proc main(): i32 { return 'ab' }",
+    "proc main(): i32 { return '' }",
+    "proc main(): i32 { return 1 as }",
+    "proc main(): i32 { return 1 +* 2 }",
+    "proc main(): i32 { return 99999999999999999999999999999999999999999 }",
+    "proc main(): i32 { var a: [0]i32; return a[0] }",
+    "proc main(): i32 { var a: [4]i32; return a[-1] }",
+    "proc f(a: proc(i32): i32): i32 { return a(a) }",
+    "proc main(): i32 { var p = main; return p(1) }",
+    "struct { }",
+    "struct S { x: S }",
+    "struct S { x: i32 } proc main(): i32 { return S(1).y }",
+    "proc main(): i32 { var (a, b) = 1; return a }",
+    "use",
+    "use \"nowhere\"",
+    "const",
+    "const X = X",
+    "#: doc with nothing after it",
+    "proc main(): i32 {\n    return 1\n",
+    "proc main(): i32 { return 1 } }",
+    "((((((((((((((((((((",
+    "proc main(): i32 { return ((((((((((1)))))))))) }",
+    "\0\u{1}\u{7f}",
+];
+
+/// The same sequence of numbers every run, so a failure can be reproduced
+struct Xorshift(u64);
+
+impl Xorshift {
+    fn next(&mut self) -> usize {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0 as usize
+    }
+}
+
+fn check(src: &str) {
+    if let Err(diags) = elgin::compile_to_ir(src) {
+        for diag in diags {
+            assert_ne!(diag.code, Some(Code::InternalError), "{:?} on {:?}", diag, src);
+            assert!(diag.pos + diag.len <= src.len(), "{:?} is outside of {:?}", diag, src);
+        }
+    }
+}
+
+#[test]
+fn corpus() {
+    for src in CORPUS {
+        check(src);
+    }
+}
+
+#[test]
+fn random_tokens() {
+    const PIECES: &[&str] = &[
+        "proc", "main", "(", ")", "{", "}", "[", "]", ":", ",", "=", "==", "+", "-", "*", "//", "**", ".", "..",
+        "i32", "n8", "f64", "bool", "str", "var", "const", "return", "if", "elif", "else", "while", "for", "in",
+        "match", "=>", "_", "struct", "as", "true", "x", "y", "1", "2.5", "0x", "\"s\"", "'c'", "\n", " ", ";",
+    ];
+    let mut rng = Xorshift(0x2545_f491_4f6c_dd1d);
+    for _ in 0..500 {
+        let len = rng.next() % 40;
+        let src: String = (0..len).map(|_| PIECES[rng.next() % PIECES.len()]).collect::<Vec<_>>().join(" ");
+        check(&src);
+    }
+}
+
+#[test]
+fn random_bytes() {
+    let mut rng = Xorshift(0x9e37_79b9_7f4a_7c15);
+    for _ in 0..500 {
+        let len = rng.next() % 64;
+        let bytes: Vec<u8> = (0..len).map(|_| rng.next() as u8).collect();
+        check(&String::from_utf8_lossy(&bytes));
+    }
+}
+
+#[test]
+fn mutated_examples() {
+    let dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("examples");
+    let mut rng = Xorshift(0xdead_beef_cafe_f00d);
+    for entry in std::fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().is_none_or(|e| e != "eln") {
+            continue;
+        }
+        let src = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = src.lines().collect();
+        for _ in 0..20 {
+            // dropping a line, or a few characters from one, leaves a program that's nearly right
+            let mut mutated = lines.clone();
+            let i = rng.next() % mutated.len();
+            let cut;
+            if rng.next().is_multiple_of(2) {
+                mutated.remove(i);
+            } else if !mutated[i].is_empty() {
+                let start = rng.next() % mutated[i].len();
+                let end = (start + 1 + rng.next() % 4).min(mutated[i].len());
+                if mutated[i].is_char_boundary(start) && mutated[i].is_char_boundary(end) {
+                    cut = format!("{}{}", &mutated[i][..start], &mutated[i][end..]);
+                    mutated[i] = &cut;
+                }
+            }
+            check(&mutated.join("\n"));
+        }
+    }
+}
+
+#[test]
+fn files_ending_mid_statement() {
+    let open = "proc main(): i32 {\n    var x = 1\n    ";
+    let closed = "proc main(): i32 {\n    return 0\n}\n";
+    let cases = [
+        (format!("{}x", open), vec![Code::UnclosedBlock, Code::UnexpectedToken]),
+        (format!("{}x +", open), vec![Code::UnclosedBlock, Code::UnexpectedEof]),
+        (format!("{}x(", open), vec![Code::UnclosedBlock, Code::UnclosedDelimiter]),
+        // the last byte being the first of what could be a two-char token, for the lexer
+        (format!("{}.", closed), vec![Code::UnexpectedToken]),
+        (format!("{}=", closed), vec![Code::UnexpectedToken]),
+        (format!("{}#", closed), vec![]),
+    ];
+    for (src, expected) in cases {
+        check(&src);
+        let found: Vec<Code> = elgin::compile_to_ir(&src).err().unwrap_or_default().iter().filter_map(|d| d.code).collect();
+        assert_eq!(found, expected, "in {:?}", src);
+    }
+}
+
+#[test]
+fn deep_nesting_is_an_error() {
+    let parens = format!("proc main(): i32 {{\n    return {}1\n}}\n", "(".repeat(10000));
+    let pointers = format!("proc main(): i32 {{\n    var p: {}i32\n    return 0\n}}\n", "*[1]".repeat(10000));
+    let blocks = format!("proc main(): i32 {{\n{}    return 0\n{}}}\n", "if true {\n".repeat(10000), "}\n".repeat(10000));
+    for src in [parens, pointers, blocks] {
+        check(&src);
+        let diags = elgin::compile_to_ir(&src).unwrap_err();
+        assert_eq!(diags[0].code, Some(Code::TooDeeplyNested), "{:?}", diags[0]);
+    }
+    // just short of the limit is fine, and the rest of the compiler copes with it too
+    let parens = format!("proc main(): i32 {{\n    return {}1{}\n}}\n", "(".repeat(120), ")".repeat(120));
+    let blocks = format!("proc main(): i32 {{\n{}    return 0\n{}}}\n", "if true {\n".repeat(120), "}\n".repeat(120));
+    for src in [parens, blocks] {
+        if let Err(diags) = elgin::compile_to_ir(&src) {
+            panic!("{:?}", diags);
+        }
+    }
+}
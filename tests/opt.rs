@@ -0,0 +1,114 @@
+//! The passes in opt.rs, on procs straight out of analysis
+
+use elgin::errors::{Code, Diagnostic, Logger};
+use elgin::interp::{self, Value};
+use elgin::ir::{IRBuilder, IRProc, InstructionType};
+use elgin::lexer::Lexer;
+use elgin::opt;
+use elgin::parser::{BufferedSource, Parser};
+
+/// The procs declared in `src` with a body, before any pass has run, along with any warnings
+fn analyzed(src: &str) -> (Vec<IRProc>, Vec<Diagnostic>) {
+    let logger = Logger::new();
+    let mut parser = Parser::from_source(BufferedSource::new(Lexer::new(src, &logger)), &logger);
+    let (nodes, _) = parser.go();
+    let mut irbuilder = IRBuilder::new(&nodes, parser.available_type_var, &logger);
+    irbuilder.go();
+    irbuilder.analyze();
+    assert_eq!(logger.error_count(), 0, "{:?}", logger.drain());
+    (irbuilder.procs, logger.drain())
+}
+
+fn proc<'a>(procs: &'a [IRProc], name: &str) -> &'a IRProc {
+    procs.iter().find(|proc| proc.name == name).unwrap()
+}
+
+/// What's pushed, in order, with its type
+fn pushes(proc: &IRProc) -> Vec<String> {
+    proc.body.iter()
+        .filter_map(|ins| match &ins.contents.ins {
+            InstructionType::Push(value) => Some(format!("{}: {}", value, ins.contents.typ)),
+            _ => None,
+        })
+        .collect()
+}
+
+#[test]
+fn constant_folding_shrinks_procs() {
+    let src = "proc arithmetic(): i32 {\n    return 2 + 3 * 4\n}\n\
+proc sized(): i8 {\n    var x: i8 = 100 +~ 100\n    var f = 1.5 * 2.0\n    println(f)\n    return x\n}\n\
+proc branchy(): i32 {\n    if 3 > 2 {\n        return 1\n    }\n    return 0\n}\n\
+proc main(): i32 {\n    return arithmetic() + sized() as i32 + branchy()\n}\n";
+    let (mut procs, _) = analyzed(src);
+    let before: Vec<usize> = ["arithmetic", "sized", "branchy"].iter().map(|name| proc(&procs, name).body.len()).collect();
+    opt::optimize(&mut procs);
+    let after: Vec<usize> = ["arithmetic", "sized", "branchy"].iter().map(|name| proc(&procs, name).body.len()).collect();
+    assert_eq!(before, [6, 14, 14]);
+    // the branch only becomes a jump, it's for tidy_labels to get rid of the labels
+    assert_eq!(after, [2, 10, 11]);
+    assert_eq!(pushes(proc(&procs, "arithmetic")), ["14: i32"]);
+    // wrapping the way an i8 does, and a float staying one
+    assert_eq!(pushes(proc(&procs, "sized"))[..2], ["-56: i8", "3: f64"]);
+    assert!(!proc(&procs, "branchy").body.iter().any(|ins| matches!(ins.contents.ins, InstructionType::Branch(..))));
+    assert!(matches!(interp::run(&procs, &[], "main"), Ok(Value::Int(-41))));
+}
+
+#[test]
+fn code_after_return_is_removed_with_a_warning() {
+    let src = "proc main(): i32 {\n    var x = 1\n    return x\n    println(x + 2)\n    x = 3\n}\n";
+    let (mut procs, _) = analyzed(src);
+    let before = proc(&procs, "main").body.len();
+    let logger = Logger::new();
+    opt::remove_dead_code(&mut procs, true, &logger);
+    let warnings = logger.drain();
+    assert_eq!(warnings.len(), 1, "{:?}", warnings);
+    assert_eq!(warnings[0].code, Some(Code::UnreachableCode));
+    // the first thing left out is loading the x in x + 2
+    assert_eq!((warnings[0].pos, warnings[0].len), (src.find("x + 2").unwrap(), 1));
+    let main = proc(&procs, "main");
+    assert_eq!((before, main.body.len()), (12, 4));
+    assert!(matches!(main.body.last().unwrap().contents.ins, InstructionType::Return));
+}
+
+/// How many labels and jumps (including branches) there are
+fn labels_and_jumps(proc: &IRProc) -> (usize, usize) {
+    let count = |f: fn(&InstructionType) -> bool| proc.body.iter().filter(|ins| f(&ins.contents.ins)).count();
+    (
+        count(|ins| matches!(ins, InstructionType::Label(_))),
+        count(|ins| matches!(ins, InstructionType::Jump(_) | InstructionType::Branch(..))),
+    )
+}
+
+#[test]
+fn tidying_labels_is_idempotent() {
+    let src = "proc sum(n: i32): i32 {\n    var total = 0\n    var i = 0\n    while i < n {\n        if i < 3 {\n            total += 1\n        } elif i < 10 {\n            if i == 5 {\n                total += 100\n            }\n        } else {\n            total += 2\n        }\n        i += 1\n    }\n    return total\n}\n\
+proc main(): i32 {\n    return sum(20)\n}\n";
+    let (mut procs, _) = analyzed(src);
+    opt::remove_dead_code(&mut procs, false, &Logger::new());
+    let before = labels_and_jumps(proc(&procs, "sum"));
+    opt::tidy_labels(&mut procs);
+    let once = proc(&procs, "sum").to_text();
+    let after = labels_and_jumps(proc(&procs, "sum"));
+    opt::tidy_labels(&mut procs);
+    assert_eq!(proc(&procs, "sum").to_text(), once);
+    // the inner if's end and the elif's end both lead straight to the outer if's end
+    assert_eq!((before, after), ((12, 12), (10, 10)));
+    assert!(matches!(interp::run(&procs, &[], "main"), Ok(Value::Int(123))));
+}
+
+#[test]
+fn tail_calls_become_loops() {
+    let src = "proc count_down(n: i64, total: i64): i64 {\n    if n == 0 {\n        return total\n    }\n    return count_down(n - 1, total + 2)\n}\n\
+proc factorial(n: i64): i64 {\n    if n <= 1 {\n        return 1\n    }\n    return n * factorial(n - 1)\n}\n\
+proc total(): i64 {\n    return count_down(1000000, 0) + factorial(20)\n}\n\
+proc main(): i32 {\n    println(total())\n    return 0\n}\n";
+    let (mut procs, _) = analyzed(src);
+    let calls_itself = |proc: &IRProc| proc.body.iter().any(|ins| matches!(&ins.contents.ins, InstructionType::Call(name) if *name == proc.name));
+    assert!(calls_itself(proc(&procs, "count_down")));
+    opt::optimize(&mut procs);
+    assert!(!calls_itself(proc(&procs, "count_down")));
+    // not a tail call, since the multiplication comes after it
+    assert!(calls_itself(proc(&procs, "factorial")));
+    let expected = 2_000_000 + 2_432_902_008_176_640_000;
+    assert!(matches!(interp::run(&procs, &[], "total"), Ok(Value::Int(v)) if v == expected));
+}
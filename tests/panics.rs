@@ -0,0 +1,36 @@
+//! A panic in the compiler comes back from the library as an internal compiler error, without being printed.
+//! This is its own test binary, since it sets the panic hook for the whole process.
+
+use elgin::errors::{self, Code, ErrorType};
+
+use std::panic;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+static PRINTED: AtomicUsize = AtomicUsize::new(0);
+
+#[test]
+fn caught_panics_are_diagnostics_and_arent_printed() {
+    // stands in for the hook the command line sets, which would print the panic
+    panic::set_hook(Box::new(|_| {
+        PRINTED.fetch_add(1, Ordering::SeqCst);
+    }));
+
+    let ice = errors::catching_panics(|| {
+        errors::working_on(12, 5);
+        panic!("no type for x")
+    }).unwrap_err();
+    assert_eq!(ice.severity, ErrorType::InternalError);
+    assert_eq!(ice.code, Some(Code::InternalError));
+    assert_eq!(ice.message, "The compiler panicked: no type for x");
+    assert_eq!((ice.pos, ice.len), (12, 5));
+    assert_eq!(PRINTED.load(Ordering::SeqCst), 0);
+
+    // the library still works afterwards, and gives back what it found as usual
+    let diags = elgin::compile_to_ir("proc main(): i32 {\n    return y\n}\n").unwrap_err();
+    assert_eq!(diags[0].code, Some(Code::UnknownVariable));
+
+    // a panic nothing catches still gets to the hook
+    assert!(thread::spawn(|| panic!("not caught")).join().is_err());
+    assert_eq!(PRINTED.load(Ordering::SeqCst), 1);
+}
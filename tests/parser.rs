@@ -0,0 +1,218 @@
+//! The shape of what the parser makes out of a file
+
+use elgin::astgen::Node;
+use elgin::errors::Span;
+
+/// The statements in the body of the only proc in `src`
+fn body(src: &str) -> Vec<Span<Node>> {
+    let nodes = elgin::parse(src).unwrap_or_else(|diags| panic!("expected no errors, got {:?}", diags));
+    match &nodes[..] {
+        [Span { contents: Node::ProcStatement(proc), .. }] => match &proc.body.contents {
+            Node::Block { nodes } => nodes.clone(),
+            other => panic!("expected a block, found {:?}", other),
+        },
+        _ => panic!("expected a single proc, found {:?}", nodes),
+    }
+}
+
+fn literal(node: &Span<Node>) -> &str {
+    match &node.contents {
+        Node::Literal { value, .. } => value,
+        other => panic!("expected a literal, found {:?}", other),
+    }
+}
+
+#[test]
+fn match_statement() {
+    let src = "proc f(x: i32): i32 {\n    match x {\n        0 { return 1 }\n        -2 {\n            x += 1\n            return x\n        }\n        else { return 3 }\n    }\n}\n";
+    let body = body(src);
+    let Node::MatchStatement { scrutinee, arms, default } = &body[0].contents else {
+        panic!("expected a match, found {:?}", body[0]);
+    };
+    assert!(matches!(&scrutinee.contents, Node::VariableRef { name } if name == "x"));
+    let patterns: Vec<&str> = arms.iter().map(|(pattern, _)| literal(pattern)).collect();
+    assert_eq!(patterns, ["0", "-2"]);
+    let lengths: Vec<usize> = arms.iter()
+        .map(|(_, block)| match &block.contents {
+            Node::Block { nodes } => nodes.len(),
+            other => panic!("expected a block, found {:?}", other),
+        })
+        .collect();
+    assert_eq!(lengths, [1, 2]);
+    let default = default.as_ref().expect("the else block");
+    assert!(matches!(&default.contents, Node::Block { nodes } if nodes.len() == 1));
+    assert_eq!(&src[body[0].pos..body[0].pos + 7], "match x");
+}
+
+#[test]
+fn match_without_else() {
+    let body = body("proc f(b: bool): i32 {\n    match b { true { return 1 } false { return 0 } }\n}\n");
+    let Node::MatchStatement { arms, default, .. } = &body[0].contents else {
+        panic!("expected a match, found {:?}", body[0]);
+    };
+    assert_eq!(arms.len(), 2);
+    assert!(default.is_none());
+}
+
+/// The name and parameters of every proc in `src`, and the number of arguments of every call in the body of the last
+fn procs_and_calls(src: &str) -> (Vec<(String, Vec<String>)>, Vec<usize>) {
+    let nodes = elgin::parse(src).unwrap_or_else(|diags| panic!("expected no errors in\n{}\ngot {:?}", src, diags));
+    let procs: Vec<_> = nodes.iter()
+        .map(|node| match &node.contents {
+            Node::ProcStatement(proc) => proc,
+            other => panic!("expected a proc, found {:?}", other),
+        })
+        .collect();
+    let mut calls = vec![];
+    if let Node::Block { nodes } = &procs.last().unwrap().body.contents {
+        for node in nodes {
+            match &node.contents {
+                Node::VarStatement { value, .. } => match &value.contents {
+                    Node::Call { args, .. } => calls.push(args.len()),
+                    other => panic!("expected a call, found {:?}", other),
+                },
+                Node::ReturnStatement { .. } => (),
+                other => panic!("expected a var statement, found {:?}", other),
+            }
+        }
+    }
+    (procs.iter().map(|proc| (proc.name.clone(), proc.args.clone())).collect(), calls)
+}
+
+#[test]
+fn trailing_commas() {
+    let single_line = "proc add(a: i32, b: i32,): i32 { return a + b }\n\
+proc main(): i32 {\n    var x = add(1, 2,)\n    var y = add(x, 3)\n    return y\n}\n";
+    let multi_line = "proc add(\n    a: i32,\n    b: i32,\n): i32 {\n    return a + b\n}\n\
+proc main(): i32 {\n    var x = add(\n        1,\n        2,\n    )\n    var y = add(\n        x,\n        3\n    )\n    return y\n}\n";
+    for src in [single_line, multi_line] {
+        let (procs, calls) = procs_and_calls(src);
+        let params = vec!["a".to_owned(), "b".to_owned()];
+        assert_eq!(procs, [("add".to_owned(), params), ("main".to_owned(), vec![])], "in\n{}", src);
+        assert_eq!(calls, [2, 2], "in\n{}", src);
+    }
+}
+
+#[test]
+fn comma_without_arguments() {
+    for src in ["proc main(): i32 {\n    return f(,)\n}\n", "proc f(,): i32 {\n    return 0\n}\n"] {
+        let diags = elgin::parse(src).unwrap_err();
+        assert_eq!(diags[0].code, Some(elgin::errors::Code::UnexpectedToken), "{:?}", diags);
+        assert_eq!(&src[diags[0].pos..diags[0].pos + diags[0].len], ",", "in\n{}", src);
+    }
+}
+
+#[test]
+fn block_layouts() {
+    let layouts = [
+        "proc f(x: i32): i32 {\n    x += 1\n    return x\n}\n",
+        "proc f(x: i32): i32 { x += 1\n    return x\n}\n",
+        "proc f(x: i32): i32 {\n    x += 1\n    return x }\n",
+        "proc f(x: i32): i32 { x += 1; return x }\n",
+        "proc f(x: i32): i32 {\n\n    x += 1\n\n\n    return x\n\n}\n",
+        "proc f(x: i32): i32 {\n    # a comment\n    x += 1\n    return x # another\n}\n",
+    ];
+    for src in layouts {
+        let body = body(src);
+        assert_eq!(body.len(), 2, "in\n{}", src);
+        assert!(matches!(body[1].contents, Node::ReturnStatement { .. }), "in\n{}", src);
+    }
+}
+
+#[test]
+fn single_line_and_empty_blocks() {
+    let src = "proc f(x: i32): i32 {\n    if x > 0 { return 1 } else { return 2 }\n    while false {}\n    while false {\n\n    }\n}\n";
+    let body = body(src);
+    assert_eq!(body.len(), 3);
+    let Node::IfStatement { body: then, else_body, .. } = &body[0].contents else {
+        panic!("expected an if, found {:?}", body[0]);
+    };
+    for block in [then, else_body] {
+        assert!(matches!(&block.contents, Node::Block { nodes } if nodes.len() == 1), "{:?}", block);
+    }
+    for node in &body[1..] {
+        let Node::WhileStatement { body, .. } = &node.contents else {
+            panic!("expected a while, found {:?}", node);
+        };
+        assert!(matches!(&body.contents, Node::Block { nodes } if nodes.is_empty()), "{:?}", body);
+    }
+}
+
+#[test]
+fn operators_next_to_each_other() {
+    let body = body("proc f(a: i32, b: i32, x: i32): bool {\n    var c = a+-b\n    return x==-1\n}\n");
+    let Node::VarStatement { value, .. } = &body[0].contents else {
+        panic!("expected a var, found {:?}", body[0]);
+    };
+    let Node::InfixOp { op, right, .. } = &value.contents else {
+        panic!("expected an infix operator, found {:?}", value);
+    };
+    assert_eq!(op.as_str(), "+");
+    assert!(matches!(&right.contents, Node::PrefixOp { op, .. } if op.as_str() == "-"), "{:?}", right);
+
+    let Node::ReturnStatement { val } = &body[1].contents else {
+        panic!("expected a return, found {:?}", body[1]);
+    };
+    let Node::InfixOp { op, right, .. } = &val.contents else {
+        panic!("expected an infix operator, found {:?}", val);
+    };
+    assert_eq!(op.as_str(), "==");
+    assert_eq!(literal(right), "-1");
+}
+
+#[test]
+fn minus_before_a_literal_is_part_of_it() {
+    let body = body("proc f(x: i64): i64 {\n    var a = -9223372036854775808\n    var b = -2.5\n    var c = - x\n    return -x\n}\n");
+    let values: Vec<&Span<Node>> = body[..3].iter()
+        .map(|node| match &node.contents {
+            Node::VarStatement { value, .. } => &**value,
+            other => panic!("expected a var, found {:?}", other),
+        })
+        .collect();
+    assert_eq!(literal(values[0]), "-9223372036854775808");
+    assert_eq!(literal(values[1]), "-2.5");
+    assert!(matches!(&values[2].contents, Node::PrefixOp { op, .. } if op.as_str() == "-"), "{:?}", values[2]);
+    let Node::ReturnStatement { val } = &body[3].contents else {
+        panic!("expected a return, found {:?}", body[3]);
+    };
+    assert!(matches!(&val.contents, Node::PrefixOp { .. }), "{:?}", val);
+}
+
+/// The expression with every operator bracketed along with its operands, to show how it nested
+fn bracketed(node: &Span<Node>) -> String {
+    match &node.contents {
+        Node::Literal { value, .. } => value.clone(),
+        Node::VariableRef { name } => name.clone(),
+        Node::InfixOp { op, left, right, .. } => format!("({} {} {})", bracketed(left), op.as_str(), bracketed(right)),
+        Node::PrefixOp { op, right, .. } => format!("({}{})", op.as_str(), bracketed(right)),
+        Node::Cast { value, typ } => format!("({} as {})", bracketed(value), typ),
+        other => panic!("no brackets for {:?}", other),
+    }
+}
+
+#[test]
+fn precedence() {
+    let cases = [
+        ("-x ** 2", "(-(x ** 2))"),
+        ("2 ** 3 ** 2", "(2 ** (3 ** 2))"),
+        ("a * b ** c", "(a * (b ** c))"),
+        ("a - b - c", "((a - b) - c)"),
+        ("a + b * c // d", "(a + ((b * c) // d))"),
+        ("-a * b", "((-a) * b)"),
+        ("!a && b || c", "(((!a) && b) || c)"),
+        ("a || b && c", "(a || (b && c))"),
+        ("a + b < c * d", "((a + b) < (c * d))"),
+        ("a << b + c", "((a << b) + c)"),
+        ("a & b | c ^ d", "((a & b) | (c ^ d))"),
+        ("a | b == c", "(a | (b == c))"),
+        ("-a as i64 + b", "((-(a as i64)) + b)"),
+        ("a +~ b *~ c", "(a +~ (b *~ c))"),
+    ];
+    for (expr, expected) in cases {
+        let body = body(&format!("proc f(a: i64, b: i64, c: i64, d: i64, x: i64): i64 {{\n    return {}\n}}\n", expr));
+        let Node::ReturnStatement { val } = &body[0].contents else {
+            panic!("expected a return, found {:?}", body[0]);
+        };
+        assert_eq!(bracketed(val), expected, "for {}", expr);
+    }
+}
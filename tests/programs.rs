@@ -0,0 +1,84 @@
+//! Each tests/programs/NAME.eln is built into an executable by the elgin binary and run,
+//! and what it prints compared against NAME.out.
+//! Setting ELGIN_BLESS writes what they print out instead, for when a change to it is intended.
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+fn programs() -> Vec<PathBuf> {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/programs");
+    let mut entries: Vec<_> = fs::read_dir(&dir).unwrap().map(|e| e.unwrap().path()).collect();
+    entries.sort();
+    entries.retain(|p| p.extension().is_some_and(|e| e == "eln"));
+    entries
+}
+
+fn elgin(path: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_elgin")).arg(path).args(args).arg("--no-cache").output().unwrap()
+}
+
+/// Somewhere to put what's built from `path`, which nothing else will be using
+fn out_dir(path: &Path) -> PathBuf {
+    let name = path.file_stem().unwrap().to_string_lossy();
+    let dir = env::temp_dir().join(format!("elgin-programs-{}-{}", name, std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[test]
+fn programs_print_what_they_should() {
+    let bless = env::var_os("ELGIN_BLESS").is_some();
+    let mut failures = vec![];
+    let programs = programs();
+    for path in &programs {
+        let dir = out_dir(path);
+        let exe = dir.join("program");
+        let built = elgin(path, &["--emit", "exe", "-o", &exe.to_string_lossy()]);
+        assert!(built.status.success(), "{}: {}", path.display(), String::from_utf8_lossy(&built.stderr));
+        let ran = Command::new(&exe).output().unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(ran.status.success(), "{}: {:?}", path.display(), ran.status);
+
+        let actual = String::from_utf8(ran.stdout).unwrap();
+        let expected_path = path.with_extension("out");
+        if bless {
+            fs::write(&expected_path, &actual).unwrap();
+        } else if fs::read_to_string(&expected_path).ok().as_deref() != Some(actual.as_str()) {
+            failures.push(format!("{} doesn't match, it's now:\n{}", expected_path.display(), actual));
+        }
+    }
+    assert!(programs.len() >= 5, "only found {} programs", programs.len());
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
+
+#[test]
+fn every_emit_writes_its_output() {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/programs/hello.eln");
+    let dir = out_dir(&path);
+    let cases = [("ast", "ProcStatement"), ("ir", "proc main"), ("llvm", "define i32 @main()"), ("obj", "")];
+    for (emit, expected) in cases {
+        let out = dir.join(format!("hello.{}", emit));
+        let built = elgin(&path, &["--emit", emit, "-o", &out.to_string_lossy()]);
+        assert!(built.status.success(), "--emit {}: {}", emit, String::from_utf8_lossy(&built.stderr));
+        let written = fs::read(&out).unwrap();
+        assert!(!written.is_empty(), "--emit {} wrote nothing", emit);
+        assert!(String::from_utf8_lossy(&written).contains(expected), "--emit {} wrote\n{}", emit, String::from_utf8_lossy(&written));
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn errors_exit_with_a_failure() {
+    let dir = env::temp_dir().join(format!("elgin-programs-broken-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("broken.eln");
+    fs::write(&path, "proc main(): i32 {\n    return missing\n}\n").unwrap();
+    let exe = dir.join("broken");
+    let built = elgin(&path, &["-o", &exe.to_string_lossy()]);
+    let written = exe.exists();
+    fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(built.status.code(), Some(1));
+    assert!(!written);
+}